@@ -0,0 +1,297 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use log::{debug, info, warn};
+use reqwest::header::{HeaderMap, CONTENT_LOCATION, REFRESH};
+use reqwest::RequestBuilder;
+use rss::extension::ExtensionBuilder;
+use rss::Item;
+use simple_eyre::eyre::{self, bail, WrapErr};
+use url::Url;
+
+use crate::config::{ArchiveConfig, ArchivePlacement, ArchiveService, ChannelConfig};
+use crate::state::FeedState;
+use crate::{download, feed, text, Client};
+
+/// Prefix and URI of the Atom namespace, used for the links to archived copies
+pub const ATOM_PREFIX: &str = "atom";
+pub const ATOM_NAMESPACE: &str = "http://www.w3.org/2005/Atom";
+/// The maximum number of captures requested in a run, the rest are requested by later runs
+const MAX_CAPTURES: usize = 10;
+
+impl ArchiveService {
+    /// The URL that requests a capture
+    fn save_url(self, link: &str) -> eyre::Result<Url> {
+        match self {
+            ArchiveService::Wayback => Url::parse(&format!("https://web.archive.org/save/{link}")),
+            // The link to capture is submitted as a form
+            ArchiveService::ArchiveToday => Url::parse("https://archive.ph/submit/"),
+        }
+        .wrap_err_with(|| format!("unable to build archive URL for {link}"))
+    }
+
+    /// The request that asks for a capture of `link` at `url`
+    fn request(self, client: &Client, url: &Url, link: &str) -> RequestBuilder {
+        match self {
+            ArchiveService::Wayback => client.http.get(url.clone()),
+            ArchiveService::ArchiveToday => client.http.post(url.clone()).form(&[("url", link)]),
+        }
+    }
+
+    /// The URL of the copy captured by a request that ended up at `url` with response `headers`
+    ///
+    /// A response that doesn't lead to the copy means the capture wasn't made, e.g. because
+    /// archive.today asked for a CAPTCHA to be solved.
+    fn archived_url(self, url: &Url, headers: &HeaderMap) -> eyre::Result<String> {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+        };
+        match self {
+            ArchiveService::Wayback => {
+                // The save request redirects to the copy or names it in Content-Location
+                let url = match header(CONTENT_LOCATION) {
+                    Some(location) if !url.path().starts_with("/web/") => url
+                        .join(location)
+                        .wrap_err_with(|| format!("invalid Content-Location: {location}"))?,
+                    _ => url.clone(),
+                };
+                if !url.path().starts_with("/web/") {
+                    bail!("the Wayback Machine didn't respond with the captured copy");
+                }
+                Ok(url.to_string())
+            }
+            ArchiveService::ArchiveToday => {
+                // The submission redirects to the copy, or to the page showing the capture in
+                // progress (/wip/), or says where it is in a Refresh header
+                let url = match header(REFRESH)
+                    .and_then(|refresh| refresh.split_once("url="))
+                    .map(|(_, location)| location)
+                {
+                    Some(location) => url
+                        .join(location)
+                        .wrap_err_with(|| format!("invalid Refresh location: {location}"))?,
+                    None => url.clone(),
+                };
+                let id = url
+                    .path()
+                    .strip_prefix("/wip/")
+                    .or_else(|| url.path().strip_prefix('/'))
+                    .unwrap_or_default();
+                if id.is_empty() || id.contains('/') || id == "submit" {
+                    bail!("archive.today didn't accept the capture, it may have asked for a CAPTCHA to be solved");
+                }
+                let mut archived = url.clone();
+                archived.set_path(id);
+                archived.set_query(None);
+                Ok(archived.to_string())
+            }
+        }
+    }
+}
+
+/// Request a capture of the link of each new item and link to the archived copy from them
+///
+/// The archived URLs are kept in `state` so that items keep their link in later runs. Captures
+/// that fail are requested again by the next run.
+pub async fn apply(
+    client: &Client,
+    channel_config: &ChannelConfig,
+    config: &ArchiveConfig,
+    state: &mut FeedState,
+    mut items: Vec<Item>,
+) -> Vec<Item> {
+    let links: BTreeSet<_> = items.iter().filter_map(Item::link).collect();
+    // Forget the items that are no longer in the feed
+    state
+        .archived
+        .retain(|link, _| links.contains(link.as_str()));
+
+    let mut requested = 0;
+    for link in links {
+        if state.archived.contains_key(link) {
+            continue;
+        }
+        if requested == MAX_CAPTURES {
+            info!("leaving the remaining captures for the next run");
+            break;
+        }
+        requested += 1;
+        match capture(client, channel_config, config.service, link).await {
+            Ok(archived) => {
+                state.archived.insert(link.to_string(), archived);
+            }
+            Err(report) => warn!("{:?}", report.wrap_err(format!("unable to archive {link}"))),
+        }
+    }
+
+    for item in &mut items {
+        if let Some(archived) = item.link().and_then(|link| state.archived.get(link)) {
+            add_archive_link(item, config.placement, archived);
+        }
+    }
+    items
+}
+
+/// Request a capture of `link`, returning the URL of the archived copy
+///
+/// Like the pages of the feed, requests are skipped while the archive is being backed off from
+/// and count towards the download budget.
+async fn capture(
+    client: &Client,
+    channel_config: &ChannelConfig,
+    service: ArchiveService,
+    link: &str,
+) -> eyre::Result<String> {
+    let url = service.save_url(link)?;
+    client.backoff.check(&url)?;
+    client.downloads.check()?;
+    debug!("requesting capture {url}");
+    let request = service
+        .request(client, &url, link)
+        .headers(feed::request_headers(client, &url, &None, channel_config));
+    let resp = match download::send(client, request).await {
+        Ok(resp) => resp,
+        Err(report) => {
+            client.backoff.failed(&url, None);
+            return Err(report.wrap_err(format!("unable to request {url}")));
+        }
+    };
+    let status = resp.status();
+    if !status.is_success() {
+        client.backoff.failed(&url, Some(status));
+        bail!(
+            "capture request failed: {} {}",
+            status.as_str(),
+            status.canonical_reason().unwrap_or("Unknown Status")
+        );
+    }
+    client.backoff.succeeded(&url);
+    let (final_url, headers) = (resp.url().clone(), resp.headers().clone());
+    download::body(client, resp).await?;
+    service.archived_url(&final_url, &headers)
+}
+
+fn add_archive_link(item: &mut Item, placement: ArchivePlacement, archived: &str) {
+    match placement {
+        ArchivePlacement::Description => {
+            let link = format!(
+                r#"<p><a href="{}">Archived copy</a></p>"#,
+                text::escape_html(archived)
+            );
+            let description = match item.description() {
+                Some(description) => format!("{description}{link}"),
+                None => link,
+            };
+            item.set_description(Some(description));
+        }
+        ArchivePlacement::Link => {
+            let link = ExtensionBuilder::default()
+                .name(format!("{ATOM_PREFIX}:link"))
+                .attrs(BTreeMap::from([
+                    ("rel".to_string(), "related".to_string()),
+                    ("href".to_string(), archived.to_string()),
+                ]))
+                .build();
+            item.extensions
+                .entry(ATOM_PREFIX.to_string())
+                .or_default()
+                .entry("link".to_string())
+                .or_default()
+                .push(link);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use rss::ItemBuilder;
+
+    #[test]
+    fn test_archived_url() {
+        let link = "https://example.com/post?id=1";
+        let headers = |name, value: &'static str| {
+            HeaderMap::from_iter([(name, HeaderValue::from_static(value))])
+        };
+        let wayback = ArchiveService::Wayback;
+        let copy = "https://web.archive.org/web/20240801102030/https://example.com/post?id=1";
+        let save_url = wayback.save_url(link).unwrap();
+        assert_eq!(
+            save_url.as_str(),
+            "https://web.archive.org/save/https://example.com/post?id=1"
+        );
+        // Redirected to the copy
+        assert_eq!(
+            wayback
+                .archived_url(&copy.parse().unwrap(), &HeaderMap::new())
+                .unwrap(),
+            copy
+        );
+        assert_eq!(
+            wayback
+                .archived_url(
+                    &save_url,
+                    &headers(
+                        CONTENT_LOCATION,
+                        "/web/20240801102030/https://example.com/post?id=1"
+                    )
+                )
+                .unwrap(),
+            copy
+        );
+        assert!(wayback.archived_url(&save_url, &HeaderMap::new()).is_err());
+
+        let archive_today = ArchiveService::ArchiveToday;
+        let submit_url = archive_today.save_url(link).unwrap();
+        assert_eq!(submit_url.as_str(), "https://archive.ph/submit/");
+        assert_eq!(
+            archive_today
+                .archived_url(
+                    &"https://archive.ph/wip/AbCd1".parse().unwrap(),
+                    &HeaderMap::new()
+                )
+                .unwrap(),
+            "https://archive.ph/AbCd1"
+        );
+        assert_eq!(
+            archive_today
+                .archived_url(
+                    &submit_url,
+                    &headers(REFRESH, "0;url=https://archive.ph/wip/AbCd1")
+                )
+                .unwrap(),
+            "https://archive.ph/AbCd1"
+        );
+        // The CAPTCHA is shown on the submission page
+        assert!(archive_today
+            .archived_url(&submit_url, &HeaderMap::new())
+            .is_err());
+        assert!(archive_today
+            .archived_url(&"https://archive.ph/".parse().unwrap(), &HeaderMap::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_add_archive_link() {
+        let archived = "https://web.archive.org/web/20240801102030/https://example.com/";
+        let mut item = ItemBuilder::default()
+            .description("<p>Post</p>".to_string())
+            .build();
+        add_archive_link(&mut item, ArchivePlacement::Description, archived);
+        assert_eq!(
+            item.description(),
+            Some(
+                r#"<p>Post</p><p><a href="https://web.archive.org/web/20240801102030/https://example.com/">Archived copy</a></p>"#
+            )
+        );
+
+        let mut item = Item::default();
+        add_archive_link(&mut item, ArchivePlacement::Link, archived);
+        let link = &item.extensions()["atom"]["link"][0];
+        assert_eq!(link.attrs()["href"], archived);
+        assert_eq!(link.attrs()["rel"], "related");
+    }
+}
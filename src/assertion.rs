@@ -0,0 +1,59 @@
+use rss::Item;
+
+use crate::config::AssertConfig;
+
+/// Check the items extracted for a feed, returning a description of each assertion that failed
+pub fn check(config: &AssertConfig, items: &[Item]) -> Vec<String> {
+    let mut failures = Vec::new();
+    if let Some(min_items) = config.min_items {
+        if items.len() < min_items {
+            failures.push(format!(
+                "expected at least {min_items} items but found {}",
+                items.len()
+            ));
+        }
+    }
+    if let Some(heading_matches) = &config.heading_matches {
+        let mismatched: Vec<_> = items
+            .iter()
+            .map(|item| item.title().unwrap_or_default())
+            .filter(|title| !heading_matches.is_match(title))
+            .collect();
+        if let Some(first) = mismatched.first() {
+            failures.push(format!(
+                "{} of {} headings don't match /{heading_matches}/, e.g. '{first}'",
+                mismatched.len(),
+                items.len()
+            ));
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+    use rss::ItemBuilder;
+
+    #[test]
+    fn test_check() {
+        let items: Vec<_> = ["Episode 1", "Episode 2", "Trailer"]
+            .into_iter()
+            .map(|title| ItemBuilder::default().title(title.to_string()).build())
+            .collect();
+        let config = AssertConfig {
+            min_items: Some(3),
+            heading_matches: Some(Regex::new(r"^Episode \d+$").unwrap()),
+            error: false,
+        };
+        assert_eq!(
+            check(&config, &items),
+            ["1 of 3 headings don't match /^Episode \\d+$/, e.g. 'Trailer'"]
+        );
+        assert_eq!(
+            check(&config, &items[..2]),
+            ["expected at least 3 items but found 2"]
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use atom_syndication::{
+    Category, Content, Entry, Feed, FixedDateTime, Generator, Link, Person, Text,
+};
+use chrono::DateTime;
+use rss::{Channel, Item};
+use time::OffsetDateTime;
+use url::Url;
+
+/// Prefix of the IDs made for entries whose GUID isn't a URI
+const ID_PREFIX: &str = "urn:rsspls:";
+
+/// Convert a generated RSS channel to an Atom feed
+///
+/// The feed is updated when its newest entry was, falling back on the build date of the channel,
+/// then `now`.
+pub fn from_channel(channel: &Channel, now: OffsetDateTime) -> Feed {
+    let entries: Vec<_> = channel.items().iter().map(entry).collect();
+    let updated = entries
+        .iter()
+        .filter_map(Entry::published)
+        .max()
+        .copied()
+        .or_else(|| channel.last_build_date().and_then(parse_date))
+        .unwrap_or_else(|| fixed_date_time(now));
+    let entries = entries
+        .into_iter()
+        .map(|mut entry| {
+            if entry.published().is_none() {
+                entry.set_updated(updated);
+            }
+            entry
+        })
+        .collect::<Vec<_>>();
+
+    let mut feed = Feed::default();
+    feed.set_id(channel.link());
+    feed.set_title(channel.title());
+    feed.set_updated(updated);
+    feed.set_links(vec![alternate_link(channel.link())]);
+    if !channel.description().is_empty() {
+        feed.set_subtitle(Text::plain(channel.description()));
+    }
+    feed.set_lang(channel.language().map(str::to_string));
+    feed.set_logo(channel.image().map(|image| image.url().to_string()));
+    feed.set_rights(channel.copyright().map(Text::plain));
+    feed.set_generator(channel.generator().map(|generator| Generator {
+        value: generator.to_string(),
+        ..Default::default()
+    }));
+    feed.set_categories(categories(channel.categories()));
+    feed.set_entries(entries);
+    feed
+}
+
+fn entry(item: &Item) -> Entry {
+    let mut entry = Entry::default();
+    entry.set_id(entry_id(item));
+    entry.set_title(item.title().unwrap_or_default());
+    let published = item.pub_date().and_then(parse_date);
+    if let Some(published) = published {
+        entry.set_updated(published);
+    }
+    entry.set_published(published);
+
+    let mut links: Vec<_> = item.link().map(alternate_link).into_iter().collect();
+    if let Some(enclosure) = item.enclosure() {
+        links.push(Link {
+            href: enclosure.url().to_string(),
+            rel: "enclosure".to_string(),
+            mime_type: Some(enclosure.mime_type().to_string()),
+            length: Some(enclosure.length().to_string()),
+            ..Default::default()
+        });
+    }
+    // Such as the links to archived copies
+    let extension_links = item
+        .extensions()
+        .get("atom")
+        .and_then(|elements| elements.get("link"))
+        .into_iter()
+        .flatten();
+    for link in extension_links {
+        if let Some(href) = link.attrs().get("href") {
+            links.push(Link {
+                href: href.to_string(),
+                rel: link
+                    .attrs()
+                    .get("rel")
+                    .map_or("alternate", String::as_str)
+                    .to_string(),
+                ..Default::default()
+            });
+        }
+    }
+    entry.set_links(links);
+
+    entry.set_summary(item.description().map(Text::html));
+    entry.set_content(item.content().map(|content| Content {
+        value: Some(content.to_string()),
+        content_type: Some("html".to_string()),
+        ..Default::default()
+    }));
+    entry.set_authors(
+        item.author()
+            .map(|name| Person {
+                name: name.to_string(),
+                ..Default::default()
+            })
+            .into_iter()
+            .collect::<Vec<_>>(),
+    );
+    entry.set_categories(categories(item.categories()));
+    entry
+}
+
+/// The ID of the entry for `item`, which has to be a URI
+fn entry_id(item: &Item) -> String {
+    match item.guid().map(|guid| guid.value()).or(item.link()) {
+        Some(value) if Url::parse(value).is_ok() => value.to_string(),
+        Some(value) => format!("{ID_PREFIX}{value}"),
+        None => format!("{ID_PREFIX}{}", item.title().unwrap_or_default()),
+    }
+}
+
+fn alternate_link(href: &str) -> Link {
+    Link {
+        href: href.to_string(),
+        rel: "alternate".to_string(),
+        ..Default::default()
+    }
+}
+
+fn categories(categories: &[rss::Category]) -> Vec<Category> {
+    categories
+        .iter()
+        .map(|category| Category {
+            term: category.name().to_string(),
+            scheme: category.domain().map(str::to_string),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Parse the RFC 2822 dates in the RSS channel
+fn parse_date(date: &str) -> Option<FixedDateTime> {
+    DateTime::parse_from_rfc2822(date).ok()
+}
+
+fn fixed_date_time(date: OffsetDateTime) -> FixedDateTime {
+    // NOTE(unwrap): The range of chrono's dates is larger than that of time's
+    DateTime::from_timestamp(date.unix_timestamp(), 0)
+        .unwrap()
+        .fixed_offset()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rss::{ChannelBuilder, GuidBuilder, ItemBuilder};
+
+    #[test]
+    fn test_from_channel() {
+        let channel = ChannelBuilder::default()
+            .title("Example")
+            .link("http://example.com/")
+            .language("en".to_string())
+            .copyright("© Example".to_string())
+            .items(vec![
+                ItemBuilder::default()
+                    .title("One".to_string())
+                    .link("http://example.com/1".to_string())
+                    .guid(GuidBuilder::default().value("http://example.com/1").build())
+                    .pub_date("Thu, 1 Aug 2024 10:00:00 +0000".to_string())
+                    .description("<p>First</p>".to_string())
+                    .build(),
+                ItemBuilder::default()
+                    .title("Two".to_string())
+                    .guid(
+                        GuidBuilder::default()
+                            .value("abc123")
+                            .permalink(false)
+                            .build(),
+                    )
+                    .build(),
+            ])
+            .build();
+        let feed = from_channel(&channel, time::macros::datetime!(2024-08-02 00:00 UTC));
+        assert_eq!(feed.id(), "http://example.com/");
+        assert_eq!(feed.updated().to_rfc3339(), "2024-08-01T10:00:00+00:00");
+        assert_eq!(feed.lang(), Some("en"));
+        assert_eq!(
+            feed.rights().map(|rights| rights.as_str()),
+            Some("© Example")
+        );
+        assert_eq!(feed.logo(), None);
+
+        let entries = feed.entries();
+        assert_eq!(entries[0].id(), "http://example.com/1");
+        assert_eq!(entries[0].links()[0].href(), "http://example.com/1");
+        assert_eq!(
+            entries[0].summary().map(|summary| summary.as_str()),
+            Some("<p>First</p>")
+        );
+        assert_eq!(entries[1].id(), "urn:rsspls:abc123");
+        assert_eq!(entries[1].updated(), feed.updated());
+        assert_eq!(entries[1].published(), None);
+    }
+
+    #[test]
+    fn test_from_channel_undated() {
+        let channel = ChannelBuilder::default()
+            .title("Example")
+            .link("http://example.com/")
+            .items(vec![ItemBuilder::default()
+                .title("One".to_string())
+                .build()])
+            .build();
+        let feed = from_channel(&channel, time::macros::datetime!(2024-08-02 12:30 UTC));
+        assert_eq!(feed.updated().to_rfc3339(), "2024-08-02T12:30:00+00:00");
+        assert_eq!(feed.entries()[0].updated(), feed.updated());
+    }
+}
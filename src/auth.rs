@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+use std::env;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::warn;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use simple_eyre::eyre::{self, eyre, WrapErr};
+use url::Url;
+
+use crate::config::AuthConfig;
+use crate::secret::Secret;
+
+/// The Authorization header sent to the hosts of a feed's pages
+///
+/// The header is only sent to the hosts the feed is configured to fetch so that credentials
+/// aren't given to the other sites its items link to.
+#[derive(Debug, Clone)]
+pub struct Authorization {
+    hosts: Vec<String>,
+    value: HeaderValue,
+}
+
+impl Authorization {
+    /// Build the header for `config`, sent to the hosts of `urls`
+    pub fn new(config: &AuthConfig, urls: &[Url]) -> eyre::Result<Authorization> {
+        let value = match config {
+            AuthConfig::Basic {
+                username,
+                password,
+                password_env,
+            } => {
+                let password = secret("password", password, password_env)?;
+                format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{username}:{password}"))
+                )
+            }
+            AuthConfig::Bearer { token, token_env } => {
+                format!("Bearer {}", secret("token", token, token_env)?)
+            }
+        };
+        let mut value =
+            HeaderValue::from_str(&value).wrap_err("credentials can't be sent in a header")?;
+        value.set_sensitive(true);
+        Ok(Authorization {
+            hosts: hosts(urls),
+            value,
+        })
+    }
+
+    /// The value of the Authorization header for a request to `url`, if it's sent there
+    pub fn header(&self, url: &Url) -> Option<HeaderValue> {
+        sent_to(&self.hosts, url).then(|| self.value.clone())
+    }
+}
+
+/// The `headers` of a feed, sent to the hosts of its pages
+///
+/// These often hold API keys, so like the Authorization header they aren't sent to the other
+/// sites the feed's items link to.
+#[derive(Debug, Clone)]
+pub struct CustomHeaders {
+    hosts: Vec<String>,
+    headers: HeaderMap,
+}
+
+impl CustomHeaders {
+    /// The valid headers in `config`, sent to the hosts of `urls`
+    pub fn new(config: &BTreeMap<String, Secret>, urls: &[Url]) -> eyre::Result<CustomHeaders> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in config {
+            let value = value
+                .expose()
+                .wrap_err_with(|| format!("unable to read value of {name} header"))?;
+            match (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                (Ok(name), Ok(mut value)) => {
+                    value.set_sensitive(true);
+                    headers.insert(name, value);
+                }
+                (Err(err), _) => warn!("ignoring invalid header name {name}: {err}"),
+                (_, Err(err)) => warn!("ignoring invalid value for {name} header: {err}"),
+            }
+        }
+        Ok(CustomHeaders {
+            hosts: hosts(urls),
+            headers,
+        })
+    }
+
+    /// The headers for a request to `url`, if they're sent there
+    pub fn headers(&self, url: &Url) -> Option<&HeaderMap> {
+        sent_to(&self.hosts, url).then_some(&self.headers)
+    }
+}
+
+fn hosts(urls: &[Url]) -> Vec<String> {
+    urls.iter()
+        .filter_map(|url| url.host_str())
+        .map(str::to_ascii_lowercase)
+        .collect()
+}
+
+fn sent_to(hosts: &[String], url: &Url) -> bool {
+    url.host_str()
+        .map(str::to_ascii_lowercase)
+        .is_some_and(|host| hosts.contains(&host))
+}
+
+/// The secret given in the config, or read from the environment variable named by `env_name`
+fn secret(name: &str, value: &Option<Secret>, env_name: &Option<String>) -> eyre::Result<String> {
+    match (value, env_name) {
+        (Some(value), _) => value.expose().map(str::to_string),
+        (None, Some(env_name)) => env::var(env_name).wrap_err_with(|| {
+            format!("unable to read {name} from environment variable {env_name}")
+        }),
+        (None, None) => Err(eyre!("auth needs {name} or {name}_env")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorization() {
+        let urls = ["https://Intranet.example.com/dashboard".parse().unwrap()];
+        let basic = AuthConfig::Basic {
+            username: "Aladdin".to_string(),
+            password: Some(Secret::Plain("open sesame".to_string())),
+            password_env: None,
+        };
+        let auth = Authorization::new(&basic, &urls).unwrap();
+        let header = auth
+            .header(&"http://intranet.example.com/other".parse().unwrap())
+            .unwrap();
+        assert_eq!(header, "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+        assert!(header.is_sensitive());
+        assert_eq!(auth.header(&"https://example.com/".parse().unwrap()), None);
+
+        env::set_var("RSSPLS_TEST_AUTH_TOKEN", "s3cret");
+        let bearer = AuthConfig::Bearer {
+            token: None,
+            token_env: Some("RSSPLS_TEST_AUTH_TOKEN".to_string()),
+        };
+        let auth = Authorization::new(&bearer, &urls).unwrap();
+        assert_eq!(auth.header(&urls[0]).unwrap(), "Bearer s3cret");
+
+        let missing = AuthConfig::Bearer {
+            token: None,
+            token_env: Some("RSSPLS_TEST_AUTH_MISSING".to_string()),
+        };
+        let err = Authorization::new(&missing, &urls).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unable to read token from environment variable RSSPLS_TEST_AUTH_MISSING"
+        );
+    }
+
+    #[test]
+    fn test_custom_headers() {
+        let urls = ["https://api.example.com/v1/posts".parse().unwrap()];
+        let config = BTreeMap::from([
+            ("X-Api-Key".to_string(), Secret::Plain("s3cret".to_string())),
+            ("Bad Name".to_string(), Secret::Plain("ignored".to_string())),
+        ]);
+        let custom = CustomHeaders::new(&config, &urls).unwrap();
+        let headers = custom
+            .headers(&"http://API.example.com/v1/comments".parse().unwrap())
+            .unwrap();
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers["x-api-key"], "s3cret");
+        assert!(headers["x-api-key"].is_sensitive());
+        assert!(custom
+            .headers(&"https://cdn.example.net/post.html".parse().unwrap())
+            .is_none());
+        assert!(custom
+            .headers(
+                &"https://web.archive.org/web/2024/https://api.example.com/"
+                    .parse()
+                    .unwrap()
+            )
+            .is_none());
+
+        // Encrypted values have to be decrypted first
+        let config = BTreeMap::from([(
+            "X-Api-Key".to_string(),
+            Secret::Keyring {
+                keyring: "api.example.com/me".to_string(),
+            },
+        )]);
+        assert!(CustomHeaders::new(&config, &urls).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use basic_toml as toml;
+use log::{debug, warn};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use simple_eyre::eyre::{self, WrapErr};
+use time::OffsetDateTime;
+use url::Url;
+
+/// The number of consecutive failures that make rsspls back off from a host by default
+pub const DEFAULT_BACKOFF_AFTER: u32 = 3;
+
+/// Tracks the requests to each host that failed so that hosts that keep failing, or ask for
+/// fewer requests, are left alone for a while
+///
+/// The failures are kept between runs so that a feed run often from cron doesn't keep tripping
+/// a site's bot protection.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    /// How long requests to a host are skipped for, zero disables backing off
+    duration: Duration,
+    /// The number of consecutive failures that trip the back off, responses with 429 Too Many
+    /// Requests trip it immediately
+    after: u32,
+    hosts: Mutex<BTreeMap<String, HostState>>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+struct HostState {
+    /// Consecutive failed requests
+    #[serde(default)]
+    failures: u32,
+    /// Unix timestamp of when requests to the host are allowed again
+    until: Option<i64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct HostsFile {
+    #[serde(default)]
+    hosts: BTreeMap<String, HostState>,
+}
+
+/// The error for requests that weren't made as the host is being backed off from
+#[derive(Debug)]
+pub struct BackingOff {
+    host: String,
+    until: i64,
+}
+
+impl fmt::Display for BackingOff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let remaining = (self.until - OffsetDateTime::now_utc().unix_timestamp()).max(0);
+        // Round up so it doesn't say 0 minutes
+        let minutes = (remaining + 59) / 60;
+        write!(
+            f,
+            "backing off from {} for another {minutes} minutes after failed requests",
+            self.host
+        )
+    }
+}
+
+impl std::error::Error for BackingOff {}
+
+impl Backoff {
+    /// Read the state of the hosts from `path`, starting afresh if it's missing or invalid
+    pub fn read(path: &Path, duration: Duration, after: u32) -> Backoff {
+        let hosts = match fs::read(path) {
+            Ok(raw) => match toml::from_slice::<HostsFile>(&raw) {
+                Ok(file) => file.hosts,
+                Err(err) => {
+                    warn!("ignoring invalid host state at {}: {err}", path.display());
+                    BTreeMap::new()
+                }
+            },
+            Err(_) => BTreeMap::new(),
+        };
+        Backoff {
+            duration,
+            after: after.max(1),
+            hosts: Mutex::new(hosts),
+        }
+    }
+
+    /// Write the state of the hosts to `path`
+    pub fn write(&self, path: &Path) -> eyre::Result<()> {
+        let file = HostsFile {
+            hosts: self.lock().clone(),
+        };
+        if file.hosts.is_empty() && !path.exists() {
+            return Ok(());
+        }
+        debug!("write host state {}", path.display());
+        let raw = toml::to_string(&file).wrap_err("unable to serialise host state")?;
+        crate::write_atomically(path, raw.as_bytes())
+            .wrap_err_with(|| format!("unable to write host state: {}", path.display()))
+    }
+
+    /// Fail if requests to the host of `url` are being skipped
+    pub fn check(&self, url: &Url) -> Result<(), BackingOff> {
+        let Some(host) = url.host_str() else {
+            return Ok(());
+        };
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        match self.lock().get(host).and_then(|state| state.until) {
+            Some(until) if until > now => Err(BackingOff {
+                host: host.to_string(),
+                until,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record a successful request to the host of `url`
+    pub fn succeeded(&self, url: &Url) {
+        if let Some(host) = url.host_str() {
+            self.lock().remove(host);
+        }
+    }
+
+    /// Record a failed request to the host of `url`, with the status of the response if there
+    /// was one
+    ///
+    /// Client errors like 404 Not Found are a problem with the URL rather than the host, so they
+    /// aren't counted, except for 403 Forbidden and 429 Too Many Requests.
+    pub fn failed(&self, url: &Url, status: Option<StatusCode>) {
+        let Some(host) = url.host_str() else {
+            return;
+        };
+        let counted = match status {
+            Some(StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS) | None => true,
+            Some(status) => !status.is_client_error(),
+        };
+        if self.duration.is_zero() || !counted {
+            return;
+        }
+        let mut hosts = self.lock();
+        let state = hosts.entry(host.to_string()).or_default();
+        state.failures += 1;
+        if status == Some(StatusCode::TOO_MANY_REQUESTS) || state.failures >= self.after {
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            state.until = Some(now + self.duration.as_secs() as i64);
+            warn!(
+                "backing off from {host} for {} after {} failed requests",
+                humantime::format_duration(self.duration),
+                state.failures
+            );
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, HostState>> {
+        // The map is always left valid, so it's fine to use after a panic
+        self.hosts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_backoff() {
+        let path = env::temp_dir().join(format!("rsspls-hosts-{}.toml", std::process::id()));
+        let backoff = Backoff::read(&path, Duration::from_secs(3600), 2);
+        let flaky: Url = "https://flaky.example.com/news".parse().unwrap();
+        let busy: Url = "https://busy.example.com/".parse().unwrap();
+
+        backoff.failed(&flaky, Some(StatusCode::SERVICE_UNAVAILABLE));
+        backoff.failed(&flaky, Some(StatusCode::NOT_FOUND));
+        assert!(backoff.check(&flaky).is_ok());
+        backoff.failed(&flaky, None);
+        assert!(backoff.check(&flaky).is_err());
+
+        backoff.failed(&busy, Some(StatusCode::TOO_MANY_REQUESTS));
+        assert!(backoff.check(&busy).is_err());
+        backoff.succeeded(&busy);
+        assert!(backoff.check(&busy).is_ok());
+
+        backoff.write(&path).unwrap();
+        let reread = Backoff::read(&path, Duration::from_secs(3600), 2);
+        fs::remove_file(&path).unwrap();
+        let err = reread.check(&flaky).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "backing off from flaky.example.com for another 60 minutes after failed requests"
+        );
+        assert!(reread.check(&busy).is_ok());
+
+        let disabled = Backoff::default();
+        disabled.failed(&busy, Some(StatusCode::TOO_MANY_REQUESTS));
+        assert!(disabled.check(&busy).is_ok());
+    }
+}
@@ -1,13 +1,25 @@
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
+use atomicwrites::AtomicFile;
 use basic_toml as toml;
+use cryptoxide::{blake2b::Blake2b, digest::Digest};
 use log::debug;
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AGE, CACHE_CONTROL, DATE, EXPIRES};
 use serde::{Deserialize, Serialize};
+use simple_eyre::eyre::{self, WrapErr};
+use time::format_description::well_known::Rfc2822;
+use time::OffsetDateTime;
+use url::Url;
 
 use crate::config::ConfigHash;
 
+/// The size the page cache is kept under when `page_cache_size` isn't set
+pub const DEFAULT_PAGE_CACHE_SIZE: u64 = 100 << 20;
+
 #[derive(Debug, Serialize)]
 pub struct RequestCacheWrite<'a> {
     pub headers: Vec<(&'a str, &'a str)>,
@@ -70,3 +82,321 @@ pub fn deserialise_cached_headers(
             .collect(),
     )
 }
+
+/// Whether a cached response can be used without asking the origin
+#[derive(Debug, PartialEq)]
+pub enum Freshness {
+    /// Fresh for this much longer
+    Fresh(Duration),
+    /// The freshness lifetime has passed
+    Stale,
+    /// The origin asked for the response to always be revalidated
+    NoCache,
+    /// There's no freshness lifetime or `Date` header
+    Unknown,
+}
+
+/// Work out the freshness of a cached response from its `Cache-Control` or `Expires` headers
+///
+/// The age of the response comes from its `Date` and `Age` headers, so the time it was fetched
+/// doesn't need to be recorded.
+pub fn freshness(headers: &HeaderMap, now: OffsetDateTime) -> Freshness {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let parse_date = |value: &str| OffsetDateTime::parse(value.trim(), &Rfc2822).ok();
+
+    let mut max_age = None;
+    for directive in header(CACHE_CONTROL).unwrap_or_default().split(',') {
+        let directive = directive.trim().to_ascii_lowercase();
+        match directive.split_once('=') {
+            None if directive == "no-cache" || directive == "no-store" => {
+                return Freshness::NoCache
+            }
+            Some(("max-age", seconds)) => {
+                max_age = seconds.trim_matches('"').parse::<i64>().ok();
+            }
+            _ => {}
+        }
+    }
+    let Some(date) = header(DATE).and_then(parse_date) else {
+        return Freshness::Unknown;
+    };
+    let lifetime = match (max_age, header(EXPIRES)) {
+        (Some(max_age), _) => max_age,
+        // Invalid dates like "0" mean it has already expired
+        (None, Some(expires)) => {
+            parse_date(expires).map_or(0, |expires| (expires - date).whole_seconds())
+        }
+        (None, None) => return Freshness::Unknown,
+    };
+    let age = header(AGE)
+        .and_then(|age| age.trim().parse::<i64>().ok())
+        .unwrap_or(0);
+    let remaining = lifetime - age - (now - date).whole_seconds();
+    if remaining > 0 {
+        Freshness::Fresh(Duration::from_secs(remaining as u64))
+    } else {
+        Freshness::Stale
+    }
+}
+
+/// Read the cached headers and body of the page at `url` from `dir`, if they're present and were
+/// created by this version with the same config
+pub fn read_page(
+    dir: &Path,
+    url: &Url,
+    config_hash: ConfigHash<'_>,
+) -> Option<(HeaderMap<HeaderValue>, String)> {
+    let (headers_path, body_path) = page_paths(dir, url);
+    let headers = deserialise_cached_headers(&headers_path, config_hash)?;
+    let body = fs::read_to_string(body_path).ok()?;
+    Some((headers, body))
+}
+
+/// Cache the serialised `headers` and `body` of the page at `url` in `dir`
+pub fn write_page(dir: &Path, url: &Url, headers: &str, body: &str) -> eyre::Result<()> {
+    fs::create_dir_all(dir)
+        .wrap_err_with(|| format!("unable to create page cache directory {}", dir.display()))?;
+    // The body is written first so that valid headers always have a body to go with them
+    let (headers_path, body_path) = page_paths(dir, url);
+    write_atomic(&body_path, body.as_bytes())?;
+    write_atomic(&headers_path, headers.as_bytes())
+}
+
+/// Mark the cached page at `url` as recently used, so it's evicted after pages that haven't been
+pub fn touch_page(dir: &Path, url: &Url) -> eyre::Result<()> {
+    // The modification time of the headers is used as the access time
+    let (headers_path, _) = page_paths(dir, url);
+    set_used(&headers_path, SystemTime::now())
+        .wrap_err_with(|| format!("unable to update {}", headers_path.display()))
+}
+
+fn set_used(headers_path: &Path, time: SystemTime) -> std::io::Result<()> {
+    fs::File::options()
+        .write(true)
+        .open(headers_path)?
+        .set_modified(time)
+}
+
+/// The outcome of a page cache collection
+#[derive(Debug, Default, PartialEq)]
+pub struct GcStats {
+    /// Number of pages removed
+    pub removed: usize,
+    /// Bytes used by the removed pages
+    pub freed: u64,
+    /// Bytes used by the remaining pages
+    pub size: u64,
+}
+
+/// Remove the least recently used pages from the page cache in `dir` until it's no larger than
+/// `max_size`, along with any incomplete entries
+pub fn gc(dir: &Path, max_size: u64) -> eyre::Result<GcStats> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(GcStats::default()),
+        Err(err) => return Err(err).wrap_err_with(|| format!("unable to read {}", dir.display())),
+    };
+
+    // Group the header and body files of each page by their shared stem
+    let mut pages: HashMap<String, Page> = HashMap::new();
+    for entry in entries {
+        let entry = entry.wrap_err_with(|| format!("unable to read {}", dir.display()))?;
+        let path = entry.path();
+        let (Some(stem), Some(extension)) = (
+            path.file_stem().and_then(|stem| stem.to_str()),
+            path.extension().and_then(|extension| extension.to_str()),
+        ) else {
+            continue;
+        };
+        let is_body = match extension {
+            "toml" => false,
+            "html" => true,
+            _ => continue,
+        };
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let page = pages.entry(stem.to_string()).or_default();
+        page.size += metadata.len();
+        if is_body {
+            page.has_body = true;
+        } else {
+            page.used = metadata.modified().ok();
+        }
+        page.paths.push(path);
+    }
+
+    let mut pages: Vec<_> = pages.into_values().collect();
+    // Incomplete pages sort first so they're always removed, followed by the least recently used
+    pages.sort_by_key(|page| (page.has_body && page.used.is_some(), page.used));
+    let mut stats = GcStats {
+        size: pages.iter().map(|page| page.size).sum(),
+        ..GcStats::default()
+    };
+    for page in pages {
+        if stats.size <= max_size && page.has_body && page.used.is_some() {
+            break;
+        }
+        for path in &page.paths {
+            debug!("remove {}", path.display());
+            fs::remove_file(path)
+                .wrap_err_with(|| format!("unable to remove {}", path.display()))?;
+        }
+        stats.removed += 1;
+        stats.freed += page.size;
+        stats.size -= page.size;
+    }
+    Ok(stats)
+}
+
+#[derive(Default)]
+struct Page {
+    paths: Vec<PathBuf>,
+    size: u64,
+    has_body: bool,
+    /// When the page was last fetched or revalidated
+    used: Option<SystemTime>,
+}
+
+/// Paths of the files holding the cached headers and body of the page at `url`
+fn page_paths(dir: &Path, url: &Url) -> (PathBuf, PathBuf) {
+    let mut context = Blake2b::new(16);
+    context.input(url.as_str().as_bytes());
+    let digest = context.result_str();
+    (
+        dir.join(format!("{digest}.toml")),
+        dir.join(format!("{digest}.html")),
+    )
+}
+
+fn write_atomic(path: &Path, content: &[u8]) -> eyre::Result<()> {
+    AtomicFile::new(path, atomicwrites::AllowOverwrite)
+        .write(|f| f.write_all(content))
+        .map_err(|err| match err {
+            atomicwrites::Error::Internal(err) | atomicwrites::Error::User(err) => err,
+        })
+        .wrap_err_with(|| format!("unable to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_freshness() {
+        let headers = |pairs: &[(&'static str, &'static str)]| -> HeaderMap {
+            pairs
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        HeaderName::from_static(name),
+                        HeaderValue::from_static(value),
+                    )
+                })
+                .collect()
+        };
+        let now = datetime!(2024-08-01 10:10 UTC);
+        let date = ("date", "Thu, 01 Aug 2024 10:00:00 GMT");
+        assert_eq!(
+            freshness(
+                &headers(&[date, ("cache-control", "public, max-age=3600")]),
+                now
+            ),
+            Freshness::Fresh(Duration::from_secs(3000))
+        );
+        assert_eq!(
+            freshness(
+                &headers(&[date, ("cache-control", "max-age=3600"), ("age", "3000")]),
+                now
+            ),
+            Freshness::Stale
+        );
+        assert_eq!(
+            freshness(
+                &headers(&[date, ("expires", "Thu, 01 Aug 2024 11:00:00 GMT")]),
+                now
+            ),
+            Freshness::Fresh(Duration::from_secs(3000))
+        );
+        assert_eq!(
+            freshness(&headers(&[date, ("expires", "0")]), now),
+            Freshness::Stale
+        );
+        assert_eq!(
+            freshness(&headers(&[date, ("cache-control", "no-cache")]), now),
+            Freshness::NoCache
+        );
+        assert_eq!(
+            freshness(&headers(&[("cache-control", "max-age=3600")]), now),
+            Freshness::Unknown
+        );
+        assert_eq!(freshness(&headers(&[date]), now), Freshness::Unknown);
+    }
+
+    #[test]
+    fn test_page_cache() {
+        let root = env::temp_dir().join(format!("rsspls-page-cache-{}", std::process::id()));
+        let dir = root.join("pages");
+        let url: Url = "http://example.com/articles/1".parse().unwrap();
+        let hash = ConfigHash("abc");
+        assert!(read_page(&dir, &url, hash).is_none());
+
+        let headers = toml::to_string(&RequestCacheWrite {
+            headers: vec![("etag", "\"1\"")],
+            version: crate::version(),
+            config_hash: hash,
+        })
+        .unwrap();
+        write_page(&dir, &url, &headers, "<h1>One</h1>").unwrap();
+        let cached = read_page(&dir, &url, hash);
+        let other: Url = "http://example.com/articles/2".parse().unwrap();
+        let other_cached = read_page(&dir, &other, hash);
+        let stale = read_page(&dir, &url, ConfigHash("def"));
+        fs::remove_dir_all(&root).unwrap();
+
+        let (headers, body) = cached.unwrap();
+        assert_eq!(headers.get("etag").unwrap(), "\"1\"");
+        assert_eq!(body, "<h1>One</h1>");
+        assert!(other_cached.is_none());
+        assert!(stale.is_none());
+    }
+
+    #[test]
+    fn test_gc() {
+        let dir = env::temp_dir().join(format!("rsspls-page-gc-{}", std::process::id()));
+        let urls: Vec<Url> = (1..=3)
+            .map(|i| format!("http://example.com/{i}").parse().unwrap())
+            .collect();
+        let written = SystemTime::now() - Duration::from_secs(60);
+        for (i, url) in urls.iter().enumerate() {
+            write_page(&dir, url, "headers", "0123456789").unwrap();
+            let headers_path = page_paths(&dir, url).0;
+            set_used(&headers_path, written + Duration::from_secs(i as u64)).unwrap();
+        }
+        // The first page is used again, making the second the least recently used
+        touch_page(&dir, &urls[0]).unwrap();
+        fs::write(dir.join("orphan.html"), "body").unwrap();
+
+        let page_size = 17;
+        let first = gc(&dir, 2 * page_size);
+        let remaining: Vec<_> = urls
+            .iter()
+            .map(|url| page_paths(&dir, url).1.exists())
+            .collect();
+        let second = gc(&dir, 2 * page_size);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            first.unwrap(),
+            GcStats {
+                removed: 2,
+                freed: page_size + 4,
+                size: 2 * page_size
+            }
+        );
+        assert_eq!(remaining, [true, false, true]);
+        assert_eq!(second.unwrap().removed, 0);
+    }
+}
@@ -0,0 +1,216 @@
+use kuchiki::iter::NodeIterator;
+use kuchiki::traits::TendrilSink;
+use kuchiki::{ElementData, NodeDataRef, Selectors};
+use log::info;
+use serde::Serialize;
+use simple_eyre::eyre;
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::config::{ChannelConfig, ConfigHash, FeedConfig, SourceType};
+use crate::feed::{self, SCOPE};
+use crate::lint::Lint;
+use crate::Client;
+
+/// Selectors that are matched against the whole page rather than within each item
+const PAGE_FIELDS: &[&str] = &["item", "region", "next_page", "discover.links", "frame"];
+/// Selectors that are matched against other pages, so can't be checked against the listing
+const OTHER_PAGE_FIELDS: &[&str] = &["content"];
+/// Selectors that are expected to match nothing on some pages, such as the last one
+const OPTIONAL_FIELDS: &[&str] = &["next_page"];
+
+/// How many elements a selector of a feed matched on one of its pages
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SelectorCount {
+    /// Name of the feed
+    pub feed: String,
+    pub url: String,
+    /// The config field of the selector
+    pub field: String,
+    pub selector: String,
+    /// The number of elements matched on the page, or the number of items with a match for
+    /// selectors within items
+    pub matches: usize,
+    /// The number of items on the page, for selectors within items
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items: Option<usize>,
+}
+
+/// Fetch the pages of `feeds` and count the elements each of their selectors match
+///
+/// Along with the counts, pages that couldn't be fetched and selectors that match nothing are
+/// returned as lints. Nothing is cached or written.
+pub async fn check_pages(
+    client: &Client,
+    feeds: &[ChannelConfig],
+    config_hash: ConfigHash<'_>,
+) -> (Vec<SelectorCount>, Vec<Lint>) {
+    let mut counts = Vec::new();
+    let mut lints = Vec::new();
+    let now = client.source_date.unwrap_or_else(OffsetDateTime::now_utc);
+    for feed in feeds {
+        let name = feed.name();
+        let mut push = |field: &str, message: String| {
+            lints.push(Lint {
+                feed: name.to_string(),
+                field: field.to_string(),
+                message,
+            })
+        };
+        if feed.config.source_type != SourceType::Html {
+            info!("skipping {name} as only the selectors of HTML sources can be checked");
+            continue;
+        }
+        let urls = feed.config.urls(now);
+        if urls.iter().any(|url| url.contains("{depends.")) {
+            info!("skipping {name} as its URL comes from the feed it depends on");
+            continue;
+        }
+        let urls = match urls
+            .iter()
+            .map(|url| url.parse::<Url>().map_err(|err| format!("{url}: {err}")))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(urls) => urls,
+            Err(err) => {
+                push("url", format!("invalid URL {err}"));
+                continue;
+            }
+        };
+        let with_credentials = match feed::with_credentials(client, feed, &urls).await {
+            Ok(with_credentials) => with_credentials,
+            Err(err) => {
+                push("url", format!("{:#}", eyre::Report::from(err)));
+                continue;
+            }
+        };
+        let client = with_credentials.as_ref().unwrap_or(client);
+
+        for url in urls {
+            info!("checking {name}: {url}");
+            let html = match feed::fetch_document(client, &url, feed, config_hash).await {
+                Ok(html) => html,
+                Err(report) => {
+                    push("url", format!("{report:#}"));
+                    continue;
+                }
+            };
+            for count in count_selectors(&feed.config, &html) {
+                if count.matches == 0 && !OPTIONAL_FIELDS.contains(&count.field.as_str()) {
+                    push(
+                        &count.field,
+                        format!("'{}' matched nothing on {url}", count.selector),
+                    );
+                }
+                counts.push(SelectorCount {
+                    feed: name.to_string(),
+                    url: url.to_string(),
+                    ..count
+                });
+            }
+        }
+    }
+    (counts, lints)
+}
+
+/// Count the elements matched by the selectors of `config` in `html`
+///
+/// The feed and URL of the counts are left empty.
+fn count_selectors(config: &FeedConfig, html: &str) -> Vec<SelectorCount> {
+    let doc = kuchiki::parse_html().one(html);
+    if config.expand_templates {
+        feed::expand_templates(&doc);
+    }
+    let count_in_page = |selector: &str| doc.select(selector).map_or(0, Iterator::count);
+    let items: Vec<_> = if config.item.is_empty() {
+        Vec::new()
+    } else {
+        doc.select(&config.item)
+            .map(|items| items.collect())
+            .unwrap_or_default()
+    };
+
+    let mut counts = Vec::new();
+    for (field, selector) in config.selectors() {
+        let (matches, items) = if PAGE_FIELDS.contains(&field.as_str()) {
+            (count_in_page(selector), None)
+        } else if OTHER_PAGE_FIELDS.contains(&field.as_str()) || config.item.is_empty() {
+            continue;
+        } else if field == "link.closest" {
+            let Ok(selectors) = Selectors::compile(selector) else {
+                continue;
+            };
+            let matches = items
+                .iter()
+                .filter(|item| {
+                    item.as_node()
+                        .inclusive_ancestors()
+                        .elements()
+                        .any(|element| selectors.matches(&element))
+                })
+                .count();
+            (matches, Some(items.len()))
+        } else {
+            let matches = items
+                .iter()
+                .filter(|item| matches_in(item, &field, selector))
+                .count();
+            (matches, Some(items.len()))
+        };
+        counts.push(SelectorCount {
+            feed: String::new(),
+            url: String::new(),
+            field,
+            selector: selector.to_string(),
+            matches,
+            items,
+        });
+    }
+    counts
+}
+
+/// Whether `selector` for `field` matches anything in `item`
+fn matches_in(item: &NodeDataRef<ElementData>, field: &str, selector: &str) -> bool {
+    let selector = selector.trim();
+    selector == SCOPE
+        || (field == "summary" && selector == "self")
+        || item.as_node().select_first(selector).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use basic_toml as toml;
+
+    #[test]
+    fn test_count_selectors() {
+        let raw = r#"
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+            link = { closest = "a.card" }
+            summary = "self"
+            date = "time"
+            next_page = "a[rel=next]"
+            content = "main"
+        "#;
+        let config: FeedConfig = toml::from_str(raw).unwrap();
+        let html = r#"<a class="card" href="/one"><article><h2>One</h2><time>today</time></article></a>
+            <article><h2>Two</h2></article>"#;
+        let counts: Vec<_> = count_selectors(&config, html)
+            .into_iter()
+            .map(|count| (count.field, count.matches, count.items))
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                ("item".to_string(), 2, None),
+                ("next_page".to_string(), 0, None),
+                ("heading".to_string(), 2, Some(2)),
+                ("link.closest".to_string(), 1, Some(2)),
+                ("summary".to_string(), 2, Some(2)),
+                ("date".to_string(), 1, Some(2)),
+            ]
+        );
+    }
+}
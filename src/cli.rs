@@ -1,16 +1,64 @@
 use std::convert::Infallible;
 use std::env;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::path::PathBuf;
 
+use eyre::bail;
 use pico_args::Arguments;
 use simple_eyre::eyre;
 
 use crate::version_string;
 
 pub struct Cli {
+    pub command: Command,
     pub config_path: Option<PathBuf>,
     pub output_path: Option<PathBuf>,
+    pub cache_dir: Option<PathBuf>,
+    pub state_dir: Option<PathBuf>,
+    /// Only read the system config, not the user's config layered on it
+    pub no_user_config: bool,
+    /// Names of the feeds to generate, all of them if empty
+    pub feeds: Vec<String>,
+    /// Keep running, generating each feed every time its refresh interval elapses
+    pub daemon: bool,
+    pub verbose: bool,
+}
+
+pub enum Command {
+    /// Generate the configured feeds
+    Run,
+    /// Interactively try selectors against a page
+    #[cfg(feature = "authoring")]
+    Repl {
+        url: String,
+        user_agent: Option<String>,
+    },
+    /// Look for feeds, sitemaps, and listing pages on a site
+    #[cfg(feature = "authoring")]
+    Discover { url: String },
+    /// Convert RSS-Bridge or rss-proxy definitions into feed config
+    #[cfg(feature = "authoring")]
+    ImportBridge { location: String },
+    /// Convert the subscriptions in an OPML file into feed config
+    #[cfg(feature = "authoring")]
+    ImportOpml { location: String },
+    /// Show the history of previous runs
+    History { feed: Option<String> },
+    /// Add the feed config from a recipe to the config file
+    #[cfg(feature = "authoring")]
+    RecipeInstall {
+        name: String,
+        params: Vec<(String, String)>,
+        /// Install feeds that run commands or send cookies, credentials, or items elsewhere
+        allow_commands: bool,
+    },
+    /// Remove the least recently used pages from the page cache
+    CacheGc,
+    /// Check the config for mistakes, printing them as JSON lines if `json` is set. With `fetch`
+    /// the pages of the feeds are fetched to count what their selectors match.
+    Check { json: bool, fetch: bool },
+    /// Show where the config, cache, and output files are
+    Paths,
 }
 
 pub fn parse_args() -> eyre::Result<Option<Cli>> {
@@ -20,11 +68,144 @@ pub fn parse_args() -> eyre::Result<Option<Cli>> {
     } else if pargs.contains(["-h", "--help"]) {
         return print_usage();
     }
+    parse(pargs).map(Some)
+}
+
+fn parse(mut pargs: Arguments) -> eyre::Result<Cli> {
+    let config_path = pargs.opt_value_from_os_str(["-c", "--config"], pathbuf)?;
+    let output_path = pargs.opt_value_from_os_str(["-o", "--output"], pathbuf)?;
+    let cache_dir = pargs.opt_value_from_os_str("--cache-dir", pathbuf)?;
+    let state_dir = pargs.opt_value_from_os_str("--state-dir", pathbuf)?;
+    let no_user_config = pargs.contains("--no-user-config");
+    if no_user_config && config_path.is_some() {
+        bail!("--no-user-config can't be used with --config");
+    }
+    let feeds = pargs.values_from_str("--feed")?;
+    let daemon = pargs.contains("--daemon");
+    let verbose = pargs.contains(["-v", "--verbose"]);
+    let json = pargs.contains("--json");
+    let fetch = pargs.contains("--fetch");
+    let user_agent: Option<String> = pargs.opt_value_from_str("--user-agent")?;
+    let allow_commands = pargs.contains("--allow-commands");
+
+    // Options can come before or after the subcommand, so it's looked for among the arguments
+    // left once they have all been taken
+    let free = pargs.finish();
+    if let Some(option) = free
+        .iter()
+        .find(|arg| arg.to_string_lossy().starts_with('-'))
+    {
+        bail!("unknown option: {}", option.to_string_lossy());
+    }
+    let mut free = free.into_iter();
+    let subcommand = free.next().map(string).transpose()?;
+    if subcommand.as_deref() != Some("check") {
+        if json {
+            bail!("--json can only be used with check");
+        } else if fetch {
+            bail!("--fetch can only be used with check");
+        }
+    }
+    if user_agent.is_some() && subcommand.as_deref() != Some("repl") {
+        bail!("--user-agent can only be used with repl");
+    }
+    if allow_commands && subcommand.as_deref() != Some("recipe") {
+        bail!("--allow-commands can only be used with recipe install");
+    }
+
+    let command = match subcommand.as_deref() {
+        None => Command::Run,
+        #[cfg(feature = "authoring")]
+        Some("repl") => Command::Repl {
+            url: required(&mut free, "URL")?,
+            user_agent,
+        },
+        #[cfg(feature = "authoring")]
+        Some("discover") => Command::Discover {
+            url: required(&mut free, "URL")?,
+        },
+        #[cfg(feature = "authoring")]
+        Some("import-bridge") => Command::ImportBridge {
+            location: required(&mut free, "FILE_OR_URL")?,
+        },
+        #[cfg(feature = "authoring")]
+        Some("import-opml") => Command::ImportOpml {
+            location: required(&mut free, "FILE_OR_URL")?,
+        },
+        Some("history") => Command::History {
+            feed: free.next().map(string).transpose()?,
+        },
+        #[cfg(feature = "authoring")]
+        Some("recipe") => match free.next().map(string).transpose()?.as_deref() {
+            Some("install") => Command::RecipeInstall {
+                name: required(&mut free, "NAME")?,
+                params: free
+                    .by_ref()
+                    .map(|arg| parse_param(&arg))
+                    .collect::<eyre::Result<_>>()?,
+                allow_commands,
+            },
+            Some(other) => bail!("unknown recipe command: {other}"),
+            None => bail!("a recipe command is required"),
+        },
+        Some("check") => Command::Check { json, fetch },
+        Some("paths") => Command::Paths,
+        Some("cache") => match free.next().map(string).transpose()?.as_deref() {
+            Some("gc") => Command::CacheGc,
+            Some(other) => bail!("unknown cache command: {other}"),
+            None => bail!("a cache command is required"),
+        },
+        #[cfg(not(feature = "authoring"))]
+        Some(command @ ("repl" | "discover" | "import-bridge" | "import-opml" | "recipe")) => {
+            bail!("the {command} command is not available as rsspls was built without the authoring feature")
+        }
+        Some(other) => bail!("unknown command: {other}"),
+    };
 
-    Ok(Some(Cli {
-        config_path: pargs.opt_value_from_os_str(["-c", "--config"], pathbuf)?,
-        output_path: pargs.opt_value_from_os_str(["-o", "--output"], pathbuf)?,
-    }))
+    if let Some(arg) = free.next() {
+        bail!("unexpected argument: {}", arg.to_string_lossy());
+    }
+
+    Ok(Cli {
+        command,
+        config_path,
+        output_path,
+        cache_dir,
+        state_dir,
+        no_user_config,
+        feeds,
+        daemon,
+        verbose,
+    })
+}
+
+/// Take the next free argument, which is required and described by `name` in the error if
+/// missing
+#[cfg(feature = "authoring")]
+fn required(free: &mut impl Iterator<Item = OsString>, name: &str) -> eyre::Result<String> {
+    match free.next() {
+        Some(arg) => string(arg),
+        None => bail!("{name} is required"),
+    }
+}
+
+fn string(arg: OsString) -> eyre::Result<String> {
+    arg.into_string()
+        .map_err(|arg| eyre::eyre!("argument is not valid UTF-8: {}", arg.to_string_lossy()))
+}
+
+/// Parse a `NAME=VALUE` parameter
+#[cfg(feature = "authoring")]
+fn parse_param(arg: &OsStr) -> eyre::Result<(String, String)> {
+    arg.to_str()
+        .and_then(|arg| arg.split_once('='))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .ok_or_else(|| {
+            eyre::eyre!(
+                "expected NAME=VALUE parameter, got {}",
+                arg.to_string_lossy()
+            )
+        })
 }
 
 fn pathbuf(s: &OsStr) -> Result<PathBuf, Infallible> {
@@ -43,7 +224,73 @@ pub fn print_usage() -> eyre::Result<Option<Cli>> {
 {bin} generates RSS feeds from web pages.
 
 USAGE:
-    {bin} [OPTIONS] -o OUTPUT_DIR
+    {bin} [OPTIONS] -o OUTPUT_DIR [--feed NAME...] [--daemon]
+    {bin} repl URL [--user-agent UA]
+    {bin} discover URL
+    {bin} import-bridge FILE_OR_URL
+    {bin} import-opml FILE_OR_URL
+    {bin} recipe install NAME [PARAM=VALUE...] [--allow-commands]
+    {bin} history [FEED]
+    {bin} cache gc
+    {bin} check [--json] [--fetch] [--feed NAME...]
+    {bin} paths [--feed NAME...]
+
+COMMANDS:
+    repl URL [--user-agent UA]
+            Fetch URL (or read a local file) and interactively try CSS
+            selectors against it, printing a config snippet at the end.
+            The page is fetched with the proxy and timeouts from the
+            configuration file, if there is one, and with UA as the
+            User-Agent if given.
+
+    discover URL
+            Crawl the site at URL one level deep and list the feeds it
+            advertises, its sitemaps, and pages with many links and
+            dates that are likely listings to generate a feed from.
+
+    import-bridge FILE_OR_URL
+            Print feed configuration converted from a RSS-Bridge URL for
+            one of the CSS selector bridges, a file of such URLs (one per
+            line), or rss-proxy rules in JSON.
+
+    import-opml FILE_OR_URL
+            Print feed configuration for each subscription in an OPML
+            file. The feeds use source_type = \"feed\" so that their items
+            are passed through rsspls.
+
+    recipe install NAME [PARAM=VALUE...] [--allow-commands]
+            Fetch the recipe NAME from the recipe_index set in the
+            configuration file, fill in its parameters, and append the
+            resulting feeds to the configuration file. Recipes with feeds
+            that run commands or send cookies, credentials, or items to
+            other sites are only installed with --allow-commands.
+
+    history [FEED]
+            Summarise the outcome of previous runs for each feed, or list
+            the recent runs of FEED (its filename, with or without the
+            extension).
+
+    cache gc
+            Remove the least recently used pages from the cache of pages
+            linked from listings until it's under page_cache_size, along
+            with incomplete cache entries. This also happens at the end
+            of each run.
+
+    check [--json] [--fetch]
+            Validate the configuration file and report likely mistakes,
+            such as a link selector that doesn't target <a> elements.
+            With --fetch the pages of each feed are fetched and the
+            number of elements, or items, each selector matches is
+            printed, reporting selectors that match nothing. No output
+            or cache files are written. With --json each one is printed
+            as a JSON object on its own line. Exits with a non-zero
+            status if anything is reported.
+
+    paths
+            Print the paths of the configuration file, the output
+            directory, and the cache, history, state, and output files of
+            each feed, with when each was last modified. Nothing is
+            created or fetched.
 
 OPTIONS:
     -h, --help
@@ -51,11 +298,37 @@ OPTIONS:
 
     -c, --config
             Specify the path to the configuration file.
-            $XDG_CONFIG_HOME/rsspls/feeds.toml is used if not supplied.
+            $XDG_CONFIG_HOME/rsspls/feeds.toml is used if not supplied,
+            layered on /etc/rsspls/feeds.toml if it exists.
+
+    --no-user-config
+            Only read the system configuration file, /etc/rsspls/feeds.toml.
 
     -o, --output
             Directory to write generated feeds to.
 
+    --cache-dir
+            Directory to store the cache in, overriding cache_dir in the
+            configuration file and the default below.
+
+    --state-dir
+            Directory to store the history and state of feeds in,
+            overriding state_dir in the configuration file and the
+            default below. Unlike the cache, this should be kept.
+
+    --feed NAME
+            Only generate the feed NAME (its name, or filename) and the
+            feeds it depends on. May be given more than once.
+
+    --daemon
+            Keep running and generate each feed every time its refresh
+            interval (refresh in the configuration file, one hour by
+            default) elapses. Stops after finishing the current run on
+            SIGTERM or Ctrl-C.
+
+    -v, --verbose
+            Enable debug logging. Has no effect if RSSPLS_LOG is set.
+
     -V, --version
             Prints version information
 
@@ -64,10 +337,26 @@ FILES:
 
      ~/$XDG_CONFIG_HOME/rsspls               Configuration directory.
 
+     /etc/rsspls/feeds.toml                  System configuration file.
+                                             Its feeds are generated along
+                                             with those of each user, who
+                                             can override its settings and
+                                             replace its feeds by name.
+
      ~/XDG_CACHE_HOME/rsspls                 Cache directory.
 
+     ~/XDG_CACHE_HOME/rsspls/pages           Cached pages linked from
+                                             listings.
+
+     ~/XDG_STATE_HOME/rsspls                 State directory, holding the
+                                             items seen by each feed.
+
+     ~/XDG_STATE_HOME/rsspls/history.jsonl   Record of previous runs.
+
      Note: XDG_CONFIG_HOME defaults to ~/.config, XDG_CACHE_HOME
-     defaults to ~/.cache.
+     defaults to ~/.cache, XDG_STATE_HOME defaults to ~/.local/state.
+     Files in the state directory that were kept in the cache
+     directory by earlier versions are moved there when first used.
 
 AUTHOR
     {}
@@ -80,3 +369,87 @@ SEE ALSO
     );
     Ok(None)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_strs(args: &[&str]) -> eyre::Result<Cli> {
+        parse(Arguments::from_vec(
+            args.iter().map(OsString::from).collect(),
+        ))
+    }
+
+    #[test]
+    fn test_parse_subcommand_after_options() {
+        let cli = parse_strs(&["-c", "feeds.toml", "check", "--json"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Check {
+                json: true,
+                fetch: false
+            }
+        ));
+        assert_eq!(cli.config_path, Some(PathBuf::from("feeds.toml")));
+
+        let cli = parse_strs(&["history", "-c", "feeds.toml", "news"]).unwrap();
+        assert!(matches!(cli.command, Command::History { feed: Some(feed) } if feed == "news"));
+        assert_eq!(cli.config_path, Some(PathBuf::from("feeds.toml")));
+
+        let cli = parse_strs(&["-o", "out", "--feed", "news"]).unwrap();
+        assert!(matches!(cli.command, Command::Run));
+        assert_eq!(cli.feeds, ["news"]);
+    }
+
+    #[test]
+    #[cfg(feature = "authoring")]
+    fn test_parse_positionals() {
+        let cli = parse_strs(&["repl", "https://example.com/", "--user-agent", "UA"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Repl { url, user_agent: Some(user_agent) }
+                if url == "https://example.com/" && user_agent == "UA"
+        ));
+
+        let cli = parse_strs(&[
+            "recipe",
+            "-c",
+            "feeds.toml",
+            "install",
+            "blog",
+            "a=1",
+            "b=2",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::RecipeInstall { name, params, allow_commands: false }
+                if name == "blog" && params == [("a".to_string(), "1".to_string()), ("b".to_string(), "2".to_string())]
+        ));
+        let cli = parse_strs(&["recipe", "install", "blog", "--allow-commands"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::RecipeInstall {
+                allow_commands: true,
+                ..
+            }
+        ));
+
+        assert!(parse_strs(&["repl"]).is_err());
+        assert!(parse_strs(&["repl", "a.html", "b.html"]).is_err());
+        assert!(parse_strs(&["discover", "https://example.com/", "--user-agent", "UA"]).is_err());
+        assert!(parse_strs(&["repl", "https://example.com/", "--allow-commands"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_unexpected_arguments() {
+        assert!(parse_strs(&["-c", "x", "bogus"]).is_err());
+        assert!(parse_strs(&["paths", "extra"]).is_err());
+        assert!(parse_strs(&["cache", "gc", "extra"]).is_err());
+        assert!(parse_strs(&["history", "a", "b"]).is_err());
+        assert!(parse_strs(&["--bogus"]).is_err());
+        assert!(parse_strs(&["check", "--bogus"]).is_err());
+        assert!(parse_strs(&["--json"]).is_err());
+        assert!(parse_strs(&["paths", "--fetch"]).is_err());
+    }
+}
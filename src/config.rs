@@ -1,18 +1,28 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::Infallible;
 use std::marker::PhantomData;
-use std::path::PathBuf;
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 use std::{fmt, fs};
 
 use basic_toml as toml;
 use cryptoxide::{blake2b::Blake2b, digest::Digest};
-use eyre::WrapErr;
+use eyre::{eyre, WrapErr};
+use kuchiki::Selectors;
 use log::{debug, warn};
+use regex::Regex;
 use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::Value as JsonValue;
 use simple_eyre::eyre;
 use time::format_description::OwnedFormatItem;
+use time::macros::format_description;
 use time::{Date, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
 
+use crate::secret::{Resolver, Secret};
+use crate::template;
+
 #[derive(Debug, Eq, PartialEq, Serialize, Clone, Copy)]
 pub struct ConfigHash<'a>(pub &'a str);
 
@@ -23,48 +33,695 @@ pub struct Config {
     /// Blake2b digest of the config file
     #[serde(skip)]
     pub hash: String,
+    /// Path the config file was read from
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// Path of the system config file the config file was layered on, if there is one
+    #[serde(skip)]
+    pub system_path: Option<PathBuf>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 pub struct RssplsConfig {
     pub output: Option<String>,
+    /// Resolve a relative `output` against the directory containing the config file instead of
+    /// the current directory
+    #[serde(default)]
+    pub output_relative_to_config: bool,
+    /// Octal permissions set on the files written to `output`, e.g. "0644"
+    #[serde(default, deserialize_with = "deserialize_mode")]
+    pub output_mode: Option<u32>,
+    /// Group set on the files written to `output`
+    pub output_group: Option<String>,
+    /// Write to the target of output files that are symlinks rather than replacing the link
+    #[serde(default)]
+    pub follow_output_symlinks: bool,
+    /// Indent the XML of the generated feeds
+    #[serde(default)]
+    pub pretty: bool,
+    /// Encoding the generated feeds are written in, UTF-8 if not set
+    pub encoding: Option<String>,
+    /// Add a comment to RSS and Atom feeds recording the pages they were generated from, when,
+    /// and by which version of rsspls with which config
+    #[serde(default)]
+    pub provenance: bool,
     pub proxy: Option<String>,
+    /// Directory used for the cache instead of the default for the platform
+    pub cache_dir: Option<String>,
+    /// age identity file that secrets given as `{ encrypted = "..." }` are decrypted with
+    pub age_identity: Option<String>,
+    /// Directory used for the history and state of feeds, which is kept when the cache is
+    /// cleared, instead of the default for the platform
+    pub state_dir: Option<String>,
+    /// The maximum size of the cached pages linked from listings, e.g. "50M". The least recently
+    /// used pages are removed once it's exceeded.
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub page_cache_size: Option<u64>,
+    /// The most that can be downloaded in a run, e.g. "20M". Once it's used up the run stops
+    /// and the feeds that weren't finished are skipped until the next run.
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub download_budget: Option<u64>,
+    /// How long requests to a host are skipped after they keep failing or it responds with 429
+    /// Too Many Requests. Defaults to 6 hours, "0s" disables backing off.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub backoff: Option<Duration>,
+    /// The number of consecutive failed requests to a host before backing off from it. Defaults
+    /// to 3.
+    pub backoff_after: Option<u32>,
     /// Whether to allow fetching web pages from file URLs
     #[serde(default)]
     pub file_urls: bool,
+    /// Save fetched pages to the cache directory when no items could be extracted from them
+    #[serde(default)]
+    pub save_failed_html: bool,
+    /// Warn about feeds that haven't had a new item in this long, unless set on the feed
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub stale_after: Option<Duration>,
+    /// Skip pages marked noindex or noarchive and items with nofollow links, unless set on the
+    /// feed
+    pub respect_meta_robots: Option<bool>,
+    /// How often feeds are generated with `--daemon`, unless set on the feed. Defaults to an
+    /// hour.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub refresh: Option<Duration>,
+    /// Value of the generator element of feeds that don't set it
+    pub generator: Option<String>,
+    /// User agent that requests rejected with 403 Forbidden are retried with, unless set on the
+    /// feed
+    pub fallback_user_agent: Option<String>,
+    /// Sort items and omit values that depend on when or with which version rsspls was run, so
+    /// the same pages always produce the same feed. `SOURCE_DATE_EPOCH` is used as the current
+    /// time if set.
+    #[serde(default)]
+    pub reproducible: bool,
+    /// Use HTTP/2 with prior knowledge if true, or only HTTP/1.1 if false. When unset the
+    /// version is negotiated with the server.
+    pub http2: Option<bool>,
+    /// How long idle connections are kept open for reuse
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub pool_idle_timeout: Option<Duration>,
+    /// How long to wait for a connection to a server. Defaults to 10 seconds.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub connect_timeout: Option<Duration>,
+    /// How long a request can take in total. Defaults to 30 seconds.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    /// The maximum number of idle connections kept open per host
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Where the certificates used to verify servers come from. The default depends on the TLS
+    /// implementation rsspls was built with.
+    pub tls_roots: Option<TlsRoots>,
+    /// Sign each generated feed, writing the signature to a `.minisig` file alongside it
+    pub sign: Option<SignConfig>,
+    /// File or URL of the index used by `recipe install`
+    #[cfg_attr(not(feature = "authoring"), allow(dead_code))]
+    pub recipe_index: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct ChannelConfig {
     pub title: String,
     pub filename: String,
+    /// Description of the feed
+    pub description: Option<String>,
+    /// Language the feed is written in, e.g. "en-us"
+    pub language: Option<String>,
+    /// URL of an image for the feed, such as the site's logo. Relative URLs are resolved against
+    /// the first page of the feed.
+    pub image: Option<String>,
+    /// How many minutes readers may cache the feed for before fetching it again
+    pub ttl: Option<u32>,
+    /// Copyright notice for the content of the feed
+    pub copyright: Option<String>,
+    /// Short identifier for the feed used on the command line, in logs, and for its cache files.
+    /// Defaults to the filename without its extension.
+    pub name: Option<String>,
     pub user_agent: Option<String>,
+    /// User agent that a request is retried with once if it's rejected with 403 Forbidden
+    pub fallback_user_agent: Option<String>,
+    /// Value of the Accept header sent when fetching the page
+    pub accept: Option<String>,
+    /// Value of the Accept-Language header sent when fetching the page, e.g. "it-IT"
+    pub accept_language: Option<String>,
+    /// Additional headers sent when fetching pages, e.g. `headers = { Referer = "..." }`. Values
+    /// can be encrypted like passwords.
+    #[serde(default)]
+    pub headers: BTreeMap<String, Secret>,
+    /// Cookies sent to the site of the feed, in the same form as the Cookie header, e.g.
+    /// "session=abc123; theme=dark". They can be encrypted like passwords.
+    pub cookies: Option<Secret>,
+    /// Which compression encodings are accepted and automatically decompressed
+    pub compression: Option<CompressionConfig>,
+    /// How pages are fetched
+    #[serde(default)]
+    pub fetcher: Fetcher,
+    /// Fetch the page on every run, even if the last response said it would still be fresh
+    #[serde(default)]
+    pub ignore_cache_control: bool,
+    /// Render pages with a headless browser so that pages built by JavaScript can be scraped
+    pub render: Option<Renderer>,
+    /// Program and arguments that write the rendered HTML of a page to stdout, instead of the
+    /// browser chosen by `render`. `{url}` in the arguments is replaced with the URL of the page,
+    /// otherwise it's added as the last argument.
+    pub render_command: Option<Vec<String>>,
+    /// Names of additional headers whose values are masked in logs
+    #[serde(default)]
+    pub redact: Vec<String>,
+    /// Log in to the site before fetching the page, sending the cookies it sets with each request
+    /// to it
+    pub login: Option<LoginConfig>,
+    /// Send the cookies from a browser or cookies.txt file with requests, so that a session
+    /// started in a browser can be used. A string is the path to a cookies.txt file.
+    #[serde(default, deserialize_with = "opt_string_or_struct")]
+    pub cookies_from: Option<CookiesFromConfig>,
+    /// Name or filename of a feed that has to be processed before this one. `{depends.link}` and
+    /// `{depends.title}` in the URL are replaced with values from its first item.
+    pub depends_on: Option<String>,
+    /// When to process this feed, based on the feed it depends on
+    #[serde(default)]
+    pub run_if: RunIf,
+    /// Warn if the feed hasn't had a new item in this long
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub stale_after: Option<Duration>,
+    /// How often the feed is generated with `--daemon`
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub refresh: Option<Duration>,
+    /// Value of the generator element, which is omitted if empty. Defaults to the rsspls
+    /// version.
+    pub generator: Option<String>,
+    /// The format the feed is written in
+    #[serde(default)]
+    pub format: OutputFormat,
+    /// Drop items that are also in another feed with `dedup` set that is configured earlier,
+    /// matching them by URL or title
+    pub dedup: Option<DedupBy>,
     pub config: FeedConfig,
 }
 
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// RSS 2.0
+    #[default]
+    Rss,
+    /// Atom 1.0
+    Atom,
+    /// JSON Feed 1.1
+    Json,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fetcher {
+    /// The built-in HTTP client
+    #[default]
+    Builtin,
+    /// The system curl command, for sites that reject requests from the built-in client
+    Curl,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LoginConfig {
+    /// The page with the login form
+    pub url: String,
+    /// Fields submitted with the form, along with the hidden fields of the form on `url`. Fields
+    /// ending in `_env` are read from the environment variable they name, e.g.
+    /// `pass_env = "FORUM_PASSWORD"` submits `pass`. Values may be encrypted or in the keyring.
+    #[serde(default)]
+    pub form: BTreeMap<String, Secret>,
+    /// Selector for an element that's only on the page shown after logging in successfully
+    pub success_selector: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AuthConfig {
+    /// HTTP basic authentication
+    Basic {
+        username: String,
+        password: Option<Secret>,
+        /// Environment variable the password is read from
+        password_env: Option<String>,
+    },
+    /// A bearer token, sent as `Authorization: Bearer <token>`
+    Bearer {
+        token: Option<Secret>,
+        /// Environment variable the token is read from
+        token_env: Option<String>,
+    },
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct CookiesFromConfig {
+    /// Browser whose cookies are used
+    pub browser: Option<Browser>,
+    /// Directory of the browser profile whose cookies are used, defaults to the default profile
+    pub profile: Option<String>,
+    /// Cookies file in the Netscape format, as written by curl and cookie exporting extensions
+    pub file: Option<String>,
+    /// Only use the cookies for this domain and its subdomains
+    pub domain: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Browser {
+    /// Firefox, whose cookie database is read with the sqlite3 command
+    Firefox,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Renderer {
+    /// Chromium in headless mode
+    Chromium,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupBy {
+    /// The link, ignoring the fragment and `utm_` tracking parameters
+    Url,
+    /// The words of the title, ignoring case and punctuation
+    Title,
+}
+
 // TODO: Rename?
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct FeedConfig {
-    pub url: String,
+    /// The page to fetch, or a list of pages whose items are combined, newest first without
+    /// duplicate links. `{1..5}` expands into a URL for each number in the range, of up to 1000
+    /// numbers.
+    #[serde(deserialize_with = "string_or_seq_string")]
+    pub url: Vec<String>,
+    /// Selector for the link to the next page of the listing, which is followed and its items
+    /// added to the feed
+    pub next_page: Option<String>,
+    /// The maximum number of pages fetched by following `next_page`, including the first.
+    /// Defaults to 10.
+    pub max_pages: Option<usize>,
+    /// Values for `{name}` placeholders in the URL, each combination of values is fetched. If
+    /// the placeholders are used in the filename a feed is written for each combination instead.
+    #[serde(default)]
+    pub params_matrix: BTreeMap<String, Vec<String>>,
+    /// Whether `url` is a web page to scrape or an existing feed
+    #[serde(default)]
+    pub source_type: SourceType,
+    /// Field of a JSON response containing the HTML to extract items from, e.g. `data.html`
+    pub json_field: Option<String>,
+    /// Credentials sent to the hosts of `url`, e.g.
+    /// `auth = { type = "basic", username = "me", password_env = "SITE_PASS" }`
+    pub auth: Option<AuthConfig>,
+    /// Backfill the feed with the items in the Wayback Machine's snapshots of the page since this
+    /// date, e.g. "2023-01-01". This happens once, on the next run.
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub wayback: Option<Date>,
+    /// Selector for the part of the page containing the items. Items are only extracted when its
+    /// content has changed since the last run.
+    pub region: Option<String>,
+    #[serde(default)]
     pub item: String,
-    pub heading: String,
-    pub link: Option<String>,
-    #[serde(default, deserialize_with = "string_or_seq_string")]
-    pub summary: Vec<String>,
+    /// Selectors for the heading, the text of each is joined with `heading_separator`. `:scope`
+    /// selects the item element itself. A table like `{ selector = "a", attribute = "title" }`
+    /// takes the value of an attribute instead of the text.
+    #[serde(default, deserialize_with = "one_or_many_string_or_struct")]
+    pub heading: Vec<SelectorConfig>,
+    #[serde(default = "default_heading_separator")]
+    pub heading_separator: String,
+    /// Selector for the element whose `href` is the link, or `:scope` for the item element
+    /// itself. Defaults to the first heading selector.
+    #[serde(default, deserialize_with = "opt_string_or_struct")]
+    pub link: Option<LinkConfig>,
+    /// Selectors for the elements that make up the description, `self` for the whole item. The
+    /// value of an attribute is added as text when one is given.
+    #[serde(default, deserialize_with = "one_or_many_string_or_struct")]
+    pub summary: Vec<SelectorConfig>,
     #[serde(default, deserialize_with = "opt_string_or_struct")]
     pub date: Option<DateConfig>,
-    pub media: Option<String>,
+    /// Selector for the media enclosure, whose URL is its `src` or `href`, or the given attribute
+    #[serde(default, deserialize_with = "opt_string_or_struct")]
+    pub media: Option<SelectorConfig>,
+    /// Selector for the author of the item
+    pub author: Option<String>,
+    /// Selectors for the categories of the item, the text of each element they match is a
+    /// category
+    #[serde(default, deserialize_with = "string_or_seq_string")]
+    pub category: Vec<String>,
+    /// Selector for the element whose `href` is the link to the item's comments
+    pub comments: Option<String>,
+    /// Scrape an item from each of the pages linked from `url` instead of extracting items from
+    /// `url` itself
+    pub discover: Option<DiscoverConfig>,
+    /// Fetch the page each item links to and use its content as the description
+    #[serde(default)]
+    pub fetch_item_content: bool,
+    /// Selector for the content of the pages fetched by `fetch_item_content`. Defaults to the
+    /// first of `article`, `main`, or `body` on the page.
+    pub content: Option<String>,
+    /// Whether to extract items from the document in the first frame of a frameset on the page
+    #[serde(default)]
+    pub follow_frames: bool,
+    /// Selector for the frame or iframe to follow, implies `follow_frames`
+    pub frame: Option<String>,
+    /// Whether to make the contents of `<template>` elements and declarative shadow DOM
+    /// searchable by selectors
+    #[serde(default)]
+    pub expand_templates: bool,
+    /// Whether to decode stray entities and normalise Unicode in extracted text
+    #[serde(default)]
+    pub normalize_text: bool,
+    /// Include the HTML each item was extracted from in an `rsspls:source` element. Its relative
+    /// URLs have been resolved against the page, and templates expanded if `expand_templates` is
+    /// set, but it's otherwise unchanged.
+    #[serde(default)]
+    pub debug_source: bool,
+    /// Selectors for elements to remove from the description
+    #[serde(
+        default,
+        alias = "summary_exclude",
+        deserialize_with = "string_or_seq_string"
+    )]
+    pub remove: Vec<String>,
+    /// Remove the elements matched by the heading selectors from the description so the title
+    /// isn't repeated
+    #[serde(default)]
+    pub summary_drop_heading: bool,
+    /// Keywords to wrap in `<mark>` within the description
+    #[serde(default, deserialize_with = "string_or_seq_string")]
+    pub highlight: Vec<String>,
+    /// Command and arguments that each item is passed through as JSON
+    #[serde(default, deserialize_with = "string_or_seq_string")]
+    pub transform_command: Vec<String>,
+    /// URL that items are POSTed to as JSON for transformation
+    pub transform_url: Option<String>,
+    /// Items with a date older than this are skipped
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub ignore_older_than: Option<Duration>,
+    /// Keep items that are no longer on the page in the feed, up to this many items in total
+    pub keep_items: Option<usize>,
+    /// Date items without a date with when they were first seen, so that it stays the same
+    /// between runs. Always on for feeds with `format = "atom"`.
+    #[serde(default)]
+    pub first_seen_dates: bool,
+    /// Skip pages with a robots meta tag containing noindex or noarchive, and items whose link
+    /// has `rel="nofollow"`
+    pub respect_meta_robots: Option<bool>,
+    /// Only include items that haven't been seen by previous runs
+    #[serde(default)]
+    pub new_items_only: bool,
+    /// Combine new items into a periodic digest item
+    pub digest: Option<DigestConfig>,
+    /// Score items by the keywords they contain
+    pub score: Option<ScoreConfig>,
+    /// Request a capture of each new item's link from a web archive and link to the copy
+    pub archive: Option<ArchiveConfig>,
+    /// Expectations about the extracted items that warn when the page changes shape
+    #[serde(rename = "assert")]
+    pub assertions: Option<AssertConfig>,
+    /// Additionally write a feed per value matched by a selector
+    pub split_by: Option<SplitConfig>,
+    /// Named selectors whose text can be referenced in templates
+    #[serde(default, deserialize_with = "map_string_or_struct")]
+    pub fields: BTreeMap<String, FieldConfig>,
+    /// Conditions on named fields that items must meet to be included
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub filter: Vec<FilterConfig>,
+    /// Template for the title, referencing `{title}` and named fields
+    pub title_template: Option<String>,
+    /// Template for the description, referencing `{summary}`, `{title}`, and named fields
+    pub description_template: Option<String>,
+    /// Prepended to the GUIDs generated from item links, e.g. "rsspls:sitename:"
+    pub guid_prefix: Option<String>,
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignConfig {
+    /// Path to the minisign secret key the feeds are signed with
+    pub key: String,
+    /// Password of the secret key, if it has one, which can be encrypted like other passwords
+    pub password: Option<Secret>,
+    /// Environment variable containing the password of the secret key, if it has one
+    pub password_env: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsRoots {
+    /// The Mozilla root certificates built into rsspls, for systems without a certificate store
+    Bundled,
+    /// The certificate store of the system
+    Native,
+}
+
+#[derive(Debug, Default, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceType {
+    /// A HTML page that items are extracted from with selectors
+    #[default]
+    Html,
+    /// A RSS or Atom feed whose items are used as is
+    Feed,
+    /// A JSON document that items are extracted from with paths instead of selectors. `item` is
+    /// the path of the array of items, and `heading`, `link`, `summary`, and `date` are paths
+    /// within each item. `link` defaults to `url`.
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SplitConfig {
+    /// Selector for the element containing the value to split by
+    pub selector: String,
+    /// Template for the file name of each feed, `{value}` is replaced with the value
+    pub filename_template: String,
+}
+
+#[derive(Debug, Default, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunIf {
+    /// Whenever the feed it depends on was processed successfully
+    #[default]
+    Always,
+    /// If the feed it depends on has any items
+    Items,
+    /// If the feed it depends on had new items
+    NewItems,
+}
+
+/// A named value extracted from each item
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FieldConfig {
+    pub selector: String,
+    /// How the text of the field is interpreted
+    #[serde(default, rename = "type")]
+    pub type_: FieldType,
+    /// Character used as the decimal point in the text of number fields
+    #[serde(default = "default_decimal_point")]
+    pub decimal_point: char,
+    /// Number of decimal places number fields are formatted with, as many as needed if unset
+    pub decimals: Option<usize>,
+    /// Separator inserted between groups of thousands when formatting number fields
+    pub thousands: Option<String>,
+    /// Text placed before formatted number fields, e.g. a currency symbol
+    #[serde(default)]
+    pub prefix: String,
+    /// Text placed after formatted number fields
+    #[serde(default)]
+    pub suffix: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArchiveConfig {
+    /// The archive that captures the pages
+    #[serde(default)]
+    pub service: ArchiveService,
+    /// Where the link to the archived copy goes
+    #[serde(default)]
+    pub placement: ArchivePlacement,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveService {
+    /// The Internet Archive's Wayback Machine
+    #[default]
+    Wayback,
+    /// archive.today
+    ArchiveToday,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchivePlacement {
+    /// Appended to the description
+    #[default]
+    Description,
+    /// An `atom:link` element with `rel="related"`
+    Link,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssertConfig {
+    /// The minimum number of items extracted from the page
+    pub min_items: Option<usize>,
+    /// Regular expression every item's heading must match
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    pub heading_matches: Option<Regex>,
+    /// Fail the feed when an assertion fails instead of warning
+    #[serde(default)]
+    pub error: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScoreConfig {
+    /// Weight added to the score of items containing each keyword, ignoring case
+    pub keywords: BTreeMap<String, i64>,
+    /// Order items by their score, highest first
+    #[serde(default)]
+    pub order: bool,
+    /// Add the score to items as a category
+    #[serde(default = "default_true")]
+    pub category: bool,
+}
+
+/// A condition on a named field, items where the field doesn't match are excluded
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FilterConfig {
+    /// Name of the field the condition applies to. Without a field `include` and `exclude` apply
+    /// to the title and link of every item, whatever the source.
+    #[serde(default)]
+    pub field: String,
+    /// The number in the field must be less than this
+    pub lt: Option<f64>,
+    /// The number in the field must be less than or equal to this
+    pub le: Option<f64>,
+    /// The number in the field must be greater than this
+    pub gt: Option<f64>,
+    /// The number in the field must be greater than or equal to this
+    pub ge: Option<f64>,
+    /// The field must contain one of these, ignoring case, e.g. a list of places
+    #[serde(default, deserialize_with = "string_or_seq_string")]
+    pub contains: Vec<String>,
+    /// The field must match this regular expression
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    pub regex: Option<Regex>,
+    /// The field must match one of these patterns
+    #[serde(default, deserialize_with = "deserialize_patterns")]
+    pub include: Vec<Pattern>,
+    /// The field must not match any of these patterns
+    #[serde(default, deserialize_with = "deserialize_patterns")]
+    pub exclude: Vec<Pattern>,
+}
+
+/// Text to find ignoring case, or a regular expression when written as a table, e.g.
+/// `{ regex = '^Notice \d+' }`
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Text(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn text(pattern: &str) -> Pattern {
+        Pattern::Text(pattern.to_lowercase())
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        match self {
+            Pattern::Text(pattern) => text.to_lowercase().contains(pattern),
+            Pattern::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+impl FilterConfig {
+    /// Whether the filter compares the field as a number
+    pub fn is_numeric(&self) -> bool {
+        [self.lt, self.le, self.gt, self.ge]
+            .iter()
+            .any(Option::is_some)
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    #[default]
+    Text,
+    /// A number, such as a price, that is parsed out of the text and formatted consistently
+    Number,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscoverConfig {
+    /// Selector for the links to the pages that are scraped
+    pub links: String,
+    /// The maximum number of pages that are scraped
+    #[serde(default = "default_discover_limit")]
+    pub limit: usize,
+    /// Link to the URL in the page's `<link rel="canonical">`, if it has one, instead of the URL
+    /// it was fetched from
+    #[serde(default = "default_true")]
+    pub canonical: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompressionConfig {
+    #[serde(default = "default_true")]
+    pub gzip: bool,
+    #[serde(default = "default_true")]
+    pub brotli: bool,
+    #[serde(default = "default_true")]
+    pub deflate: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DigestConfig {
+    /// How often a digest item is produced
+    #[serde(deserialize_with = "deserialize_required_duration")]
+    pub every: Duration,
+    /// Template for the title of digest items
+    #[serde(default = "default_digest_title")]
+    pub title: String,
+    /// Template for each item included in the digest
+    #[serde(default = "default_digest_item_template")]
+    pub item_template: String,
+    /// The number of digest items to keep in the feed
+    #[serde(default = "default_digest_keep")]
+    pub keep: usize,
+}
+
+/// A selector, and optionally the attribute of the element it matches to use in place of its
+/// text
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub struct SelectorConfig {
+    pub selector: String,
+    pub attribute: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
 pub struct DateConfig {
     pub selector: String,
+    /// Attribute holding the date, e.g. `data-timestamp`. `datetime` is used for `<time>`
+    /// elements without one.
+    pub attribute: Option<String>,
     #[serde(rename = "type", default)]
     type_: DateType,
-    #[serde(deserialize_with = "deserialize_format")]
+    #[serde(default, deserialize_with = "deserialize_format")]
     pub format: Option<OwnedFormatItem>,
 }
 
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct LinkConfig {
+    /// Selector for an element within the item
+    #[serde(default)]
+    pub selector: String,
+    /// Selector for the closest ancestor of the item, or the item itself, to take the link from.
+    /// Used in place of `selector` for items wrapped in a link.
+    pub closest: Option<String>,
+    /// Attribute holding the link, `href` by default
+    pub attribute: Option<String>,
+}
+
 #[derive(Debug, Default, Deserialize, Copy, Clone)]
 enum DateType {
     Date,
@@ -73,32 +730,859 @@ enum DateType {
 }
 
 impl Config {
-    /// Read the config file path and the supplied path or default if None
-    pub fn read(config_path: Option<PathBuf>) -> eyre::Result<Config> {
-        let dirs = crate::dirs::new()?;
-        let config_path = config_path.ok_or(()).or_else(|()| {
-            dirs.place_config_file("feeds.toml")
-                .wrap_err("unable to create path to config file")
-        })?;
-        let raw_config = fs::read(&config_path).wrap_err_with(|| {
-            format!(
-                "unable to read configuration file: {}",
-                config_path.display()
-            )
-        })?;
+    /// Read the config file at the supplied path or the default if None
+    ///
+    /// Without a supplied path the user config is layered on the system config, if there is one.
+    /// With `no_user_config` only the system config is read.
+    pub fn read(config_path: Option<PathBuf>, no_user_config: bool) -> eyre::Result<Config> {
+        let system_path = crate::dirs::system_config_file().filter(|path| path.exists());
+        let paths = match (config_path, system_path) {
+            (Some(path), _) => vec![path],
+            (None, Some(system_path)) if no_user_config => vec![system_path],
+            (None, None) if no_user_config => {
+                return Err(eyre!("there is no system configuration file"))
+            }
+            (None, system_path) => {
+                let user_path = Config::path(None)?;
+                match system_path {
+                    // Users don't need a config of their own to use the system one
+                    Some(system_path) if !user_path.exists() => vec![system_path],
+                    Some(system_path) => vec![system_path, user_path],
+                    None => vec![user_path],
+                }
+            }
+        };
+        let layers = paths
+            .into_iter()
+            .map(|path| {
+                let raw = fs::read(&path).wrap_err_with(|| {
+                    format!("unable to read configuration file: {}", path.display())
+                })?;
+                Ok((path, raw))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        Config::from_layers(layers)
+    }
+
+    /// Decrypt the encrypted secrets of the feeds and signing key, and read those kept in the
+    /// keyring
+    ///
+    /// This is left to the commands that fetch the feeds, once they have been selected, so that
+    /// the others don't need the keys to decrypt them.
+    pub fn resolve_secrets(&mut self) -> eyre::Result<()> {
+        let age_identity = self
+            .rsspls
+            .age_identity
+            .as_ref()
+            .map(|path| crate::dirs::expand_path(path))
+            .transpose()?
+            .map(|path| crate::relative_to_file(&self.path, path));
+        let resolver = Resolver::new(&self.path, age_identity);
+        for feed in &mut self.feed {
+            let title = &feed.title;
+            let auth = match &mut feed.config.auth {
+                Some(AuthConfig::Basic { password, .. }) => password.as_mut(),
+                Some(AuthConfig::Bearer { token, .. }) => token.as_mut(),
+                None => None,
+            };
+            if let Some(secret) = auth {
+                resolver
+                    .resolve(secret)
+                    .wrap_err_with(|| format!("unable to resolve auth secret of feed '{title}'"))?;
+            }
+            for (name, secret) in feed.login.iter_mut().flat_map(|login| &mut login.form) {
+                resolver.resolve(secret).wrap_err_with(|| {
+                    format!("unable to resolve login field {name} of feed '{title}'")
+                })?;
+            }
+            for (name, secret) in &mut feed.headers {
+                resolver.resolve(secret).wrap_err_with(|| {
+                    format!("unable to resolve {name} header of feed '{title}'")
+                })?;
+            }
+            if let Some(secret) = &mut feed.cookies {
+                resolver
+                    .resolve(secret)
+                    .wrap_err_with(|| format!("unable to resolve cookies of feed '{title}'"))?;
+            }
+        }
+        if let Some(secret) = self
+            .rsspls
+            .sign
+            .as_mut()
+            .and_then(|sign| sign.password.as_mut())
+        {
+            resolver
+                .resolve(secret)
+                .wrap_err("unable to resolve the password of the signing key")?;
+        }
+        Ok(())
+    }
+
+    /// Parse the raw config files in `layers`, each overriding the ones before it
+    ///
+    /// Settings in `[rsspls]` override the same setting in earlier layers and feeds are added to
+    /// theirs, replacing any with the same name. The config is read from the last path.
+    fn from_layers(layers: Vec<(PathBuf, Vec<u8>)>) -> eyre::Result<Config> {
         let mut context = Blake2b::new(32);
-        context.input(&raw_config);
+        for (_, raw) in &layers {
+            context.input(raw);
+        }
         let digest = context.result_str();
 
-        let mut config: Config = toml::from_slice(&raw_config).wrap_err_with(|| {
-            format!(
-                "unable to parse configuration file: {}",
-                config_path.display()
-            )
-        })?;
+        fn parse<T: de::DeserializeOwned>(path: &Path, raw: &[u8]) -> eyre::Result<T> {
+            toml::from_slice(raw)
+                .wrap_err_with(|| format!("unable to parse configuration file: {}", path.display()))
+        }
+
+        let mut config: Config = match layers.as_slice() {
+            [] => return Err(eyre!("no configuration file to read")),
+            [(path, raw)] => parse(path, raw)?,
+            [(system_path, system_raw), (path, raw)] => {
+                let mut merged: JsonValue = parse(system_path, system_raw)?;
+                let user: JsonValue = parse(path, raw)?;
+                let system_feeds = merge_layer(&mut merged, user);
+                let mut config = Config::deserialize(merged).wrap_err_with(|| {
+                    format!(
+                        "unable to parse configuration file layered on {}: {}",
+                        system_path.display(),
+                        path.display()
+                    )
+                })?;
+                let user_feeds: BTreeSet<_> = config.feed[system_feeds..]
+                    .iter()
+                    .map(|feed| feed.name().to_string())
+                    .collect();
+                let mut index = 0;
+                config.feed.retain(|feed| {
+                    index += 1;
+                    index > system_feeds || !user_feeds.contains(feed.name())
+                });
+                config.system_path = Some(system_path.clone());
+                config
+            }
+            _ => return Err(eyre!("only two configuration files can be layered")),
+        };
+        let config_path = layers
+            .last()
+            .map(|(path, _)| path.clone())
+            .unwrap_or_default();
         config.hash = digest;
+        config.path = config_path.clone();
+        config.feed = config
+            .feed
+            .into_iter()
+            .flat_map(expand_params_matrix)
+            .collect();
+        for feed in &mut config.feed {
+            feed.stale_after = feed.stale_after.or(config.rsspls.stale_after);
+            feed.refresh = feed.refresh.or(config.rsspls.refresh);
+            feed.config.respect_meta_robots = feed
+                .config
+                .respect_meta_robots
+                .or(config.rsspls.respect_meta_robots);
+            if feed.generator.is_none() {
+                feed.generator.clone_from(&config.rsspls.generator);
+            }
+            if feed.fallback_user_agent.is_none() {
+                feed.fallback_user_agent
+                    .clone_from(&config.rsspls.fallback_user_agent);
+            }
+            #[cfg(windows)]
+            {
+                feed.filename =
+                    crate::dirs::expand_env_vars(&feed.filename, |name| std::env::var(name).ok());
+            }
+        }
+        config
+            .validate()
+            .wrap_err_with(|| format!("invalid configuration file: {}", config_path.display()))?;
+        // Refer to dependencies by name from here on
+        let names: Vec<_> = config
+            .feed
+            .iter()
+            .map(|feed| {
+                feed.depends_on
+                    .as_deref()
+                    .and_then(|dependency| find_feed(&config.feed, dependency))
+                    .map(|dependency| dependency.name().to_string())
+            })
+            .collect();
+        for (feed, name) in config.feed.iter_mut().zip(names) {
+            if name.is_some() {
+                feed.depends_on = name;
+            }
+        }
         Ok(config)
     }
+
+    /// Return the supplied config file path or the default if None
+    pub fn path(config_path: Option<PathBuf>) -> eyre::Result<PathBuf> {
+        match config_path {
+            Some(path) => Ok(path),
+            None => crate::dirs::new(None, None)?
+                .place_config_file("feeds.toml")
+                .wrap_err("unable to create path to config file"),
+        }
+    }
+
+    /// Check that all of the CSS selectors in the config are valid
+    fn validate(&self) -> eyre::Result<()> {
+        validate_feeds(&self.feed)
+    }
+}
+
+/// Merge the parsed config `layer` into `base`, returning how many feeds `base` had
+fn merge_layer(base: &mut JsonValue, mut layer: JsonValue) -> usize {
+    let mut feeds = take_feeds(base);
+    let base_feeds = feeds.len();
+    feeds.extend(take_feeds(&mut layer));
+    merge_tables(base, layer);
+    if let JsonValue::Object(base) = base {
+        base.insert("feed".to_string(), JsonValue::Array(feeds));
+    }
+    base_feeds
+}
+
+/// Remove the feeds from the parsed `config`
+fn take_feeds(config: &mut JsonValue) -> Vec<JsonValue> {
+    match config
+        .as_object_mut()
+        .and_then(|config| config.remove("feed"))
+    {
+        Some(JsonValue::Array(feeds)) => feeds,
+        Some(feed) => vec![feed],
+        None => Vec::new(),
+    }
+}
+
+/// Merge `layer` into `base`, where tables in both are merged and other values replaced
+fn merge_tables(base: &mut JsonValue, layer: JsonValue) {
+    match (base, layer) {
+        (JsonValue::Object(base), JsonValue::Object(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_tables(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// Check that all of `feeds` have a URL and valid CSS selectors
+pub fn validate_feeds(feeds: &[ChannelConfig]) -> eyre::Result<()> {
+    let errors: Vec<_> = feeds
+        .iter()
+        .flat_map(|feed| {
+            let missing_url = feed
+                .config
+                .url
+                .is_empty()
+                .then(|| format!("missing url in feed '{}'", feed.title));
+            missing_url
+                .into_iter()
+                .chain(
+                    feed.config
+                        .selectors()
+                        .into_iter()
+                        .filter(|(_, selector)| Selectors::compile(selector).is_err())
+                        .map(move |(field, selector)| {
+                            if selector.is_empty() {
+                                format!("missing selector for {field} in feed '{}'", feed.title)
+                            } else {
+                                format!(
+                                    "invalid selector for {field} in feed '{}': {selector}",
+                                    feed.title
+                                )
+                            }
+                        }),
+                )
+                .chain(filter_errors(feed))
+        })
+        .chain(name_errors(feeds))
+        .chain(refresh_errors(feeds))
+        .chain(range_errors(feeds))
+        .chain(render_errors(feeds))
+        .chain(format_errors(feeds))
+        .chain(cookies_from_errors(feeds))
+        .chain(auth_errors(feeds))
+        .chain(login_errors(feeds))
+        .chain(dependency_errors(feeds))
+        .collect();
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(eyre!("{}", errors.join("\n")))
+    }
+}
+
+/// Check that the filters of `feed` refer to fields that can be compared
+fn filter_errors(feed: &ChannelConfig) -> Vec<String> {
+    let fields = &feed.config.fields;
+    feed.config
+        .filter
+        .iter()
+        .filter_map(|filter| match fields.get(&filter.field) {
+            None if filter.field.is_empty() => {
+                let only_patterns =
+                    !filter.is_numeric() && filter.contains.is_empty() && filter.regex.is_none();
+                (!only_patterns).then(|| {
+                    format!(
+                        "filter in feed '{}' needs a field unless it only has include and exclude",
+                        feed.title
+                    )
+                })
+            }
+            None => Some(format!(
+                "filter in feed '{}' refers to unknown field '{}'",
+                feed.title, filter.field
+            )),
+            Some(field) if filter.is_numeric() && field.type_ != FieldType::Number => {
+                Some(format!(
+                    "filter in feed '{}' compares field '{}' as a number but it doesn't have \
+                     type = \"number\"",
+                    feed.title, filter.field
+                ))
+            }
+            Some(_) => None,
+        })
+        .collect()
+}
+
+/// Check that the refresh intervals of `feeds` aren't zero
+fn refresh_errors(feeds: &[ChannelConfig]) -> Vec<String> {
+    feeds
+        .iter()
+        .filter(|feed| feed.refresh.is_some_and(|refresh| refresh.is_zero()))
+        .map(|feed| format!("refresh of feed '{}' must be longer than zero", feed.title))
+        .collect()
+}
+
+/// Check that render commands have a program to run
+fn render_errors(feeds: &[ChannelConfig]) -> Vec<String> {
+    feeds
+        .iter()
+        .filter(|feed| matches!(&feed.render_command, Some(command) if command.is_empty()))
+        .map(|feed| format!("render_command of feed '{}' is empty", feed.title))
+        .collect()
+}
+
+/// Check that feeds are only written as Atom when rsspls was built with the atom feature
+fn format_errors(feeds: &[ChannelConfig]) -> Vec<String> {
+    feeds
+        .iter()
+        .filter(|feed| feed.format == OutputFormat::Atom && !cfg!(feature = "atom"))
+        .map(|feed| {
+            format!(
+                "feed '{}' has format = \"atom\" but rsspls was built without the atom feature",
+                feed.title
+            )
+        })
+        .collect()
+}
+
+/// Check that cookies are read from either a file or a browser
+fn cookies_from_errors(feeds: &[ChannelConfig]) -> Vec<String> {
+    feeds
+        .iter()
+        .filter(|feed| {
+            feed.cookies_from
+                .as_ref()
+                .is_some_and(|from| from.file.is_some() == from.browser.is_some())
+        })
+        .map(|feed| {
+            format!(
+                "cookies_from of feed '{}' needs one of file or browser",
+                feed.title
+            )
+        })
+        .collect()
+}
+
+/// Check that the secret of each feed's credentials comes from exactly one place
+fn auth_errors(feeds: &[ChannelConfig]) -> Vec<String> {
+    feeds
+        .iter()
+        .filter_map(|feed| {
+            let (name, value, env) = match feed.config.auth.as_ref()? {
+                AuthConfig::Basic {
+                    password,
+                    password_env,
+                    ..
+                } => ("password", password, password_env),
+                AuthConfig::Bearer { token, token_env } => ("token", token, token_env),
+            };
+            (value.is_some() == env.is_some()).then(|| {
+                format!(
+                    "auth of feed '{}' needs one of {name} or {name}_env",
+                    feed.title
+                )
+            })
+        })
+        .collect()
+}
+
+/// Check that the selector telling whether logging in worked is valid
+fn login_errors(feeds: &[ChannelConfig]) -> Vec<String> {
+    feeds
+        .iter()
+        .filter_map(|feed| {
+            let selector = feed.login.as_ref()?.success_selector.as_deref()?;
+            Selectors::compile(selector).is_err().then(|| {
+                format!(
+                    "invalid login.success_selector in feed '{}': {selector}",
+                    feed.title
+                )
+            })
+        })
+        .collect()
+}
+
+/// Check that the names given to feeds are unique and can be used in file names
+///
+/// Feeds without a name that end up with the same one from their filenames are only linted, as
+/// configs like that were accepted before feeds had names.
+fn name_errors(feeds: &[ChannelConfig]) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (i, feed) in feeds.iter().enumerate() {
+        let name = feed.name();
+        let valid = name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if feed.name.is_some() && (name.is_empty() || name.starts_with('.') || !valid) {
+            errors.push(format!(
+                "invalid name for feed '{}': '{name}' may only contain letters, numbers, '-', '_', and '.'",
+                feed.title
+            ));
+        }
+        if feeds[..i]
+            .iter()
+            .any(|other| other.name() == name && (feed.name.is_some() || other.name.is_some()))
+        {
+            errors.push(format!(
+                "feed '{}' has the same name as another feed: {name}",
+                feed.title
+            ));
+        }
+    }
+    errors
+}
+
+/// Find the feed with the name or else filename `reference`
+pub fn find_feed<'a>(feeds: &'a [ChannelConfig], reference: &str) -> Option<&'a ChannelConfig> {
+    feeds
+        .iter()
+        .find(|feed| feed.name() == reference)
+        .or_else(|| feeds.iter().find(|feed| feed.filename == reference))
+}
+
+/// Check that feeds only depend on feeds that exist, without cycles
+fn dependency_errors(feeds: &[ChannelConfig]) -> Vec<String> {
+    let depends_on =
+        |reference: &str| find_feed(feeds, reference).map(|feed| feed.depends_on.as_deref());
+    let mut errors = Vec::new();
+    for feed in feeds {
+        let mut dependency = feed.depends_on.as_deref();
+        for _ in 0..feeds.len() {
+            let Some(filename) = dependency else {
+                break;
+            };
+            match depends_on(filename) {
+                Some(next) => dependency = next,
+                None => {
+                    errors.push(format!(
+                        "feed '{}' depends on {filename}, which isn't configured",
+                        feed.title
+                    ));
+                    dependency = None;
+                }
+            }
+        }
+        if dependency.is_some() {
+            errors.push(format!("feed '{}' has circular dependencies", feed.title));
+        }
+        // Deduplicated feeds wait for each other, so they can't also wait for a dependency
+        if feed.dedup.is_some() && feed.depends_on.is_some() {
+            errors.push(format!(
+                "feed '{}' can't use both dedup and depends_on",
+                feed.title
+            ));
+        }
+    }
+    errors
+}
+
+impl ChannelConfig {
+    /// The name of the feed, which defaults to the filename without its extension
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or_else(|| {
+            Path::new(&self.filename)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or(&self.filename)
+        })
+    }
+
+    /// The name of the file in the cache directory holding the response headers of the feed
+    pub fn cache_filename(&self) -> String {
+        format!("{}.toml", self.name())
+    }
+
+    /// The name of the file in the state directory holding the state kept between runs
+    pub fn state_filename(&self) -> String {
+        format!("{}.state.toml", self.name())
+    }
+
+    /// The names of the cache and state files of the feed from before it was given a name, if
+    /// they're different to the current ones
+    ///
+    /// These were named after the output file.
+    pub fn unnamed_filenames(&self) -> Option<(String, String)> {
+        let filename = Path::new(Path::new(&self.filename).file_name()?);
+        let cache_filename = filename.with_extension("toml").to_str()?.to_string();
+        let state_filename = filename.with_extension("state.toml").to_str()?.to_string();
+        (cache_filename != self.cache_filename()).then_some((cache_filename, state_filename))
+    }
+}
+
+impl FeedConfig {
+    /// Whether an item dated `date` is older than `ignore_older_than` at `now`
+    ///
+    /// An age reaching back further than dates can go keeps everything.
+    pub fn is_too_old(&self, date: OffsetDateTime, now: OffsetDateTime) -> bool {
+        self.ignore_older_than
+            .and_then(|max_age| time::Duration::try_from(max_age).ok())
+            .and_then(|max_age| now.checked_sub(max_age))
+            .is_some_and(|cutoff| date < cutoff)
+    }
+
+    /// The URLs to fetch at `now` with date placeholders filled in and ranges expanded
+    pub fn urls(&self, now: OffsetDateTime) -> Vec<String> {
+        self.url
+            .iter()
+            .flat_map(|url| expand_ranges(&expand_dates(url, now)))
+            .collect()
+    }
+
+    /// All of the CSS selectors in the config along with the name of the field they belong to
+    pub fn selectors(&self) -> Vec<(String, &str)> {
+        let mut selectors = Vec::new();
+        // The fields of items extracted from JSON are paths rather than selectors
+        let item_selectors = self.source_type != SourceType::Json;
+        // Feed sources don't need item and heading selectors, and discovered pages default to
+        // the whole page being the item
+        let requires_item = self.source_type == SourceType::Html && self.discover.is_none();
+        if requires_item || (item_selectors && !self.item.is_empty()) {
+            selectors.push(("item".to_string(), self.item.as_str()));
+        }
+        if let Some(region) = &self.region {
+            selectors.push(("region".to_string(), region));
+        }
+        if let Some(next_page) = &self.next_page {
+            selectors.push(("next_page".to_string(), next_page));
+        }
+        if self.source_type == SourceType::Html && self.heading.is_empty() {
+            selectors.push(("heading".to_string(), ""));
+        }
+        if item_selectors {
+            selectors.extend(
+                self.heading
+                    .iter()
+                    .map(|heading| ("heading".to_string(), heading.selector.as_str())),
+            );
+            match &self.link {
+                Some(LinkConfig {
+                    closest: Some(closest),
+                    ..
+                }) => selectors.push(("link.closest".to_string(), closest)),
+                Some(link) => selectors.push(("link".to_string(), &link.selector)),
+                None => {}
+            }
+            selectors.extend(
+                self.summary
+                    .iter()
+                    .map(|summary| ("summary".to_string(), summary.selector.as_str())),
+            );
+            if let Some(date) = &self.date {
+                selectors.push(("date".to_string(), date.selector()));
+            }
+            if let Some(author) = &self.author {
+                selectors.push(("author".to_string(), author));
+            }
+            selectors.extend(
+                self.category
+                    .iter()
+                    .map(|category| ("category".to_string(), category.as_str())),
+            );
+            if let Some(comments) = &self.comments {
+                selectors.push(("comments".to_string(), comments));
+            }
+        }
+        if let Some(media) = &self.media {
+            selectors.push(("media".to_string(), &media.selector));
+        }
+        if let Some(content) = &self.content {
+            selectors.push(("content".to_string(), content));
+        }
+        selectors.extend(
+            self.remove
+                .iter()
+                .map(|remove| ("remove".to_string(), remove.as_str())),
+        );
+        if let Some(discover) = &self.discover {
+            selectors.push(("discover.links".to_string(), &discover.links));
+        }
+        if let Some(frame) = &self.frame {
+            selectors.push(("frame".to_string(), frame));
+        }
+        if let Some(split_by) = &self.split_by {
+            selectors.push(("split_by".to_string(), &split_by.selector));
+        }
+        selectors.extend(
+            self.fields
+                .iter()
+                .map(|(name, field)| (format!("fields.{name}"), field.selector.as_str())),
+        );
+        selectors
+    }
+}
+
+/// Expand the `params_matrix` of `feed`
+///
+/// If the filename contains placeholders for the parameters a feed is produced for each
+/// combination of values, otherwise the URLs for every combination are combined into one feed.
+fn expand_params_matrix(mut feed: ChannelConfig) -> Vec<ChannelConfig> {
+    let matrix = mem::take(&mut feed.config.params_matrix);
+    if matrix.is_empty() {
+        return vec![feed];
+    }
+
+    let mut combinations = vec![BTreeMap::new()];
+    for (name, values) in &matrix {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|combination| {
+                values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.insert(name.as_str(), value.as_str());
+                    combination
+                })
+            })
+            .collect();
+    }
+    let render = |template: &str, combination: &BTreeMap<&str, &str>| {
+        template::render(template, |name| {
+            combination.get(name).map(|value| value.to_string())
+        })
+    };
+
+    let split = matrix
+        .keys()
+        .any(|name| feed.filename.contains(&format!("{{{name}}}")));
+    if split {
+        combinations
+            .iter()
+            .map(|combination| {
+                let mut feed = feed.clone();
+                feed.title = render(&feed.title, combination);
+                feed.filename = render(&feed.filename, combination);
+                feed.name = feed.name.as_deref().map(|name| render(name, combination));
+                feed.config.url = feed
+                    .config
+                    .url
+                    .iter()
+                    .map(|url| render(url, combination))
+                    .collect();
+                feed
+            })
+            .collect()
+    } else {
+        feed.config.url = feed
+            .config
+            .url
+            .iter()
+            .flat_map(|url| {
+                combinations
+                    .iter()
+                    .map(|combination| render(url, combination))
+            })
+            .collect();
+        vec![feed]
+    }
+}
+
+/// Fill in date placeholders in `url` relative to `now`
+///
+/// `{today}`, `{year}`, `{month}`, and `{day}` are supported. They may be followed by an offset
+/// in days, months, or years respectively, and a format description, e.g. `{month-1:[year]/[month]}`.
+fn expand_dates(url: &str, now: OffsetDateTime) -> String {
+    template::render(url, |placeholder| {
+        let (spec, format) = match placeholder.split_once(':') {
+            Some((spec, format)) => (spec, Some(format)),
+            None => (placeholder, None),
+        };
+        let (name, offset) = match spec.find(['+', '-'].as_slice()) {
+            Some(i) => (
+                &spec[..i],
+                spec[i..].trim_start_matches('+').parse::<i32>().ok()?,
+            ),
+            None => (spec, 0),
+        };
+        let date = now.date();
+        let (date, default_format) = match name {
+            "today" | "day" => (
+                date.checked_add(time::Duration::days(offset.into()))?,
+                if name == "day" {
+                    "[day]"
+                } else {
+                    "[year]-[month]-[day]"
+                },
+            ),
+            "month" => {
+                let months = date.year() * 12 + i32::from(u8::from(date.month())) - 1 + offset;
+                let month =
+                    time::Month::try_from(u8::try_from(months.rem_euclid(12) + 1).ok()?).ok()?;
+                (
+                    Date::from_calendar_date(months.div_euclid(12), month, 1).ok()?,
+                    "[month]",
+                )
+            }
+            "year" => (
+                Date::from_calendar_date(date.year() + offset, date.month(), 1).ok()?,
+                "[year]",
+            ),
+            _ => return None,
+        };
+        let format = time::format_description::parse_owned::<2>(format.unwrap_or(default_format))
+            .map_err(|err| warn!("invalid date format in URL {url}: {err}"))
+            .ok()?;
+        date.format(&format).ok()
+    })
+}
+
+/// Most numbers a range in a URL can expand to, so that a typo can't make millions of URLs
+const MAX_RANGE_LEN: u64 = 1000;
+
+/// A numeric range like `{1..5}` in a URL
+struct UrlRange {
+    /// Byte offsets of the opening and closing braces
+    start: usize,
+    end: usize,
+    from: u64,
+    to: u64,
+    /// Width the numbers are zero padded to
+    width: usize,
+}
+
+/// Find the first numeric range in `url`
+fn find_range(url: &str) -> Option<UrlRange> {
+    let mut search_from = 0;
+    while let Some(start) = url[search_from..].find('{').map(|i| i + search_from) {
+        let end = url[start..].find('}')? + start;
+        let range = url[start + 1..end].split_once("..").and_then(|(from, to)| {
+            let width = if from.starts_with('0') { from.len() } else { 0 };
+            Some((from.parse::<u64>().ok()?, to.parse::<u64>().ok()?, width))
+        });
+        match range {
+            Some((from, to, width)) => {
+                return Some(UrlRange {
+                    start,
+                    end,
+                    from,
+                    to,
+                    width,
+                })
+            }
+            None => search_from = start + 1,
+        }
+    }
+    None
+}
+
+/// Expand numeric ranges like `{1..5}` in `url` into a URL for each number in the range
+///
+/// The numbers are zero padded to the width of the start of the range if it has a leading zero.
+/// Ranges are cut off after `MAX_RANGE_LEN` numbers.
+fn expand_ranges(url: &str) -> Vec<String> {
+    let Some(UrlRange {
+        start,
+        end,
+        from,
+        to,
+        width,
+    }) = find_range(url)
+    else {
+        return vec![url.to_string()];
+    };
+    let (prefix, suffix) = (&url[..start], &url[end + 1..]);
+    let to = to.min(from.saturating_add(MAX_RANGE_LEN - 1));
+    (from..=to)
+        .flat_map(|n| {
+            // Later ranges in the URL are expanded too
+            expand_ranges(suffix)
+                .into_iter()
+                .map(move |suffix| format!("{prefix}{n:0width$}{suffix}"))
+        })
+        .collect()
+}
+
+/// Check that the ranges in the URLs of `feeds` count up and aren't too long
+fn range_errors(feeds: &[ChannelConfig]) -> Vec<String> {
+    let mut errors = Vec::new();
+    for feed in feeds {
+        for url in &feed.config.url {
+            let mut rest = url.as_str();
+            while let Some(range) = find_range(rest) {
+                let text = &rest[range.start..=range.end];
+                if range.from > range.to {
+                    errors.push(format!(
+                        "range {text} in url of feed '{}' counts down",
+                        feed.title
+                    ));
+                } else if range.to - range.from >= MAX_RANGE_LEN {
+                    errors.push(format!(
+                        "range {text} in url of feed '{}' has more than {MAX_RANGE_LEN} numbers",
+                        feed.title
+                    ));
+                }
+                rest = &rest[range.end + 1..];
+            }
+        }
+    }
+    errors
+}
+
+fn default_decimal_point() -> char {
+    '.'
+}
+
+fn default_heading_separator() -> String {
+    " ".to_string()
+}
+
+fn default_discover_limit() -> usize {
+    20
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_digest_title() -> String {
+    "Digest for {date}".to_string()
+}
+
+fn default_digest_item_template() -> String {
+    r#"<h2><a href="{link}">{title}</a></h2>{description}"#.to_string()
+}
+
+fn default_digest_keep() -> usize {
+    10
 }
 
 impl DateConfig {
@@ -149,6 +1633,56 @@ impl DateConfig {
     }
 }
 
+impl FromStr for FieldConfig {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(FieldConfig {
+            selector: s.to_string(),
+            type_: FieldType::Text,
+            decimal_point: default_decimal_point(),
+            decimals: None,
+            thousands: None,
+            prefix: String::new(),
+            suffix: String::new(),
+        })
+    }
+}
+
+impl FromStr for LinkConfig {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(LinkConfig {
+            selector: s.to_string(),
+            closest: None,
+            attribute: None,
+        })
+    }
+}
+
+impl FromStr for SelectorConfig {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(SelectorConfig {
+            selector: s.to_string(),
+            attribute: None,
+        })
+    }
+}
+
+impl FromStr for CookiesFromConfig {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(CookiesFromConfig {
+            file: Some(s.to_string()),
+            ..CookiesFromConfig::default()
+        })
+    }
+}
+
 impl FromStr for DateConfig {
     // This implementation of `from_str` can never fail, so use the
     // `Infallible` type as the error type.
@@ -175,6 +1709,103 @@ where
         })
 }
 
+pub fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Option::deserialize(deserializer)?;
+    s.map(|s| humantime::parse_duration(&s))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_date<'de, D>(deserializer: D) -> Result<Option<Date>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Option::deserialize(deserializer)?;
+    s.map(|s| Date::parse(&s, format_description!("[year]-[month]-[day]")))
+        .transpose()
+        .map_err(de::Error::custom)
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Option::deserialize(deserializer)?;
+    s.map(|s| Regex::new(&s))
+        .transpose()
+        .map_err(de::Error::custom)
+}
+
+fn deserialize_patterns<'de, D>(deserializer: D) -> Result<Vec<Pattern>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum PatternConfig {
+        Text(String),
+        Regex { regex: String },
+    }
+
+    let patterns: Vec<PatternConfig> = one_or_many(deserializer)?;
+    patterns
+        .into_iter()
+        .map(|pattern| match pattern {
+            PatternConfig::Text(text) => Ok(Pattern::text(&text)),
+            PatternConfig::Regex { regex } => Regex::new(&regex)
+                .map(Pattern::Regex)
+                .map_err(de::Error::custom),
+        })
+        .collect()
+}
+
+fn deserialize_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Option::deserialize(deserializer)?;
+    s.map(|s| parse_size(&s).ok_or_else(|| de::Error::custom(format!("invalid size: {s}"))))
+        .transpose()
+}
+
+/// Parse a number of bytes with an optional K, M, or G suffix for powers of 1024
+fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let s = s.strip_suffix(&['B', 'b'][..]).unwrap_or(s);
+    let (number, multiplier) = match s.char_indices().last()? {
+        (i, 'K' | 'k') => (&s[..i], 1 << 10),
+        (i, 'M' | 'm') => (&s[..i], 1 << 20),
+        (i, 'G' | 'g') => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    number.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+fn deserialize_mode<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Option::deserialize(deserializer)?;
+    s.map(|s| {
+        u32::from_str_radix(&s, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .ok_or_else(|| de::Error::custom(format!("invalid octal file mode: {s}")))
+    })
+    .transpose()
+}
+
+pub fn deserialize_required_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    humantime::parse_duration(&s).map_err(serde::de::Error::custom)
+}
+
 // https://serde.rs/string-or-struct.html
 fn string_or_struct<'de, T, D>(deserializer: D) -> Result<T, D::Error>
 where
@@ -252,6 +1883,66 @@ where
     deserializer.deserialize_any(StringOrVec(PhantomData))
 }
 
+/// Deserialize a single value or a list of them
+fn one_or_many<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+/// A value that may be given as a string or a struct, for use in collections
+struct StringOrStruct<T>(T);
+
+impl<'de, T> Deserialize<'de> for StringOrStruct<T>
+where
+    T: Deserialize<'de> + FromStr<Err = Infallible>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        string_or_struct(deserializer).map(StringOrStruct)
+    }
+}
+
+/// Deserialize a string, a struct, or a list of either
+fn one_or_many_string_or_struct<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    T: Deserialize<'de> + FromStr<Err = Infallible>,
+    D: Deserializer<'de>,
+{
+    let values: Vec<StringOrStruct<T>> = one_or_many(deserializer)?;
+    Ok(values
+        .into_iter()
+        .map(|StringOrStruct(value)| value)
+        .collect())
+}
+
+/// Deserialize a map whose values may be a string or a struct
+fn map_string_or_struct<'de, T, D>(deserializer: D) -> Result<BTreeMap<String, T>, D::Error>
+where
+    T: Deserialize<'de> + FromStr<Err = Infallible>,
+    D: Deserializer<'de>,
+{
+    let map = BTreeMap::<String, StringOrStruct<T>>::deserialize(deserializer)?;
+    Ok(map
+        .into_iter()
+        .map(|(name, StringOrStruct(value))| (name, value))
+        .collect())
+}
+
 // https://github.com/emk/compose_yml/blob/7e8e0f47dcc41cf08e15fe082ef4c40b5f0475eb/src/v2/string_or_struct.rs#L69
 fn opt_string_or_struct<'de, T, D>(d: D) -> Result<Option<T>, D::Error>
 where
@@ -296,6 +1987,7 @@ mod tests {
     fn test_date(format: &'static str) -> DateConfig {
         DateConfig {
             selector: String::new(),
+            attribute: None,
             type_: DateType::Date,
             format: Some(time::format_description::parse_owned::<2>(format).unwrap()),
         }
@@ -304,11 +1996,635 @@ mod tests {
     fn test_anydate() -> DateConfig {
         DateConfig {
             selector: String::new(),
+            attribute: None,
             type_: DateType::Date,
             format: None,
         }
     }
 
+    #[test]
+    fn test_expand_params_matrix() {
+        let raw = r#"
+            [rsspls]
+
+            [[feed]]
+            title = "Notices"
+            filename = "notices.rss"
+            [feed.config]
+            url = "http://example.com/?dept={dept}&region={region}"
+            item = "article"
+            heading = "h2"
+            params_matrix = { dept = ["roads", "parks"], region = ["north"] }
+
+            [[feed]]
+            title = "{dept} notices"
+            filename = "{dept}.rss"
+            [feed.config]
+            url = "http://example.com/?dept={dept}"
+            item = "article"
+            heading = "h2"
+            params_matrix = { dept = ["roads", "parks"] }
+        "#;
+        let config: Config = toml::from_str(raw).unwrap();
+        let feeds: Vec<_> = config
+            .feed
+            .into_iter()
+            .flat_map(expand_params_matrix)
+            .collect();
+
+        assert_eq!(feeds.len(), 3);
+        assert_eq!(
+            feeds[0].config.url,
+            [
+                "http://example.com/?dept=roads&region=north",
+                "http://example.com/?dept=parks&region=north"
+            ]
+        );
+        assert_eq!(feeds[1].title, "roads notices");
+        assert_eq!(feeds[1].filename, "roads.rss");
+        assert_eq!(feeds[2].config.url, ["http://example.com/?dept=parks"]);
+    }
+
+    #[test]
+    fn test_expand_dates() {
+        let now = OffsetDateTime::from_unix_timestamp(1_704_153_600).unwrap(); // 2024-01-02
+        assert_eq!(
+            expand_dates("http://example.com/{year}/{month}/{today}", now),
+            "http://example.com/2024/01/2024-01-02"
+        );
+        assert_eq!(
+            expand_dates(
+                "http://example.com/{month-1:[year]/[month]}/{today+30:[day]}",
+                now
+            ),
+            "http://example.com/2023/12/01"
+        );
+        assert_eq!(
+            expand_dates("http://example.com/{year+1}/{page}", now),
+            "http://example.com/2025/{page}"
+        );
+    }
+
+    #[test]
+    fn test_resolve_secrets() {
+        let raw = r#"
+            [rsspls]
+            output = "/srv/feeds"
+            sign = { key = "feeds.key", password = "hunter2" }
+
+            [[feed]]
+            title = "News"
+            filename = "news.rss"
+            headers = { Referer = "http://example.com/", X-Api-Key = { encrypted = "-----BEGIN AGE ENCRYPTED FILE-----" } }
+            cookies = "session=abc123"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+        "#;
+        // Secrets aren't decrypted when the config is read
+        let mut config =
+            Config::from_layers(vec![(PathBuf::from("feeds.toml"), raw.into())]).unwrap();
+        let feed = &config.feed[0];
+        assert_eq!(
+            feed.headers["Referer"].expose().unwrap(),
+            "http://example.com/"
+        );
+        assert!(matches!(feed.headers["X-Api-Key"], Secret::Age { .. }));
+        assert_eq!(
+            feed.cookies.as_ref().unwrap().expose().unwrap(),
+            "session=abc123"
+        );
+        let sign = config.rsspls.sign.as_ref().unwrap();
+        assert_eq!(sign.password.as_ref().unwrap().expose().unwrap(), "hunter2");
+
+        let err = config.resolve_secrets().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unable to resolve X-Api-Key header of feed 'News'"
+        );
+    }
+
+    #[test]
+    fn test_from_layers() {
+        let system = r#"
+            [rsspls]
+            output = "/srv/feeds"
+            refresh = "2h"
+            page_cache_size = "50M"
+
+            [[feed]]
+            title = "Status"
+            filename = "status.rss"
+            [feed.config]
+            url = "http://status.example.com/"
+            item = "li"
+            heading = { selector = "a", attribute = "title" }
+            fields.price = { selector = ".price", type = "number" }
+
+            [[feed]]
+            title = "News"
+            filename = "news.rss"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+        "#;
+        let user = r#"
+            [rsspls]
+            refresh = "30m"
+
+            [[feed]]
+            title = "My News"
+            filename = "news.rss"
+            [feed.config]
+            url = "http://example.com/mine"
+            item = "article"
+            heading = "h2"
+        "#;
+        let config = Config::from_layers(vec![
+            (PathBuf::from("/etc/rsspls/feeds.toml"), system.into()),
+            (PathBuf::from("feeds.toml"), user.into()),
+        ])
+        .unwrap();
+        assert_eq!(config.path, Path::new("feeds.toml"));
+        assert_eq!(
+            config.system_path.as_deref(),
+            Some(Path::new("/etc/rsspls/feeds.toml"))
+        );
+        assert_eq!(config.rsspls.output.as_deref(), Some("/srv/feeds"));
+        assert_eq!(config.rsspls.refresh, Some(Duration::from_secs(30 * 60)));
+        assert_eq!(config.rsspls.page_cache_size, Some(50 << 20));
+        let titles: Vec<_> = config.feed.iter().map(|feed| feed.title.as_str()).collect();
+        assert_eq!(titles, ["Status", "My News"]);
+        assert_eq!(
+            config.feed[0].config.heading[0].attribute.as_deref(),
+            Some("title")
+        );
+        assert_eq!(config.feed[0].refresh, Some(Duration::from_secs(30 * 60)));
+    }
+
+    #[test]
+    fn test_name_errors() {
+        let raw = r#"
+            [rsspls]
+
+            [[feed]]
+            title = "A"
+            filename = "news.rss"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+
+            [[feed]]
+            title = "B"
+            filename = "other.rss"
+            name = "news"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+
+            [[feed]]
+            title = "C"
+            filename = "c.rss"
+            name = "../c"
+            depends_on = "news.rss"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+
+            [[feed]]
+            title = "D"
+            filename = "a/d.rss"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+
+            [[feed]]
+            title = "E"
+            filename = "b/d.rss"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+        "#;
+        let config: Config = toml::from_str(raw).unwrap();
+        assert_eq!(config.feed[0].name(), "news");
+        assert_eq!(
+            name_errors(&config.feed),
+            [
+                "feed 'B' has the same name as another feed: news",
+                "invalid name for feed 'C': '../c' may only contain letters, numbers, '-', '_', and '.'"
+            ]
+        );
+        // Names are preferred to filenames when they're ambiguous
+        assert_eq!(
+            find_feed(&config.feed, "news").map(|feed| feed.title.as_str()),
+            Some("A")
+        );
+        assert_eq!(
+            find_feed(&config.feed, "other.rss").map(|feed| feed.title.as_str()),
+            Some("B")
+        );
+        assert!(dependency_errors(&config.feed).is_empty());
+    }
+
+    #[test]
+    fn test_dependency_errors() {
+        let raw = r#"
+            [rsspls]
+
+            [[feed]]
+            title = "A"
+            filename = "a.rss"
+            depends_on = "b.rss"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+
+            [[feed]]
+            title = "B"
+            filename = "b.rss"
+            depends_on = "a.rss"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+
+            [[feed]]
+            title = "C"
+            filename = "c.rss"
+            depends_on = "missing.rss"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+        "#;
+        let config: Config = toml::from_str(raw).unwrap();
+        assert_eq!(
+            dependency_errors(&config.feed),
+            [
+                "feed 'A' has circular dependencies",
+                "feed 'B' has circular dependencies",
+                "feed 'C' depends on missing.rss, which isn't configured"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_refresh_errors() {
+        let raw = r#"
+            [rsspls]
+
+            [[feed]]
+            title = "A"
+            filename = "a.rss"
+            refresh = "30m"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+
+            [[feed]]
+            title = "B"
+            filename = "b.rss"
+            refresh = "0s"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+        "#;
+        let config: Config = toml::from_str(raw).unwrap();
+        assert_eq!(config.feed[0].refresh, Some(Duration::from_secs(30 * 60)));
+        assert_eq!(
+            refresh_errors(&config.feed),
+            ["refresh of feed 'B' must be longer than zero"]
+        );
+    }
+
+    #[test]
+    fn test_auth_errors() {
+        let raw = r#"
+            [rsspls]
+
+            [[feed]]
+            title = "Dashboard"
+            filename = "dashboard.rss"
+            [feed.config]
+            url = "https://intranet.example.com/"
+            item = "article"
+            heading = "h2"
+            auth = { type = "basic", username = "me", password_env = "SITE_PASS" }
+
+            [[feed]]
+            title = "API"
+            filename = "api.rss"
+            [feed.config]
+            url = "https://api.example.com/"
+            item = "article"
+            heading = "h2"
+            auth = { type = "bearer" }
+        "#;
+        let config: Config = toml::from_str(raw).unwrap();
+        assert!(matches!(
+            &config.feed[0].config.auth,
+            Some(AuthConfig::Basic { username, password_env: Some(env), .. })
+                if username == "me" && env == "SITE_PASS"
+        ));
+        assert_eq!(
+            auth_errors(&config.feed),
+            ["auth of feed 'API' needs one of token or token_env"]
+        );
+    }
+
+    #[test]
+    fn test_login_errors() {
+        let raw = r#"
+            [rsspls]
+
+            [[feed]]
+            title = "Forum"
+            filename = "forum.rss"
+            login = { url = "https://forum.example.com/login", success_selector = "a.logout" }
+            [feed.config]
+            url = "https://forum.example.com/"
+            item = "article"
+            heading = "h2"
+
+            [[feed]]
+            title = "Members"
+            filename = "members.rss"
+            login = { url = "https://members.example.com/login", success_selector = "a[" }
+            [feed.config]
+            url = "https://members.example.com/"
+            item = "article"
+            heading = "h2"
+        "#;
+        let config: Config = toml::from_str(raw).unwrap();
+        assert_eq!(
+            login_errors(&config.feed),
+            ["invalid login.success_selector in feed 'Members': a["]
+        );
+    }
+
+    #[test]
+    fn test_is_too_old() {
+        let now = time::macros::datetime!(2024-03-01 12:00 UTC);
+        let old = time::macros::datetime!(2000-01-01 00:00 UTC);
+        let mut config: FeedConfig = toml::from_str(r#"url = "http://example.com/""#).unwrap();
+        assert!(!config.is_too_old(old, now));
+        config.ignore_older_than = Some(Duration::from_secs(24 * 60 * 60));
+        assert!(config.is_too_old(old, now));
+        assert!(!config.is_too_old(now - time::Duration::HOUR, now));
+        config.ignore_older_than = Some(Duration::from_secs(u64::MAX));
+        assert!(!config.is_too_old(old, now));
+        config.ignore_older_than = Some(Duration::from_secs(i64::MAX as u64));
+        assert!(!config.is_too_old(old, now));
+    }
+
+    #[test]
+    fn test_format_errors() {
+        let raw = r#"
+            [rsspls]
+
+            [[feed]]
+            title = "Atom"
+            filename = "atom.xml"
+            format = "atom"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+        "#;
+        let config: Config = toml::from_str(raw).unwrap();
+        let errors = format_errors(&config.feed);
+        if cfg!(feature = "atom") {
+            assert!(errors.is_empty());
+        } else {
+            assert_eq!(
+                errors,
+                [
+                    r#"feed 'Atom' has format = "atom" but rsspls was built without the atom feature"#
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("50M"), Some(50 << 20));
+        assert_eq!(parse_size("2 GB"), Some(2 << 30));
+        assert_eq!(parse_size("10kb"), Some(10 << 10));
+        assert_eq!(parse_size("B"), None);
+        assert_eq!(parse_size("lots"), None);
+    }
+
+    #[test]
+    fn test_expand_ranges() {
+        assert_eq!(
+            expand_ranges("http://example.com/?page={1..3}"),
+            [
+                "http://example.com/?page=1",
+                "http://example.com/?page=2",
+                "http://example.com/?page=3"
+            ]
+        );
+        assert_eq!(
+            expand_ranges("http://example.com/{a}/{08..09}/{1..2}"),
+            [
+                "http://example.com/{a}/08/1",
+                "http://example.com/{a}/08/2",
+                "http://example.com/{a}/09/1",
+                "http://example.com/{a}/09/2"
+            ]
+        );
+        assert_eq!(
+            expand_ranges("http://example.com/"),
+            ["http://example.com/"]
+        );
+        assert_eq!(
+            expand_ranges("http://example.com/?page={1..100000000}").len(),
+            MAX_RANGE_LEN as usize
+        );
+    }
+
+    #[test]
+    fn test_range_errors() {
+        let raw = r#"
+            [rsspls]
+
+            [[feed]]
+            title = "Pages"
+            filename = "pages.rss"
+            [feed.config]
+            url = ["http://example.com/{a}/?page={1..1000}", "http://example.com/{5..1}/{0..1000}"]
+            item = "article"
+            heading = "h2"
+        "#;
+        let config: Config = toml::from_str(raw).unwrap();
+        assert_eq!(
+            range_errors(&config.feed),
+            [
+                "range {5..1} in url of feed 'Pages' counts down",
+                "range {0..1000} in url of feed 'Pages' has more than 1000 numbers"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fields() {
+        let raw = r#"
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+            [fields]
+            author = ".author"
+            price = { selector = ".price", type = "number", decimals = 2, prefix = "$" }
+        "#;
+        let config: FeedConfig = toml::from_str(raw).unwrap();
+        assert_eq!(config.fields["author"], ".author".parse().unwrap());
+        let price = &config.fields["price"];
+        assert_eq!(price.type_, FieldType::Number);
+        assert_eq!(price.decimal_point, '.');
+        assert_eq!(price.decimals, Some(2));
+        assert_eq!(price.prefix, "$");
+    }
+
+    #[test]
+    fn test_attribute_selectors() {
+        let raw = r#"
+            url = "http://example.com/"
+            item = "article"
+            heading = ["h2", { selector = "a.item", attribute = "title" }]
+            summary = { selector = "img", attribute = "alt" }
+            link = { selector = "a.item", attribute = "data-href" }
+            date = { selector = ".date", attribute = "data-timestamp" }
+            media = "video source"
+        "#;
+        let config: FeedConfig = toml::from_str(raw).unwrap();
+        assert_eq!(
+            config.heading,
+            [
+                "h2".parse().unwrap(),
+                SelectorConfig {
+                    selector: "a.item".to_string(),
+                    attribute: Some("title".to_string()),
+                }
+            ]
+        );
+        assert_eq!(config.summary[0].attribute.as_deref(), Some("alt"));
+        assert_eq!(config.link.unwrap().attribute.as_deref(), Some("data-href"));
+        assert_eq!(
+            config.date.unwrap().attribute.as_deref(),
+            Some("data-timestamp")
+        );
+        assert_eq!(config.media, Some("video source".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_filter_errors() {
+        let raw = r#"
+            [rsspls]
+
+            [[feed]]
+            title = "Classifieds"
+            filename = "classifieds.rss"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+            fields.price = { selector = ".price", type = "number" }
+            fields.place = ".place"
+            filter = [{ field = "price", lt = 500 }, { field = "place", gt = 1 }, { field = "size" }, { exclude = "sold" }, { gt = 1 }]
+        "#;
+        let config: Config = toml::from_str(raw).unwrap();
+        assert_eq!(
+            filter_errors(&config.feed[0]),
+            [
+                r#"filter in feed 'Classifieds' compares field 'place' as a number but it doesn't have type = "number""#,
+                "filter in feed 'Classifieds' refers to unknown field 'size'",
+                "filter in feed 'Classifieds' needs a field unless it only has include and exclude"
+            ]
+        );
+
+        let raw = r#"
+            url = "http://example.com/"
+            filter = { field = "price", lt = 500 }
+        "#;
+        let config: FeedConfig = toml::from_str(raw).unwrap();
+        assert_eq!(config.filter[0].lt, Some(500.0));
+
+        let raw = r#"
+            url = "http://example.com/"
+            filter = { field = "place", regex = "(?i)\\b(north|south) ?side\\b" }
+        "#;
+        let config: FeedConfig = toml::from_str(raw).unwrap();
+        assert!(config.filter[0]
+            .regex
+            .as_ref()
+            .unwrap()
+            .is_match("Northside"));
+        let raw = r#"
+            url = "http://example.com/"
+            filter = { field = "place", regex = "(" }
+        "#;
+        assert!(toml::from_str::<FeedConfig>(raw).is_err());
+
+        let raw = r#"
+            url = "http://example.com/"
+            filter = { include = ["Road Works", { regex = "^Notice \\d+" }], exclude = ["cancelled", "/archive/"] }
+        "#;
+        let config: FeedConfig = toml::from_str(raw).unwrap();
+        let filter = &config.filter[0];
+        assert!(filter.include[0].is_match("Road works on High St"));
+        assert!(filter.include[1].is_match("Notice 12: Bin collection"));
+        assert!(!filter.include[1].is_match("Public notice 12"));
+        assert!(filter.exclude[0].is_match("CANCELLED: Fete"));
+        assert!(filter.exclude[1].is_match("http://example.com/archive/5"));
+        assert!(!filter.exclude[1].is_match("http://example.com/archives"));
+        let raw = r#"
+            url = "http://example.com/"
+            filter = { include = { regex = "(" } }
+        "#;
+        assert!(toml::from_str::<FeedConfig>(raw).is_err());
+    }
+
+    #[test]
+    fn test_validate_selectors() {
+        let raw = r#"
+            [rsspls]
+
+            [[feed]]
+            title = "Good"
+            filename = "good.rss"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+
+            [[feed]]
+            title = "Bad"
+            filename = "bad.rss"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+            summary = ["p", "div >"]
+        "#;
+        let config: Config = toml::from_str(raw).unwrap();
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid selector for summary in feed 'Bad': div >"
+        );
+    }
+
     #[test]
     fn test_without_format() {
         assert!(test_anydate().parse("January 8, 2021").is_ok());
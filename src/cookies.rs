@@ -0,0 +1,519 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs, io};
+
+use log::{debug, info};
+use reqwest::header::{HeaderMap, HeaderValue, SET_COOKIE};
+use serde::Deserialize;
+use simple_eyre::eyre::{self, bail, eyre, WrapErr};
+use time::OffsetDateTime;
+use tokio::process::Command;
+use url::Url;
+
+use crate::config::{Browser, CookiesFromConfig};
+use crate::dirs;
+
+/// The program used to read the cookie database of Firefox
+const SQLITE: &str = "sqlite3";
+/// Cookie expiry times larger than this are taken to be in milliseconds rather than seconds
+const MAX_EXPIRY_SECONDS: i64 = 100_000_000_000;
+
+/// Cookies received from a site, sent back with later requests to it
+///
+/// Only the domain and `Secure` attributes are honoured. Cookies are kept for the rest of the
+/// run regardless of when they expire.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cookie {
+    name: String,
+    value: String,
+    domain: String,
+    /// Whether the cookie is only sent to `domain` itself rather than its subdomains too
+    host_only: bool,
+    /// Whether the cookie is only sent over HTTPS
+    secure: bool,
+}
+
+impl Cookie {
+    fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        let domain_matches = host == self.domain
+            || (!self.host_only
+                && host
+                    .strip_suffix(self.domain.as_str())
+                    .is_some_and(|subdomain| subdomain.ends_with('.')));
+        domain_matches && (!self.secure || url.scheme() == "https")
+    }
+}
+
+impl CookieJar {
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+
+    /// Add the cookies in `cookies`, written as in a Cookie header, for the host of `url`
+    pub fn add_header_cookies(&mut self, url: &Url, cookies: &str) {
+        let Some(host) = url.host_str() else {
+            return;
+        };
+        for cookie in cookies.split(';') {
+            let Some((name, value)) = cookie.split_once('=') else {
+                debug!("ignoring invalid cookie for {host}");
+                continue;
+            };
+            self.insert(Cookie {
+                name: name.trim().to_string(),
+                value: value.trim().to_string(),
+                domain: host.to_ascii_lowercase(),
+                host_only: true,
+                secure: false,
+            });
+        }
+    }
+
+    /// Add all of the cookies in `other`, replacing those with the same name and domain
+    pub fn extend(&mut self, other: CookieJar) {
+        for cookie in other.cookies {
+            self.insert(cookie);
+        }
+    }
+
+    /// Store the cookies set by the response to a request for `url`
+    pub fn store(&mut self, url: &Url, headers: &HeaderMap) {
+        for set_cookie in headers.get_all(SET_COOKIE) {
+            match set_cookie.to_str().ok().and_then(|value| parse(url, value)) {
+                Some(cookie) => self.insert(cookie),
+                None => debug!("ignoring invalid cookie from {url}"),
+            }
+        }
+    }
+
+    fn insert(&mut self, cookie: Cookie) {
+        self.cookies
+            .retain(|existing| existing.name != cookie.name || existing.domain != cookie.domain);
+        // Setting an empty value is how sites remove cookies
+        if !cookie.value.is_empty() {
+            self.cookies.push(cookie);
+        }
+    }
+
+    /// Value of the Cookie header for a request to `url`, if there are cookies to send to it
+    pub fn header(&self, url: &Url) -> Option<HeaderValue> {
+        let cookies: Vec<_> = self
+            .cookies
+            .iter()
+            .filter(|cookie| cookie.matches(url))
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+        let mut value = HeaderValue::from_str(&cookies.join("; ")).ok()?;
+        value.set_sensitive(true);
+        Some(value)
+    }
+}
+
+/// Parse a Set-Cookie header received from `url`
+fn parse(url: &Url, set_cookie: &str) -> Option<Cookie> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    let mut parts = set_cookie.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    let mut cookie = Cookie {
+        name: name.to_string(),
+        value: value.trim().trim_matches('"').to_string(),
+        domain: host.clone(),
+        host_only: true,
+        secure: false,
+    };
+    for attribute in parts {
+        let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let key = key.trim();
+        if key.eq_ignore_ascii_case("domain") {
+            let domain = value.trim().trim_start_matches('.').to_ascii_lowercase();
+            // Sites can only set cookies for themselves and their parent domains, but not for
+            // whole top level domains
+            let allowed = host == domain
+                || (domain.contains('.')
+                    && host
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|subdomain| subdomain.ends_with('.')));
+            if !allowed {
+                return None;
+            }
+            cookie.domain = domain;
+            cookie.host_only = false;
+        } else if key.eq_ignore_ascii_case("secure") {
+            cookie.secure = true;
+        } else if key.eq_ignore_ascii_case("max-age")
+            && value.trim().parse::<i64>().is_ok_and(|age| age <= 0)
+        {
+            // Expired immediately, which removes the cookie
+            cookie.value = String::new();
+        }
+    }
+    Some(cookie)
+}
+
+/// Read the cookies set by `config`, only keeping those for its domain if it has one
+pub async fn load(config: &CookiesFromConfig) -> eyre::Result<CookieJar> {
+    let cookies = match (&config.file, config.browser) {
+        (Some(file), None) => {
+            let path = dirs::expand_path(file)?;
+            let raw = fs::read_to_string(&path)
+                .wrap_err_with(|| format!("unable to read cookies from {}", path.display()))?;
+            parse_netscape(&raw, OffsetDateTime::now_utc())
+        }
+        (None, Some(Browser::Firefox)) => {
+            let profile = match &config.profile {
+                Some(profile) => dirs::expand_path(profile)?,
+                None => firefox_profile()?,
+            };
+            firefox_cookies(&profile).await?
+        }
+        _ => bail!("cookies_from needs one of file or browser"),
+    };
+    let cookies: Vec<_> = match &config.domain {
+        Some(domain) => {
+            let domain = domain.trim().trim_start_matches('.').to_ascii_lowercase();
+            cookies
+                .into_iter()
+                .filter(|cookie| {
+                    cookie.domain == domain
+                        || cookie
+                            .domain
+                            .strip_suffix(domain.as_str())
+                            .is_some_and(|subdomain| subdomain.ends_with('.'))
+                })
+                .collect()
+        }
+        None => cookies,
+    };
+    info!("loaded {} cookies", cookies.len());
+    let mut jar = CookieJar::default();
+    for cookie in cookies {
+        jar.insert(cookie);
+    }
+    Ok(jar)
+}
+
+/// Parse a cookies.txt file in the Netscape format, skipping cookies that expired before `now`
+fn parse_netscape(raw: &str, now: OffsetDateTime) -> Vec<Cookie> {
+    raw.lines()
+        .filter_map(|line| {
+            // curl and browser extensions mark HttpOnly cookies with a prefix
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            if line.starts_with('#') || line.trim().is_empty() {
+                return None;
+            }
+            let fields: Vec<_> = line.split('\t').collect();
+            let [domain, include_subdomains, _path, secure, expiry, name, value] = fields[..]
+            else {
+                debug!("ignoring invalid line in cookies file");
+                return None;
+            };
+            cookie(
+                domain,
+                include_subdomains.eq_ignore_ascii_case("true"),
+                name,
+                value,
+                secure.eq_ignore_ascii_case("true"),
+                expiry.parse().unwrap_or(0),
+                now,
+            )
+        })
+        .collect()
+}
+
+/// Build a cookie read from a cookie store, if it hasn't expired
+fn cookie(
+    domain: &str,
+    include_subdomains: bool,
+    name: &str,
+    value: &str,
+    secure: bool,
+    expiry: i64,
+    now: OffsetDateTime,
+) -> Option<Cookie> {
+    let expiry = if expiry > MAX_EXPIRY_SECONDS {
+        expiry / 1000
+    } else {
+        expiry
+    };
+    // Session cookies have no expiry time
+    if expiry != 0 && expiry < now.unix_timestamp() {
+        return None;
+    }
+    Some(Cookie {
+        name: name.to_string(),
+        value: value.to_string(),
+        domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+        host_only: !include_subdomains && !domain.starts_with('.'),
+        secure,
+    })
+}
+
+/// The directory of the default Firefox profile
+fn firefox_profile() -> eyre::Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| eyre!("unable to determine home directory"))?;
+    let mut roots = vec![
+        home.join(".mozilla/firefox"),
+        home.join("Library/Application Support/Firefox/Profiles"),
+        home.join("snap/firefox/common/.mozilla/firefox"),
+    ];
+    if let Some(appdata) = env::var_os("APPDATA") {
+        roots.push(Path::new(&appdata).join(r"Mozilla\Firefox\Profiles"));
+    }
+    let mut profiles: Vec<PathBuf> = roots
+        .iter()
+        .filter_map(|root| fs::read_dir(root).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.join("cookies.sqlite").is_file())
+        .collect();
+    // Prefer the profile Firefox uses by default
+    profiles.sort_by_key(|path| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        !name.ends_with(".default-release") && !name.ends_with(".default")
+    });
+    profiles
+        .into_iter()
+        .next()
+        .ok_or_else(|| eyre!("unable to find a Firefox profile, set the profile of cookies_from"))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FirefoxCookie {
+    host: String,
+    name: String,
+    value: String,
+    is_secure: i64,
+    expiry: i64,
+}
+
+/// Read the cookies of the Firefox profile in `profile`
+///
+/// The database is copied first as Firefox keeps it locked while it's running. The copy is made
+/// in a directory only the current user can read, as it holds the cookies of every site.
+async fn firefox_cookies(profile: &Path) -> eyre::Result<Vec<Cookie>> {
+    let database = profile.join("cookies.sqlite");
+    let dir = PrivateDir::create().wrap_err("unable to create directory to copy cookies to")?;
+    let copy = dir.0.join("cookies.sqlite");
+    fs::copy(&database, &copy)
+        .wrap_err_with(|| format!("unable to copy {}", database.display()))?;
+    // Recent changes are in the write-ahead log until Firefox checkpoints it
+    let wal = profile.join("cookies.sqlite-wal");
+    if wal.is_file() {
+        if let Err(err) = fs::copy(&wal, copy.with_extension("sqlite-wal")) {
+            debug!("unable to copy {}: {err}", wal.display());
+        }
+    }
+
+    debug!("reading cookies from {} with {SQLITE}", database.display());
+    let output = Command::new(SQLITE)
+        .arg("-json")
+        .arg(&copy)
+        .arg("SELECT host, name, value, isSecure, expiry FROM moz_cookies")
+        .kill_on_drop(true)
+        .output()
+        .await
+        .wrap_err_with(|| format!("unable to run {SQLITE}"))?;
+    if !output.status.success() {
+        bail!(
+            "{SQLITE} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    parse_firefox(&output.stdout, OffsetDateTime::now_utc())
+}
+
+/// A new directory in the temporary directory that only the current user can access, removed
+/// along with its contents when dropped
+///
+/// Each has its own name so that feeds loading cookies at the same time don't share one.
+struct PrivateDir(PathBuf);
+
+impl PrivateDir {
+    fn create() -> io::Result<PrivateDir> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        #[cfg_attr(not(unix), allow(unused_mut))]
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        loop {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.subsec_nanos());
+            let name = format!(
+                "rsspls-cookies-{}-{}-{nanos:x}",
+                std::process::id(),
+                COUNT.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = env::temp_dir().join(name);
+            // Creating the directory fails if it exists, so it can't be one made by someone else
+            match builder.create(&path) {
+                Ok(()) => return Ok(PrivateDir(path)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Drop for PrivateDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn parse_firefox(output: &[u8], now: OffsetDateTime) -> eyre::Result<Vec<Cookie>> {
+    // sqlite3 doesn't output anything when there are no rows
+    if output.iter().all(u8::is_ascii_whitespace) {
+        return Ok(Vec::new());
+    }
+    let rows: Vec<FirefoxCookie> =
+        serde_json::from_slice(output).wrap_err("unable to parse Firefox cookies")?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            cookie(
+                &row.host,
+                row.host.starts_with('.'),
+                &row.name,
+                &row.value,
+                row.is_secure != 0,
+                row.expiry,
+                now,
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(jar: &CookieJar, url: &str) -> Option<String> {
+        jar.header(&url.parse().unwrap())
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_cookie_jar() {
+        let url = "https://forum.example.com/login".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        for set_cookie in [
+            "session=abc123; Path=/; HttpOnly",
+            "theme=dark; Domain=.example.com",
+            "token=secret; Secure",
+            "tracker=1; Domain=elsewhere.com",
+            "everywhere=1; Domain=com",
+        ] {
+            headers.append(SET_COOKIE, HeaderValue::from_static(set_cookie));
+        }
+        let mut jar = CookieJar::default();
+        jar.store(&url, &headers);
+        assert_eq!(
+            header(&jar, "https://forum.example.com/threads").as_deref(),
+            Some("session=abc123; theme=dark; token=secret")
+        );
+        assert_eq!(
+            header(&jar, "http://forum.example.com/threads").as_deref(),
+            Some("session=abc123; theme=dark")
+        );
+        assert_eq!(
+            header(&jar, "https://cdn.example.com/").as_deref(),
+            Some("theme=dark")
+        );
+        assert_eq!(header(&jar, "https://elsewhere.com/"), None);
+        assert!(jar.header(&url).unwrap().is_sensitive());
+
+        let mut headers = HeaderMap::new();
+        headers.append(SET_COOKIE, HeaderValue::from_static("session=; Max-Age=-1"));
+        jar.store(&url, &headers);
+        assert_eq!(
+            header(&jar, "https://forum.example.com/").as_deref(),
+            Some("theme=dark; token=secret")
+        );
+    }
+
+    #[test]
+    fn test_add_header_cookies() {
+        let url = "https://Example.com/news".parse().unwrap();
+        let mut jar = CookieJar::default();
+        jar.add_header_cookies(&url, "session=abc123; theme = dark;invalid");
+        assert_eq!(
+            header(&jar, "http://example.com/").as_deref(),
+            Some("session=abc123; theme=dark")
+        );
+        assert_eq!(header(&jar, "https://cdn.example.com/"), None);
+    }
+
+    #[test]
+    fn test_parse_netscape() {
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let raw = "# Netscape HTTP Cookie File\n\
+            .example.com\tTRUE\t/\tFALSE\t1800000000\ttheme\tdark\n\
+            #HttpOnly_forum.example.com\tFALSE\t/\tTRUE\t0\tsession\tabc123\n\
+            forum.example.com\tFALSE\t/\tFALSE\t1600000000\told\texpired\n\
+            not a cookie\n";
+        let mut jar = CookieJar::default();
+        for cookie in parse_netscape(raw, now) {
+            jar.insert(cookie);
+        }
+        assert_eq!(
+            header(&jar, "https://forum.example.com/").as_deref(),
+            Some("theme=dark; session=abc123")
+        );
+        assert_eq!(
+            header(&jar, "http://forum.example.com/").as_deref(),
+            Some("theme=dark")
+        );
+        assert_eq!(
+            header(&jar, "https://www.forum.example.com/").as_deref(),
+            Some("theme=dark")
+        );
+    }
+
+    #[test]
+    fn test_parse_firefox() {
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let output = br#"[{"host":".example.com","name":"theme","value":"dark","isSecure":0,"expiry":1800000000000},
+            {"host":"forum.example.com","name":"session","value":"abc123","isSecure":1,"expiry":1800000000}]"#;
+        let cookies = parse_firefox(output, now).unwrap();
+        assert_eq!(cookies.len(), 2);
+        assert!(!cookies[0].host_only);
+        assert!(cookies[1].host_only && cookies[1].secure);
+        assert!(parse_firefox(b"\n", now).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_private_dir() {
+        let (a, b) = (PrivateDir::create().unwrap(), PrivateDir::create().unwrap());
+        assert_ne!(a.0, b.0);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&a.0).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+        let path = a.0.clone();
+        fs::write(path.join("cookies.sqlite"), "").unwrap();
+        drop(a);
+        assert!(!path.exists());
+    }
+}
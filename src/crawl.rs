@@ -0,0 +1,273 @@
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+
+use kuchiki::traits::TendrilSink;
+use log::{debug, info};
+use regex::Regex;
+use reqwest::Client as HttpClient;
+use simple_eyre::eyre;
+use url::Url;
+
+use crate::feed::fetch_location;
+
+/// The maximum number of pages fetched, including the first
+const MAX_PAGES: usize = 20;
+/// Pages with fewer dates than this aren't listed as candidate listing pages
+const MIN_DATES: usize = 3;
+/// Pages with fewer links than this aren't listed as candidate listing pages
+const MIN_LINKS: usize = 5;
+/// Feed types advertised in `<link rel="alternate">` elements
+const FEED_TYPES: &[&str] = &[
+    "application/rss+xml",
+    "application/atom+xml",
+    "application/feed+json",
+    "application/json",
+];
+/// Dates written out in the text of a page, such as 2024-08-01, 1/8/2024, or Aug 1, 2024
+const DATE_PATTERN: &str = r"(?i)\b(\d{4}-\d{1,2}-\d{1,2}|\d{1,2}/\d{1,2}/\d{2,4}|(jan|feb|mar|apr|may|jun|jul|aug|sep|oct|nov|dec)[a-z]*\.? \d{1,2}(st|nd|rd|th)?,? \d{4}|\d{1,2}(st|nd|rd|th)? (jan|feb|mar|apr|may|jun|jul|aug|sep|oct|nov|dec)[a-z]*\.?,? \d{4})\b";
+
+/// A feed advertised by a page
+#[derive(Debug, PartialEq)]
+struct FoundFeed {
+    url: Url,
+    mime_type: String,
+    title: String,
+}
+
+/// What was found on a crawled page
+#[derive(Debug, PartialEq)]
+struct PageSummary {
+    feeds: Vec<FoundFeed>,
+    /// The distinct pages on the same site linked from the page
+    links: Vec<Url>,
+    /// The number of dates on the page, from `<time>` elements or text that looks like a date
+    dates: usize,
+}
+
+/// Crawl the site at `location` one level deep and print the feeds, sitemaps, and pages that
+/// look like listings of items
+pub async fn run(http: &HttpClient, location: &str) -> eyre::Result<()> {
+    let date_pattern = Regex::new(DATE_PATTERN)?;
+    let (url, html) = fetch_location(http, location).await?;
+    let start = summarise_page(&html, &url, &date_pattern);
+
+    let mut feeds = Vec::new();
+    let mut listings = Vec::new();
+    let mut visited = BTreeSet::from([url.clone()]);
+    let mut pages = vec![(url.clone(), start)];
+    let links = pages[0].1.links.clone();
+    for link in links {
+        if visited.len() >= MAX_PAGES {
+            info!("stopping after {MAX_PAGES} pages");
+            break;
+        }
+        if !visited.insert(link.clone()) {
+            continue;
+        }
+        match fetch_location(http, link.as_str()).await {
+            Ok((link, html)) => {
+                let summary = summarise_page(&html, &link, &date_pattern);
+                pages.push((link, summary));
+            }
+            Err(err) => debug!("skipping {link}: {err:#}"),
+        }
+    }
+    for (page_url, summary) in pages {
+        for feed in summary.feeds {
+            if !feeds.iter().any(|found: &FoundFeed| found.url == feed.url) {
+                feeds.push(feed);
+            }
+        }
+        if summary.dates >= MIN_DATES && summary.links.len() >= MIN_LINKS {
+            listings.push((page_url, summary.links.len(), summary.dates));
+        }
+    }
+    listings.sort_by_key(|&(_, links, dates)| Reverse((dates, links)));
+    let sitemaps = find_sitemaps(http, &url).await;
+
+    println!("Feeds:");
+    if feeds.is_empty() {
+        println!("    none found");
+    }
+    for feed in &feeds {
+        println!("    {} ({}) {}", feed.url, feed.mime_type, feed.title);
+    }
+    println!("\nSitemaps:");
+    if sitemaps.is_empty() {
+        println!("    none found");
+    }
+    for sitemap in &sitemaps {
+        println!("    {sitemap}");
+    }
+    println!("\nListing pages:");
+    if listings.is_empty() {
+        println!("    none found");
+    }
+    for (page_url, links, dates) in &listings {
+        println!("    {page_url} ({links} links, {dates} dates)");
+    }
+    Ok(())
+}
+
+/// Find the feeds, links, and dates on a page
+fn summarise_page(html: &str, url: &Url, date_pattern: &Regex) -> PageSummary {
+    let doc = kuchiki::parse_html().one(html);
+
+    let mut feeds = Vec::new();
+    if let Ok(links) = doc.select("link[rel~=alternate][href][type]") {
+        for link in links {
+            let attrs = link.attributes.borrow();
+            let mime_type = attrs.get("type").unwrap_or_default().trim().to_lowercase();
+            if !FEED_TYPES.contains(&mime_type.as_str()) {
+                continue;
+            }
+            if let Some(feed_url) = attrs
+                .get("href")
+                .and_then(|href| url.join(href.trim()).ok())
+            {
+                feeds.push(FoundFeed {
+                    url: feed_url,
+                    mime_type,
+                    title: attrs.get("title").unwrap_or_default().trim().to_string(),
+                });
+            }
+        }
+    }
+
+    let mut links: Vec<Url> = Vec::new();
+    if let Ok(anchors) = doc.select("a[href]") {
+        for anchor in anchors {
+            let attrs = anchor.attributes.borrow();
+            let Some(mut link) = attrs
+                .get("href")
+                .and_then(|href| url.join(href.trim()).ok())
+            else {
+                continue;
+            };
+            link.set_fragment(None);
+            if link.origin() == url.origin()
+                && link != *url
+                && is_page(&link)
+                && !feeds.iter().any(|feed| feed.url == link)
+                && !links.contains(&link)
+            {
+                links.push(link);
+            }
+        }
+    }
+
+    let times = doc.select("time").map_or(0, Iterator::count);
+    let text_dates = doc.select_first("body").map_or(0, |body| {
+        date_pattern.find_iter(&body.text_contents()).count()
+    });
+
+    PageSummary {
+        feeds,
+        links,
+        dates: times.max(text_dates),
+    }
+}
+
+/// Whether `url` looks like it links to a web page rather than an image, stylesheet, etc.
+fn is_page(url: &Url) -> bool {
+    match mime_guess::from_path(url.path()).first() {
+        Some(mime) => mime == mime_guess::mime::TEXT_HTML,
+        None => true,
+    }
+}
+
+/// The sitemaps listed in the robots.txt of the site, or its /sitemap.xml if it has one
+async fn find_sitemaps(http: &HttpClient, url: &Url) -> Vec<Url> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Vec::new();
+    }
+    let mut sitemaps = Vec::new();
+    if let Ok(robots_url) = url.join("/robots.txt") {
+        match fetch_location(http, robots_url.as_str()).await {
+            Ok((robots_url, robots)) => sitemaps = robots_sitemaps(&robots, &robots_url),
+            Err(err) => debug!("no robots.txt: {err:#}"),
+        }
+    }
+    if sitemaps.is_empty() {
+        if let Ok(sitemap_url) = url.join("/sitemap.xml") {
+            match fetch_location(http, sitemap_url.as_str()).await {
+                Ok(_) => sitemaps.push(sitemap_url),
+                Err(err) => debug!("no sitemap.xml: {err:#}"),
+            }
+        }
+    }
+    sitemaps
+}
+
+/// Parse the `Sitemap:` lines out of a robots.txt file
+fn robots_sitemaps(robots: &str, url: &Url) -> Vec<Url> {
+    robots
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("sitemap")
+                .then(|| url.join(value.trim()).ok())
+                .flatten()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarise_page() {
+        let url = "http://example.com/blog/".parse().unwrap();
+        let html = r#"<html><head>
+            <link rel="alternate" type="application/rss+xml" title="Blog" href="/feed.xml">
+            <link rel="alternate" hreflang="fr" type="text/html" href="/fr/">
+            <link rel="stylesheet" href="/style.css">
+            </head><body>
+            <a href="/feed.xml">RSS</a>
+            <a href="post-1.html">One</a> <time>2024-08-01</time>
+            <a href="post-1.html#comments">Comments</a>
+            <a href="post-2.html">Two</a> Posted Aug 2, 2024
+            <a href="/logo.png">Logo</a>
+            <a href="https://elsewhere.example.com/">Elsewhere</a>
+            <a href="/blog/">Blog</a>
+            </body></html>"#;
+        let date_pattern = Regex::new(DATE_PATTERN).unwrap();
+        let summary = summarise_page(html, &url, &date_pattern);
+        assert_eq!(
+            summary.feeds,
+            [FoundFeed {
+                url: "http://example.com/feed.xml".parse().unwrap(),
+                mime_type: "application/rss+xml".to_string(),
+                title: "Blog".to_string(),
+            }]
+        );
+        let links: Vec<_> = summary.links.iter().map(Url::as_str).collect();
+        assert_eq!(
+            links,
+            [
+                "http://example.com/blog/post-1.html",
+                "http://example.com/blog/post-2.html"
+            ]
+        );
+        assert_eq!(summary.dates, 2);
+    }
+
+    #[test]
+    fn test_robots_sitemaps() {
+        let url = "http://example.com/robots.txt".parse().unwrap();
+        let robots = "User-agent: *\nDisallow: /admin\nSitemap: https://example.com/sitemap-index.xml\nsitemap: /news.xml\n";
+        let sitemaps: Vec<_> = robots_sitemaps(robots, &url)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            sitemaps,
+            [
+                "https://example.com/sitemap-index.xml",
+                "http://example.com/news.xml"
+            ]
+        );
+    }
+}
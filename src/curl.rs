@@ -0,0 +1,242 @@
+use std::process::Stdio;
+
+use encoding_rs::{Encoding, UTF_8};
+use log::debug;
+use mime_guess::mime::Mime;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, LOCATION};
+use reqwest::StatusCode;
+use simple_eyre::eyre::{self, bail, eyre, WrapErr};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use url::Url;
+
+use crate::Timeouts;
+
+/// The program run to fetch pages
+const CURL: &str = "curl";
+
+/// The settings of the HTTP client that are passed to curl
+pub struct Options<'a> {
+    pub proxy: Option<&'a str>,
+    /// The User-Agent header, none is sent if it's empty
+    pub user_agent: String,
+    pub timeouts: Timeouts,
+}
+
+/// The final response curl received
+#[derive(Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+    /// The size of the body as received, before it was decoded
+    pub size: u64,
+}
+
+/// Fetch `url` by running curl, sending `headers` with the request
+///
+/// The options are passed to curl as a config file on stdin so that header values aren't visible
+/// in the process list.
+pub async fn fetch(
+    url: &Url,
+    headers: &HeaderMap,
+    options: &Options<'_>,
+) -> eyre::Result<Response> {
+    let config = config(url, headers, options)?;
+
+    debug!("fetching {url} with {CURL}");
+    let mut child = Command::new(CURL)
+        .args(["--config", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .wrap_err_with(|| format!("unable to run {CURL}"))?;
+
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| eyre!("unable to open stdin of {CURL}"))?;
+    stdin
+        .write_all(config.as_bytes())
+        .await
+        .wrap_err_with(|| format!("unable to write config to {CURL}"))?;
+    // Close stdin so curl sees the end of the config
+    drop(stdin);
+
+    let output = child
+        .wait_with_output()
+        .await
+        .wrap_err_with(|| format!("error waiting for {CURL}"))?;
+    if !output.status.success() {
+        bail!(
+            "{CURL} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let (status, headers, body) = parse_output(&output.stdout)?;
+    Ok(Response {
+        status,
+        body: decode(&headers, body),
+        headers,
+        size: body.len() as u64,
+    })
+}
+
+/// The config file passed to curl to fetch `url`
+fn config(url: &Url, headers: &HeaderMap, options: &Options<'_>) -> eyre::Result<String> {
+    // The response to the CONNECT request made to a proxy for HTTPS isn't written with
+    // suppress-connect-headers, so it isn't mistaken for the response
+    let mut config = String::from(
+        "silent\nshow-error\nlocation\ncompressed\nsuppress-connect-headers\n\
+         dump-header = \"-\"\n",
+    );
+    config.push_str(&format!(
+        "connect-timeout = {}\nmax-time = {}\n",
+        options.timeouts.connect.as_secs_f64(),
+        options.timeouts.total.as_secs_f64()
+    ));
+    config.push_str(&format!("url = {}\n", quote(url.as_str())));
+    config.push_str(&format!("user-agent = {}\n", quote(&options.user_agent)));
+    if let Some(proxy) = options.proxy {
+        config.push_str(&format!("proxy = {}\n", quote(proxy)));
+    }
+    for (name, value) in headers {
+        let value = value
+            .to_str()
+            .wrap_err_with(|| format!("value of {name} header can't be passed to curl"))?;
+        config.push_str(&format!(
+            "header = {}\n",
+            quote(&format!("{name}: {value}"))
+        ));
+    }
+    Ok(config)
+}
+
+/// Split the output of curl into the status and headers of the final response, and its body
+///
+/// The headers of every response are written before the body, including those of redirects that
+/// were followed.
+fn parse_output(output: &[u8]) -> eyre::Result<(StatusCode, HeaderMap, &[u8])> {
+    let mut rest = output;
+    loop {
+        let end = rest
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| eyre!("unable to find the end of the response headers"))?;
+        let block = String::from_utf8_lossy(&rest[..end]);
+        rest = &rest[end + 4..];
+
+        let mut lines = block.lines();
+        let status = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse::<u16>().ok())
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .ok_or_else(|| eyre!("unable to parse the response status"))?;
+        let mut headers = HeaderMap::new();
+        for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+            match (
+                HeaderName::from_bytes(name.trim().as_bytes()),
+                HeaderValue::from_str(value.trim()),
+            ) {
+                (Ok(name), Ok(value)) => {
+                    headers.append(name, value);
+                }
+                _ => debug!("ignoring invalid header from {CURL}: {name}"),
+            }
+        }
+
+        let followed = status.is_informational()
+            || (status.is_redirection() && headers.contains_key(LOCATION));
+        if !(followed && rest.starts_with(b"HTTP/")) {
+            return Ok((status, headers, rest));
+        }
+    }
+}
+
+/// Decode `body` using the charset in the Content-Type header, or UTF-8
+pub fn decode(headers: &HeaderMap, body: &[u8]) -> String {
+    let encoding = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<Mime>().ok())
+        .and_then(|mime| {
+            mime.get_param("charset")
+                .and_then(|charset| Encoding::for_label(charset.as_str().as_bytes()))
+        })
+        .unwrap_or(UTF_8);
+    encoding.decode(body).0.into_owned()
+}
+
+/// Quote `value` as a string in a curl config file
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        let output = b"HTTP/1.1 301 Moved Permanently\r\nLocation: https://example.com/\r\n\r\n\
+            HTTP/2 200\r\ncontent-type: text/html; charset=iso-8859-1\r\netag: \"abc\"\r\n\r\n\
+            <p>Caf\xe9</p>";
+        let (status, headers, body) = parse_output(output).unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers.get("etag").unwrap(), "\"abc\"");
+        assert_eq!(decode(&headers, body), "<p>Café</p>");
+
+        let (status, _, body) = parse_output(b"HTTP/1.1 304 Not Modified\r\n\r\n").unwrap();
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert!(body.is_empty());
+
+        assert!(parse_output(b"<html>").is_err());
+    }
+
+    #[test]
+    fn test_config() {
+        let url = "https://example.com/news".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("s3cret"));
+        let options = Options {
+            proxy: Some("http://proxy.example:3128"),
+            user_agent: "Mozilla/5.0".to_string(),
+            timeouts: Timeouts {
+                connect: Duration::from_secs(5),
+                total: Duration::from_millis(1500),
+            },
+        };
+        let config = config(&url, &headers, &options).unwrap();
+        let lines: Vec<_> = config.lines().collect();
+        assert!(lines.contains(&"suppress-connect-headers"));
+        assert!(lines.contains(&"connect-timeout = 5"));
+        assert!(lines.contains(&"max-time = 1.5"));
+        assert!(lines.contains(&r#"user-agent = "Mozilla/5.0""#));
+        assert!(lines.contains(&r#"proxy = "http://proxy.example:3128""#));
+        assert!(lines.contains(&r#"header = "x-api-key: s3cret""#));
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote(r#"X-Token: a"b\c"#), r#""X-Token: a\"b\\c""#);
+    }
+}
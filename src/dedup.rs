@@ -0,0 +1,310 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use log::debug;
+use rss::{Item, Source};
+use tokio::sync::Notify;
+use url::Url;
+
+use crate::config::{ChannelConfig, DedupBy};
+
+/// Items of the feeds taking part in deduplication this run
+///
+/// Each feed registers its items and then waits for the others, so that every feed sees all the
+/// items of the run. An item that appears in several feeds is kept by the one configured first and
+/// dropped from the others.
+pub struct Dedup {
+    /// Names of the participating feeds, in the order they're configured
+    feeds: Vec<String>,
+    state: Mutex<State>,
+    registered: Notify,
+}
+
+#[derive(Default)]
+struct State {
+    /// Feeds that have registered their items or won't be registering any
+    done: HashSet<usize>,
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    feed: usize,
+    url: Option<String>,
+    title: Option<String>,
+}
+
+impl Dedup {
+    /// Create the registry for the feeds in `feeds` that have `dedup` set, if there are any
+    pub fn new(feeds: &[ChannelConfig]) -> Option<Self> {
+        let feeds: Vec<_> = feeds
+            .iter()
+            .filter(|feed| feed.dedup.is_some())
+            .map(|feed| feed.name().to_string())
+            .collect();
+        (!feeds.is_empty()).then(|| Dedup {
+            feeds,
+            state: Mutex::default(),
+            registered: Notify::new(),
+        })
+    }
+
+    /// Register the items of `feed`, wait for the other feeds, then drop the items that were
+    /// already in a feed configured earlier
+    ///
+    /// Kept items that also appear in later feeds get a `<source>` naming `feed`, so that they
+    /// can be told apart when the feeds are read together.
+    pub async fn resolve(&self, feed: &ChannelConfig, link: &str, items: Vec<Item>) -> Vec<Item> {
+        let (Some(by), Some(index)) = (feed.dedup, self.index(feed.name())) else {
+            return items;
+        };
+        self.register(feed, &items);
+
+        loop {
+            let registered = self.registered.notified();
+            if self.state.lock().unwrap().done.len() == self.feeds.len() {
+                break;
+            }
+            registered.await;
+        }
+
+        let state = self.state.lock().unwrap();
+        items
+            .into_iter()
+            .filter_map(|mut item| {
+                // Items without a key can't be matched with any others
+                let (url, title) = (url_key(&item), title_key(&item));
+                let Some(key) = select_key(by, &url, &title) else {
+                    return Some(item);
+                };
+                let mut others = state.entries.iter().filter(|entry| {
+                    entry.feed != index && select_key(by, &entry.url, &entry.title) == Some(key)
+                });
+                if let Some(entry) = others.clone().find(|entry| entry.feed < index) {
+                    debug!(
+                        "dropping {key} from {} as it's in {}",
+                        feed.name(),
+                        self.feeds[entry.feed]
+                    );
+                    return None;
+                }
+                if others.next().is_some() {
+                    item.set_source(Source {
+                        url: link.to_string(),
+                        title: Some(feed.title.clone()),
+                    });
+                }
+                Some(item)
+            })
+            .collect()
+    }
+
+    /// Register the items of `feed` without waiting for the other feeds
+    ///
+    /// This is used for feeds that keep the items they wrote previously, such as when their page
+    /// hasn't changed, so that the later feeds still drop them.
+    pub fn register(&self, feed: &ChannelConfig, items: &[Item]) {
+        let Some(index) = self.index(feed.name()) else {
+            return;
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.done.contains(&index) {
+                return;
+            }
+            state.entries.extend(items.iter().map(|item| Entry {
+                feed: index,
+                url: url_key(item),
+                title: title_key(item),
+            }));
+        }
+        self.finish(feed.name());
+    }
+
+    /// Record that the feed `name` won't register any more items, so the other feeds don't wait
+    /// for it
+    pub fn finish(&self, name: &str) {
+        let Some(index) = self.index(name) else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        if state.done.insert(index) && state.done.len() == self.feeds.len() {
+            self.registered.notify_waiters();
+        }
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        self.feeds.iter().position(|feed| feed == name)
+    }
+}
+
+fn select_key<'a>(
+    by: DedupBy,
+    url: &'a Option<String>,
+    title: &'a Option<String>,
+) -> Option<&'a str> {
+    match by {
+        DedupBy::Url => url.as_deref(),
+        DedupBy::Title => title.as_deref(),
+    }
+}
+
+/// The link of `item` without its fragment and `utm_` tracking parameters
+fn url_key(item: &Item) -> Option<String> {
+    let mut url: Url = item.link()?.trim().parse().ok()?;
+    url.set_fragment(None);
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| !name.starts_with("utm_"))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if query.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+    Some(url.into())
+}
+
+/// The words of the title of `item` in lowercase, so titles that only differ in case and
+/// punctuation match
+fn title_key(item: &Item) -> Option<String> {
+    let words: Vec<_> = item
+        .title()?
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    (!words.is_empty()).then(|| words.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use basic_toml as toml;
+    use rss::ItemBuilder;
+
+    use crate::config::Config;
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let config: Config = toml::from_str(
+            r#"
+            [rsspls]
+
+            [[feed]]
+            title = "A"
+            filename = "a.rss"
+            dedup = "url"
+            [feed.config]
+            url = "http://a.example/"
+            item = "article"
+            heading = "h2"
+
+            [[feed]]
+            title = "B"
+            filename = "b.rss"
+            dedup = "url"
+            [feed.config]
+            url = "http://b.example/"
+            item = "article"
+            heading = "h2"
+
+            [[feed]]
+            title = "C"
+            filename = "c.rss"
+            [feed.config]
+            url = "http://c.example/"
+            item = "article"
+            heading = "h2"
+        "#,
+        )
+        .unwrap();
+        let dedup = Dedup::new(&config.feed).unwrap();
+        let item = |title: &str, link: &str| {
+            ItemBuilder::default()
+                .title(title.to_string())
+                .link(link.to_string())
+                .build()
+        };
+        let a = vec![
+            item("Launch", "http://news.example/launch?utm_source=a#top"),
+            item("Other", "http://a.example/other"),
+        ];
+        let b = vec![
+            item("Launch!", "http://news.example/launch?utm_medium=rss"),
+            item("Only B", "http://b.example/only"),
+        ];
+
+        let (a, b) = tokio::join!(
+            dedup.resolve(&config.feed[0], "http://a.example/", a),
+            dedup.resolve(&config.feed[1], "http://b.example/", b),
+        );
+        let titles = |items: &[Item]| -> Vec<String> {
+            items
+                .iter()
+                .map(|item| item.title().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(titles(&a), ["Launch", "Other"]);
+        assert_eq!(titles(&b), ["Only B"]);
+        assert_eq!(
+            a[0].source(),
+            Some(&Source {
+                url: "http://a.example/".to_string(),
+                title: Some("A".to_string()),
+            })
+        );
+        assert_eq!(a[1].source(), None);
+
+        assert_eq!(
+            url_key(&a[0]).as_deref(),
+            Some("http://news.example/launch")
+        );
+        assert_eq!(
+            title_key(&item(" Launch: Day 1! ", "")).as_deref(),
+            Some("launch day 1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register() {
+        let config: Config = toml::from_str(
+            r#"
+            [rsspls]
+
+            [[feed]]
+            title = "A"
+            filename = "a.rss"
+            dedup = "title"
+            [feed.config]
+            url = "http://a.example/"
+            item = "article"
+            heading = "h2"
+
+            [[feed]]
+            title = "B"
+            filename = "b.rss"
+            dedup = "title"
+            [feed.config]
+            url = "http://b.example/"
+            item = "article"
+            heading = "h2"
+        "#,
+        )
+        .unwrap();
+        let dedup = Dedup::new(&config.feed).unwrap();
+        let item = |title: &str| ItemBuilder::default().title(title.to_string()).build();
+
+        // A's page is unchanged, so it registers the items it wrote last time
+        dedup.register(&config.feed[0], &[item("Launch")]);
+        let b = dedup
+            .resolve(
+                &config.feed[1],
+                "http://b.example/",
+                vec![item("Launch"), item("Only B")],
+            )
+            .await;
+        assert_eq!(b.len(), 1);
+        assert_eq!(b[0].title(), Some("Only B"));
+    }
+}
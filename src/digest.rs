@@ -0,0 +1,127 @@
+use std::mem;
+
+use log::{debug, info};
+use rss::Item;
+use time::format_description::well_known::Rfc2822;
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+use crate::config::DigestConfig;
+use crate::state::{FeedState, StoredItem};
+use crate::template;
+use crate::text::escape_html;
+
+/// Accumulate `new_items` in `state` and return the digest items for the feed
+///
+/// When the digest period has elapsed all pending items are combined into a single new digest
+/// item.
+pub fn collect(
+    config: &DigestConfig,
+    state: &mut FeedState,
+    new_items: Vec<Item>,
+    now: OffsetDateTime,
+) -> Vec<Item> {
+    state.pending.extend(new_items.iter().map(StoredItem::from));
+
+    let started = match state.digest_started {
+        Some(started) => started,
+        None => {
+            state.digest_started = Some(now.unix_timestamp());
+            now.unix_timestamp()
+        }
+    };
+    let elapsed = now.unix_timestamp().saturating_sub(started);
+    if elapsed >= config.every.as_secs() as i64 && !state.pending.is_empty() {
+        let pending = mem::take(&mut state.pending);
+        info!("producing digest of {} items", pending.len());
+        state.digests.insert(0, digest_item(config, &pending, now));
+        state.digests.truncate(config.keep);
+        state.digest_started = Some(now.unix_timestamp());
+    } else {
+        debug!(
+            "{} items pending for next digest in {}s",
+            state.pending.len(),
+            (config.every.as_secs() as i64).saturating_sub(elapsed)
+        );
+    }
+
+    state.digests.iter().cloned().map(Item::from).collect()
+}
+
+fn digest_item(config: &DigestConfig, items: &[StoredItem], now: OffsetDateTime) -> StoredItem {
+    let date = now
+        .format(format_description!("[year]-[month]-[day]"))
+        .unwrap_or_default();
+    let count = items.len().to_string();
+    let title = template::render(&config.title, |name| match name {
+        "date" => Some(date.clone()),
+        "count" => Some(count.clone()),
+        _ => None,
+    });
+    let description = items
+        .iter()
+        .map(|item| {
+            template::render(&config.item_template, |name| match name {
+                "title" => Some(escape_html(item.title.as_deref().unwrap_or_default())),
+                "link" => Some(escape_html(item.link.as_deref().unwrap_or_default())),
+                "description" => Some(item.description.clone().unwrap_or_default()),
+                "date" => Some(item.pub_date.clone().unwrap_or_default()),
+                _ => None,
+            })
+        })
+        .collect();
+
+    StoredItem {
+        title: Some(title),
+        link: None,
+        description: Some(description),
+        pub_date: now.format(&Rfc2822).ok(),
+        guid: Some(format!("digest:{}", now.unix_timestamp())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rss::ItemBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_collect() {
+        let config = DigestConfig {
+            every: Duration::from_secs(7 * 24 * 60 * 60),
+            title: "Digest {date} ({count})".to_string(),
+            item_template: r#"<a href="{link}">{title}</a>"#.to_string(),
+            keep: 10,
+        };
+        let item = |title: &str| {
+            ItemBuilder::default()
+                .title(Some(title.to_string()))
+                .link(Some("http://example.com/?a&b".to_string()))
+                .build()
+        };
+        let mut state = FeedState::default();
+        let start = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+
+        let items = collect(&config, &mut state, vec![item("one")], start);
+        assert!(items.is_empty());
+
+        let items = collect(
+            &config,
+            &mut state,
+            vec![item("two")],
+            start + Duration::from_secs(8 * 24 * 60 * 60),
+        );
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].title(), Some("Digest 2023-11-22 (2)"));
+        assert_eq!(
+            items[0].description(),
+            Some(
+                r#"<a href="http://example.com/?a&amp;b">one</a><a href="http://example.com/?a&amp;b">two</a>"#
+            )
+        );
+        assert!(state.pending.is_empty());
+    }
+}
@@ -1,21 +1,74 @@
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::{env, fs};
 
-use eyre::eyre;
+use eyre::{eyre, WrapErr};
 use simple_eyre::eyre;
 
 pub type Dirs = Arc<Mutex<BaseDirs>>;
 
-pub struct BaseDirs;
+pub struct BaseDirs {
+    /// Directory used in place of the rsspls directory in the user cache dir
+    cache_dir: Option<PathBuf>,
+    /// Directory used in place of the rsspls state directory in the user local data dir
+    state_dir: Option<PathBuf>,
+}
+
+pub fn new(cache_dir: Option<PathBuf>, state_dir: Option<PathBuf>) -> eyre::Result<BaseDirs> {
+    Ok(BaseDirs {
+        cache_dir,
+        state_dir,
+    })
+}
 
-pub fn new() -> eyre::Result<BaseDirs> {
-    Ok(BaseDirs)
+/// The config file shared by all users, which the config of each user is layered on
+pub fn system_config_file() -> Option<PathBuf> {
+    env::var_os("ProgramData").map(|dir| Path::new(&dir).join("rsspls").join("feeds.toml"))
 }
 
 pub fn home_dir() -> Option<PathBuf> {
     ::dirs::home_dir()
 }
 
+/// Expand `%VAR%` environment variables and a leading `~` in `path`
+pub fn expand_path(path: &str) -> eyre::Result<PathBuf> {
+    let home = home_dir().ok_or_else(|| eyre!("unable to determine home directory"))?;
+    let path = expand_env_vars(path, |name| env::var(name).ok());
+    Ok(crate::expand_tilde(path, home))
+}
+
+/// Replace `%NAME%` in `s` with the value of `NAME` returned by `lookup`
+///
+/// Like cmd.exe, references to variables that aren't set are left as is.
+pub fn expand_env_vars(s: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('%') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('%') else {
+            expanded.push('%');
+            rest = after;
+            break;
+        };
+        let name = &after[..end];
+        match Some(name).filter(|name| !name.is_empty()).and_then(&lookup) {
+            Some(value) => {
+                expanded.push_str(&value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                // The closing % may start the next reference
+                expanded.push('%');
+                expanded.push_str(name);
+                rest = &after[end..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
 impl BaseDirs {
     pub fn place_config_file<P: AsRef<Path>>(&self, path: P) -> eyre::Result<PathBuf> {
         ::dirs::config_dir()
@@ -28,6 +81,15 @@ impl BaseDirs {
     }
 
     pub fn place_cache_file<P: AsRef<Path>>(&self, path: P) -> eyre::Result<PathBuf> {
+        self.cache_file(path)
+    }
+
+    /// The path of `path` in the cache directory, which like `place_cache_file` doesn't create
+    /// any directories on Windows
+    pub fn cache_file<P: AsRef<Path>>(&self, path: P) -> eyre::Result<PathBuf> {
+        if let Some(cache_dir) = &self.cache_dir {
+            return Ok(cache_dir.join(path));
+        }
         ::dirs::cache_dir()
             .ok_or_else(|| eyre!("unable to dermine user cache dir"))
             .map(|mut config| {
@@ -36,4 +98,31 @@ impl BaseDirs {
                 config
             })
     }
+
+    pub fn place_state_file<P: AsRef<Path>>(&self, path: P) -> eyre::Result<PathBuf> {
+        let path = self.state_file(path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).wrap_err_with(|| {
+                format!("unable to create state directory: {}", parent.display())
+            })?;
+        }
+        Ok(path)
+    }
+
+    /// Like `place_state_file` but without creating any directories
+    pub fn state_file<P: AsRef<Path>>(&self, path: P) -> eyre::Result<PathBuf> {
+        if let Some(state_dir) = &self.state_dir {
+            return Ok(state_dir.join(path));
+        }
+        // On Windows this is LocalAppData, which is also where the cache is, hence the state
+        // subdirectory
+        ::dirs::data_local_dir()
+            .ok_or_else(|| eyre!("unable to dermine user local data dir"))
+            .map(|mut state| {
+                state.push("rsspls");
+                state.push("state");
+                state.push(path);
+                state
+            })
+    }
 }
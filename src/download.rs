@@ -0,0 +1,197 @@
+use std::fmt;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use log::error;
+use reqwest::{RequestBuilder, Response};
+use simple_eyre::eyre::{self, WrapErr};
+use tokio::sync::Notify;
+
+use crate::{curl, Client};
+
+/// The bytes downloaded by all the feeds in a run, and the limit on them
+#[derive(Debug, Default)]
+pub struct Downloads {
+    budget: Option<u64>,
+    total: AtomicU64,
+    /// Set once the budget has been used up, which stops the run
+    exceeded: AtomicBool,
+    notify: Notify,
+}
+
+/// The error for requests that weren't made, or were cut short, as the download budget was used
+/// up
+#[derive(Debug)]
+pub struct BudgetExceeded {
+    budget: u64,
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "download budget of {} used up", format_size(self.budget))
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+impl Downloads {
+    pub fn new(budget: Option<u64>) -> Self {
+        Downloads {
+            budget,
+            ..Default::default()
+        }
+    }
+
+    /// Return an error if the budget has already been used up
+    pub fn check(&self) -> Result<(), BudgetExceeded> {
+        match self.budget {
+            Some(budget) if self.total() >= budget => Err(self.stop(budget)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record that `bytes` were downloaded, returning an error if that exceeded the budget
+    pub fn add(&self, bytes: u64) -> Result<(), BudgetExceeded> {
+        let total = self.total.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        match self.budget {
+            Some(budget) if total > budget => Err(self.stop(budget)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// Whether the budget has been used up, stopping the run
+    pub fn is_exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::Relaxed)
+    }
+
+    /// Wait until the budget is used up
+    pub async fn exceeded(&self) -> BudgetExceeded {
+        loop {
+            let mut notified = pin!(self.notify.notified());
+            // Register for the notification before checking so it can't be missed
+            notified.as_mut().enable();
+            if self.is_exceeded() {
+                return BudgetExceeded {
+                    budget: self.budget.unwrap_or_default(),
+                };
+            }
+            notified.await;
+        }
+    }
+
+    /// Stop the run as the budget was used up, which is reported the first time
+    fn stop(&self, budget: u64) -> BudgetExceeded {
+        if !self.exceeded.swap(true, Ordering::Relaxed) {
+            error!(
+                "download budget of {} used up, stopping the run",
+                format_size(budget)
+            );
+            self.notify.notify_waiters();
+        }
+        BudgetExceeded { budget }
+    }
+}
+
+/// Count `bytes` downloaded for the feed `client` is used for against the budget
+pub fn record(client: &Client, bytes: u64) -> Result<(), BudgetExceeded> {
+    client.downloaded.fetch_add(bytes, Ordering::Relaxed);
+    client.downloads.add(bytes)
+}
+
+/// Send `request` made for the feed `client` is used for, unless the budget has been used up
+///
+/// The body of the response should be read with [`body`] or [`text`] so that it is counted.
+pub async fn send(client: &Client, request: RequestBuilder) -> eyre::Result<Response> {
+    client.downloads.check()?;
+    Ok(request.send().await?)
+}
+
+/// Read the body of `resp`, counting it as it's received so that it's cut short once the budget
+/// is used up
+pub async fn body(client: &Client, mut resp: Response) -> eyre::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    while let Some(chunk) = resp
+        .chunk()
+        .await
+        .wrap_err("unable to read response body")?
+    {
+        record(client, chunk.len() as u64)?;
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Read the body of `resp` like [`body`], decoding it with the charset in its Content-Type
+/// header
+pub async fn text(client: &Client, resp: Response) -> eyre::Result<String> {
+    let headers = resp.headers().clone();
+    let body = body(client, resp).await?;
+    Ok(curl::decode(&headers, &body))
+}
+
+/// Format a number of bytes with a K, M, or G suffix for powers of 1024
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 3] = ["K", "M", "G"];
+    if bytes < 1 << 10 {
+        return format!("{bytes}B");
+    }
+    let mut size = bytes as f64;
+    let mut unit = UNITS[0];
+    for next in UNITS {
+        size /= 1024.0;
+        unit = next;
+        if size < 1024.0 {
+            break;
+        }
+    }
+    format!("{size:.1}{unit}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downloads() {
+        let downloads = Downloads::new(Some(100));
+        assert!(downloads.check().is_ok());
+        assert!(downloads.add(60).is_ok());
+        assert!(downloads.check().is_ok());
+        assert!(downloads.add(60).is_err());
+        assert!(downloads.check().is_err());
+        assert_eq!(downloads.total(), 120);
+
+        assert!(Downloads::new(None).add(u32::MAX.into()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_downloads_exceeded() {
+        let downloads = Downloads::new(Some(100));
+        assert!(!downloads.is_exceeded());
+        let waiting = downloads.exceeded();
+        assert!(downloads.add(60).is_ok());
+        assert!(!downloads.is_exceeded());
+        assert!(downloads.add(60).is_err());
+        assert!(downloads.is_exceeded());
+        waiting.await;
+        // Waiting after the budget was used up returns straight away
+        downloads.exceeded().await;
+
+        let downloads = Downloads::new(Some(100));
+        assert!(downloads.add(100).is_ok());
+        assert!(downloads.check().is_err());
+        assert!(downloads.is_exceeded());
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512B");
+        assert_eq!(format_size(1536), "1.5K");
+        assert_eq!(format_size(50 << 20), "50.0M");
+        assert_eq!(format_size(3 << 40), "3072.0G");
+    }
+}
@@ -0,0 +1,102 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use simple_eyre::eyre::Report;
+
+/// The class of an error that occurred while processing a feed
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// A page couldn't be fetched
+    Fetch,
+    /// A URL, page, or feed couldn't be parsed
+    Parse,
+    /// A selector was invalid or didn't match anything
+    Selector,
+    /// A date couldn't be parsed or formatted
+    Date,
+    /// The output, cache, or state couldn't be written
+    Write,
+    /// The extracted items didn't meet the feed's assertions
+    Assertion,
+}
+
+/// An error processing a feed, tagged with its class
+///
+/// The wrapped report is kept so that the full chain of causes is still reported.
+#[derive(Debug)]
+pub struct FeedError {
+    kind: ErrorKind,
+    report: Report,
+}
+
+impl FeedError {
+    pub fn new(kind: ErrorKind, report: impl Into<Report>) -> Self {
+        FeedError {
+            kind,
+            report: report.into(),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.report.fmt(f)
+    }
+}
+
+impl StdError for FeedError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.report.chain().nth(1)
+    }
+}
+
+/// Tag the error of a result with its class
+pub trait ResultExt<T> {
+    fn kind(self, kind: ErrorKind) -> Result<T, FeedError>;
+}
+
+impl<T, E: Into<Report>> ResultExt<T> for Result<T, E> {
+    fn kind(self, kind: ErrorKind) -> Result<T, FeedError> {
+        self.map_err(|err| FeedError::new(kind, err))
+    }
+}
+
+/// The class of the outermost `FeedError` in `report`, if there is one
+pub fn kind(report: &Report) -> Option<ErrorKind> {
+    report
+        .chain()
+        .find_map(|err| err.downcast_ref::<FeedError>())
+        .map(FeedError::kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_eyre::eyre::{eyre, WrapErr};
+
+    #[test]
+    fn test_kind() {
+        let result: Result<(), _> = Err(eyre!("connection refused"))
+            .wrap_err("unable to fetch http://example.com/")
+            .kind(ErrorKind::Fetch);
+        let report = Report::from(result.unwrap_err()).wrap_err("error processing feed");
+        assert_eq!(kind(&report), Some(ErrorKind::Fetch));
+        let causes: Vec<_> = report.chain().map(|err| err.to_string()).collect();
+        assert_eq!(
+            causes,
+            [
+                "error processing feed",
+                "unable to fetch http://example.com/",
+                "connection refused"
+            ]
+        );
+
+        assert_eq!(kind(&eyre!("unclassified")), None);
+    }
+}
@@ -1,32 +1,68 @@
-use std::{fs, mem};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, fs, mem};
 
 use basic_toml as toml;
+use cryptoxide::{blake2b::Blake2b, digest::Digest};
+use futures::{stream, FutureExt, StreamExt};
+use kuchiki::iter::NodeIterator;
 use kuchiki::traits::TendrilSink;
-use kuchiki::{ElementData, NodeDataRef, NodeRef};
-use log::{debug, error, info, warn};
+use kuchiki::{ElementData, NodeDataRef, NodeRef, Selectors};
+use log::{debug, error, info, log_enabled, warn, Level};
 use mime_guess::mime;
-use reqwest::header::HeaderMap;
-use reqwest::{RequestBuilder, StatusCode};
-use rss::{Channel, ChannelBuilder, EnclosureBuilder, GuidBuilder, Item, ItemBuilder};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_ENCODING, COOKIE, ETAG,
+    LAST_MODIFIED, PROXY_AUTHORIZATION, USER_AGENT,
+};
+use reqwest::StatusCode;
+use rss::extension::ExtensionBuilder;
+use rss::{
+    CategoryBuilder, Channel, ChannelBuilder, EnclosureBuilder, GuidBuilder, ImageBuilder, Item,
+    ItemBuilder,
+};
 use simple_eyre::eyre::{self, bail, eyre, WrapErr};
 use time::format_description::well_known::Rfc2822;
-use time::OffsetDateTime;
+use time::macros::format_description;
+use time::{Date, OffsetDateTime};
 use tokio::task;
 use url::Url;
 
-use crate::cache::RequestCacheWrite;
-use crate::config::{ChannelConfig, ConfigHash, DateConfig, FeedConfig};
-use crate::Client;
+use crate::auth::{Authorization, CustomHeaders};
+use crate::cache::{self, Freshness, RequestCacheWrite};
+use crate::config::{
+    ArchiveConfig, ArchivePlacement, ChannelConfig, ConfigHash, DateConfig, DiscoverConfig,
+    FeedConfig, Fetcher, FieldType, FilterConfig, OutputFormat, Pattern, SelectorConfig,
+    SourceType, SplitConfig,
+};
+use crate::cookies::CookieJar;
+use crate::error::{ErrorKind, FeedError, ResultExt};
+use crate::state::{FeedState, StoredItem};
+use crate::{
+    archive, assertion, cookies, curl, digest, download, json, login, number, render, score,
+    syndication, template, text, transform, wayback, Client,
+};
 
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum ProcessResult {
     NotModified,
     Ok {
         channel: Channel,
+        /// The feeds split from `channel` by `split_by`, along with their file names
+        splits: Vec<(String, Channel)>,
         headers: Option<String>,
+        /// The number of items that weren't in the previous run
+        new_items: usize,
+        /// Descriptions of the assertions that failed
+        failed_assertions: Vec<String>,
     },
 }
 
+#[derive(Clone)]
 pub enum FetchResult {
     NotModified,
     Ok {
@@ -40,462 +76,3433 @@ pub async fn process_feed(
     channel_config: &ChannelConfig,
     config_hash: ConfigHash<'_>,
     cached_headers: &Option<HeaderMap>,
-) -> eyre::Result<ProcessResult> {
+    state: &mut FeedState,
+) -> Result<ProcessResult, FeedError> {
     let config = &channel_config.config;
-    info!("processing {}", config.url);
-    let url: Url = config
-        .url
-        .parse()
-        .wrap_err_with(|| format!("unable to parse {} as a URL", config.url))?;
-
-    let (html, serialised_headers) =
-        match fetch_webpage(client, &url, cached_headers, channel_config, config_hash).await? {
-            FetchResult::Ok { html, headers } => (html, headers),
-            FetchResult::NotModified => return Ok(ProcessResult::NotModified),
+    let now = client.source_date.unwrap_or_else(OffsetDateTime::now_utc);
+    let urls = config
+        .urls(now)
+        .iter()
+        .map(|url| {
+            url.parse::<Url>()
+                .wrap_err_with(|| format!("unable to parse {} as a URL", url))
+        })
+        .collect::<eyre::Result<Vec<_>>>()
+        .kind(ErrorKind::Parse)?;
+    let url = urls
+        .first()
+        .ok_or_else(|| eyre!("no URL to fetch"))
+        .kind(ErrorKind::Parse)?
+        .clone();
+    info!("processing {}: {}", channel_config.name(), url);
+
+    let with_credentials = with_credentials(client, channel_config, &urls).await?;
+    let client = with_credentials.as_ref().unwrap_or(client);
+
+    // Conditional requests are only made when the feed is built from a single page, otherwise
+    // an unmodified first page could hide changes to the others. The same goes for a frameset
+    // and the frames in it.
+    let single_page = urls.len() == 1;
+    let conditional = single_page && !(config.follow_frames || config.frame.is_some());
+    let cached_headers = if conditional { cached_headers } else { &None };
+    let mut items = Vec::new();
+    let mut serialised_headers = None;
+    let mut region_hash = None;
+    for mut page_url in urls.iter().cloned() {
+        let mut html = match fetch_listing(
+            client,
+            &page_url,
+            cached_headers,
+            channel_config,
+            config_hash,
+        )
+        .await
+        .kind(ErrorKind::Fetch)?
+        {
+            FetchResult::Ok { html, headers } => {
+                serialised_headers = headers;
+                html
+            }
+            FetchResult::NotModified => {
+                check_stale(channel_config, state, &[], now);
+                register_deduped(client, channel_config, state);
+                return Ok(ProcessResult::NotModified);
+            }
         };
+        if let (true, Some(region)) = (conditional, &config.region) {
+            region_hash = hash_region(region, &html, config_hash);
+            if region_hash.is_some() && region_hash == state.region_hash {
+                info!("{} is unchanged in {}", region, page_url);
+                check_stale(channel_config, state, &[], now);
+                register_deduped(client, channel_config, state);
+                return Ok(ProcessResult::NotModified);
+            }
+        }
 
-    let link_selector = config.link.as_ref().unwrap_or(&config.heading);
-
-    let doc = kuchiki::parse_html().one(html);
-    let base_url = Url::options().base_url(Some(&url));
-    rewrite_urls(&doc, &base_url)?;
+        let mut visited = vec![page_url.clone()];
+        loop {
+            let next_url = match &config.next_page {
+                Some(selector) if visited.len() < config.max_pages.unwrap_or(DEFAULT_MAX_PAGES) => {
+                    next_page_url(&html, &page_url, selector)
+                        .filter(|next_url| !visited.contains(next_url))
+                }
+                _ => None,
+            };
+            items.extend(
+                extract_page(client, channel_config, config_hash, html, page_url, now).await?,
+            );
+            let Some(next_url) = next_url else {
+                break;
+            };
+            info!("following next page {next_url}");
+            // Keep the items from the pages fetched so far if a later one fails
+            html = match fetch_document(client, &next_url, channel_config, config_hash).await {
+                Ok(html) => html,
+                Err(report) => {
+                    warn!("unable to fetch next page, stopping: {report:?}");
+                    break;
+                }
+            };
+            visited.push(next_url.clone());
+            page_url = next_url;
+        }
+    }
+    if !conditional {
+        serialised_headers = None;
+    }
+    if !single_page {
+        items = combine_items(items);
+    }
+    if let Some(from) = config.wayback {
+        backfill(client, channel_config, config_hash, from, &urls, state, now).await;
+    }
+    if !state.backfill.is_empty() {
+        let guids: BTreeSet<_> = items
+            .iter()
+            .filter_map(|item| item.guid().map(|guid| guid.value().to_string()))
+            .collect();
+        items.extend(
+            state
+                .backfill
+                .iter()
+                .filter(|stored| {
+                    !stored
+                        .guid
+                        .as_ref()
+                        .is_some_and(|guid| guids.contains(guid))
+                })
+                .cloned()
+                .map(Item::from),
+        );
+    }
+    if config.filter.iter().any(|filter| filter.field.is_empty()) {
+        items.retain(|item| {
+            let keep = title_link_filters_match(config, item);
+            if !keep {
+                debug!(
+                    "skipping item '{}' as it doesn't match the filter",
+                    item.title().unwrap_or_default().trim()
+                );
+            }
+            keep
+        });
+    }
+    let items = match config.keep_items {
+        Some(keep) => keep_items(keep, state, items),
+        None => items,
+    };
+    // Atom entries need a date, which would otherwise be the time of each run for undated items
+    let items = if config.first_seen_dates || channel_config.format == OutputFormat::Atom {
+        date_first_seen(state, items, now).kind(ErrorKind::Date)?
+    } else {
+        items
+    };
 
-    let mut items = Vec::new();
-    for item in doc
-        .select(&config.item)
-        .map_err(|()| eyre!("invalid selector for item: {}", config.item))?
-    {
-        match process_item(config, item, link_selector, &base_url) {
-            Ok(rss_item) => items.push(rss_item),
-            Err(err) => {
-                let report = err.wrap_err(format!(
-                    "unable to process RSS item matching '{}'",
-                    config.item
+    check_stale(channel_config, state, &items, now);
+    state.region_hash = region_hash;
+    let failed_assertions = match &config.assertions {
+        Some(assertions) => {
+            let failures = assertion::check(assertions, &items);
+            if assertions.error && !failures.is_empty() {
+                return Err(FeedError::new(
+                    ErrorKind::Assertion,
+                    eyre!("assertions failed: {}", failures.join("; ")),
                 ));
-                error!("{report:?}");
             }
+            for failure in &failures {
+                warn!("assertion failed for {}: {failure}", channel_config.name());
+            }
+            failures
         }
-    }
+        None => Vec::new(),
+    };
+    let new_items = items
+        .iter()
+        .filter(|item| !item.guid().is_some_and(|guid| state.has_seen(guid.value())))
+        .count();
+    let items = select_new_items(config, state, items);
+    let items = if config.fetch_item_content {
+        fetch_item_content(client, channel_config, config_hash, &url, items).await
+    } else {
+        items
+    };
+    let items = match &config.archive {
+        Some(archive) => archive::apply(client, channel_config, archive, state, items).await,
+        None => items,
+    };
+    let items = transform::apply(client, config, items).await;
+    let items = match &client.dedup {
+        Some(dedup) if channel_config.dedup.is_some() => {
+            let items = dedup.resolve(channel_config, url.as_str(), items).await;
+            state.deduped = items
+                .iter()
+                .map(|item| StoredItem {
+                    title: item.title.clone(),
+                    link: item.link.clone(),
+                    ..Default::default()
+                })
+                .collect();
+            items
+        }
+        _ => items,
+    };
+    let items = match &config.digest {
+        Some(digest) => digest::collect(digest, state, items, now),
+        None => items,
+    };
+    let items = if client.reproducible {
+        sort_items(items)
+    } else {
+        items
+    };
+    let items = match &config.score {
+        Some(score) => score::apply(score, items),
+        None => items,
+    };
 
-    let channel = ChannelBuilder::default()
+    let generator = match channel_config.generator.as_deref() {
+        Some("") => None,
+        Some(generator) => Some(generator.to_string()),
+        // The version changes between builds
+        None if client.reproducible => None,
+        None => Some(crate::version_string()),
+    };
+    let last_build_date = client
+        .source_date
+        .map(|date| date.format(&Rfc2822))
+        .transpose()
+        .kind(ErrorKind::Date)?;
+    let mut namespaces = BTreeMap::new();
+    if config.debug_source {
+        namespaces.insert(RSSPLS_PREFIX.to_string(), RSSPLS_NAMESPACE.to_string());
+    }
+    if let Some(ArchiveConfig {
+        placement: ArchivePlacement::Link,
+        ..
+    }) = &config.archive
+    {
+        namespaces.insert(
+            archive::ATOM_PREFIX.to_string(),
+            archive::ATOM_NAMESPACE.to_string(),
+        );
+    }
+    let image = channel_config.image.as_ref().map(|image| {
+        ImageBuilder::default()
+            .url(url.join(image).map_or_else(|_| image.clone(), String::from))
+            .title(&channel_config.title)
+            .link(url.to_string())
+            .build()
+    });
+    let mut channel = ChannelBuilder::default()
+        .namespaces(namespaces)
         .title(&channel_config.title)
         .link(url.to_string())
-        .generator(Some(crate::version_string()))
+        .description(channel_config.description.clone().unwrap_or_default())
+        .language(channel_config.language.clone())
+        .image(image)
+        .ttl(channel_config.ttl.map(|ttl| ttl.to_string()))
+        .copyright(channel_config.copyright.clone())
+        .generator(generator)
+        .last_build_date(last_build_date)
         .items(items)
         .build();
 
+    let splits = match &config.split_by {
+        Some(split_by) => split_channel(&mut channel, split_by),
+        None => Vec::new(),
+    };
+
     Ok(ProcessResult::Ok {
         channel,
+        splits,
         headers: serialised_headers,
+        new_items,
+        failed_assertions,
     })
 }
 
-async fn fetch_webpage(
+/// Register the items written by the last run with the deduplicated feeds when they're kept as
+/// the page hasn't changed
+fn register_deduped(client: &Client, channel_config: &ChannelConfig, state: &FeedState) {
+    if let Some(dedup) = &client.dedup {
+        let items: Vec<_> = state.deduped.iter().cloned().map(Item::from).collect();
+        dedup.register(channel_config, &items);
+    }
+}
+
+/// Store the items from the Wayback Machine's snapshots of `urls` since `from` in `state`
+///
+/// This only happens once for each `from` date. If it fails it's tried again on the next run.
+async fn backfill(
     client: &Client,
-    url: &Url,
-    cached_headers: &Option<HeaderMap>,
     channel_config: &ChannelConfig,
     config_hash: ConfigHash<'_>,
-) -> eyre::Result<FetchResult> {
-    if url.scheme() == "file" {
-        if client.file_urls {
-            fetch_webpage_local(url).await
-        } else {
-            bail!("unable to fetch: {url} as file URLs are not enabled in config")
+    from: Date,
+    urls: &[Url],
+    state: &mut FeedState,
+    now: OffsetDateTime,
+) {
+    let from_date = from.to_string();
+    if state.wayback_from.as_ref() == Some(&from_date) {
+        return;
+    }
+    let mut items = Vec::new();
+    for url in urls {
+        match snapshot_items(client, channel_config, config_hash, from, url, now).await {
+            Ok(found) => items.extend(found),
+            Err(report) => {
+                warn!(
+                    "{:?}",
+                    report.wrap_err(format!("unable to backfill {url} from the Wayback Machine"))
+                );
+                return;
+            }
         }
-    } else {
-        fetch_webpage_http(client, url, cached_headers, channel_config, config_hash).await
     }
+
+    let mut guids = BTreeSet::new();
+    items.retain(|item| match item.guid() {
+        Some(guid) => guids.insert(guid.value().to_string()),
+        None => true,
+    });
+    items.truncate(MAX_BACKFILL_ITEMS);
+    info!(
+        "backfilled {} items for {} from the Wayback Machine",
+        items.len(),
+        channel_config.name()
+    );
+    state.backfill = items.iter().map(StoredItem::from).collect();
+    state.wayback_from = Some(from_date);
 }
 
-async fn fetch_webpage_http(
+/// Extract the items from the Wayback Machine's snapshots of `url` since `from`, newest first
+async fn snapshot_items(
+    client: &Client,
+    channel_config: &ChannelConfig,
+    config_hash: ConfigHash<'_>,
+    from: Date,
+    url: &Url,
+    now: OffsetDateTime,
+) -> eyre::Result<Vec<Item>> {
+    let index_url = wayback::index_url(url, from)?;
+    let index = fetch_document(client, &index_url, channel_config, config_hash).await?;
+    let snapshots = wayback::snapshots(&index)?;
+    info!(
+        "extracting items from {} snapshots of {url}",
+        snapshots.len()
+    );
+
+    let mut items = Vec::new();
+    for snapshot in snapshots {
+        let html = match fetch_document(client, &snapshot, channel_config, config_hash).await {
+            Ok(html) => html,
+            Err(report) => {
+                warn!("{report:?}");
+                continue;
+            }
+        };
+        // Links in the snapshot are as they were on the original page
+        match extract_page(client, channel_config, config_hash, html, url.clone(), now).await {
+            Ok(found) => items.extend(found),
+            Err(err) => warn!(
+                "{:?}",
+                eyre::Report::from(err)
+                    .wrap_err(format!("unable to extract items from {snapshot}"))
+            ),
+        }
+    }
+    Ok(items)
+}
+
+/// Fetch a page listing items, sharing the response with other feeds that request it in the same
+/// way
+async fn fetch_listing(
     client: &Client,
     url: &Url,
     cached_headers: &Option<HeaderMap>,
     channel_config: &ChannelConfig,
     config_hash: ConfigHash<'_>,
 ) -> eyre::Result<FetchResult> {
-    let config = &channel_config.config;
+    if !client.shared.is_shared(url.as_str()) {
+        return fetch_webpage(client, url, cached_headers, channel_config, config_hash).await;
+    }
 
-    let req = add_headers(
-        client.http.get(url.clone()),
-        cached_headers,
-        &channel_config.user_agent,
+    // Feeds only share a response if they would make the same request
+    let validator = |name| {
+        cached_headers
+            .as_ref()
+            .and_then(|headers| headers.get(name))
+            .and_then(|value| value.to_str().ok())
+    };
+    let cookie = client
+        .cookies
+        .as_ref()
+        .and_then(|cookies| cookies.header(url))
+        .and_then(|cookie| cookie.to_str().map(str::to_string).ok());
+    let auth = client
+        .auth
+        .as_ref()
+        .and_then(|auth| auth.header(url))
+        .and_then(|auth| auth.to_str().map(str::to_string).ok());
+    let key = format!(
+        "{url} {:?}",
+        (
+            &channel_config.user_agent,
+            &channel_config.fallback_user_agent,
+            &channel_config.accept,
+            &channel_config.accept_language,
+            // The values of secrets are left out of their debug output
+            channel_config
+                .headers
+                .iter()
+                .map(|(name, value)| (name, value.expose().ok()))
+                .collect::<Vec<_>>(),
+            &channel_config.compression,
+            (channel_config.fetcher, channel_config.ignore_cache_control),
+            channel_config.render,
+            &channel_config.render_command,
+            (cookie, auth),
+            validator(ETAG),
+            validator(LAST_MODIFIED),
+        )
     );
+    let fetch = client.shared.get_or_insert_with(key, || {
+        // What's downloaded is counted for each of the feeds using the response below
+        let client = Client {
+            downloaded: Arc::default(),
+            ..client.clone()
+        };
+        let url = url.clone();
+        let cached_headers = cached_headers.clone();
+        let channel_config = channel_config.clone();
+        let config_hash = config_hash.0.to_string();
+        async move {
+            let result = fetch_webpage(
+                &client,
+                &url,
+                &cached_headers,
+                &channel_config,
+                ConfigHash(&config_hash),
+            )
+            .await
+            .map_err(|report| format!("{report:#}"));
+            (result, client.downloaded.load(Ordering::Relaxed))
+        }
+        .boxed()
+    });
+    debug!("sharing the response from {url} with other feeds");
+    let (result, downloaded) = fetch.await;
+    client.downloaded.fetch_add(downloaded, Ordering::Relaxed);
+    result.map_err(|err| eyre!(err))
+}
 
-    let resp = req
-        .send()
-        .await
-        .wrap_err_with(|| format!("unable to fetch {}", url))?;
-
-    // Check response
-    let status = resp.status();
-    if status == StatusCode::NOT_MODIFIED {
-        // Cache hit, nothing to do
-        info!("{} is unmodified", url);
-        return Ok(FetchResult::NotModified);
-    }
+/// Extract the items from a fetched page, skipping those older than `ignore_older_than` at `now`
+async fn extract_page(
+    client: &Client,
+    channel_config: &ChannelConfig,
+    config_hash: ConfigHash<'_>,
+    html: String,
+    url: Url,
+    now: OffsetDateTime,
+) -> Result<Vec<Item>, FeedError> {
+    let config = &channel_config.config;
+    let (html, url) = if config.follow_frames || config.frame.is_some() {
+        follow_frames(client, channel_config, config_hash, html, url)
+            .await
+            .kind(ErrorKind::Fetch)?
+    } else {
+        (html, url)
+    };
 
-    if !status.is_success() {
-        return Err(eyre!(
-            "failed to fetch {}: {} {}",
-            config.url,
-            status.as_str(),
-            status.canonical_reason().unwrap_or("Unknown Status")
-        ));
+    if config.source_type == SourceType::Html && config.respect_meta_robots == Some(true) {
+        if let Some(directive) = meta_robots_opt_out(&html) {
+            info!("not extracting items from {url} as it's marked {directive}");
+            return Ok(Vec::new());
+        }
     }
 
-    if config.link.is_none() {
-        info!(
-            "no explicit link selector provided, falling back to heading selector: {:?}",
-            config.heading
-        );
+    let items = match config.source_type {
+        SourceType::Html => match (&config.discover, &config.json_field) {
+            (Some(discover), _) => discover_items(
+                client,
+                channel_config,
+                config_hash,
+                discover,
+                &html,
+                &url,
+                now,
+            )
+            .await
+            .kind(ErrorKind::Selector),
+            (None, Some(path)) => json::html_field(&html, path)
+                .kind(ErrorKind::Parse)
+                .and_then(|fragment| {
+                    extract_items(config, &fragment, &url, now).kind(ErrorKind::Selector)
+                }),
+            (None, None) => extract_items(config, &html, &url, now).kind(ErrorKind::Selector),
+        },
+        SourceType::Json => json::extract_items(config, &html, &url, now)
+            .kind(ErrorKind::Parse)
+            .map(|items| process_feed_items(config, items, &url)),
+        SourceType::Feed => syndication::parse_items(&html)
+            .wrap_err_with(|| format!("unable to parse feed from {url}"))
+            .kind(ErrorKind::Parse)
+            .map(|items| process_feed_items(config, items, &url)),
+    };
+    match items {
+        Ok(items) if !items.is_empty() => Ok(items),
+        result => {
+            if let Some(dir) = &client.failed_html_dir {
+                save_failed_html(dir, channel_config, &html);
+            }
+            result
+        }
     }
+}
 
-    // Collect the headers for later
-    let headers: Vec<_> = resp
-        .headers()
-        .iter()
-        .filter_map(|(name, value)| value.to_str().ok().map(|val| (name.as_str(), val)))
-        .collect();
-    let map = RequestCacheWrite {
-        headers,
-        version: crate::version(),
-        config_hash,
+/// Digest of the content of the element matching `selector` in `html`, and the config
+///
+/// The config is included so that the items are extracted again when it changes.
+fn hash_region(selector: &str, html: &str, config_hash: ConfigHash<'_>) -> Option<String> {
+    let doc = kuchiki::parse_html().one(html);
+    let Ok(region) = doc.select_first(selector) else {
+        warn!("region {selector} does not match anything");
+        return None;
     };
-    let serialised_headers = toml::to_string(&map)
-        .map_err(|err| warn!("unable to serialise headers: {}", err))
-        .ok();
-
-    // Read body
-    let html = resp.text().await.wrap_err("unable to read response body")?;
+    let mut context = Blake2b::new(16);
+    context.input(config_hash.0.as_bytes());
+    context.input(region.as_node().to_string().as_bytes());
+    Some(context.result_str())
+}
 
-    Ok(FetchResult::Ok {
-        html,
-        headers: serialised_headers,
-    })
+/// Follow frames in a page, returning the document that contains the items and its URL
+async fn follow_frames(
+    client: &Client,
+    channel_config: &ChannelConfig,
+    config_hash: ConfigHash<'_>,
+    mut html: String,
+    mut url: Url,
+) -> eyre::Result<(String, Url)> {
+    let selector = channel_config
+        .config
+        .frame
+        .as_deref()
+        .unwrap_or(DEFAULT_FRAME_SELECTOR);
+    for _ in 0..MAX_FRAME_DEPTH {
+        let Some(frame_url) = frame_url(&html, &url, selector) else {
+            break;
+        };
+        info!("following frame {frame_url}");
+        html = fetch_document(client, &frame_url, channel_config, config_hash).await?;
+        url = frame_url;
+    }
+    Ok((html, url))
 }
 
-async fn fetch_webpage_local(url: &Url) -> eyre::Result<FetchResult> {
-    let path = url
-        .to_file_path()
-        .map_err(|()| eyre!("unable to extract path from: {}", url))?;
-    debug!("read {}", path.display());
-    let html = task::spawn_blocking(move || {
-        fs::read_to_string(&path).wrap_err_with(|| format!("error reading {}", path.display()))
-    })
-    .await
-    .wrap_err_with(|| format!("error joining task for {url}"))??;
+/// Find the URL of the frame matching `selector` in `html`
+fn frame_url(html: &str, url: &Url, selector: &str) -> Option<Url> {
+    linked_url(html, url, selector, "src")
+}
 
-    Ok(FetchResult::Ok {
-        html,
-        headers: None,
-    })
+/// Find the URL of the next page linked to by the element matching `selector` in `html`
+fn next_page_url(html: &str, url: &Url, selector: &str) -> Option<Url> {
+    linked_url(html, url, selector, "href")
 }
 
-fn process_item(
-    config: &FeedConfig,
-    item: NodeDataRef<ElementData>,
-    link_selector: &str,
-    base_url: &url::ParseOptions,
-) -> eyre::Result<Item> {
-    let title = item
-        .as_node()
-        .select_first(&config.heading)
-        .map_err(|()| eyre!("invalid selector for heading: {}", config.heading))?;
-    let link = item
-        .as_node()
-        .select_first(link_selector)
-        .map_err(|()| eyre!("invalid selector for link: {}", link_selector))?;
-    // TODO: Need to make links absolute (probably ones in content too)
-    let attrs = link.attributes.borrow();
-    let link_url = attrs
-        .get("href")
-        .ok_or_else(|| eyre!("element selected as link has no 'href' attribute"))?;
-    let title_text = title.text_contents();
-    let description = extract_description(config, &item, &title_text)?;
-    let date = extract_pub_date(config, &item)?;
-    let guid = GuidBuilder::default()
-        .value(link_url)
-        .permalink(false)
-        .build();
+/// Resolve the URL in `attribute` of the first element matching `selector` in `html`
+fn linked_url(html: &str, url: &Url, selector: &str, attribute: &str) -> Option<Url> {
+    let doc = kuchiki::parse_html().one(html);
+    let element = doc.select_first(selector).ok()?;
+    let attrs = element.attributes.borrow();
+    let value = attrs.get(attribute)?;
+    url.join(value)
+        .map_err(|err| warn!("unable to parse URL {value}: {err}"))
+        .ok()
+        .filter(|link| may_follow(link, url))
+}
 
-    let mut rss_item_builder = ItemBuilder::default();
-    rss_item_builder
-        .title(title_text)
-        .link(base_url.parse(link_url).ok().map(|u| u.to_string()))
-        .guid(Some(guid))
-        .pub_date(date.map(|date| date.format(&Rfc2822).unwrap()))
-        .description(description);
+/// Whether `link`, found on the page at `page`, can be fetched
+///
+/// Remote pages can only lead to other remote pages, so that a scraped page can't make rsspls
+/// read local files when `file_urls` is enabled.
+fn may_follow(link: &Url, page: &Url) -> bool {
+    let follow = matches!(link.scheme(), "http" | "https")
+        || (link.scheme() == "file" && page.scheme() == "file");
+    if !follow {
+        debug!("not following {link} from {page}");
+    }
+    follow
+}
 
-    // Media enclosure
-    if let Some(media_selector) = &config.media {
-        debug!("checking for media matching {media_selector}");
-        let media = item
-            .as_node()
-            .select_first(media_selector)
-            .map_err(|()| eyre!("invalid selector for media: {}", media_selector))?;
+/// Fetch a page with a conditional request, using its own cache entry rather than the feed's
+///
+/// This is used for the pages linked from the listing, which usually don't change even when the
+/// listing does. Without a page cache directory it behaves like `fetch_document`.
+async fn fetch_cached_document(
+    client: &Client,
+    url: &Url,
+    channel_config: &ChannelConfig,
+    config_hash: ConfigHash<'_>,
+) -> eyre::Result<String> {
+    let Some(dir) = &client.page_cache_dir else {
+        return fetch_document(client, url, channel_config, config_hash).await;
+    };
+    let (cached_headers, cached_body) = match cache::read_page(dir, url, config_hash) {
+        Some((headers, body)) => (Some(headers), Some(body)),
+        None => (None, None),
+    };
+    match fetch_webpage(client, url, &cached_headers, channel_config, config_hash).await? {
+        FetchResult::Ok { html, headers } => {
+            if let Some(headers) = headers {
+                if let Err(report) = cache::write_page(dir, url, &headers, &html) {
+                    warn!("unable to cache {url}: {report:?}");
+                }
+            }
+            Ok(html)
+        }
+        FetchResult::NotModified => {
+            if let Err(report) = cache::touch_page(dir, url) {
+                warn!("unable to update cache entry for {url}: {report:?}");
+            }
+            cached_body.ok_or_else(|| eyre!("unexpected Not Modified response for {url}"))
+        }
+    }
+}
 
-        let media_attrs = media.attributes.borrow();
-        let media_url = media_attrs
-            .get("src")
-            .or_else(|| media_attrs.get("href"))
-            .ok_or_else(|| eyre!("element selected as media has no 'src' or 'href' attribute"))?;
+/// Fetch a page that isn't subject to caching, such as the contents of a frame
+pub async fn fetch_document(
+    client: &Client,
+    url: &Url,
+    channel_config: &ChannelConfig,
+    config_hash: ConfigHash<'_>,
+) -> eyre::Result<String> {
+    match fetch_webpage(client, url, &None, channel_config, config_hash).await? {
+        FetchResult::Ok { html, .. } => Ok(html),
+        FetchResult::NotModified => bail!("unexpected Not Modified response for {url}"),
+    }
+}
 
-        let parsed_url = base_url
-            .parse(media_url)
-            .map_err(|e| eyre!("media enclosure url invalid: {e}"))?;
+/// A copy of `client` that sends the cookies and credentials of the feed, if it has any
+///
+/// This logs in to the site when the feed has a login form.
+pub async fn with_credentials(
+    client: &Client,
+    channel_config: &ChannelConfig,
+    urls: &[Url],
+) -> Result<Option<Client>, FeedError> {
+    let headers = (!channel_config.headers.is_empty())
+        .then(|| CustomHeaders::new(&channel_config.headers, urls).map(Arc::new))
+        .transpose()
+        .wrap_err("unable to set up headers")
+        .kind(ErrorKind::Fetch)?;
+    let mut cookies = None;
+    if let Some(from) = &channel_config.cookies_from {
+        let loaded = cookies::load(from)
+            .await
+            .wrap_err("unable to load cookies")
+            .kind(ErrorKind::Fetch)?;
+        cookies = Some(loaded);
+    }
+    if let (Some(header_cookies), Some(url)) = (&channel_config.cookies, urls.first()) {
+        let header_cookies = header_cookies
+            .expose()
+            .wrap_err("unable to read cookies")
+            .kind(ErrorKind::Fetch)?;
+        cookies
+            .get_or_insert_with(CookieJar::default)
+            .add_header_cookies(url, header_cookies);
+    }
+    if let Some(login) = &channel_config.login {
+        let client = Client {
+            headers: headers.clone(),
+            ..client.clone()
+        };
+        let logged_in = login::log_in(&client, channel_config, login)
+            .await
+            .wrap_err_with(|| format!("unable to log in at {}", login.url))
+            .kind(ErrorKind::Fetch)?;
+        cookies
+            .get_or_insert_with(CookieJar::default)
+            .extend(logged_in);
+    }
+    let auth = channel_config
+        .config
+        .auth
+        .as_ref()
+        .map(|auth| Authorization::new(auth, urls))
+        .transpose()
+        .wrap_err("unable to set up authentication")
+        .kind(ErrorKind::Fetch)?;
+    if headers.is_none() && cookies.is_none() && auth.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(Client {
+        headers,
+        cookies: cookies.map(Arc::new),
+        auth: auth.map(Arc::new),
+        ..client.clone()
+    }))
+}
 
-        // Guessing the MIME type from the url as we don't have the full media
-        let media_mime_type = parsed_url
-            .path_segments()
-            .and_then(|segments| segments.last())
-            .map(|media_filename| mime_guess::from_path(media_filename).first_or_octet_stream())
-            .unwrap_or_else(|| mime::APPLICATION_OCTET_STREAM);
+/// Parse `html` and extract the RSS items from it
+///
+/// This is kept separate from the async code as the parsed document is not `Send`.
+fn extract_items(
+    config: &FeedConfig,
+    html: &str,
+    url: &Url,
+    now: OffsetDateTime,
+) -> eyre::Result<Vec<Item>> {
+    let link = LinkSource::from_config(config).unwrap_or_else(|| {
+        LinkSource::Selector(
+            config
+                .heading
+                .first()
+                .map_or("", |heading| heading.selector.as_str()),
+        )
+    });
 
-        let mut enclosure_bld = EnclosureBuilder::default();
-        enclosure_bld.url(parsed_url.to_string());
-        enclosure_bld.mime_type(media_mime_type.to_string());
-        // "When an enclosure's size cannot be determined, a publisher should use a length of 0."
-        // https://www.rssboard.org/rss-profile#element-channel-item-enclosure
-        enclosure_bld.length("0".to_string());
+    let doc = kuchiki::parse_html().one(html);
+    if config.expand_templates {
+        expand_templates(&doc);
+    }
+    let base_url = Url::options().base_url(Some(url));
+    rewrite_urls(&doc, &base_url)?;
 
-        rss_item_builder.enclosure(Some(enclosure_bld.build()));
+    let mut items = Vec::new();
+    for item in doc
+        .select(&config.item)
+        .map_err(|()| eyre!("invalid selector for item: {}", config.item))?
+    {
+        match process_item(config, item, link, &base_url, now) {
+            Ok(Some(rss_item)) => items.push(rss_item),
+            Ok(None) => {}
+            Err(err) => {
+                let report = eyre::Report::from(err).wrap_err(format!(
+                    "unable to process RSS item matching '{}'",
+                    config.item
+                ));
+                error!("{report:?}");
+            }
+        }
     }
 
-    Ok(rss_item_builder.build())
+    Ok(items)
 }
 
-fn rewrite_urls(doc: &NodeRef, base_url: &url::ParseOptions) -> eyre::Result<()> {
-    for el in doc
-        .select("*[href]")
-        .map_err(|()| eyre!("unable to select links for rewriting"))?
-    {
-        let mut attrs = el.attributes.borrow_mut();
-        attrs.get_mut("href").and_then(|href| {
+/// Save the fetched page to `dir` so that extraction failures can be investigated later
+fn save_failed_html(dir: &Path, channel_config: &ChannelConfig, html: &str) {
+    let stem = channel_config.name();
+    let timestamp = OffsetDateTime::now_utc()
+        .format(format_description!(
+            "[year][month][day]T[hour][minute][second]Z"
+        ))
+        .unwrap_or_default();
+    let path = dir.join(format!("{stem}-{timestamp}.html"));
+    match fs::create_dir_all(dir).and_then(|()| fs::write(&path, html)) {
+        Ok(()) => warn!("saved fetched page to {}", path.display()),
+        Err(err) => warn!("unable to save fetched page to {}: {err}", path.display()),
+    }
+}
+
+/// Run the items from a feed source through the same clean up as items extracted from HTML
+fn process_feed_items(config: &FeedConfig, mut items: Vec<Item>, url: &Url) -> Vec<Item> {
+    for item in &mut items {
+        let base_url = Url::options().base_url(Some(url));
+        let link = item
+            .link()
+            .and_then(|link| base_url.parse(link).ok())
+            .unwrap_or_else(|| url.clone());
+        item.set_link(Some(link.to_string()));
+
+        let Some(description) = item.description() else {
+            continue;
+        };
+        let doc = kuchiki::parse_html().one(description);
+        let Ok(body) = doc.select_first("body") else {
+            continue;
+        };
+        let base_url = Url::options().base_url(Some(&link));
+        if let Err(err) = rewrite_urls(body.as_node(), &base_url) {
+            warn!("{err}");
+        }
+        if config.normalize_text {
+            text::normalize_tree(body.as_node());
+        }
+        postprocess_description(config, body.as_node());
+
+        let mut description = Vec::new();
+        for child in body.as_node().children() {
+            if let Err(err) = child.serialize(&mut description) {
+                warn!("unable to serialise description: {err}");
+            }
+        }
+        // NOTE(unwrap): Should be safe as XML has to be legit Unicode)
+        item.set_description(Some(String::from_utf8(description).unwrap()));
+    }
+    items
+}
+
+/// Track the date of the newest item in `state` and warn if it's older than `stale_after`
+///
+/// Returns true if the feed is stale. Items without a date count as new when they are first seen.
+/// A feed that silently stops producing items usually means the selectors no longer match.
+fn check_stale(
+    channel_config: &ChannelConfig,
+    state: &mut FeedState,
+    items: &[Item],
+    now: OffsetDateTime,
+) -> bool {
+    let newest = items
+        .iter()
+        .filter_map(|item| match item.pub_date() {
+            Some(date) => OffsetDateTime::parse(date, &Rfc2822).ok(),
+            None => item
+                .guid()
+                .filter(|guid| !state.has_seen(guid.value()))
+                .map(|_| now),
+        })
+        .map(|date| date.unix_timestamp())
+        .max();
+    let newest = match (state.newest_item, newest) {
+        (Some(previous), Some(newest)) => previous.max(newest),
+        (previous, newest) => previous.or(newest).unwrap_or(now.unix_timestamp()),
+    };
+    state.newest_item = Some(newest);
+
+    let Some(stale_after) = channel_config.stale_after else {
+        return false;
+    };
+    let age = now.unix_timestamp().saturating_sub(newest);
+    let stale = age > stale_after.as_secs() as i64;
+    if stale {
+        warn!(
+            "feed '{}' hasn't had a new item in {}, its selectors may no longer match",
+            channel_config.title,
+            humantime::format_duration(Duration::from_secs(age as u64))
+        );
+    }
+    stale
+}
+
+/// Find the pages linked from the hub page `html` and extract an item from each of them
+async fn discover_items(
+    client: &Client,
+    channel_config: &ChannelConfig,
+    config_hash: ConfigHash<'_>,
+    discover: &DiscoverConfig,
+    html: &str,
+    url: &Url,
+    now: OffsetDateTime,
+) -> eyre::Result<Vec<Item>> {
+    let config = &channel_config.config;
+    let links = discover_links(discover, html, url)?;
+    info!("discovered {} pages on {url}", links.len());
+
+    let mut items = Vec::new();
+    for link in links {
+        let result = match fetch_cached_document(client, &link, channel_config, config_hash).await {
+            Ok(html) => extract_page_item(config, &html, &link, discover.canonical, now),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(Some(item)) => items.push(item),
+            Ok(None) => {}
+            Err(err) => error!(
+                "{:?}",
+                err.wrap_err(format!("unable to process discovered page {link}"))
+            ),
+        }
+    }
+    Ok(items)
+}
+
+/// Replace the description of each item with the content of the page it links to
+///
+/// The pages are fetched through the page cache so unchanged articles aren't downloaded again.
+/// Items whose page can't be fetched keep the description extracted from the listing.
+async fn fetch_item_content(
+    client: &Client,
+    channel_config: &ChannelConfig,
+    config_hash: ConfigHash<'_>,
+    url: &Url,
+    items: Vec<Item>,
+) -> Vec<Item> {
+    let config = &channel_config.config;
+    stream::iter(items)
+        .map(|mut item| async move {
+            let Some(link) = item
+                .link()
+                .and_then(|link| Url::parse(link).ok())
+                .filter(|link| may_follow(link, url))
+            else {
+                return item;
+            };
+            let result =
+                match fetch_cached_document(client, &link, channel_config, config_hash).await {
+                    Ok(html) => match (config.respect_meta_robots, meta_robots_opt_out(&html)) {
+                        (Some(true), Some(directive)) => {
+                            info!("not using the content of {link} as it's marked {directive}");
+                            return item;
+                        }
+                        _ => extract_content(config, &html, &link),
+                    },
+                    Err(err) => Err(err),
+                };
+            match result {
+                Ok(Some(content)) => item.set_description(Some(content)),
+                Ok(None) => warn!("no content found on {link}"),
+                Err(err) => warn!(
+                    "{:?}",
+                    err.wrap_err(format!("unable to fetch the content of {link}"))
+                ),
+            }
+            item
+        })
+        .buffered(ITEM_CONTENT_CONCURRENCY)
+        .collect()
+        .await
+}
+
+/// The directive in the robots meta tags of `html` that opts the page out of being scraped, if it
+/// has one
+fn meta_robots_opt_out(html: &str) -> Option<&'static str> {
+    let doc = kuchiki::parse_html().one(html);
+    let metas = doc.select("meta[name][content]").ok()?;
+    for meta in metas {
+        let attrs = meta.attributes.borrow();
+        if !attrs
+            .get("name")
+            .is_some_and(|name| name.trim().eq_ignore_ascii_case("robots"))
+        {
+            continue;
+        }
+        let content = attrs
+            .get("content")
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        for directive in content.split(',').map(str::trim) {
+            match directive {
+                "noindex" => return Some("noindex"),
+                "noarchive" => return Some("noarchive"),
+                // none is short for noindex, nofollow
+                "none" => return Some("noindex"),
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+/// Whether the `rel` attribute of `link` contains nofollow
+fn is_nofollow(link: &NodeDataRef<ElementData>) -> bool {
+    link.attributes.borrow().get("rel").is_some_and(|rel| {
+        rel.split_ascii_whitespace()
+            .any(|value| value.eq_ignore_ascii_case("nofollow"))
+    })
+}
+
+/// Extract the HTML of the content element of an item page
+fn extract_content(config: &FeedConfig, html: &str, url: &Url) -> eyre::Result<Option<String>> {
+    let doc = kuchiki::parse_html().one(html);
+    if config.expand_templates {
+        expand_templates(&doc);
+    }
+    let base_url = Url::options().base_url(Some(url));
+    rewrite_urls(&doc, &base_url)?;
+
+    let content = match &config.content {
+        Some(selector) => doc
+            .select_first(selector)
+            .map_err(|()| eyre!("content selector '{selector}' did not match anything"))?,
+        None => match DEFAULT_CONTENT_SELECTORS
+            .iter()
+            .find_map(|selector| doc.select_first(selector).ok())
+        {
+            Some(content) => content,
+            None => return Ok(None),
+        },
+    };
+    if config.normalize_text {
+        text::normalize_tree(content.as_node());
+    }
+    postprocess_description(config, content.as_node());
+
+    let mut description = Vec::new();
+    for child in content.as_node().children() {
+        child
+            .serialize(&mut description)
+            .wrap_err("unable to serialise content")?;
+    }
+    // NOTE(unwrap): Should be safe as XML has to be legit Unicode)
+    Ok(Some(String::from_utf8(description).unwrap()))
+}
+
+/// Collect the unique URLs of the links matching the discover selector
+fn discover_links(discover: &DiscoverConfig, html: &str, url: &Url) -> eyre::Result<Vec<Url>> {
+    let doc = kuchiki::parse_html().one(html);
+    let mut links: Vec<Url> = Vec::new();
+    for link in doc
+        .select(&discover.links)
+        .map_err(|()| eyre!("invalid selector for discover links: {}", discover.links))?
+    {
+        let attrs = link.attributes.borrow();
+        let Some(link) = attrs.get("href").and_then(|href| url.join(href).ok()) else {
+            continue;
+        };
+        if !may_follow(&link, url) {
+            continue;
+        }
+        if !links.contains(&link) {
+            links.push(link);
+        }
+        if links.len() == discover.limit {
+            break;
+        }
+    }
+    Ok(links)
+}
+
+/// Extract a single item from a discovered page, linking to the page itself unless there's a
+/// link selector
+///
+/// If `canonical` is set and the page declares a canonical URL that is used as the link to the
+/// page instead, so that pages reached through different URLs produce the same item.
+fn extract_page_item(
+    config: &FeedConfig,
+    html: &str,
+    url: &Url,
+    canonical: bool,
+    now: OffsetDateTime,
+) -> eyre::Result<Option<Item>> {
+    let doc = kuchiki::parse_html().one(html);
+    let canonical_url = if canonical {
+        canonical_url(&doc, url)
+    } else {
+        None
+    };
+    let url = canonical_url.as_ref().unwrap_or(url);
+    if config.expand_templates {
+        expand_templates(&doc);
+    }
+    let base_url = Url::options().base_url(Some(url));
+    rewrite_urls(&doc, &base_url)?;
+
+    let selector = if config.item.is_empty() {
+        "html"
+    } else {
+        &config.item
+    };
+    let item = doc
+        .select_first(selector)
+        .map_err(|()| eyre!("item selector '{selector}' did not match anything"))?;
+    let link = LinkSource::from_config(config).unwrap_or(LinkSource::Page(url));
+    process_item(config, item, link, &base_url, now).map_err(eyre::Report::from)
+}
+
+/// The URL in the `<link rel="canonical">` of `doc`, resolved against `url`
+fn canonical_url(doc: &NodeRef, url: &Url) -> Option<Url> {
+    let link = doc
+        .select("link[rel~=canonical][href]")
+        .ok()?
+        .find_map(|link| {
+            let attrs = link.attributes.borrow();
+            attrs
+                .get("href")
+                .and_then(|href| url.join(href.trim()).ok())
+        })?;
+    if link != *url {
+        debug!("using canonical URL {link} for {url}");
+    }
+    Some(link)
+}
+
+/// Combine the items of several pages into one list, newest first without duplicate links
+///
+/// The sort is stable so items without a date stay in page order after those with one.
+fn combine_items(items: Vec<Item>) -> Vec<Item> {
+    let mut links = BTreeSet::new();
+    let mut items: Vec<_> = items
+        .into_iter()
+        .filter(|item| match item.link() {
+            Some(link) => {
+                let new = links.insert(link.to_string());
+                if !new {
+                    debug!("skipping item with duplicate link {link}");
+                }
+                new
+            }
+            None => true,
+        })
+        .collect();
+    items.sort_by_cached_key(|item| {
+        Reverse(
+            item.pub_date()
+                .and_then(|date| OffsetDateTime::parse(date, &Rfc2822).ok()),
+        )
+    });
+    items
+}
+
+/// Sort `items` newest first, then by GUID and title, so their order is independent of the page
+fn sort_items(mut items: Vec<Item>) -> Vec<Item> {
+    items.sort_by_cached_key(|item| {
+        let date = item
+            .pub_date()
+            .and_then(|date| OffsetDateTime::parse(date, &Rfc2822).ok());
+        (
+            Reverse(date),
+            item.guid().map(|guid| guid.value().to_string()),
+            item.title().map(str::to_string),
+        )
+    });
+    items
+}
+
+/// Record the items seen this run in `state`, dropping previously seen ones if requested
+fn select_new_items(config: &FeedConfig, state: &mut FeedState, items: Vec<Item>) -> Vec<Item> {
+    let new_items = if config.new_items_only || config.digest.is_some() {
+        items
+            .iter()
+            .map(|item| !item.guid().is_some_and(|guid| state.has_seen(guid.value())))
+            .collect()
+    } else {
+        vec![true; items.len()]
+    };
+    state.record_seen(
+        items
+            .iter()
+            .filter_map(|item| item.guid())
+            .map(|guid| guid.value()),
+    );
+
+    items
+        .into_iter()
+        .zip(new_items)
+        .filter_map(|(item, new)| new.then_some(item))
+        .collect()
+}
+
+/// Add the items from the previous run that are no longer on the page, up to `keep` items in total
+fn keep_items(keep: usize, state: &mut FeedState, mut items: Vec<Item>) -> Vec<Item> {
+    let guids: BTreeSet<_> = items
+        .iter()
+        .filter_map(|item| item.guid().map(|guid| guid.value().to_string()))
+        .collect();
+    let room = keep.saturating_sub(items.len());
+    items.extend(
+        state
+            .kept
+            .iter()
+            .filter(|stored| {
+                stored
+                    .guid
+                    .as_ref()
+                    .is_some_and(|guid| !guids.contains(guid))
+            })
+            .take(room)
+            .cloned()
+            .map(Item::from),
+    );
+    state.kept = items.iter().take(keep).map(StoredItem::from).collect();
+    items
+}
+
+/// Record when each item was first seen in `state` and use it as the date of items without one
+fn date_first_seen(
+    state: &mut FeedState,
+    mut items: Vec<Item>,
+    now: OffsetDateTime,
+) -> eyre::Result<Vec<Item>> {
+    let mut first_seen = BTreeMap::new();
+    for item in &mut items {
+        let Some(guid) = item.guid().map(|guid| guid.value().to_string()) else {
+            continue;
+        };
+        let timestamp = state
+            .first_seen
+            .get(&guid)
+            .copied()
+            .unwrap_or_else(|| now.unix_timestamp());
+        if item.pub_date().is_none() {
+            let date = OffsetDateTime::from_unix_timestamp(timestamp)?.format(&Rfc2822)?;
+            item.set_pub_date(Some(date));
+        }
+        first_seen.insert(guid, timestamp);
+    }
+    // Only the items still in the feed are remembered
+    state.first_seen = first_seen;
+    Ok(items)
+}
+
+async fn fetch_webpage(
+    client: &Client,
+    url: &Url,
+    cached_headers: &Option<HeaderMap>,
+    channel_config: &ChannelConfig,
+    config_hash: ConfigHash<'_>,
+) -> eyre::Result<FetchResult> {
+    if url.scheme() == "file" {
+        if client.file_urls {
+            fetch_webpage_local(url).await
+        } else {
+            bail!("unable to fetch: {url} as file URLs are not enabled in config")
+        }
+    } else {
+        if let (false, Some(headers)) = (channel_config.ignore_cache_control, cached_headers) {
+            match cache::freshness(headers, OffsetDateTime::now_utc()) {
+                Freshness::Fresh(remaining) => {
+                    info!(
+                        "{url} is fresh for another {}s, not fetching",
+                        remaining.as_secs()
+                    );
+                    return Ok(FetchResult::NotModified);
+                }
+                Freshness::Stale => debug!("cached response from {url} is stale"),
+                Freshness::NoCache => debug!("cached response from {url} has to be revalidated"),
+                Freshness::Unknown => {
+                    debug!("cached response from {url} has no freshness lifetime")
+                }
+            }
+        }
+        client
+            .backoff
+            .check(url)
+            .wrap_err_with(|| format!("not fetching {url}"))?;
+        client.downloads.check()?;
+        let render_command = render::command(channel_config, url, client.proxy.as_deref());
+        let mut result = if let Some(command) = &render_command {
+            render::render(command)
+                .await
+                .wrap_err_with(|| format!("unable to render {url}"))
+                .map(|html| FetchResult::Ok {
+                    html,
+                    headers: None,
+                })
+        } else {
+            fetch_remote(client, url, cached_headers, channel_config, config_hash).await
+        };
+        if let (None, Some(fallback)) = (&render_command, &channel_config.fallback_user_agent) {
+            let forbidden = result.as_ref().err().is_some_and(|report| {
+                report
+                    .downcast_ref::<StatusError>()
+                    .is_some_and(|err| err.status == StatusCode::FORBIDDEN)
+            });
+            if forbidden && channel_config.user_agent.as_ref() != Some(fallback) {
+                warn!("{url} responded with 403 Forbidden, retrying with the fallback user agent");
+                let channel_config = ChannelConfig {
+                    user_agent: Some(fallback.clone()),
+                    ..channel_config.clone()
+                };
+                result =
+                    fetch_remote(client, url, cached_headers, &channel_config, config_hash).await;
+                if result.is_ok() {
+                    info!("fetched {url} with the fallback user agent");
+                }
+            }
+        }
+        match &result {
+            Ok(_) => client.backoff.succeeded(url),
+            // A renderer failing is more likely to be a problem with the renderer than the host
+            Err(_) if render_command.is_some() => {}
+            Err(report) => client.backoff.failed(
+                url,
+                report.downcast_ref::<StatusError>().map(|err| err.status),
+            ),
+        }
+        let result = result?;
+        // Only the page the renderer ends up with is known, not what it downloaded
+        if let (Some(_), FetchResult::Ok { html, .. }) = (&render_command, &result) {
+            download::record(client, html.len() as u64)
+                .wrap_err_with(|| format!("stopping after rendering {url}"))?;
+        }
+        Ok(result)
+    }
+}
+
+/// Fetch `url` with the fetcher set in `channel_config`
+async fn fetch_remote(
+    client: &Client,
+    url: &Url,
+    cached_headers: &Option<HeaderMap>,
+    channel_config: &ChannelConfig,
+    config_hash: ConfigHash<'_>,
+) -> eyre::Result<FetchResult> {
+    if channel_config.fetcher == Fetcher::Curl {
+        fetch_webpage_curl(client, url, cached_headers, channel_config, config_hash).await
+    } else {
+        fetch_webpage_http(client, url, cached_headers, channel_config, config_hash).await
+    }
+}
+
+async fn fetch_webpage_http(
+    client: &Client,
+    url: &Url,
+    cached_headers: &Option<HeaderMap>,
+    channel_config: &ChannelConfig,
+    config_hash: ConfigHash<'_>,
+) -> eyre::Result<FetchResult> {
+    let config = &channel_config.config;
+
+    let req = client.http.get(url.clone()).headers(request_headers(
+        client,
+        url,
+        cached_headers,
+        channel_config,
+    ));
+
+    let resp = download::send(client, req)
+        .await
+        .wrap_err_with(|| format!("unable to fetch {}", url))?;
+
+    // Check response
+    let status = resp.status();
+    if status == StatusCode::NOT_MODIFIED {
+        // Cache hit, nothing to do
+        info!("{} is unmodified", url);
+        return Ok(FetchResult::NotModified);
+    }
+
+    if !status.is_success() {
+        return Err(status_error(url, status));
+    }
+
+    if config.link.is_none() && config.source_type == SourceType::Html {
+        info!(
+            "no explicit link selector provided, falling back to heading selector: {:?}",
+            config.heading.first().map(|heading| &heading.selector)
+        );
+    }
+
+    // Encodings the client decodes are removed from the response headers by reqwest
+    match resp.headers().get(CONTENT_ENCODING) {
+        Some(encoding) => debug!("Content-Encoding (not decoded): {:?}", encoding),
+        None => debug!("response was not compressed or has been decoded"),
+    }
+
+    // Collect the headers for later
+    let serialised_headers = serialise_headers(resp.headers(), config_hash);
+
+    // Read body
+    let html = download::text(client, resp)
+        .await
+        .wrap_err_with(|| format!("unable to download {url}"))?;
+
+    Ok(FetchResult::Ok {
+        html,
+        headers: serialised_headers,
+    })
+}
+
+async fn fetch_webpage_curl(
+    client: &Client,
+    url: &Url,
+    cached_headers: &Option<HeaderMap>,
+    channel_config: &ChannelConfig,
+    config_hash: ConfigHash<'_>,
+) -> eyre::Result<FetchResult> {
+    let mut headers = request_headers(client, url, cached_headers, channel_config);
+    let options = curl::Options {
+        proxy: client.proxy.as_deref(),
+        // Like the HTTP client, no User-Agent is sent unless the feed sets one
+        user_agent: headers
+            .remove(USER_AGENT)
+            .and_then(|value| value.to_str().map(str::to_string).ok())
+            .unwrap_or_default(),
+        timeouts: client.timeouts,
+    };
+    let resp = curl::fetch(url, &headers, &options)
+        .await
+        .wrap_err_with(|| format!("unable to fetch {} with curl", url))?;
+    download::record(client, resp.size)
+        .wrap_err_with(|| format!("stopping after fetching {url}"))?;
+
+    if resp.status == StatusCode::NOT_MODIFIED {
+        info!("{} is unmodified", url);
+        return Ok(FetchResult::NotModified);
+    }
+    if !resp.status.is_success() {
+        return Err(status_error(url, resp.status));
+    }
+
+    Ok(FetchResult::Ok {
+        headers: serialise_headers(&resp.headers, config_hash),
+        html: resp.body,
+    })
+}
+
+/// The error for responses with an unsuccessful status
+#[derive(Debug)]
+struct StatusError {
+    url: Url,
+    status: StatusCode,
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to fetch {}: {} {}",
+            self.url,
+            self.status.as_str(),
+            self.status.canonical_reason().unwrap_or("Unknown Status")
+        )
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+fn status_error(url: &Url, status: StatusCode) -> eyre::Report {
+    eyre::Report::new(StatusError {
+        url: url.clone(),
+        status,
+    })
+}
+
+/// Serialise the headers of a response for the cache
+fn serialise_headers(headers: &HeaderMap, config_hash: ConfigHash<'_>) -> Option<String> {
+    let headers: Vec<_> = headers
+        .iter()
+        .filter_map(|(name, value)| value.to_str().ok().map(|val| (name.as_str(), val)))
+        .collect();
+    let map = RequestCacheWrite {
+        headers,
+        version: crate::version(),
+        config_hash,
+    };
+    toml::to_string(&map)
+        .map_err(|err| warn!("unable to serialise headers: {}", err))
+        .ok()
+}
+
+async fn fetch_webpage_local(url: &Url) -> eyre::Result<FetchResult> {
+    let path = url
+        .to_file_path()
+        .map_err(|()| eyre!("unable to extract path from: {}", url))?;
+    debug!("read {}", path.display());
+    let html = task::spawn_blocking(move || {
+        fs::read_to_string(&path).wrap_err_with(|| format!("error reading {}", path.display()))
+    })
+    .await
+    .wrap_err_with(|| format!("error joining task for {url}"))??;
+
+    Ok(FetchResult::Ok {
+        html,
+        headers: None,
+    })
+}
+
+/// Fetch the page at `location` given on the command line, which may be a URL or path to a
+/// local file, with `http`
+#[cfg(feature = "authoring")]
+pub async fn fetch_location(http: &reqwest::Client, location: &str) -> eyre::Result<(Url, String)> {
+    let url = match Url::parse(location) {
+        Ok(url) => url,
+        Err(_) => {
+            let path = fs::canonicalize(Path::new(location))
+                .wrap_err_with(|| format!("unable to find {location}"))?;
+            Url::from_file_path(&path)
+                .map_err(|()| eyre!("unable to build URL from path: {}", path.display()))?
+        }
+    };
+
+    let html = if url.scheme() == "file" {
+        let path = url
+            .to_file_path()
+            .map_err(|()| eyre!("unable to extract path from: {}", url))?;
+        fs::read_to_string(&path).wrap_err_with(|| format!("error reading {}", path.display()))?
+    } else {
+        let resp = http
+            .get(url.clone())
+            .send()
+            .await
+            .wrap_err_with(|| format!("unable to fetch {}", url))?;
+        let status = resp.status();
+        if !status.is_success() {
+            bail!("failed to fetch {}: {}", url, status);
+        }
+        resp.text().await.wrap_err("unable to read response body")?
+    };
+
+    Ok((url, html))
+}
+
+/// Where the link of an item comes from
+#[derive(Copy, Clone)]
+enum LinkSource<'a> {
+    /// The `href` of the first element in the item matching the selector
+    Selector(&'a str),
+    /// The `href` of the closest ancestor of the item, or the item itself, matching the selector
+    Closest(&'a str),
+    /// The page the item was extracted from
+    Page(&'a Url),
+}
+
+impl<'a> LinkSource<'a> {
+    /// The link source set in `config`, if there is one
+    fn from_config(config: &'a FeedConfig) -> Option<Self> {
+        config.link.as_ref().map(|link| match &link.closest {
+            Some(closest) => LinkSource::Closest(closest),
+            None => LinkSource::Selector(&link.selector),
+        })
+    }
+}
+
+fn process_item(
+    config: &FeedConfig,
+    item: NodeDataRef<ElementData>,
+    link: LinkSource<'_>,
+    base_url: &url::ParseOptions,
+    now: OffsetDateTime,
+) -> Result<Option<Item>, FeedError> {
+    // Captured before the item is normalised or its description is built, although its URLs
+    // have already been resolved against the page and any templates expanded
+    let source_html = config.debug_source.then(|| item.as_node().to_string());
+    if config.normalize_text {
+        text::normalize_tree(item.as_node());
+    }
+    let title_text = heading_text(config, &item)?;
+    let link_attribute = config
+        .link
+        .as_ref()
+        .and_then(|link| link.attribute.as_deref())
+        .unwrap_or("href");
+    let (link_url, nofollow) = match link {
+        LinkSource::Selector(link_selector) => {
+            let link = select_in(&item, link_selector)
+                .ok_or_else(|| no_match("link", link_selector, &item))?;
+            (href(&link, link_attribute)?, is_nofollow(&link))
+        }
+        LinkSource::Closest(link_selector) => {
+            let selectors = Selectors::compile(link_selector)
+                .map_err(|()| eyre!("invalid selector for link.closest: {link_selector}"))
+                .kind(ErrorKind::Selector)?;
+            let link = item
+                .as_node()
+                .inclusive_ancestors()
+                .elements()
+                .find(|element| selectors.matches(element))
+                .ok_or_else(|| no_match("link.closest", link_selector, &item))?;
+            (href(&link, link_attribute)?, is_nofollow(&link))
+        }
+        LinkSource::Page(url) => (url.to_string(), false),
+    };
+    if nofollow && config.respect_meta_robots == Some(true) {
+        debug!(
+            "skipping item '{}' as its link is nofollow",
+            title_text.trim()
+        );
+        return Ok(None);
+    }
+    let link_url = link_url.as_str();
+    let date = extract_pub_date(config, &item)?;
+    if let Some(date) = date {
+        if config.is_too_old(date, now) {
+            debug!(
+                "skipping item '{}' as its date ({date}) is older than ignore_older_than",
+                title_text.trim()
+            );
+            return Ok(None);
+        }
+    }
+    let fields = extract_fields(config, &item, &title_text);
+    if let Some(filter) = config
+        .filter
+        .iter()
+        .filter(|filter| !filter.field.is_empty())
+        .find(|filter| !filter_matches(filter, fields.get(&filter.field)))
+    {
+        debug!(
+            "skipping item '{}' as its {} field doesn't match the filter",
+            title_text.trim(),
+            filter.field
+        );
+        return Ok(None);
+    }
+    let description =
+        extract_description(config, &item, &title_text, &fields).kind(ErrorKind::Parse)?;
+    let title_text = match &config.title_template {
+        Some(title_template) => render_title(config, title_template, &title_text, &fields),
+        None => title_text,
+    };
+    let pub_date = date
+        .map(|date| date.format(&Rfc2822))
+        .transpose()
+        .kind(ErrorKind::Date)?;
+    let guid = match &config.guid_prefix {
+        Some(prefix) => format!("{prefix}{link_url}"),
+        None => link_url.to_string(),
+    };
+    let guid = GuidBuilder::default().value(guid).permalink(false).build();
+
+    let mut rss_item_builder = ItemBuilder::default();
+    rss_item_builder
+        .title(title_text.clone())
+        .link(base_url.parse(link_url).ok().map(|u| u.to_string()))
+        .guid(Some(guid))
+        .pub_date(pub_date)
+        .description(description);
+
+    let split_value = config.split_by.as_ref().and_then(|split_by| {
+        let value = item
+            .as_node()
+            .select_first(&split_by.selector)
+            .map(|node| node.text_contents().trim().to_string())
+            .ok()
+            .filter(|value| !value.is_empty());
+        if value.is_none() {
+            debug!(
+                "split_by selector '{}' did not match anything for item '{}'",
+                split_by.selector,
+                title_text.trim()
+            );
+            debug_item_html(&item);
+        }
+        value
+    });
+
+    if let Some(author) = config
+        .author
+        .as_deref()
+        .and_then(|selector| select_in(&item, selector))
+        .map(|author| author.text_contents().trim().to_string())
+        .filter(|author| !author.is_empty())
+    {
+        rss_item_builder.author(author);
+    }
+    for category in item_categories(config, &item) {
+        rss_item_builder.category(CategoryBuilder::default().name(category).build());
+    }
+    if let Some(selector) = &config.comments {
+        let comments = select_in(&item, selector).and_then(|comments| {
+            let href = comments.attributes.borrow().get("href")?.to_string();
+            base_url.parse(&href).ok()
+        });
+        match comments {
+            Some(comments) => {
+                rss_item_builder.comments(comments.to_string());
+            }
+            None => debug!(
+                "comments selector '{selector}' did not match a link for item '{}'",
+                title_text.trim()
+            ),
+        }
+    }
+
+    // Media enclosure
+    if let Some(media_config) = &config.media {
+        let media_selector = &media_config.selector;
+        debug!("checking for media matching {media_selector}");
+        let media = item
+            .as_node()
+            .select_first(media_selector)
+            .map_err(|()| no_match("media", media_selector, &item))?;
+
+        let media_attrs = media.attributes.borrow();
+        let media_url = match &media_config.attribute {
+            Some(attribute) => media_attrs
+                .get(attribute.as_str())
+                .ok_or_else(|| eyre!("element selected as media has no '{attribute}' attribute")),
+            None => media_attrs
+                .get("src")
+                .or_else(|| media_attrs.get("href"))
+                .ok_or_else(|| eyre!("element selected as media has no 'src' or 'href' attribute")),
+        }
+        .kind(ErrorKind::Selector)?;
+
+        let parsed_url = base_url
+            .parse(media_url)
+            .map_err(|e| eyre!("media enclosure url invalid: {e}"))
+            .kind(ErrorKind::Parse)?;
+
+        // Guessing the MIME type from the url as we don't have the full media
+        let media_mime_type = parsed_url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(|media_filename| mime_guess::from_path(media_filename).first_or_octet_stream())
+            .unwrap_or_else(|| mime::APPLICATION_OCTET_STREAM);
+
+        let mut enclosure_bld = EnclosureBuilder::default();
+        enclosure_bld.url(parsed_url.to_string());
+        enclosure_bld.mime_type(media_mime_type.to_string());
+        // "When an enclosure's size cannot be determined, a publisher should use a length of 0."
+        // https://www.rssboard.org/rss-profile#element-channel-item-enclosure
+        enclosure_bld.length("0".to_string());
+
+        rss_item_builder.enclosure(Some(enclosure_bld.build()));
+    }
+
+    let mut elements = BTreeMap::new();
+    if let Some(html) = source_html {
+        let source = ExtensionBuilder::default()
+            .name(format!("{RSSPLS_PREFIX}:source"))
+            .value(Some(html))
+            .build();
+        elements.insert("source".to_string(), vec![source]);
+    }
+    // The value to split the feed by is kept in an element that's removed by `split_channel`
+    if let Some(value) = split_value {
+        let split = ExtensionBuilder::default()
+            .name(format!("{RSSPLS_PREFIX}:{SPLIT_ELEMENT}"))
+            .value(Some(value))
+            .build();
+        elements.insert(SPLIT_ELEMENT.to_string(), vec![split]);
+    }
+    if !elements.is_empty() {
+        rss_item_builder.extensions(BTreeMap::from([(RSSPLS_PREFIX.to_string(), elements)]));
+    }
+
+    Ok(Some(rss_item_builder.build()))
+}
+
+/// Split `channel` into a channel per value extracted by the `split_by` selector
+///
+/// The values are removed from the items of `channel`, so they don't end up in any of the feeds.
+/// Items without a value are only kept in `channel`.
+pub fn split_channel(channel: &mut Channel, split_by: &SplitConfig) -> Vec<(String, Channel)> {
+    let mut groups: BTreeMap<String, (String, Vec<Item>)> = BTreeMap::new();
+    for item in channel.items_mut() {
+        let Some(value) = take_split_value(item) else {
+            continue;
+        };
+        let slug = text::slugify(&value);
+        if slug.is_empty() {
+            continue;
+        }
+        groups
+            .entry(slug)
+            .or_insert_with(|| (value, Vec::new()))
+            .1
+            .push(item.clone());
+    }
+
+    groups
+        .into_iter()
+        .map(|(slug, (value, items))| {
+            let filename = template::render(&split_by.filename_template, |name| {
+                (name == "value").then(|| slug.clone())
+            });
+            let mut split = channel.clone();
+            split.set_title(format!("{}: {}", channel.title(), value));
+            split.set_items(items);
+            (filename, split)
+        })
+        .collect()
+}
+
+/// Remove the value extracted by the `split_by` selector from `item`
+fn take_split_value(item: &mut Item) -> Option<String> {
+    let extensions = &mut item.extensions;
+    let elements = extensions.get_mut(RSSPLS_PREFIX)?;
+    let split = elements.remove(SPLIT_ELEMENT);
+    if elements.is_empty() {
+        extensions.remove(RSSPLS_PREFIX);
+    }
+    split?.into_iter().next()?.value
+}
+
+/// The text of the heading of `item`
+///
+/// When there are several heading selectors the trimmed text of each one that matches is joined
+/// with the separator.
+fn heading_text(config: &FeedConfig, item: &NodeDataRef<ElementData>) -> Result<String, FeedError> {
+    if let [selector] = config.heading.as_slice() {
+        return select_value(item, selector)
+            .ok_or_else(|| no_match("heading", &selector.selector, item));
+    }
+
+    let parts: Vec<_> = config
+        .heading
+        .iter()
+        .filter_map(|selector| select_value(item, selector))
+        .map(|heading| heading.trim().to_string())
+        .filter(|text| !text.is_empty())
+        .collect();
+    if parts.is_empty() {
+        let selectors: Vec<_> = config
+            .heading
+            .iter()
+            .map(|heading| heading.selector.as_str())
+            .collect();
+        return Err(no_match("heading", &selectors.join(", "), item));
+    }
+    Ok(parts.join(&config.heading_separator))
+}
+
+/// The text of each element in `item` matched by the category selectors, without duplicates
+fn item_categories(config: &FeedConfig, item: &NodeDataRef<ElementData>) -> Vec<String> {
+    let mut categories: Vec<String> = Vec::new();
+    for selector in &config.category {
+        let elements: Vec<_> = if selector.trim() == SCOPE {
+            vec![item.clone()]
+        } else {
+            item.as_node()
+                .select(selector)
+                .map(|elements| elements.collect())
+                .unwrap_or_default()
+        };
+        for element in elements {
+            let category = element.text_contents().trim().to_string();
+            if !category.is_empty() && !categories.contains(&category) {
+                categories.push(category);
+            }
+        }
+    }
+    categories
+}
+
+/// The `href`, or other `attribute`, of the element selected as an item's link
+fn href(link: &NodeDataRef<ElementData>, attribute: &str) -> Result<String, FeedError> {
+    let attrs = link.attributes.borrow();
+    attrs
+        .get(attribute)
+        .map(str::to_string)
+        .ok_or_else(|| eyre!("element selected as link has no '{attribute}' attribute"))
+        .kind(ErrorKind::Selector)
+}
+
+/// The text of the first element in `item` matched by `selector`, or the value of its attribute
+///
+/// An element without the attribute is treated as not matching.
+fn select_value(item: &NodeDataRef<ElementData>, selector: &SelectorConfig) -> Option<String> {
+    let element = select_in(item, &selector.selector)?;
+    match &selector.attribute {
+        Some(attribute) => element
+            .attributes
+            .borrow()
+            .get(attribute.as_str())
+            .map(str::to_string),
+        None => Some(element.text_contents()),
+    }
+}
+
+/// The first element in `item` matching `selector`, or the item itself if `selector` is
+/// `:scope`
+fn select_in(item: &NodeDataRef<ElementData>, selector: &str) -> Option<NodeDataRef<ElementData>> {
+    if selector.trim() == SCOPE {
+        return Some(item.clone());
+    }
+    item.as_node().select_first(selector).ok()
+}
+
+/// Build the error for when `selector` for `field` doesn't match anything within `item`
+fn no_match(field: &str, selector: &str, item: &NodeDataRef<ElementData>) -> FeedError {
+    debug_item_html(item);
+    FeedError::new(
+        ErrorKind::Selector,
+        eyre!("{field} selector '{selector}' did not match anything"),
+    )
+}
+
+/// Log a truncated snippet of the item's HTML to help diagnose selectors that don't match
+fn debug_item_html(item: &NodeDataRef<ElementData>) {
+    const MAX_SNIPPET_LEN: usize = 500;
+
+    if !log_enabled!(Level::Debug) {
+        return;
+    }
+    let html = item.as_node().to_string();
+    let mut end = html.len().min(MAX_SNIPPET_LEN);
+    while !html.is_char_boundary(end) {
+        end -= 1;
+    }
+    let ellipsis = if end < html.len() { "…" } else { "" };
+    debug!("item HTML: {}{ellipsis}", &html[..end]);
+}
+
+pub fn rewrite_urls(doc: &NodeRef, base_url: &url::ParseOptions) -> eyre::Result<()> {
+    for el in doc
+        .select("*[href]")
+        .map_err(|()| eyre!("unable to select links for rewriting"))?
+    {
+        let mut attrs = el.attributes.borrow_mut();
+        attrs.get_mut("href").and_then(|href| {
             let mut url = base_url.parse(href).ok().map(|url| url.to_string())?;
             mem::swap(href, &mut url);
             Some(())
         });
     }
 
-    Ok(())
+    Ok(())
+}
+
+/// Selector for the item element itself
+pub const SCOPE: &str = ":scope";
+/// Prefix and URI of the namespace for rsspls extension elements
+const RSSPLS_PREFIX: &str = "rsspls";
+const RSSPLS_NAMESPACE: &str = "https://github.com/wezm/rsspls";
+/// Name of the extension element holding the value an item is split by
+const SPLIT_ELEMENT: &str = "split";
+/// Summary selector for the whole item element
+const SUMMARY_SELF: &str = "self";
+
+/// Frames that are followed when `follow_frames` is enabled without a `frame` selector
+///
+/// Iframes aren't followed by default as they are more often adverts and embeds than content.
+const DEFAULT_FRAME_SELECTOR: &str = "frame[src]";
+/// The maximum number of nested frames that are followed
+const MAX_FRAME_DEPTH: usize = 3;
+/// The maximum number of pages fetched with `next_page` when `max_pages` isn't set
+const DEFAULT_MAX_PAGES: usize = 10;
+/// The maximum number of items kept from the Wayback Machine
+const MAX_BACKFILL_ITEMS: usize = 200;
+/// Selectors tried in turn for the content of item pages when there's no `content` selector
+const DEFAULT_CONTENT_SELECTORS: &[&str] = &["article", "main", "body"];
+/// The number of item pages fetched at once by `fetch_item_content`
+const ITEM_CONTENT_CONCURRENCY: usize = 4;
+
+/// Headers whose values are always masked when logged
+const SENSITIVE_HEADERS: &[HeaderName] = &[AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION];
+
+/// Replace `<template>` elements, including declarative shadow DOM, with their contents
+///
+/// The parser keeps template contents out of the tree so they wouldn't otherwise be matched by
+/// selectors.
+pub fn expand_templates(doc: &NodeRef) {
+    // Templates nested in other templates only appear once their parent has been expanded
+    loop {
+        let templates: Vec<_> = match doc.select("template") {
+            Ok(templates) => templates.collect(),
+            Err(()) => return,
+        };
+        if templates.is_empty() {
+            return;
+        }
+        for template in templates {
+            if let Some(contents) = &template.template_contents {
+                for child in contents.children() {
+                    template.as_node().insert_before(child);
+                }
+            }
+            template.as_node().detach();
+        }
+    }
+}
+
+/// The headers sent when fetching a page for `channel_config`, including conditional request
+/// headers from the cache
+pub fn request_headers(
+    client: &Client,
+    url: &Url,
+    cached_headers: &Option<HeaderMap>,
+    channel_config: &ChannelConfig,
+) -> HeaderMap {
+    use reqwest::header::{ACCEPT, ACCEPT_LANGUAGE, IF_MODIFIED_SINCE, IF_NONE_MATCH};
+
+    let mut headers = HeaderMap::new();
+    let configured = [
+        (USER_AGENT, &channel_config.user_agent),
+        (ACCEPT, &channel_config.accept),
+        (ACCEPT_LANGUAGE, &channel_config.accept_language),
+    ];
+    for (name, value) in configured {
+        let Some(value) = value else {
+            continue;
+        };
+        match HeaderValue::from_str(value) {
+            Ok(value) => {
+                headers.insert(name, value);
+            }
+            Err(err) => warn!("ignoring invalid value for {name} header: {err}"),
+        }
+    }
+    if let Some(custom) = client
+        .headers
+        .as_ref()
+        .and_then(|headers| headers.headers(url))
+    {
+        headers.extend(custom.clone());
+    }
+
+    if let Some(value) = client.auth.as_ref().and_then(|auth| auth.header(url)) {
+        headers.insert(AUTHORIZATION, value);
+    }
+    if let Some(cookie) = client
+        .cookies
+        .as_ref()
+        .and_then(|cookies| cookies.header(url))
+    {
+        headers.insert(COOKIE, cookie);
+    }
+
+    if let Some(cached_headers) = cached_headers {
+        if let Some(last_modified) = cached_headers.get(LAST_MODIFIED) {
+            headers.insert(IF_MODIFIED_SINCE, last_modified.clone());
+        }
+        if let Some(etag) = cached_headers.get(ETAG) {
+            headers.insert(IF_NONE_MATCH, etag.clone());
+        }
+    }
+
+    log_headers(&headers, &channel_config.redact);
+    headers
+}
+
+/// Log the headers added to a request, masking sensitive values
+fn log_headers(headers: &HeaderMap, redact: &[String]) {
+    if !log_enabled!(Level::Debug) {
+        return;
+    }
+    for (name, value) in headers {
+        debug!("add {name}: {}", loggable_header_value(name, value, redact));
+    }
+}
+
+fn loggable_header_value(name: &HeaderName, value: &HeaderValue, redact: &[String]) -> String {
+    let redacted = SENSITIVE_HEADERS.contains(name)
+        || redact
+            .iter()
+            .any(|redact| name.as_str().eq_ignore_ascii_case(redact));
+    if redacted {
+        "[redacted]".to_string()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+fn extract_pub_date(
+    config: &FeedConfig,
+    item: &NodeDataRef<ElementData>,
+) -> Result<Option<OffsetDateTime>, FeedError> {
+    config
+        .date
+        .as_ref()
+        .map(|date| {
+            item.as_node()
+                .select_first(date.selector())
+                .map_err(|()| no_match("date", date.selector(), item))
+                .map(|node| parse_date(date, &node))
+        })
+        .transpose()
+        .map(Option::flatten)
+}
+
+fn parse_date(date: &DateConfig, node: &NodeDataRef<ElementData>) -> Option<OffsetDateTime> {
+    let attrs = node.attributes.borrow();
+    if let Some(attribute) = &date.attribute {
+        let Some(value) = attrs.get(attribute.as_str()) else {
+            warn!("date element has no '{attribute}' attribute");
+            return None;
+        };
+        let value = trim_date(value);
+        return date
+            .parse(value)
+            .map_err(|_err| {
+                warn!("unable to parse date '{}'", value);
+            })
+            .ok();
+    }
+    (&node.name.local == "time")
+        .then(|| attrs.get("datetime"))
+        .flatten()
+        .and_then(|datetime| {
+            debug!("trying datetime attribute");
+            date.parse(trim_date(datetime)).ok()
+        })
+        .inspect(|_| debug!("using datetime attribute"))
+        .or_else(|| {
+            let text = node.text_contents();
+            let text = trim_date(&text);
+            date.parse(text)
+                .map_err(|_err| {
+                    warn!("unable to parse date '{}'", text);
+                })
+                .ok()
+        })
+}
+
+// Trim non-alphanumeric chars from either side of the string
+fn trim_date(s: &str) -> &str {
+    s.trim_matches(|c: char| !c.is_alphanumeric())
+}
+
+/// The value of a named field of an item
+#[derive(Debug, PartialEq)]
+struct FieldValue {
+    /// The text of the field, formatted if it's a number
+    text: String,
+    number: Option<f64>,
+}
+
+/// Extract the text of the named fields of an item
+fn extract_fields(
+    config: &FeedConfig,
+    item: &NodeDataRef<ElementData>,
+    title: &str,
+) -> BTreeMap<String, FieldValue> {
+    let mut fields = BTreeMap::new();
+    for (name, field) in &config.fields {
+        let selector = &field.selector;
+        let Some(node) = select_in(item, selector) else {
+            debug!(
+                "selector '{selector}' for field '{name}' did not match anything for item '{}'",
+                title.trim()
+            );
+            debug_item_html(item);
+            continue;
+        };
+        let text = node.text_contents().trim().to_string();
+        let value = match field.type_ {
+            FieldType::Text => FieldValue { text, number: None },
+            FieldType::Number => match number::parse(&text, field.decimal_point) {
+                Some(value) => FieldValue {
+                    text: format!(
+                        "{}{}{}",
+                        field.prefix,
+                        number::format(value, field.decimals, field.thousands.as_deref()),
+                        field.suffix
+                    ),
+                    number: Some(value),
+                },
+                None => {
+                    warn!(
+                        "unable to parse a number from '{text}' for field '{name}' of item '{}'",
+                        title.trim()
+                    );
+                    continue;
+                }
+            },
+        };
+        fields.insert(name.clone(), value);
+    }
+    fields
+}
+
+/// Whether the value of a field meets the condition of `filter`, fields that didn't match
+/// anything never do
+fn filter_matches(filter: &FilterConfig, value: Option<&FieldValue>) -> bool {
+    let Some(value) = value else {
+        return false;
+    };
+    if filter.is_numeric() {
+        let Some(number) = value.number else {
+            return false;
+        };
+        let conditions = [
+            filter.lt.map(|lt| number < lt),
+            filter.le.map(|le| number <= le),
+            filter.gt.map(|gt| number > gt),
+            filter.ge.map(|ge| number >= ge),
+        ];
+        if conditions.contains(&Some(false)) {
+            return false;
+        }
+    }
+    if !filter.contains.is_empty() && !text::contains_keyword(&value.text, &filter.contains) {
+        return false;
+    }
+    if let Some(regex) = &filter.regex {
+        if !regex.is_match(&value.text) {
+            return false;
+        }
+    }
+    patterns_match(filter, &[&value.text])
+}
+
+/// Whether one of `texts` matches an include pattern of `filter`, and none match an exclude
+/// pattern
+fn patterns_match(filter: &FilterConfig, texts: &[&str]) -> bool {
+    let matches = |pattern: &Pattern| texts.iter().any(|text| pattern.is_match(text));
+    (filter.include.is_empty() || filter.include.iter().any(matches))
+        && !filter.exclude.iter().any(matches)
+}
+
+/// Whether an item meets the filters without a field, which apply to its title and link
+fn title_link_filters_match(config: &FeedConfig, item: &Item) -> bool {
+    let texts: Vec<_> = [item.title(), item.link()].into_iter().flatten().collect();
+    config
+        .filter
+        .iter()
+        .filter(|filter| filter.field.is_empty())
+        .all(|filter| patterns_match(filter, &texts))
+}
+
+/// Render the title template of an item, fields that didn't match are rendered as empty
+fn render_title(
+    config: &FeedConfig,
+    title_template: &str,
+    title: &str,
+    fields: &BTreeMap<String, FieldValue>,
+) -> String {
+    template::render(title_template, |name| match name {
+        "title" => Some(title.trim().to_string()),
+        name => config.fields.contains_key(name).then(|| {
+            fields
+                .get(name)
+                .map(|value| value.text.clone())
+                .unwrap_or_default()
+        }),
+    })
+}
+
+fn extract_description(
+    config: &FeedConfig,
+    item: &NodeDataRef<ElementData>,
+    title: &str,
+    fields: &BTreeMap<String, FieldValue>,
+) -> eyre::Result<Option<String>> {
+    let summary = extract_summary(config, item, title)?;
+    let Some(description_template) = &config.description_template else {
+        return Ok(summary);
+    };
+
+    let description = template::render(description_template, |name| match name {
+        "summary" => Some(summary.clone().unwrap_or_default()),
+        "title" => Some(text::escape_html(title.trim())),
+        // Fields that didn't match anything for this item are rendered as empty
+        name => config
+            .fields
+            .contains_key(name)
+            .then(|| fields.get(name).map(|value| text::escape_html(&value.text)))
+            .map(Option::unwrap_or_default),
+    });
+    Ok(Some(description))
+}
+
+fn extract_summary(
+    config: &FeedConfig,
+    item: &NodeDataRef<ElementData>,
+    title: &str,
+) -> eyre::Result<Option<String>> {
+    /// Part of the summary, either an element or the value of one of its attributes
+    enum Part {
+        Node(NodeDataRef<ElementData>),
+        Text(String),
+    }
+
+    let mut parts = Vec::new();
+    for summary in &config.summary {
+        let selector = &summary.selector;
+        let nodes: Vec<_> = if matches!(selector.trim(), SUMMARY_SELF | SCOPE) {
+            vec![item.clone()]
+        } else {
+            item.as_node()
+                .select(selector)
+                .map(|nodes| nodes.collect())
+                .unwrap_or_default()
+        };
+        if nodes.is_empty() {
+            warn!(
+                "summary selector '{selector}' for item with title '{}' did not match anything",
+                title.trim()
+            );
+            debug_item_html(item);
+        }
+        match &summary.attribute {
+            Some(attribute) => parts.extend(nodes.iter().filter_map(|node| {
+                node.attributes
+                    .borrow()
+                    .get(attribute.as_str())
+                    .map(|value| Part::Text(text::escape_html(value)))
+            })),
+            None => parts.extend(nodes.into_iter().map(Part::Node)),
+        }
+    }
+
+    let mut description = Vec::new();
+    for part in parts {
+        match part {
+            Part::Node(node) => {
+                // The rest of the item is extracted from the same elements, so they're left as is
+                let node = deep_copy(node.as_node());
+                postprocess_description(config, &node);
+                node.serialize(&mut description)
+                    .wrap_err("unable to serialise description")?
+            }
+            Part::Text(text) => description.extend_from_slice(text.as_bytes()),
+        }
+    }
+
+    if !description.is_empty() {
+        // NOTE(unwrap): Should be safe as XML has to be legit Unicode)
+        Ok(Some(String::from_utf8(description).unwrap()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Copy `node` and its descendants into a new tree without a parent
+fn deep_copy(node: &NodeRef) -> NodeRef {
+    let copy = NodeRef::new(node.data().clone());
+    for child in node.children() {
+        copy.append(deep_copy(&child));
+    }
+    copy
+}
+
+/// Apply transformations to a node that will be serialised into the description
+fn postprocess_description(config: &FeedConfig, node: &NodeRef) {
+    for selector in &config.remove {
+        let Ok(matches) = node.select(selector) else {
+            continue;
+        };
+        // Collect first so the iterator isn't invalidated by detaching, the node itself is kept
+        let matches: Vec<_> = matches.filter(|el| el.as_node() != node).collect();
+        for el in matches {
+            el.as_node().detach();
+        }
+    }
+    if config.summary_drop_heading {
+        for heading in &config.heading {
+            let Ok(matches) = node.select(&heading.selector) else {
+                continue;
+            };
+            let matches: Vec<_> = matches.filter(|el| el.as_node() != node).collect();
+            for el in matches {
+                // Also remove elements that only contained the heading, such as the `h2` of
+                // `h2 a`
+                let mut heading = el.as_node().clone();
+                while let Some(parent) = heading.parent().filter(|parent| parent != node) {
+                    let only_child =
+                        parent
+                            .children()
+                            .filter(|child| *child != heading)
+                            .all(|child| {
+                                child
+                                    .as_text()
+                                    .is_some_and(|text| text.borrow().trim().is_empty())
+                            });
+                    if !only_child || parent.as_element().is_none() {
+                        break;
+                    }
+                    heading = parent;
+                }
+                heading.detach();
+            }
+        }
+    }
+    if !config.highlight.is_empty() {
+        text::highlight_tree(node, &config.highlight);
+    }
 }
 
-fn add_headers(
-    mut req: RequestBuilder,
-    cached_headers: &Option<HeaderMap>,
-    user_agent: &Option<String>,
-) -> RequestBuilder {
-    use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, USER_AGENT};
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::{env, process};
+
+    use reqwest::Client as HttpClient;
+    use time::macros::datetime;
+
+    use super::*;
+    use crate::config::{FieldConfig, LinkConfig, RunIf};
+    use crate::output::Output;
+
+    const HTML: &str = include_str!("../tests/local.html");
+
+    struct RmOnDrop(PathBuf);
+
+    impl RmOnDrop {
+        fn new(path: PathBuf) -> Self {
+            RmOnDrop(path)
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for RmOnDrop {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn test_config() -> FeedConfig {
+        FeedConfig {
+            url: Vec::new(),
+            params_matrix: BTreeMap::new(),
+            source_type: SourceType::Html,
+            next_page: None,
+            max_pages: None,
+            json_field: None,
+            wayback: None,
+            region: None,
+            discover: None,
+            fetch_item_content: false,
+            content: None,
+            follow_frames: false,
+            frame: None,
+            expand_templates: false,
+            item: String::new(),
+            heading: Vec::new(),
+            heading_separator: " ".to_string(),
+            link: None,
+            summary: Vec::new(),
+            date: None,
+            media: None,
+            author: None,
+            category: Vec::new(),
+            comments: None,
+            normalize_text: false,
+            debug_source: false,
+            summary_drop_heading: false,
+            remove: Vec::new(),
+            highlight: Vec::new(),
+            transform_command: Vec::new(),
+            transform_url: None,
+            ignore_older_than: None,
+            new_items_only: false,
+            digest: None,
+            score: None,
+            archive: None,
+            assertions: None,
+            keep_items: None,
+            first_seen_dates: false,
+            respect_meta_robots: None,
+            auth: None,
+            split_by: None,
+            fields: BTreeMap::new(),
+            filter: Vec::new(),
+            title_template: None,
+            description_template: None,
+            guid_prefix: None,
+        }
+    }
+
+    #[test]
+    fn test_trim_date() {
+        assert_eq!(trim_date("2021-05-20 —"), "2021-05-20");
+        assert_eq!(
+            trim_date("2022-04-20T06:38:27+10:00"),
+            "2022-04-20T06:38:27+10:00"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_urls() {
+        let html = r#"<html><body><a href="/cool">cool thing</a> <div href="dont-do-this">ok</div><a href="http://example.com">example</a></body></html>"#;
+        let expected = r#"<html><head></head><body><a href="http://example.com/cool">cool thing</a> <div href="http://example.com/dont-do-this">ok</div><a href="http://example.com/">example</a></body></html>"#;
+        let doc = kuchiki::parse_html().one(html);
+        let base_url = "http://example.com".parse().unwrap();
+        let base = Url::options().base_url(Some(&base_url));
+        rewrite_urls(&doc, &base).unwrap();
+        let rewritten = doc.to_string();
+        assert_eq!(rewritten, expected);
+    }
+
+    #[test]
+    fn test_debug_source() {
+        let html = r#"<ul><li><a href="/one">One &amp; only</a></li></ul>"#;
+        let url = "http://example.com/".parse().unwrap();
+        let config = FeedConfig {
+            item: "li".to_string(),
+            heading: vec!["a".parse().unwrap()],
+            debug_source: true,
+            ..test_config()
+        };
+        let items = extract_items(&config, html, &url, OffsetDateTime::now_utc()).unwrap();
+        let source = &items[0].extensions()["rsspls"]["source"][0];
+        assert_eq!(
+            source.value(),
+            Some(r#"<li><a href="http://example.com/one">One &amp; only</a></li>"#)
+        );
+
+        let channel = ChannelBuilder::default()
+            .namespaces(BTreeMap::from([(
+                RSSPLS_PREFIX.to_string(),
+                RSSPLS_NAMESPACE.to_string(),
+            )]))
+            .items(items)
+            .build();
+        let xml = channel.to_string();
+        assert!(xml.contains(r#"xmlns:rsspls="https://github.com/wezm/rsspls""#));
+        assert!(xml.contains("<rsspls:source>&lt;li&gt;"));
+    }
+
+    #[test]
+    fn test_scope() {
+        let html = r#"<nav><a href="/one">One</a> <a href="/two"><span>Two</span></a></nav>"#;
+        let url = "http://example.com/".parse().unwrap();
+        let config = FeedConfig {
+            item: "nav a".to_string(),
+            heading: vec![SCOPE.parse().unwrap()],
+            ..test_config()
+        };
+        let items = extract_items(&config, html, &url, OffsetDateTime::now_utc()).unwrap();
+        let items: Vec<_> = items
+            .iter()
+            .map(|item| (item.title().unwrap(), item.link().unwrap()))
+            .collect();
+        assert_eq!(
+            items,
+            [
+                ("One", "http://example.com/one"),
+                ("Two", "http://example.com/two")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_link_closest() {
+        let html = r#"<a href="/one"><ul><li class="card">One</li></ul></a><a href="/two"><li class="card"><h3>Two</h3></li></a>"#;
+        let url = "http://example.com/".parse().unwrap();
+        let config = FeedConfig {
+            item: "li.card".to_string(),
+            heading: vec![":scope".parse().unwrap()],
+            link: Some(LinkConfig {
+                selector: String::new(),
+                closest: Some("a[href]".to_string()),
+                attribute: None,
+            }),
+            ..test_config()
+        };
+        let links: Vec<_> = extract_items(&config, html, &url, OffsetDateTime::now_utc())
+            .unwrap()
+            .iter()
+            .map(|item| item.link().unwrap().to_string())
+            .collect();
+        assert_eq!(links, ["http://example.com/one", "http://example.com/two"]);
+    }
+
+    #[test]
+    fn test_extract_description_multi() {
+        // Test CSS selector for description that matches multiple elements
+        let html = r#"<html><body><div class="item"><p>one</p><span>two</span></body></html>"#;
+        let doc = kuchiki::parse_html().one(html);
+        let item = doc.select_first(".item").unwrap();
+        let config = FeedConfig {
+            summary: vec!["span, p".parse().unwrap()],
+            ..test_config()
+        };
+
+        let description = extract_description(&config, &item, "title", &BTreeMap::new())
+            .unwrap()
+            .unwrap();
+
+        // Items come out in DOM order
+        assert_eq!(description, "<p>one</p><span>two</span>");
+    }
+
+    #[test]
+    fn test_extract_description_self() {
+        let html = r#"<html><body><div class="item"><h2><a href="/a">Title</a></h2><p>Body</p><button>Share</button></div></body></html>"#;
+        let doc = kuchiki::parse_html().one(html);
+        let item = doc.select_first(".item").unwrap();
+        let config: FeedConfig = toml::from_str(
+            r#"
+            url = "http://example.com/"
+            item = ".item"
+            heading = "h2"
+            summary = "self"
+            summary_exclude = "button"
+            "#,
+        )
+        .unwrap();
+
+        let description = extract_description(&config, &item, "title", &BTreeMap::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            description,
+            r#"<div class="item"><h2><a href="/a">Title</a></h2><p>Body</p></div>"#
+        );
+    }
+
+    #[test]
+    fn test_summary_drop_heading() {
+        let html = r#"<html><body><div class="item"><div class="card"><h2> <a href="/a">Title</a> </h2><p>Body</p></div></div></body></html>"#;
+        let doc = kuchiki::parse_html().one(html);
+        let item = doc.select_first(".item").unwrap();
+        let config = FeedConfig {
+            heading: vec!["h2 a".parse().unwrap()],
+            summary: vec![".card".parse().unwrap()],
+            summary_drop_heading: true,
+            ..test_config()
+        };
+
+        let description = extract_description(&config, &item, "title", &BTreeMap::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(description, r#"<div class="card"><p>Body</p></div>"#);
+    }
+
+    #[test]
+    fn test_extract_description_array() {
+        // Test CSS selector for description that matches multiple elements
+        let html = r#"<html><body><div class="item"><p>one</p><span>two</span></body></html>"#;
+        let doc = kuchiki::parse_html().one(html);
+        let item = doc.select_first(".item").unwrap();
+        let config = FeedConfig {
+            summary: vec!["span".parse().unwrap(), "p".parse().unwrap()],
+            ..test_config()
+        };
+
+        let description = extract_description(&config, &item, "title", &BTreeMap::new())
+            .unwrap()
+            .unwrap();
+
+        // Items come out in the order of the selector array
+        assert_eq!(description, "<span>two</span><p>one</p>");
+    }
+
+    #[test]
+    fn test_extract_description_template() {
+        let html = r#"<html><body><div class="item"><h2>Flat</h2><span class="price">$300 &amp; bills</span><p>Roomy</p></body></html>"#;
+        let doc = kuchiki::parse_html().one(html);
+        let item = doc.select_first(".item").unwrap();
+        let config = FeedConfig {
+            summary: vec!["p".parse().unwrap()],
+            fields: [("price".to_string(), ".price".parse().unwrap())].into(),
+            description_template: Some("<p>{price}</p>{summary}{missing}".to_string()),
+            ..test_config()
+        };
+
+        let fields = extract_fields(&config, &item, "Flat");
+        let description = extract_description(&config, &item, "Flat", &fields)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(description, "<p>$300 &amp; bills</p><p>Roomy</p>{missing}");
+    }
+
+    #[test]
+    fn test_number_fields() {
+        let html = r#"<html><body><div class="item"><h2>Flat</h2><span class="price">Now 1.250,5 €</span><span class="rooms">no rooms</span></body></html>"#;
+        let doc = kuchiki::parse_html().one(html);
+        let item = doc.select_first(".item").unwrap();
+        let price = FieldConfig {
+            type_: FieldType::Number,
+            decimal_point: ',',
+            decimals: Some(2),
+            thousands: Some(",".to_string()),
+            prefix: "€".to_string(),
+            ..".price".parse().unwrap()
+        };
+        let rooms = FieldConfig {
+            type_: FieldType::Number,
+            ..".rooms".parse().unwrap()
+        };
+        let config = FeedConfig {
+            fields: [("price".to_string(), price), ("rooms".to_string(), rooms)].into(),
+            ..test_config()
+        };
+
+        let fields = extract_fields(&config, &item, "Flat");
+        assert_eq!(
+            fields.get("price"),
+            Some(&FieldValue {
+                text: "€1,250.50".to_string(),
+                number: Some(1250.5)
+            })
+        );
+        assert_eq!(fields.get("rooms"), None);
+        assert_eq!(
+            render_title(&config, "{title} ({price}){rooms}", " Flat ", &fields),
+            "Flat (€1,250.50)"
+        );
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let filter = FilterConfig {
+            field: "price".to_string(),
+            lt: Some(500.0),
+            ..Default::default()
+        };
+        let value = |number| FieldValue {
+            text: String::new(),
+            number: Some(number),
+        };
+        assert!(filter_matches(&filter, Some(&value(499.0))));
+        assert!(!filter_matches(&filter, Some(&value(500.0))));
+        assert!(!filter_matches(&filter, None));
+
+        let filter = FilterConfig {
+            field: "location".to_string(),
+            contains: vec!["Berlin".to_string(), "Potsdam".to_string()],
+            ..Default::default()
+        };
+        let value = |text: &str| FieldValue {
+            text: text.to_string(),
+            number: None,
+        };
+        assert!(filter_matches(&filter, Some(&value("Berlin-Mitte"))));
+        assert!(filter_matches(&filter, Some(&value("near potsdam"))));
+        assert!(!filter_matches(&filter, Some(&value("Hamburg"))));
+    }
+
+    #[test]
+    fn test_title_link_filters_match() {
+        let config = FeedConfig {
+            filter: vec![
+                FilterConfig {
+                    include: vec![
+                        Pattern::text("road"),
+                        Pattern::Regex(regex::Regex::new("^Planning").unwrap()),
+                    ],
+                    ..Default::default()
+                },
+                FilterConfig {
+                    exclude: vec![Pattern::text("/archive/")],
+                    ..Default::default()
+                },
+            ],
+            ..test_config()
+        };
+        let item = |title: &str, link: &str| {
+            ItemBuilder::default()
+                .title(title.to_string())
+                .link(link.to_string())
+                .build()
+        };
+        let matches = |title, link| title_link_filters_match(&config, &item(title, link));
+        assert!(matches("Road closure", "http://example.com/notices/1"));
+        assert!(matches(
+            "Planning application",
+            "http://example.com/notices/2"
+        ));
+        assert!(matches("Notice", "http://example.com/roadworks/3"));
+        assert!(!matches("Bin collection", "http://example.com/notices/4"));
+        assert!(!matches("Road closure", "http://example.com/archive/5"));
+    }
+
+    #[test]
+    fn test_extract_description_highlight() {
+        let html = r#"<html><body><div class="item"><p>New <a href="/rust">Rust</a> release</p></body></html>"#;
+        let doc = kuchiki::parse_html().one(html);
+        let item = doc.select_first(".item").unwrap();
+        let config = FeedConfig {
+            summary: vec!["p".parse().unwrap()],
+            highlight: vec!["rust".to_string(), "release".to_string()],
+            ..test_config()
+        };
+
+        let description = extract_description(&config, &item, "title", &BTreeMap::new())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            description,
+            r#"<p>New <a href="/rust"><mark>Rust</mark></a> <mark>release</mark></p>"#
+        );
+    }
+
+    #[test]
+    fn test_process_feed_items() {
+        let config = FeedConfig {
+            remove: vec![".ad".to_string()],
+            ..test_config()
+        };
+        let item = ItemBuilder::default()
+            .link(Some("/posts/1".to_string()))
+            .description(Some(
+                r#"<p>See <a href="more">more</a></p><div class="ad">Buy</div>"#.to_string(),
+            ))
+            .build();
+        let url = "http://example.com/feed.xml".parse().unwrap();
+
+        let items = process_feed_items(&config, vec![item], &url);
+        assert_eq!(items[0].link(), Some("http://example.com/posts/1"));
+        assert_eq!(
+            items[0].description(),
+            Some(r#"<p>See <a href="http://example.com/posts/more">more</a></p>"#)
+        );
+    }
+
+    #[test]
+    fn test_ignore_older_than() {
+        let today = OffsetDateTime::now_utc().date();
+        let html = format!(
+            r#"<html><body>
+            <div class="item"><a href="/old">Old</a><time>2000-01-01</time></div>
+            <div class="item"><a href="/new">New</a><time>{today}</time></div>
+            </body></html>"#
+        );
+        let config = FeedConfig {
+            item: ".item".to_string(),
+            heading: vec!["a".parse().unwrap()],
+            date: Some("time".parse().unwrap()),
+            ignore_older_than: Some(std::time::Duration::from_secs(90 * 24 * 60 * 60)),
+            ..test_config()
+        };
+        let url = "http://example.com/".parse().unwrap();
+
+        let items = extract_items(&config, &html, &url, OffsetDateTime::now_utc()).unwrap();
+        let titles: Vec<_> = items.iter().filter_map(|item| item.title()).collect();
+        assert_eq!(titles, ["New"]);
+
+        // The cutoff is relative to the time of the run, such as SOURCE_DATE_EPOCH
+        let items = extract_items(&config, &html, &url, datetime!(2000-02-01 00:00 UTC)).unwrap();
+        assert_eq!(items.len(), 2);
+    }
+
+    #[test]
+    fn test_select_new_items() {
+        let item = |guid: &str| {
+            ItemBuilder::default()
+                .guid(Some(GuidBuilder::default().value(guid.to_string()).build()))
+                .build()
+        };
+        let config = FeedConfig {
+            new_items_only: true,
+            ..test_config()
+        };
+        let mut state = FeedState::default();
+
+        let items = select_new_items(&config, &mut state, vec![item("a"), item("b")]);
+        assert_eq!(items.len(), 2);
+
+        let items = select_new_items(&config, &mut state, vec![item("b"), item("c")]);
+        let guids: Vec<_> = items.iter().filter_map(|item| item.guid()).collect();
+        assert_eq!(
+            guids,
+            [&GuidBuilder::default().value("c".to_string()).build()]
+        );
+        assert_eq!(state.seen, ["b", "c", "a"]);
+
+        // Items that drop off the page and come back aren't new again
+        let items = select_new_items(&config, &mut state, vec![item("a"), item("d")]);
+        let guids: Vec<_> = items.iter().filter_map(|item| item.guid()).collect();
+        assert_eq!(
+            guids,
+            [&GuidBuilder::default().value("d".to_string()).build()]
+        );
+    }
+
+    #[test]
+    fn test_keep_items() {
+        let item = |guid: &str| {
+            ItemBuilder::default()
+                .guid(Some(GuidBuilder::default().value(guid.to_string()).build()))
+                .build()
+        };
+        let guids = |items: &[Item]| -> Vec<String> {
+            items
+                .iter()
+                .filter_map(|item| item.guid().map(|guid| guid.value().to_string()))
+                .collect()
+        };
+        let mut state = FeedState::default();
+
+        let items = keep_items(3, &mut state, vec![item("b"), item("a")]);
+        assert_eq!(guids(&items), ["b", "a"]);
+        let items = keep_items(3, &mut state, vec![item("d"), item("c")]);
+        assert_eq!(guids(&items), ["d", "c", "b"]);
+        let items = keep_items(3, &mut state, vec![item("e"), item("d")]);
+        assert_eq!(guids(&items), ["e", "d", "c"]);
+        assert_eq!(state.kept.len(), 3);
+    }
+
+    #[test]
+    fn test_date_first_seen() {
+        let item = |guid: &str, date: Option<&str>| {
+            ItemBuilder::default()
+                .guid(Some(GuidBuilder::default().value(guid.to_string()).build()))
+                .pub_date(date.map(str::to_string))
+                .build()
+        };
+        let mut state = FeedState::default();
+        let first_run = datetime!(2024-08-01 10:00 UTC);
+        let items = date_first_seen(&mut state, vec![item("a", None)], first_run).unwrap();
+        assert_eq!(items[0].pub_date(), Some("Thu, 01 Aug 2024 10:00:00 +0000"));
+
+        let items = vec![
+            item("b", None),
+            item("a", None),
+            item("c", Some("Fri, 02 Aug 2024 09:00:00 +0000")),
+        ];
+        let second_run = datetime!(2024-08-02 10:00 UTC);
+        let items = date_first_seen(&mut state, items, second_run).unwrap();
+        let dates: Vec<_> = items.iter().filter_map(Item::pub_date).collect();
+        assert_eq!(
+            dates,
+            [
+                "Fri, 02 Aug 2024 10:00:00 +0000",
+                "Thu, 01 Aug 2024 10:00:00 +0000",
+                "Fri, 02 Aug 2024 09:00:00 +0000"
+            ]
+        );
+        assert_eq!(state.first_seen.len(), 3);
+    }
+
+    #[test]
+    fn test_discover() {
+        let discover = DiscoverConfig {
+            links: "a.report".to_string(),
+            limit: 2,
+            canonical: true,
+        };
+        let url = "http://example.com/reports/".parse().unwrap();
+        let hub = r#"<a class="report" href="q1.html">Q1</a><a class="report" href="q1.html">Q1</a><a href="about.html">About</a><a class="report" href="/q2.html">Q2</a><a class="report" href="q3.html">Q3</a>"#;
+        let links: Vec<String> = discover_links(&discover, hub, &url)
+            .unwrap()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            links,
+            [
+                "http://example.com/reports/q1.html",
+                "http://example.com/q2.html"
+            ]
+        );
+
+        let config = FeedConfig {
+            heading: vec!["h1".parse().unwrap()],
+            summary: vec!["main p".parse().unwrap()],
+            guid_prefix: Some("rsspls:example:".to_string()),
+            ..test_config()
+        };
+        let page_url = "http://example.com/reports/q1.html".parse().unwrap();
+        let page = "<html><body><h1>Q1 Report</h1><main><p>Results</p></main></body></html>";
+        let item = extract_page_item(&config, page, &page_url, true, OffsetDateTime::now_utc())
+            .unwrap()
+            .unwrap();
+        assert_eq!(item.title(), Some("Q1 Report"));
+        assert_eq!(item.link(), Some("http://example.com/reports/q1.html"));
+        assert_eq!(item.description(), Some("<p>Results</p>"));
+        assert_eq!(
+            item.guid().map(|guid| guid.value()),
+            Some("rsspls:example:http://example.com/reports/q1.html")
+        );
 
-    if let Some(ua) = user_agent {
-        debug!("add User-Agent: {:?}", ua);
-        req = req.header(USER_AGENT, ua);
+        let page_url = "http://example.com/reports/q1.html?utm_source=hub"
+            .parse()
+            .unwrap();
+        let page = r#"<html><head><link rel="canonical" href="/reports/q1.html"></head><body><h1>Q1 Report</h1></body></html>"#;
+        let item = extract_page_item(&config, page, &page_url, true, OffsetDateTime::now_utc())
+            .unwrap()
+            .unwrap();
+        assert_eq!(item.link(), Some("http://example.com/reports/q1.html"));
+        assert_eq!(
+            item.guid().map(|guid| guid.value()),
+            Some("rsspls:example:http://example.com/reports/q1.html")
+        );
+        let item = extract_page_item(&config, page, &page_url, false, OffsetDateTime::now_utc())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            item.link(),
+            Some("http://example.com/reports/q1.html?utm_source=hub")
+        );
     }
 
-    let headers = match cached_headers {
-        Some(headers) => headers,
-        None => return req,
-    };
+    #[test]
+    fn test_extract_content() {
+        let url = "http://example.com/posts/1.html".parse().unwrap();
+        let page = r#"<html><body><nav>Menu</nav><article><p>Full <a href="2.html">text</a></p><aside>Ad</aside></article></body></html>"#;
+        let config = FeedConfig {
+            remove: vec!["aside".to_string()],
+            ..test_config()
+        };
+        assert_eq!(
+            extract_content(&config, page, &url).unwrap().as_deref(),
+            Some(r#"<p>Full <a href="http://example.com/posts/2.html">text</a></p>"#)
+        );
 
-    if let Some(last_modified) = headers.get(LAST_MODIFIED) {
-        debug!("add If-Modified-Since: {:?}", last_modified.to_str().ok());
-        req = req.header(IF_MODIFIED_SINCE, last_modified);
-    }
-    if let Some(etag) = headers.get(ETAG) {
-        debug!("add If-None-Match: {:?}", etag.to_str().ok());
-        req = req.header(IF_NONE_MATCH, etag);
+        let config = FeedConfig {
+            content: Some("nav".to_string()),
+            ..test_config()
+        };
+        assert_eq!(
+            extract_content(&config, page, &url).unwrap().as_deref(),
+            Some("Menu")
+        );
+        let config = FeedConfig {
+            content: Some(".missing".to_string()),
+            ..test_config()
+        };
+        assert!(extract_content(&config, page, &url).is_err());
     }
-    req
-}
 
-fn extract_pub_date(
-    config: &FeedConfig,
-    item: &NodeDataRef<ElementData>,
-) -> eyre::Result<Option<OffsetDateTime>> {
-    config
-        .date
-        .as_ref()
-        .map(|date| {
-            item.as_node()
-                .select_first(date.selector())
-                .map_err(|()| eyre!("invalid selector for date: {}", date.selector()))
-                .map(|node| parse_date(date, &node))
-        })
-        .transpose()
-        .map(Option::flatten)
-}
+    #[test]
+    fn test_meta_robots() {
+        assert_eq!(
+            meta_robots_opt_out(r#"<head><meta name="Robots" content="index, NoArchive"></head>"#),
+            Some("noarchive")
+        );
+        assert_eq!(
+            meta_robots_opt_out(r#"<meta name="robots" content="none">"#),
+            Some("noindex")
+        );
+        assert_eq!(
+            meta_robots_opt_out(r#"<meta name="googlebot" content="noindex">"#),
+            None
+        );
 
-fn parse_date(date: &DateConfig, node: &NodeDataRef<ElementData>) -> Option<OffsetDateTime> {
-    let attrs = node.attributes.borrow();
-    (&node.name.local == "time")
-        .then(|| attrs.get("datetime"))
-        .flatten()
-        .and_then(|datetime| {
-            debug!("trying datetime attribute");
-            date.parse(trim_date(datetime)).ok()
-        })
-        .map(|x| {
-            debug!("using datetime attribute");
-            x
-        })
-        .or_else(|| {
-            let text = node.text_contents();
-            let text = trim_date(&text);
-            date.parse(text)
-                .map_err(|_err| {
-                    warn!("unable to parse date '{}'", text);
-                })
-                .ok()
-        })
-}
+        let html = r#"<ul><li><a href="/one">One</a></li><li><a rel="ugc nofollow" href="/two">Two</a></li></ul>"#;
+        let url = "http://example.com/".parse().unwrap();
+        let config = FeedConfig {
+            item: "li".to_string(),
+            heading: vec!["a".parse().unwrap()],
+            ..test_config()
+        };
+        assert_eq!(
+            extract_items(&config, html, &url, OffsetDateTime::now_utc())
+                .unwrap()
+                .len(),
+            2
+        );
+        let config = FeedConfig {
+            respect_meta_robots: Some(true),
+            ..config
+        };
+        let links: Vec<_> = extract_items(&config, html, &url, OffsetDateTime::now_utc())
+            .unwrap()
+            .iter()
+            .map(|item| item.link().unwrap().to_string())
+            .collect();
+        assert_eq!(links, ["http://example.com/one"]);
+    }
 
-// Trim non-alphanumeric chars from either side of the string
-fn trim_date(s: &str) -> &str {
-    s.trim_matches(|c: char| !c.is_alphanumeric())
-}
+    #[test]
+    fn test_author_category_comments() {
+        let html = r#"<article><h2><a href="/one">One</a></h2><span class="by"> Ann </span>
+            <ul class="tags"><li>rust</li><li>news</li></ul><span class="section">news</span>
+            <a class="comments" href="/one#comments">3 comments</a></article>
+            <article><h2><a href="/two">Two</a></h2></article>"#;
+        let url = "http://example.com/".parse().unwrap();
+        let config = FeedConfig {
+            item: "article".to_string(),
+            heading: vec!["h2 a".parse().unwrap()],
+            author: Some(".by".to_string()),
+            category: vec![".tags li".to_string(), ".section".to_string()],
+            comments: Some("a.comments".to_string()),
+            ..test_config()
+        };
+        let items = extract_items(&config, html, &url, OffsetDateTime::now_utc()).unwrap();
+        assert_eq!(items[0].author(), Some("Ann"));
+        let categories: Vec<_> = items[0].categories().iter().map(|c| c.name()).collect();
+        assert_eq!(categories, ["rust", "news"]);
+        assert_eq!(items[0].comments(), Some("http://example.com/one#comments"));
+        assert_eq!(items[1].author(), None);
+        assert!(items[1].categories().is_empty());
+        assert_eq!(items[1].comments(), None);
+    }
 
-fn extract_description(
-    config: &FeedConfig,
-    item: &NodeDataRef<ElementData>,
-    title: &str,
-) -> eyre::Result<Option<String>> {
-    let mut description = Vec::new();
+    #[test]
+    fn test_author_category_comments_removed_from_summary() {
+        let html = r#"<article><h2><a href="/one">One</a></h2><p class="byline">Ann</p>
+            <p class="tags">rust</p><a class="comments" href="/one#comments">3 comments</a></article>"#;
+        let url = "http://example.com/".parse().unwrap();
+        let config = FeedConfig {
+            item: "article".to_string(),
+            heading: vec!["h2 a".parse().unwrap()],
+            summary: vec![":scope".parse().unwrap()],
+            remove: vec![
+                "h2".to_string(),
+                ".byline".to_string(),
+                ".tags".to_string(),
+                "a.comments".to_string(),
+            ],
+            author: Some(".byline".to_string()),
+            category: vec![".tags".to_string()],
+            comments: Some("a.comments".to_string()),
+            ..test_config()
+        };
+        let items = extract_items(&config, html, &url, OffsetDateTime::now_utc()).unwrap();
+        let description = items[0].description().unwrap();
+        assert!(!description.contains("Ann"));
+        assert!(!description.contains("rust"));
+        assert!(!description.contains("comments"));
+        assert_eq!(items[0].author(), Some("Ann"));
+        let categories: Vec<_> = items[0].categories().iter().map(|c| c.name()).collect();
+        assert_eq!(categories, ["rust"]);
+        assert_eq!(items[0].comments(), Some("http://example.com/one#comments"));
+    }
 
-    for selector in &config.summary {
-        let nodes = item
-            .as_node()
-            .select(selector)
-            .map_err(|()| {
-                warn!(
-                    "summary selector '{selector}' for item with title '{}' did not match anything",
-                    title.trim()
+    #[test]
+    fn test_attributes() {
+        let html = r#"<article data-href="/one" data-summary="Fish &amp; chips">
+            <a class="item" href="/ignored" title="One">Read more</a>
+            <span class="date" data-timestamp="2024-03-01T10:00:00Z">Yesterday</span>
+            <img data-src="/one.jpg" src="placeholder.gif"></article>
+            <article><a class="item" href="/two">Two</a></article>"#;
+        let url = "http://example.com/".parse().unwrap();
+        let config = FeedConfig {
+            item: "article".to_string(),
+            heading: vec![SelectorConfig {
+                selector: "a.item".to_string(),
+                attribute: Some("title".to_string()),
+            }],
+            link: Some(LinkConfig {
+                selector: ":scope".to_string(),
+                closest: None,
+                attribute: Some("data-href".to_string()),
+            }),
+            summary: vec![SelectorConfig {
+                selector: ":scope".to_string(),
+                attribute: Some("data-summary".to_string()),
+            }],
+            date: Some(
+                toml::from_str(
+                    r#"selector = ".date"
+                    attribute = "data-timestamp""#,
                 )
-            })
-            .ok();
-        let Some(nodes) = nodes else {
-            continue;
+                .unwrap(),
+            ),
+            media: Some(SelectorConfig {
+                selector: "img".to_string(),
+                attribute: Some("data-src".to_string()),
+            }),
+            ..test_config()
         };
-
-        for node in nodes {
-            node.as_node()
-                .serialize(&mut description)
-                .wrap_err("unable to serialise description")?
-        }
+        let items = extract_items(&config, html, &url, OffsetDateTime::now_utc()).unwrap();
+        // The second item has no title attribute so its heading doesn't match
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].title(), Some("One"));
+        assert_eq!(items[0].link(), Some("http://example.com/one"));
+        assert_eq!(items[0].description(), Some("Fish &amp; chips"));
+        assert_eq!(items[0].pub_date(), Some("Fri, 01 Mar 2024 10:00:00 +0000"));
+        assert_eq!(
+            items[0].enclosure().map(|enclosure| enclosure.url()),
+            Some("http://example.com/one.jpg")
+        );
     }
 
-    if !description.is_empty() {
-        // NOTE(unwrap): Should be safe as XML has to be legit Unicode)
-        Ok(Some(String::from_utf8(description).unwrap()))
-    } else {
-        Ok(None)
+    #[test]
+    fn test_summary_remove_keeps_item() {
+        let html = r#"<article><h2><a href="/one">One</a></h2>
+            <p>Body <span class="by">Jo</span><img src="/one.jpg"></p></article>"#;
+        let url = "http://example.com/".parse().unwrap();
+        let config = FeedConfig {
+            item: "article".to_string(),
+            heading: vec!["h2 a".parse().unwrap()],
+            summary: vec!["p".parse().unwrap()],
+            remove: vec![".by".to_string(), "img".to_string()],
+            author: Some(".by".to_string()),
+            media: Some("img".parse().unwrap()),
+            ..test_config()
+        };
+        let items = extract_items(&config, html, &url, OffsetDateTime::now_utc()).unwrap();
+        assert_eq!(items[0].description(), Some("<p>Body </p>"));
+        assert_eq!(items[0].author(), Some("Jo"));
+        assert_eq!(
+            items[0].enclosure().map(|enclosure| enclosure.url()),
+            Some("http://example.com/one.jpg")
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::path::{Path, PathBuf};
-    use std::{env, process};
+    #[test]
+    fn test_combine_items() {
+        let item = |link: &str, date: Option<&str>| {
+            ItemBuilder::default()
+                .link(Some(link.to_string()))
+                .pub_date(date.map(str::to_string))
+                .build()
+        };
+        let items = vec![
+            item("/a", None),
+            item("/b", Some("Mon, 01 Jan 2024 00:00:00 +0000")),
+            item("/c", None),
+            item("/d", Some("Wed, 03 Jan 2024 00:00:00 +0000")),
+            item("/b", Some("Mon, 01 Jan 2024 00:00:00 +0000")),
+            item("/e", Some("Tue, 02 Jan 2024 00:00:00 +0000")),
+        ];
+        let links: Vec<_> = combine_items(items)
+            .iter()
+            .map(|item| item.link().unwrap().to_string())
+            .collect();
+        assert_eq!(links, ["/d", "/e", "/b", "/a", "/c"]);
+    }
 
-    use reqwest::Client as HttpClient;
+    #[test]
+    fn test_sort_items() {
+        let item = |guid: &str, date: Option<&str>| {
+            ItemBuilder::default()
+                .guid(Some(GuidBuilder::default().value(guid).build()))
+                .pub_date(date.map(str::to_string))
+                .build()
+        };
+        let items = vec![
+            item("b", None),
+            item("c", Some("Mon, 01 Jan 2024 00:00:00 +0000")),
+            item("a", None),
+            item("d", Some("Tue, 02 Jan 2024 00:00:00 +0000")),
+        ];
+        let guids: Vec<_> = sort_items(items)
+            .iter()
+            .map(|item| item.guid().unwrap().value().to_string())
+            .collect();
+        assert_eq!(guids, ["d", "c", "a", "b"]);
+    }
 
-    use super::*;
+    #[test]
+    fn test_heading_text() {
+        let html = r#"<div class="issue"><span class="number">#42</span> <span class="title"> Crash on start </span></div>"#;
+        let config = FeedConfig {
+            item: ".issue".to_string(),
+            heading: vec![".number".parse().unwrap(), ".title".parse().unwrap()],
+            heading_separator: ": ".to_string(),
+            link: Some("a".parse().unwrap()),
+            ..test_config()
+        };
+        let doc = kuchiki::parse_html().one(html);
+        let item = doc.select_first(".issue").unwrap();
+        assert_eq!(heading_text(&config, &item).unwrap(), "#42: Crash on start");
 
-    const HTML: &str = include_str!("../tests/local.html");
+        let config = FeedConfig {
+            heading: vec![".title".parse().unwrap(), ".missing".parse().unwrap()],
+            ..config
+        };
+        assert_eq!(heading_text(&config, &item).unwrap(), "Crash on start");
+    }
 
-    struct RmOnDrop(PathBuf);
+    #[test]
+    fn test_frame_url() {
+        let html = r#"<frameset><frame name="nav" src="nav.html"><frame name="main" src="/list.html"></frameset>"#;
+        let url = "http://example.com/portal/".parse().unwrap();
+        assert_eq!(
+            frame_url(html, &url, DEFAULT_FRAME_SELECTOR).map(String::from),
+            Some("http://example.com/portal/nav.html".to_string())
+        );
+        assert_eq!(
+            frame_url(html, &url, r#"frame[name="main"]"#).map(String::from),
+            Some("http://example.com/list.html".to_string())
+        );
+        assert_eq!(
+            frame_url("<p>No frames</p>", &url, DEFAULT_FRAME_SELECTOR),
+            None
+        );
+        let embed =
+            r#"<iframe src="https://ads.example.net/banner"></iframe><ul><li>Post</li></ul>"#;
+        assert_eq!(frame_url(embed, &url, DEFAULT_FRAME_SELECTOR), None);
+    }
 
-    impl RmOnDrop {
-        fn new(path: PathBuf) -> Self {
-            RmOnDrop(path)
-        }
+    #[test]
+    fn test_next_page_url() {
+        let html = r#"<nav><a href="?page=1">1</a><a rel="next" href="?page=3">Next</a></nav>"#;
+        let url = "http://example.com/news?page=2".parse().unwrap();
+        assert_eq!(
+            next_page_url(html, &url, "a[rel=next]").map(String::from),
+            Some("http://example.com/news?page=3".to_string())
+        );
+        assert_eq!(next_page_url(html, &url, "a.missing"), None);
+    }
 
-        fn path(&self) -> &Path {
-            &self.0
-        }
+    #[test]
+    fn test_may_follow() {
+        let remote: Url = "https://example.com/news".parse().unwrap();
+        let local: Url = "file:///home/user/news.html".parse().unwrap();
+        let page: Url = "https://example.com/post".parse().unwrap();
+        let file: Url = "file:///etc/passwd".parse().unwrap();
+        assert!(may_follow(&page, &remote));
+        assert!(!may_follow(&file, &remote));
+        assert!(!may_follow(&"ftp://example.com/".parse().unwrap(), &remote));
+        assert!(may_follow(&file, &local));
+        assert!(may_follow(&page, &local));
+
+        let discover = DiscoverConfig {
+            links: "a".to_string(),
+            limit: 10,
+            canonical: false,
+        };
+        let hub = r#"<a href="/etc/passwd">Relative</a><a href="file:///etc/passwd">File</a>"#;
+        let links: Vec<String> = discover_links(&discover, hub, &remote)
+            .unwrap()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(links, ["https://example.com/etc/passwd"]);
+        assert_eq!(next_page_url(hub, &remote, r#"a[href^="file"]"#), None);
     }
 
-    impl Drop for RmOnDrop {
-        fn drop(&mut self) {
-            let _ = fs::remove_file(&self.0);
-        }
+    #[test]
+    fn test_hash_region() {
+        let hash = |html| hash_region("table", html, ConfigHash("a"));
+        let page = "<p>Visitors: 1</p><table><tr><td>One</td></tr></table>";
+        let later = "<p>Visitors: 2</p><table><tr><td>One</td></tr></table>";
+        let changed = "<p>Visitors: 2</p><table><tr><td>Two</td></tr></table>";
+        assert!(hash(page).is_some());
+        assert_eq!(hash(page), hash(later));
+        assert_ne!(hash(page), hash(changed));
+        assert_ne!(hash(page), hash_region("table", page, ConfigHash("b")));
+        assert_eq!(hash("<p>No table</p>"), None);
     }
 
-    fn test_config() -> FeedConfig {
-        FeedConfig {
-            url: String::new(),
-            item: String::new(),
-            heading: String::new(),
-            link: None,
-            summary: Vec::new(),
-            date: None,
-            media: None,
-        }
+    #[test]
+    fn test_expand_templates() {
+        let html = r#"<div id="host"><template shadowrootmode="open"><article><h2>One</h2><template><p>Two</p></template></article></template></div>"#;
+        let doc = kuchiki::parse_html().one(html);
+        assert!(doc.select_first("article").is_err());
+
+        expand_templates(&doc);
+        let host = doc.select_first("#host").unwrap();
+        let mut serialised = Vec::new();
+        host.as_node().serialize(&mut serialised).unwrap();
+        assert_eq!(
+            String::from_utf8(serialised).unwrap(),
+            r#"<div id="host"><article><h2>One</h2><p>Two</p></article></div>"#
+        );
     }
 
     #[test]
-    fn test_trim_date() {
-        assert_eq!(trim_date("2021-05-20 —"), "2021-05-20");
+    fn test_loggable_header_value() {
+        let value = HeaderValue::from_static("secret");
+        let redact = ["X-Api-Key".to_string()];
         assert_eq!(
-            trim_date("2022-04-20T06:38:27+10:00"),
-            "2022-04-20T06:38:27+10:00"
+            loggable_header_value(&AUTHORIZATION, &value, &redact),
+            "[redacted]"
+        );
+        assert_eq!(
+            loggable_header_value(&HeaderName::from_static("x-api-key"), &value, &redact),
+            "[redacted]"
+        );
+        assert_eq!(
+            loggable_header_value(&reqwest::header::ACCEPT, &value, &redact),
+            r#""secret""#
         );
     }
 
     #[test]
-    fn test_rewrite_urls() {
-        let html = r#"<html><body><a href="/cool">cool thing</a> <div href="dont-do-this">ok</div><a href="http://example.com">example</a></body></html>"#;
-        let expected = r#"<html><head></head><body><a href="http://example.com/cool">cool thing</a> <div href="http://example.com/dont-do-this">ok</div><a href="http://example.com/">example</a></body></html>"#;
-        let doc = kuchiki::parse_html().one(html);
-        let base_url = "http://example.com".parse().unwrap();
-        let base = Url::options().base_url(Some(&base_url));
-        rewrite_urls(&doc, &base).unwrap();
-        let rewritten = doc.to_string();
-        assert_eq!(rewritten, expected);
+    fn test_check_stale() {
+        let channel_config = ChannelConfig {
+            title: "Test".to_string(),
+            filename: "test.rss".to_string(),
+            name: None,
+            description: None,
+            language: None,
+            image: None,
+            ttl: None,
+            copyright: None,
+            user_agent: None,
+            fallback_user_agent: None,
+            accept: None,
+            accept_language: None,
+            headers: BTreeMap::new(),
+            cookies: None,
+            compression: None,
+            fetcher: Fetcher::Builtin,
+            ignore_cache_control: false,
+            render: None,
+            render_command: None,
+            redact: Vec::new(),
+            login: None,
+            cookies_from: None,
+            depends_on: None,
+            run_if: RunIf::Always,
+            dedup: None,
+            generator: None,
+            format: OutputFormat::Rss,
+            stale_after: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+            refresh: None,
+            config: test_config(),
+        };
+        let items = [ItemBuilder::default()
+            .pub_date(Some("Mon, 01 Jan 2024 00:00:00 +0000".to_string()))
+            .build()];
+        let mut state = FeedState::default();
+        let jan = OffsetDateTime::from_unix_timestamp(1_704_067_200).unwrap();
+
+        assert!(!check_stale(
+            &channel_config,
+            &mut state,
+            &items,
+            jan + Duration::from_secs(24 * 60 * 60)
+        ));
+        assert_eq!(state.newest_item, Some(jan.unix_timestamp()));
+        assert!(check_stale(
+            &channel_config,
+            &mut state,
+            &items,
+            jan + Duration::from_secs(60 * 24 * 60 * 60)
+        ));
     }
 
     #[test]
-    fn test_extract_description_multi() {
-        // Test CSS selector for description that matches multiple elements
-        let html = r#"<html><body><div class="item"><p>one</p><span>two</span></body></html>"#;
-        let doc = kuchiki::parse_html().one(html);
-        let item = doc.select_first(".item").unwrap();
+    fn test_split_channel() {
+        let html = r#"<html><body>
+            <div class="item"><a href="/a">A</a><span>World News</span></div>
+            <div class="item"><a href="/b">B</a><span>Sport</span></div>
+            <div class="item"><a href="/c">C</a><span>world news</span></div>
+            <div class="item"><a href="/d">D</a></div>
+            </body></html>"#;
+        let split_by = SplitConfig {
+            selector: "span".to_string(),
+            filename_template: "news-{value}.xml".to_string(),
+        };
         let config = FeedConfig {
-            summary: vec!["span, p".to_string()],
+            item: ".item".to_string(),
+            heading: vec!["a".parse().unwrap()],
+            split_by: Some(split_by),
             ..test_config()
         };
-
-        let description = extract_description(&config, &item, "title")
-            .unwrap()
-            .unwrap();
-
-        // Items come out in DOM order
-        assert_eq!(description, "<p>one</p><span>two</span>");
+        let url = "http://example.com/".parse().unwrap();
+        let items = extract_items(&config, html, &url, OffsetDateTime::now_utc()).unwrap();
+        let mut channel = ChannelBuilder::default().title("News").items(items).build();
+
+        let split = split_channel(&mut channel, config.split_by.as_ref().unwrap());
+        let summary: Vec<_> = split
+            .iter()
+            .map(|(filename, channel)| (filename.as_str(), channel.title(), channel.items().len()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("news-sport.xml", "News: Sport", 1),
+                ("news-world-news.xml", "News: World News", 2)
+            ]
+        );
+        // The values don't end up in any of the feeds
+        assert!(channel
+            .items()
+            .iter()
+            .all(|item| item.extensions().is_empty() && item.categories().is_empty()));
+        assert!(split[0].1.items()[0].extensions().is_empty());
     }
 
     #[test]
-    fn test_extract_description_array() {
-        // Test CSS selector for description that matches multiple elements
-        let html = r#"<html><body><div class="item"><p>one</p><span>two</span></body></html>"#;
-        let doc = kuchiki::parse_html().one(html);
-        let item = doc.select_first(".item").unwrap();
+    fn test_split_channel_categories() {
+        let html = r#"<html><body>
+            <div class="item"><a href="/a">A</a><span>Sport</span><b>Football</b></div>
+            <div class="item"><a href="/b">B</a><b>Tennis</b></div>
+            </body></html>"#;
         let config = FeedConfig {
-            summary: vec!["span".to_string(), "p".to_string()],
+            item: ".item".to_string(),
+            heading: vec!["a".parse().unwrap()],
+            category: vec!["b".to_string()],
+            split_by: Some(SplitConfig {
+                selector: "span".to_string(),
+                filename_template: "news-{value}.xml".to_string(),
+            }),
             ..test_config()
         };
-
-        let description = extract_description(&config, &item, "title")
-            .unwrap()
-            .unwrap();
-
-        // Items come out in the order of the selector array
-        assert_eq!(description, "<span>two</span><p>one</p>");
+        let url = "http://example.com/".parse().unwrap();
+        let items = extract_items(&config, html, &url, OffsetDateTime::now_utc()).unwrap();
+        let mut channel = ChannelBuilder::default().title("News").items(items).build();
+
+        // An item the split selector doesn't match isn't split by its categories
+        let split = split_channel(&mut channel, config.split_by.as_ref().unwrap());
+        assert_eq!(split.len(), 1);
+        assert_eq!(split[0].0, "news-sport.xml");
+        let categories = |item: &Item| -> Vec<String> {
+            item.categories()
+                .iter()
+                .map(|category| category.name().to_string())
+                .collect()
+        };
+        assert_eq!(categories(&split[0].1.items()[0]), ["Football"]);
+        assert_eq!(categories(&channel.items()[1]), ["Tennis"]);
     }
 
-    #[test]
-    fn test_process_local_html() {
-        let html_file_name = format!("rsspls.local.{}.html", process::id());
+    /// Generate a feed from the local test HTML with the channel config changed by `configure`,
+    /// returning it along with the URL of the HTML
+    fn process_local_html(
+        name: &str,
+        configure: impl FnOnce(&mut ChannelConfig),
+    ) -> (rss::Channel, Url) {
+        let html_file_name = format!("rsspls.{name}.{}.html", process::id());
         let local_html = RmOnDrop::new(env::temp_dir().join(&html_file_name));
         fs::write(local_html.path(), HTML.as_bytes()).expect("unable to write test HTML");
 
@@ -505,41 +3512,125 @@ mod tests {
         let client = Client {
             file_urls: true,
             http: HttpClient::new(),
+            proxy: None,
+            timeouts: Default::default(),
+            downloads: Default::default(),
+            downloaded: Default::default(),
+            shared: Default::default(),
+            backoff: Default::default(),
+            login_http: None,
+            headers: None,
+            cookies: None,
+            auth: None,
+            failed_html_dir: None,
+            page_cache_dir: None,
+            output: Output::new(&Default::default(), None).unwrap(),
+            reproducible: false,
+            source_date: None,
+            dedup: None,
         };
 
         let config = FeedConfig {
-            url: url.to_string(),
+            url: vec![url.to_string()],
             item: "nav a".to_string(),
-            heading: "a".to_string(),
+            heading: vec!["a".parse().unwrap()],
             ..test_config()
         };
-        let channel_config = ChannelConfig {
+        let mut channel_config = ChannelConfig {
             title: "Local Site".to_string(),
             filename: Path::new(&html_file_name)
                 .with_extension("rss")
                 .to_string_lossy()
                 .into_owned(),
+            name: None,
+            description: None,
+            language: None,
+            image: None,
+            ttl: None,
+            copyright: None,
             user_agent: None,
+            fallback_user_agent: None,
+            accept: None,
+            accept_language: None,
+            headers: BTreeMap::new(),
+            cookies: None,
+            compression: None,
+            fetcher: Fetcher::Builtin,
+            ignore_cache_control: false,
+            render: None,
+            render_command: None,
+            redact: Vec::new(),
+            login: None,
+            cookies_from: None,
+            depends_on: None,
+            run_if: RunIf::Always,
+            dedup: None,
+            generator: None,
+            format: OutputFormat::Rss,
+            stale_after: None,
+            refresh: None,
             config,
         };
+        configure(&mut channel_config);
         let config_hash = ConfigHash(&html_file_name);
 
         let runtime = tokio::runtime::Builder::new_current_thread()
             .build()
             .unwrap();
         let res = runtime
-            .block_on(process_feed(&client, &channel_config, config_hash, &None))
+            .block_on(process_feed(
+                &client,
+                &channel_config,
+                config_hash,
+                &None,
+                &mut FeedState::default(),
+            ))
             .expect("unable to process local feed");
 
         let ProcessResult::Ok { channel, .. } = res else {
             panic!("expected ProcessResult::Ok but got: {:?}", res)
         };
+        (channel, url)
+    }
+
+    #[test]
+    fn test_process_local_html() {
+        let (channel, _) = process_local_html("local", |_| {});
 
         assert_eq!(channel.items().len(), 5);
         assert_eq!(channel.items()[0].title, Some("Install".to_string()));
     }
 
     #[test]
+    fn test_channel_metadata() {
+        let (channel, url) = process_local_html("metadata", |channel_config| {
+            channel_config.description = Some("The local site".to_string());
+            channel_config.language = Some("en-nz".to_string());
+            channel_config.image = Some("logo.png".to_string());
+            channel_config.ttl = Some(90);
+        });
+        assert_eq!(channel.description(), "The local site");
+        assert_eq!(channel.language(), Some("en-nz"));
+        assert_eq!(channel.ttl(), Some("90"));
+        let image = channel.image().unwrap();
+        assert_eq!(image.url(), url.join("logo.png").unwrap().as_str());
+        assert_eq!(image.title(), "Local Site");
+    }
+
+    #[test]
+    fn test_generator_omitted() {
+        let (channel, _) = process_local_html("generator", |channel_config| {
+            channel_config.generator = Some(String::new());
+        });
+        assert_eq!(channel.generator(), None);
+        let (channel, _) = process_local_html("generator-set", |channel_config| {
+            channel_config.generator = Some("my scraper".to_string());
+        });
+        assert_eq!(channel.generator(), Some("my scraper"));
+    }
+
+    #[test]
+    #[allow(clippy::needless_borrow, clippy::needless_borrows_for_generic_args)]
     fn test_process_local_files_disabled() {
         let html_file_name = "rsspls.local.html";
         let local_html = env::temp_dir().join(&html_file_name);
@@ -549,12 +3640,28 @@ mod tests {
         let client = Client {
             file_urls: false,
             http: HttpClient::new(),
+            proxy: None,
+            timeouts: Default::default(),
+            downloads: Default::default(),
+            downloaded: Default::default(),
+            shared: Default::default(),
+            backoff: Default::default(),
+            login_http: None,
+            headers: None,
+            cookies: None,
+            auth: None,
+            failed_html_dir: None,
+            page_cache_dir: None,
+            output: Output::new(&Default::default(), None).unwrap(),
+            reproducible: false,
+            source_date: None,
+            dedup: None,
         };
 
         let config = FeedConfig {
-            url: url.to_string(),
+            url: vec![url.to_string()],
             item: "nav a".to_string(),
-            heading: "a".to_string(),
+            heading: vec!["a".parse().unwrap()],
             ..test_config()
         };
         let channel_config = ChannelConfig {
@@ -563,7 +3670,33 @@ mod tests {
                 .with_extension("rss")
                 .to_string_lossy()
                 .into_owned(),
+            name: None,
+            description: None,
+            language: None,
+            image: None,
+            ttl: None,
+            copyright: None,
             user_agent: None,
+            fallback_user_agent: None,
+            accept: None,
+            accept_language: None,
+            headers: BTreeMap::new(),
+            cookies: None,
+            compression: None,
+            fetcher: Fetcher::Builtin,
+            ignore_cache_control: false,
+            render: None,
+            render_command: None,
+            redact: Vec::new(),
+            login: None,
+            cookies_from: None,
+            depends_on: None,
+            run_if: RunIf::Always,
+            dedup: None,
+            generator: None,
+            format: OutputFormat::Rss,
+            stale_after: None,
+            refresh: None,
             config,
         };
         let config_hash = ConfigHash(&html_file_name);
@@ -571,7 +3704,13 @@ mod tests {
         let runtime = tokio::runtime::Builder::new_current_thread()
             .build()
             .unwrap();
-        let res = runtime.block_on(process_feed(&client, &channel_config, config_hash, &None));
+        let res = runtime.block_on(process_feed(
+            &client,
+            &channel_config,
+            config_hash,
+            &None,
+            &mut FeedState::default(),
+        ));
 
         let Err(err) = res else {
             panic!("expected error, got: {:?}", res)
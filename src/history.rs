@@ -0,0 +1,380 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use simple_eyre::eyre::{self, bail, WrapErr};
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+use crate::download::format_size;
+use crate::error::ErrorKind;
+
+/// The number of runs kept in the history file
+const MAX_RUNS: usize = 1000;
+/// The number of runs shown for a single feed
+const MAX_SHOWN_RUNS: usize = 30;
+
+/// A record of one run of rsspls
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// Unix timestamp of the start of the run
+    pub timestamp: i64,
+    pub feeds: Vec<FeedRecord>,
+}
+
+/// The outcome of processing one feed in a run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeedRecord {
+    /// The name of the feed, missing from records created by older versions
+    #[serde(default)]
+    pub name: String,
+    pub filename: String,
+    pub status: FeedStatus,
+    /// Number of items in the generated feed
+    #[serde(default)]
+    pub items: usize,
+    /// Number of items that weren't in the previous run
+    #[serde(default)]
+    pub new_items: usize,
+    /// The class of error when the status is `Error`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorKind>,
+    /// Assertions that failed without failing the feed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_assertions: Vec<String>,
+    /// Bytes of the response bodies downloaded for the feed
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub downloaded: u64,
+}
+
+impl FeedRecord {
+    /// The name of the feed, or the default name for records without one
+    pub fn key(&self) -> &str {
+        if self.name.is_empty() {
+            Path::new(&self.filename)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or(&self.filename)
+        } else {
+            &self.name
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedStatus {
+    Ok,
+    NotModified,
+    Error,
+    /// Not processed because of the outcome of the feed it depends on, or because its host is
+    /// being backed off from
+    Skipped,
+}
+
+/// Append `record` to the history file at `path`, which contains one JSON record per line
+pub fn append(path: &Path, record: &RunRecord) -> eyre::Result<()> {
+    let mut line = serde_json::to_string(record).wrap_err("unable to serialise run history")?;
+    line.push('\n');
+    debug!("append run history to {}", path.display());
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .wrap_err_with(|| format!("unable to write history file: {}", path.display()))?;
+
+    // Drop the oldest runs once the file gets too long
+    let runs = read(path)?;
+    if runs.len() > MAX_RUNS {
+        let mut content = String::new();
+        for run in &runs[runs.len() - MAX_RUNS..] {
+            content.push_str(&serde_json::to_string(run).wrap_err("unable to serialise run")?);
+            content.push('\n');
+        }
+        crate::write_atomically(path, content.as_bytes())
+            .wrap_err_with(|| format!("unable to write history file: {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Read all the runs in the history file at `path`, oldest first
+pub fn read(path: &Path) -> eyre::Result<Vec<RunRecord>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(err)
+                .wrap_err_with(|| format!("unable to read history file: {}", path.display()))
+        }
+    };
+    Ok(content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            serde_json::from_str(line)
+                .map_err(|err| warn!("skipping invalid history record: {err}"))
+                .ok()
+        })
+        .collect())
+}
+
+/// Print a summary of each feed in `runs`, or the recent runs of `feed` if supplied
+pub fn show(runs: &[RunRecord], feed: Option<&str>) -> eyre::Result<()> {
+    match feed {
+        Some(feed) => {
+            let matches = |record: &&FeedRecord| record.key() == feed || record.filename == feed;
+            let feed_runs: Vec<_> = runs
+                .iter()
+                .filter_map(|run| run.feeds.iter().find(matches).map(|record| (run, record)))
+                .collect();
+            if feed_runs.is_empty() {
+                bail!("no history for feed {feed}");
+            }
+            for (run, record) in feed_runs.iter().rev().take(MAX_SHOWN_RUNS).rev() {
+                let status = match record.error {
+                    Some(kind) => format!("{} ({})", status_str(record.status), kind_str(kind)),
+                    None => status_str(record.status).to_string(),
+                };
+                println!(
+                    "{}  {:<16} {:>4} items {:>8}",
+                    format_timestamp(run.timestamp),
+                    status,
+                    record.items,
+                    format_size(record.downloaded)
+                );
+            }
+        }
+        None => {
+            for summary in summarise(runs) {
+                let last_ok = summary
+                    .last_ok
+                    .map_or_else(|| "never".to_string(), format_timestamp);
+                println!(
+                    "{}: last run {} ({}, {} items), last ok {}, {}/{} runs failed",
+                    summary.name,
+                    format_timestamp(summary.last_run),
+                    status_str(summary.last_status),
+                    summary.last_items,
+                    last_ok,
+                    summary.errors,
+                    summary.runs
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A table of the outcome of each feed in a run, one line per feed
+pub fn run_summary(feeds: &[FeedRecord]) -> String {
+    let width = feeds
+        .iter()
+        .map(|record| record.key().chars().count())
+        .chain(Some("FEED".len()))
+        .max()
+        .unwrap_or_default();
+    let mut table = format!(
+        "{:<width$}  {:<10}  {:>5}  {:>5}  {:>10}\n",
+        "FEED", "STATUS", "ITEMS", "NEW", "DOWNLOADED"
+    );
+    for record in feeds {
+        let status = match record.status {
+            FeedStatus::Ok if !record.failed_assertions.is_empty() => "ASSERT",
+            FeedStatus::Ok => "OK",
+            FeedStatus::NotModified => "UNMODIFIED",
+            FeedStatus::Error => "FAILED",
+            FeedStatus::Skipped => "SKIPPED",
+        };
+        table.push_str(&format!(
+            "{:<width$}  {:<10}  {:>5}  {:>5}  {:>10}\n",
+            record.key(),
+            status,
+            record.items,
+            record.new_items,
+            format_size(record.downloaded)
+        ));
+    }
+    table
+}
+
+#[derive(Debug, PartialEq)]
+struct FeedSummary<'a> {
+    name: &'a str,
+    runs: usize,
+    errors: usize,
+    last_run: i64,
+    last_status: FeedStatus,
+    last_items: usize,
+    last_ok: Option<i64>,
+}
+
+/// Summarise the history of each feed, in the order they were first seen
+fn summarise(runs: &[RunRecord]) -> Vec<FeedSummary<'_>> {
+    let mut summaries: Vec<FeedSummary> = Vec::new();
+    for run in runs {
+        for record in &run.feeds {
+            let index = match summaries
+                .iter()
+                .position(|summary| summary.name == record.key())
+            {
+                Some(index) => index,
+                None => {
+                    summaries.push(FeedSummary {
+                        name: record.key(),
+                        runs: 0,
+                        errors: 0,
+                        last_run: run.timestamp,
+                        last_status: record.status,
+                        last_items: 0,
+                        last_ok: None,
+                    });
+                    summaries.len() - 1
+                }
+            };
+            let summary = &mut summaries[index];
+            summary.runs += 1;
+            summary.last_run = run.timestamp;
+            summary.last_status = record.status;
+            match record.status {
+                FeedStatus::Ok => {
+                    summary.last_items = record.items;
+                    summary.last_ok = Some(run.timestamp);
+                }
+                FeedStatus::NotModified => summary.last_ok = Some(run.timestamp),
+                FeedStatus::Error => summary.errors += 1,
+                FeedStatus::Skipped => {}
+            }
+        }
+    }
+    summaries
+}
+
+fn status_str(status: FeedStatus) -> &'static str {
+    match status {
+        FeedStatus::Ok => "ok",
+        FeedStatus::NotModified => "not modified",
+        FeedStatus::Error => "error",
+        FeedStatus::Skipped => "skipped",
+    }
+}
+
+fn kind_str(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::Fetch => "fetch",
+        ErrorKind::Parse => "parse",
+        ErrorKind::Selector => "selector",
+        ErrorKind::Date => "date",
+        ErrorKind::Write => "write",
+        ErrorKind::Assertion => "assertion",
+    }
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+pub fn format_timestamp(timestamp: i64) -> String {
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .ok()
+        .and_then(|date| {
+            date.format(format_description!(
+                "[year]-[month]-[day] [hour]:[minute]:[second]"
+            ))
+            .ok()
+        })
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarise() {
+        let record = |status, items| FeedRecord {
+            name: "a".to_string(),
+            filename: "a.rss".to_string(),
+            status,
+            items,
+            new_items: 0,
+            error: None,
+            failed_assertions: Vec::new(),
+            downloaded: 0,
+        };
+        let runs = vec![
+            RunRecord {
+                timestamp: 1,
+                feeds: vec![record(FeedStatus::Ok, 5)],
+            },
+            RunRecord {
+                timestamp: 2,
+                feeds: vec![record(FeedStatus::Error, 0)],
+            },
+        ];
+        assert_eq!(
+            summarise(&runs),
+            [FeedSummary {
+                name: "a",
+                runs: 2,
+                errors: 1,
+                last_run: 2,
+                last_status: FeedStatus::Error,
+                last_items: 5,
+                last_ok: Some(1),
+            }]
+        );
+
+        let line = serde_json::to_string(&runs[0]).unwrap();
+        assert_eq!(runs[0].feeds[0].key(), "a");
+        assert_eq!(
+            line,
+            r#"{"timestamp":1,"feeds":[{"name":"a","filename":"a.rss","status":"ok","items":5,"new_items":0}]}"#
+        );
+    }
+
+    #[test]
+    fn test_run_summary() {
+        let feeds = [
+            FeedRecord {
+                name: "news".to_string(),
+                filename: "news.rss".to_string(),
+                status: FeedStatus::Ok,
+                items: 20,
+                new_items: 3,
+                error: None,
+                failed_assertions: Vec::new(),
+                downloaded: 1536,
+            },
+            FeedRecord {
+                name: String::new(),
+                filename: "a.rss".to_string(),
+                status: FeedStatus::Error,
+                items: 0,
+                new_items: 0,
+                error: Some(ErrorKind::Fetch),
+                failed_assertions: Vec::new(),
+                downloaded: 0,
+            },
+            FeedRecord {
+                name: "shop".to_string(),
+                filename: "shop.rss".to_string(),
+                status: FeedStatus::Ok,
+                items: 1,
+                new_items: 0,
+                error: None,
+                failed_assertions: vec!["expected at least 5 items but found 1".to_string()],
+                downloaded: 0,
+            },
+        ];
+        assert_eq!(
+            run_summary(&feeds),
+            "FEED  STATUS      ITEMS    NEW  DOWNLOADED\n\
+             news  OK             20      3        1.5K\n\
+             a     FAILED          0      0          0B\n\
+             shop  ASSERT          1      0          0B\n"
+        );
+    }
+}
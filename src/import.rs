@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+
+use kuchiki::traits::TendrilSink;
+use log::warn;
+use reqwest::Client as HttpClient;
+use serde::Deserialize;
+use simple_eyre::eyre::{self, bail, WrapErr};
+use url::Url;
+
+use crate::feed::fetch_location;
+use crate::snippet::{ConfigSnippet, FeedConfigSnippet, FeedSnippet};
+
+/// A scraping rule as exported by rss-proxy
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RssProxyRule {
+    #[serde(alias = "homePageUrl")]
+    url: Option<String>,
+    title: Option<String>,
+    context_x_path: String,
+    link_x_path: String,
+    date_x_path: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RssProxyRules {
+    Wrapped { rules: Vec<RssProxyRule> },
+    Many(Vec<RssProxyRule>),
+    One(RssProxyRule),
+}
+
+/// Convert RSS-Bridge URLs or rss-proxy rules at `location` into feed config
+///
+/// `location` may be a RSS-Bridge URL, or a file or URL containing a list of RSS-Bridge URLs
+/// (one per line) or rss-proxy rules in JSON.
+pub async fn bridge(http: &HttpClient, location: &str) -> eyre::Result<ConfigSnippet> {
+    if let Some(feed) = Url::parse(location)
+        .ok()
+        .and_then(|url| rss_bridge_feed(&url))
+    {
+        return Ok(ConfigSnippet { feed: vec![feed] });
+    }
+
+    let (_, content) = fetch_location(http, location).await?;
+    let mut snippet = ConfigSnippet::default();
+    if matches!(content.trim_start().chars().next(), Some('{' | '[')) {
+        let rules: RssProxyRules =
+            serde_json::from_str(&content).wrap_err("unable to parse rss-proxy rules")?;
+        let rules = match rules {
+            RssProxyRules::Wrapped { rules } | RssProxyRules::Many(rules) => rules,
+            RssProxyRules::One(rule) => vec![rule],
+        };
+        snippet.feed.extend(rules.iter().filter_map(rss_proxy_feed));
+    } else {
+        for line in content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            match Url::parse(line).ok().and_then(|url| rss_bridge_feed(&url)) {
+                Some(feed) => snippet.feed.push(feed),
+                None => warn!("skipping unsupported RSS-Bridge URL: {line}"),
+            }
+        }
+    }
+
+    if snippet.feed.is_empty() {
+        bail!("nothing could be imported from {location}");
+    }
+    Ok(snippet)
+}
+
+/// Convert the subscriptions in the OPML file at `location` into feed config that passes the
+/// feeds through
+pub async fn opml(http: &HttpClient, location: &str) -> eyre::Result<ConfigSnippet> {
+    let (_, content) = fetch_location(http, location).await?;
+    let snippet = ConfigSnippet {
+        feed: opml_feeds(&content),
+    };
+    if snippet.feed.is_empty() {
+        bail!("no subscriptions found in {location}");
+    }
+    Ok(snippet)
+}
+
+fn opml_feeds(content: &str) -> Vec<FeedSnippet> {
+    // The HTML parser is lenient enough to pull the outline elements out of OPML, attribute
+    // names are lowercased by it.
+    let doc = kuchiki::parse_html().one(content);
+    let Ok(outlines) = doc.select("outline") else {
+        return Vec::new();
+    };
+    outlines
+        .filter_map(|outline| {
+            let attrs = outline.attributes.borrow();
+            let url = attrs.get("xmlurl")?.trim().to_string();
+            let title = attrs
+                .get("title")
+                .or_else(|| attrs.get("text"))
+                .map(|title| title.trim().to_string())
+                .filter(|title| !title.is_empty())
+                .unwrap_or_else(|| url.clone());
+            let config = FeedConfigSnippet {
+                url,
+                source_type: Some("feed".to_string()),
+                ..Default::default()
+            };
+            Some(FeedSnippet::new(title, config))
+        })
+        .collect()
+}
+
+/// Build a feed from the parameters of a RSS-Bridge URL for one of the CSS selector bridges
+fn rss_bridge_feed(url: &Url) -> Option<FeedSnippet> {
+    let params: HashMap<_, _> = url.query_pairs().collect();
+    let param = |name: &str| {
+        params
+            .get(name)
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let home_page = param("home_page")?;
+
+    let config = match params.get("bridge")?.as_ref() {
+        "CssSelectorBridge" => {
+            // This bridge selects links on the page and treats each one as an item
+            let url_selector = param("url_selector")?;
+            if param("content_selector").is_some() {
+                warn!("content_selector from {url} is not supported and has been skipped");
+            }
+            FeedConfigSnippet {
+                url: home_page.clone(),
+                item: url_selector.clone(),
+                heading: url_selector,
+                ..Default::default()
+            }
+        }
+        "CssSelectorComplexBridge" => {
+            let url_selector = param("url_selector");
+            FeedConfigSnippet {
+                url: home_page.clone(),
+                item: param("entry_element_selector")?,
+                heading: param("title_selector")
+                    .or_else(|| url_selector.clone())
+                    .unwrap_or_else(|| "a".to_string()),
+                link: url_selector,
+                summary: param("content_selector"),
+                date: param("time_selector"),
+                media: param("enclosures_selector"),
+                ..Default::default()
+            }
+        }
+        bridge => {
+            warn!("the {bridge} bridge can't be converted as it is not selector based");
+            return None;
+        }
+    };
+
+    Some(FeedSnippet::new(home_page, config))
+}
+
+fn rss_proxy_feed(rule: &RssProxyRule) -> Option<FeedSnippet> {
+    let Some(url) = rule.url.clone() else {
+        warn!("skipping rss-proxy rule without a URL");
+        return None;
+    };
+    let convert = |xpath: &str| {
+        let css = xpath_to_css(xpath);
+        if css.is_none() {
+            warn!("unable to convert XPath '{xpath}' from rule for {url} into a CSS selector");
+        }
+        css
+    };
+
+    let link = convert(&rule.link_x_path)?;
+    let config = FeedConfigSnippet {
+        url: url.clone(),
+        item: convert(&rule.context_x_path)?,
+        heading: link.clone(),
+        link: Some(link),
+        date: match &rule.date_x_path {
+            Some(date) => Some(convert(date)?),
+            None => None,
+        },
+        ..Default::default()
+    };
+    Some(FeedSnippet::new(
+        rule.title.clone().unwrap_or(url.clone()),
+        config,
+    ))
+}
+
+/// Convert simple XPath expressions into an equivalent CSS selector
+///
+/// Only paths made up of element names with attribute equality, `contains(@class, ...)`, and
+/// position predicates are supported.
+fn xpath_to_css(xpath: &str) -> Option<String> {
+    let xpath = xpath.trim();
+    let xpath = xpath.strip_prefix('.').unwrap_or(xpath);
+    let mut css = String::new();
+    let mut rest = xpath;
+    while !rest.is_empty() {
+        let combinator = if let Some(after) = rest.strip_prefix("//") {
+            rest = after;
+            " "
+        } else if let Some(after) = rest.strip_prefix('/') {
+            rest = after;
+            " > "
+        } else if css.is_empty() {
+            // A relative path without a leading slash
+            " "
+        } else {
+            return None;
+        };
+        if !css.is_empty() {
+            css.push_str(combinator);
+        }
+
+        // Find the end of this step, skipping over slashes within predicates
+        let mut depth = 0;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                match c {
+                    '[' => depth += 1,
+                    ']' => depth -= 1,
+                    _ => {}
+                }
+                c == '/' && depth == 0
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        css.push_str(&xpath_step_to_css(&rest[..end])?);
+        rest = &rest[end..];
+    }
+
+    (!css.is_empty()).then_some(css)
+}
+
+fn xpath_step_to_css(step: &str) -> Option<String> {
+    let (name, mut predicates) = step.split_at(step.find('[').unwrap_or(step.len()));
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '*' || c == '-')
+    {
+        return None;
+    }
+
+    let mut css = name.to_string();
+    while let Some(predicate) = predicates.strip_prefix('[') {
+        let end = predicate.find(']')?;
+        let inner = predicate[..end].trim();
+        predicates = &predicate[end + 1..];
+
+        if let Ok(position) = inner.parse::<u32>() {
+            css.push_str(&format!(":nth-of-type({position})"));
+        } else if let Some(args) = inner
+            .strip_prefix("contains(")
+            .and_then(|args| args.strip_suffix(')'))
+        {
+            let (attr, value) = args.split_once(',')?;
+            let attr = attr.trim().strip_prefix('@')?;
+            css.push_str(&format!("[{attr}*=\"{}\"]", unquote(value)?));
+        } else {
+            let (attr, value) = inner.split_once('=')?;
+            let attr = attr.trim().strip_prefix('@')?;
+            css.push_str(&format!("[{attr}=\"{}\"]", unquote(value)?));
+        }
+    }
+
+    predicates.is_empty().then_some(css)
+}
+
+fn unquote(value: &str) -> Option<&str> {
+    let value = value.trim();
+    value
+        .strip_prefix('\'')
+        .and_then(|value| value.strip_suffix('\''))
+        .or_else(|| {
+            value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+        })
+        .filter(|value| !value.contains('"'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xpath_to_css() {
+        assert_eq!(
+            xpath_to_css("//div[@class='post']/h2").as_deref(),
+            Some(r#"div[class="post"] > h2"#)
+        );
+        assert_eq!(
+            xpath_to_css("./a[contains(@class, 'title')][1]").as_deref(),
+            Some(r#"a[class*="title"]:nth-of-type(1)"#)
+        );
+        assert_eq!(xpath_to_css("//ul//li").as_deref(), Some("ul li"));
+        assert_eq!(xpath_to_css("//a/@href"), None);
+        assert_eq!(xpath_to_css("//a[text()='x']"), None);
+    }
+
+    #[test]
+    fn test_opml_feeds() {
+        let feeds = opml_feeds(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="2.0">
+  <head><title>Subscriptions</title></head>
+  <body>
+    <outline text="Tech">
+      <outline type="rss" text="Example &amp; Co" xmlUrl="https://example.com/feed.xml" htmlUrl="https://example.com/"/>
+      <outline type="rss" xmlUrl="https://example.org/atom.xml"/>
+    </outline>
+  </body>
+</opml>"#,
+        );
+        assert_eq!(feeds.len(), 2);
+        assert_eq!(feeds[0].title, "Example & Co");
+        assert_eq!(feeds[0].filename, "example-co.rss");
+        assert_eq!(feeds[0].config.url, "https://example.com/feed.xml");
+        assert_eq!(feeds[0].config.source_type.as_deref(), Some("feed"));
+        assert_eq!(feeds[1].title, "https://example.org/atom.xml");
+    }
+
+    #[test]
+    fn test_rss_bridge_feed() {
+        let url = "https://rss-bridge.example/?action=display&bridge=CssSelectorComplexBridge&home_page=https%3A%2F%2Fexample.com%2Fnews&entry_element_selector=article&title_selector=h2&url_selector=a.more&time_selector=time&format=Atom".parse().unwrap();
+        let feed = rss_bridge_feed(&url).unwrap();
+        assert_eq!(feed.filename, "https-example-com-news.rss");
+        assert_eq!(feed.config.url, "https://example.com/news");
+        assert_eq!(feed.config.item, "article");
+        assert_eq!(feed.config.heading, "h2");
+        assert_eq!(feed.config.link.as_deref(), Some("a.more"));
+        assert_eq!(feed.config.date.as_deref(), Some("time"));
+    }
+}
@@ -0,0 +1,288 @@
+use log::warn;
+use rss::{CategoryBuilder, GuidBuilder, Item, ItemBuilder};
+use serde_json::Value;
+use simple_eyre::eyre::{self, bail, eyre, WrapErr};
+use time::format_description::well_known::Rfc2822;
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::config::{DateConfig, FeedConfig};
+
+/// The path of the link of JSON items when `link` isn't set
+const DEFAULT_LINK_PATH: &str = "url";
+/// Numeric dates larger than this are taken to be in milliseconds rather than seconds
+const MAX_TIMESTAMP_SECONDS: i64 = 100_000_000_000;
+
+/// Look up the value at `path` in `value`
+///
+/// `path` is a dot separated list of object keys and array indices, e.g. `data.items.0.html`.
+pub fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .filter(|key| !key.is_empty())
+        .try_fold(value, |value, key| match value {
+            Value::Object(object) => object.get(key),
+            Value::Array(array) => key.parse::<usize>().ok().and_then(|i| array.get(i)),
+            _ => None,
+        })
+}
+
+/// Look up the value at `path` in `value`, where `path` is a JSON Pointer (`/data/items/0`), a
+/// simple JSONPath (`$.data.items[0]`), or a dot separated path (`data.items.0`)
+pub fn select<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.trim();
+    if path.starts_with('/') {
+        return value.pointer(path);
+    }
+    let path = path
+        .strip_prefix('$')
+        .unwrap_or(path)
+        .replace("['", ".")
+        .replace("']", "")
+        .replace('[', ".")
+        .replace(']', "");
+    lookup(value, &path)
+}
+
+/// The text of a value, with the text of arrays joined by `separator`
+fn text(value: &Value, separator: &str) -> Option<String> {
+    match value {
+        Value::Null | Value::Object(_) => None,
+        Value::String(text) => Some(text.clone()),
+        Value::Array(values) => {
+            let texts: Vec<_> = values
+                .iter()
+                .filter_map(|value| text(value, separator))
+                .collect();
+            (!texts.is_empty()).then(|| texts.join(separator))
+        }
+        value => Some(value.to_string()),
+    }
+}
+
+/// Parse a date that's either text or a Unix timestamp in seconds or milliseconds
+fn parse_date(date: &DateConfig, value: &Value) -> Option<OffsetDateTime> {
+    match value {
+        Value::Number(number) => {
+            let timestamp = number.as_i64()?;
+            let timestamp = if timestamp > MAX_TIMESTAMP_SECONDS {
+                timestamp / 1000
+            } else {
+                timestamp
+            };
+            OffsetDateTime::from_unix_timestamp(timestamp).ok()
+        }
+        Value::String(text) => date
+            .parse(text.trim())
+            .map_err(|_err| warn!("unable to parse date '{text}'"))
+            .ok(),
+        _ => None,
+    }
+}
+
+/// Extract items from the JSON document `body` with the paths in `config`
+///
+/// `item` is the path of the array of items, and the other fields are paths within each item.
+/// Items older than `ignore_older_than` at `now` are skipped.
+pub fn extract_items(
+    config: &FeedConfig,
+    body: &str,
+    url: &Url,
+    now: OffsetDateTime,
+) -> eyre::Result<Vec<Item>> {
+    let document: Value =
+        serde_json::from_str(body).wrap_err_with(|| format!("unable to parse {url} as JSON"))?;
+    let values = match select(&document, &config.item) {
+        Some(Value::Array(values)) => values,
+        Some(_) => bail!("JSON item path '{}' is not an array", config.item),
+        None => bail!("JSON item path '{}' does not exist", config.item),
+    };
+    let link_path = config
+        .link
+        .as_ref()
+        .map_or(DEFAULT_LINK_PATH, |link| link.selector.as_str());
+
+    let mut items = Vec::new();
+    for value in values {
+        let title = config
+            .heading
+            .iter()
+            .filter_map(|path| {
+                select(value, &path.selector)
+                    .and_then(|value| text(value, &config.heading_separator))
+            })
+            .collect::<Vec<_>>()
+            .join(&config.heading_separator);
+        let Some(link) = select(value, link_path).and_then(|value| text(value, "")) else {
+            warn!("skipping JSON item '{title}' as it has no link at '{link_path}'");
+            continue;
+        };
+        let link = url.join(&link).map_or(link, String::from);
+        let date = config.date.as_ref().and_then(|date| {
+            select(value, date.selector()).and_then(|value| parse_date(date, value))
+        });
+        if let Some(date) = date {
+            if config.is_too_old(date, now) {
+                continue;
+            }
+        }
+        let description: String = config
+            .summary
+            .iter()
+            .filter_map(|path| select(value, &path.selector).and_then(|value| text(value, "")))
+            .collect();
+        let guid = match &config.guid_prefix {
+            Some(prefix) => format!("{prefix}{link}"),
+            None => link.clone(),
+        };
+        let author = config
+            .author
+            .as_ref()
+            .and_then(|path| select(value, path).and_then(|value| text(value, ", ")));
+        let mut categories: Vec<String> = Vec::new();
+        for value in config
+            .category
+            .iter()
+            .filter_map(|path| select(value, path))
+        {
+            let values = match value {
+                Value::Array(values) => values.as_slice(),
+                value => std::slice::from_ref(value),
+            };
+            for category in values.iter().filter_map(|value| text(value, "")) {
+                if !categories.contains(&category) {
+                    categories.push(category);
+                }
+            }
+        }
+        let comments = config
+            .comments
+            .as_ref()
+            .and_then(|path| select(value, path).and_then(|value| text(value, "")))
+            .and_then(|comments| url.join(&comments).ok())
+            .map(String::from);
+
+        items.push(
+            ItemBuilder::default()
+                .title((!title.is_empty()).then_some(title))
+                .link(Some(link))
+                .guid(Some(
+                    GuidBuilder::default().value(guid).permalink(false).build(),
+                ))
+                .pub_date(date.and_then(|date| date.format(&Rfc2822).ok()))
+                .description((!description.is_empty()).then_some(description))
+                .author(author)
+                .categories(
+                    categories
+                        .into_iter()
+                        .map(|name| CategoryBuilder::default().name(name).build())
+                        .collect::<Vec<_>>(),
+                )
+                .comments(comments)
+                .build(),
+        );
+    }
+    Ok(items)
+}
+
+/// Extract the HTML fragment stored in the field at `path` of the JSON document `body`
+///
+/// If the field holds an array of strings they are concatenated.
+pub fn html_field(body: &str, path: &str) -> eyre::Result<String> {
+    let value: Value = serde_json::from_str(body).wrap_err("unable to parse response as JSON")?;
+    match lookup(&value, path).ok_or_else(|| eyre!("JSON field '{path}' does not exist"))? {
+        Value::String(html) => Ok(html.clone()),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| {
+                value.as_str().ok_or_else(|| {
+                    eyre!("JSON field '{path}' contains a value that isn't a string")
+                })
+            })
+            .collect(),
+        _ => bail!("JSON field '{path}' is not a string"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LinkConfig;
+
+    #[test]
+    fn test_html_field() {
+        let body = r#"{"data": {"pages": [{"html": "<ul><li>One</li></ul>"}]}, "parts": ["<p>a</p>", "<p>b</p>"]}"#;
+        assert_eq!(
+            html_field(body, "data.pages.0.html").unwrap(),
+            "<ul><li>One</li></ul>"
+        );
+        assert_eq!(html_field(body, "parts").unwrap(), "<p>a</p><p>b</p>");
+        assert!(html_field(body, "data.missing").is_err());
+        assert!(html_field(body, "data").is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let value: Value =
+            serde_json::from_str(r#"{"data": {"items": [{"a.b": 1}, {"title": "Two"}]}}"#).unwrap();
+        let two = Some(&value["data"]["items"][1]);
+        assert_eq!(select(&value, "/data/items/1"), two);
+        assert_eq!(select(&value, "$.data.items[1]"), two);
+        assert_eq!(select(&value, "$['data']['items'][1]"), two);
+        assert_eq!(select(&value, "data.items.1"), two);
+        assert_eq!(select(&value, "/data/items/0/a.b"), Some(&Value::from(1)));
+        assert_eq!(select(&value, ""), Some(&value));
+        assert_eq!(select(&value, "data.missing"), None);
+    }
+
+    #[test]
+    fn test_extract_items() {
+        let config: FeedConfig = basic_toml::from_str(
+            r#"
+            url = "http://example.com/api/posts"
+            source_type = "json"
+            item = "/posts"
+            heading = ["$.title", "meta.section"]
+            heading_separator = " - "
+            link = "permalink"
+            summary = "body"
+            date = "published"
+            author = "author.name"
+            category = ["tags", "meta.section"]
+            comments = "discussion"
+            "#,
+        )
+        .unwrap();
+        let body = r#"{"posts": [
+            {"title": "One", "meta": {"section": "News"}, "permalink": "/posts/1", "body": "<p>First</p>", "published": "2024-08-01T10:00:00Z",
+             "author": {"name": "Ann"}, "tags": ["rust", "News"], "discussion": "/posts/1#comments"},
+            {"title": "Two", "permalink": "/posts/2", "published": 1722592800000},
+            {"title": "No link"}
+        ]}"#;
+        let url = "http://example.com/api/posts".parse().unwrap();
+        let items = extract_items(&config, body, &url, OffsetDateTime::now_utc()).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].title(), Some("One - News"));
+        assert_eq!(items[0].link(), Some("http://example.com/posts/1"));
+        assert_eq!(items[0].description(), Some("<p>First</p>"));
+        assert_eq!(items[0].pub_date(), Some("Thu, 01 Aug 2024 10:00:00 +0000"));
+        assert_eq!(items[0].author(), Some("Ann"));
+        let categories: Vec<_> = items[0].categories().iter().map(|c| c.name()).collect();
+        assert_eq!(categories, ["rust", "News"]);
+        assert_eq!(
+            items[0].comments(),
+            Some("http://example.com/posts/1#comments")
+        );
+        assert_eq!(items[1].author(), None);
+        assert!(items[1].categories().is_empty());
+        assert_eq!(items[1].title(), Some("Two"));
+        assert_eq!(items[1].description(), None);
+        assert_eq!(items[1].pub_date(), Some("Fri, 02 Aug 2024 10:00:00 +0000"));
+
+        let config = FeedConfig {
+            item: "posts.0".to_string(),
+            link: Some(LinkConfig::default()),
+            ..config
+        };
+        assert!(extract_items(&config, body, &url, OffsetDateTime::now_utc()).is_err());
+    }
+}
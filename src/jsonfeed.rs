@@ -0,0 +1,199 @@
+use rss::{Channel, EnclosureBuilder, GuidBuilder, Item, ItemBuilder};
+use serde::{Deserialize, Serialize};
+use simple_eyre::eyre::{self, WrapErr};
+use time::format_description::well_known::{Rfc2822, Rfc3339};
+use time::OffsetDateTime;
+
+/// The version of JSON Feed that's written
+pub const VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+/// A JSON Feed document
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Feed {
+    pub version: String,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub home_page_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub items: Vec<FeedItem>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct FeedItem {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_html: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_text: Option<String>,
+    /// RFC 3339 publication date
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_published: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<Author>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Author {
+    pub name: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub url: String,
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_in_bytes: Option<u64>,
+}
+
+impl From<&Channel> for Feed {
+    fn from(channel: &Channel) -> Self {
+        Feed {
+            version: VERSION.to_string(),
+            title: channel.title().to_string(),
+            home_page_url: Some(channel.link().to_string()),
+            description: Some(channel.description())
+                .filter(|description| !description.is_empty())
+                .map(str::to_string),
+            icon: channel.image().map(|image| image.url().to_string()),
+            language: channel.language().map(str::to_string),
+            items: channel.items().iter().map(FeedItem::from).collect(),
+        }
+    }
+}
+
+impl From<&Item> for FeedItem {
+    fn from(item: &Item) -> Self {
+        // One of the content fields is required
+        let content_html = item.content().or(item.description()).map(str::to_string);
+        let content_text = content_html.is_none().then(String::new);
+        FeedItem {
+            id: item
+                .guid()
+                .map(|guid| guid.value())
+                .or(item.link())
+                .or(item.title())
+                .unwrap_or_default()
+                .to_string(),
+            url: item.link().map(str::to_string),
+            title: item.title().map(str::to_string),
+            content_html,
+            content_text,
+            date_published: item.pub_date().and_then(|date| {
+                OffsetDateTime::parse(date, &Rfc2822)
+                    .ok()
+                    .and_then(|date| date.format(&Rfc3339).ok())
+            }),
+            authors: item
+                .author()
+                .map(|name| Author {
+                    name: name.to_string(),
+                })
+                .into_iter()
+                .collect(),
+            tags: item
+                .categories()
+                .iter()
+                .map(|category| category.name().to_string())
+                .collect(),
+            attachments: item
+                .enclosure()
+                .map(|enclosure| Attachment {
+                    url: enclosure.url().to_string(),
+                    mime_type: enclosure.mime_type().to_string(),
+                    size_in_bytes: enclosure.length().parse().ok(),
+                })
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+impl From<FeedItem> for Item {
+    fn from(item: FeedItem) -> Self {
+        ItemBuilder::default()
+            .guid(
+                GuidBuilder::default()
+                    .value(item.id)
+                    .permalink(false)
+                    .build(),
+            )
+            .link(item.url)
+            .title(item.title)
+            .description(item.content_html.or(item.content_text))
+            .pub_date(item.date_published.and_then(|date| {
+                OffsetDateTime::parse(&date, &Rfc3339)
+                    .ok()
+                    .and_then(|date| date.format(&Rfc2822).ok())
+            }))
+            .enclosure(item.attachments.into_iter().next().map(|attachment| {
+                EnclosureBuilder::default()
+                    .url(attachment.url)
+                    .mime_type(attachment.mime_type)
+                    .length(attachment.size_in_bytes.unwrap_or_default().to_string())
+                    .build()
+            }))
+            .build()
+    }
+}
+
+/// Serialise `channel` as a JSON Feed document
+pub fn write(channel: &Channel, pretty: bool) -> eyre::Result<Vec<u8>> {
+    let feed = Feed::from(channel);
+    if pretty {
+        serde_json::to_vec_pretty(&feed)
+    } else {
+        serde_json::to_vec(&feed)
+    }
+    .wrap_err("unable to serialise JSON Feed")
+}
+
+/// Parse the items out of a JSON Feed document
+pub fn parse_items(body: &str) -> eyre::Result<Vec<Item>> {
+    let feed: Feed = serde_json::from_str(body).wrap_err("unable to parse JSON Feed")?;
+    Ok(feed.items.into_iter().map(Item::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rss::ChannelBuilder;
+
+    #[test]
+    fn test_round_trip() {
+        let channel = ChannelBuilder::default()
+            .title("Example")
+            .link("http://example.com/")
+            .items(vec![ItemBuilder::default()
+                .title("One".to_string())
+                .link("http://example.com/1".to_string())
+                .guid(GuidBuilder::default().value("http://example.com/1").build())
+                .pub_date("Thu, 1 Aug 2024 10:00:00 +0000".to_string())
+                .description("<p>First</p>".to_string())
+                .build()])
+            .build();
+        let json = String::from_utf8(write(&channel, false).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"version":"https://jsonfeed.org/version/1.1","title":"Example","home_page_url":"http://example.com/","items":[{"id":"http://example.com/1","url":"http://example.com/1","title":"One","content_html":"<p>First</p>","date_published":"2024-08-01T10:00:00Z"}]}"#
+        );
+
+        let items = parse_items(&json).unwrap();
+        assert_eq!(items[0].link(), Some("http://example.com/1"));
+        assert_eq!(items[0].pub_date(), Some("Thu, 01 Aug 2024 10:00:00 +0000"));
+        assert_eq!(items[0].description(), Some("<p>First</p>"));
+    }
+}
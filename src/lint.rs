@@ -0,0 +1,223 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::config::{ChannelConfig, LinkConfig, SourceType};
+
+/// A suspicious part of a feed's config that is valid but probably not what was intended
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Lint {
+    /// Name of the feed
+    pub feed: String,
+    /// The config field the lint is about
+    pub field: String,
+    pub message: String,
+}
+
+/// Check `feeds` for common mistakes
+pub fn lint(feeds: &[ChannelConfig]) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    for (i, feed) in feeds.iter().enumerate() {
+        let mut push = |field: &str, message: String| {
+            lints.push(Lint {
+                feed: feed.name().to_string(),
+                field: field.to_string(),
+                message,
+            })
+        };
+        let config = &feed.config;
+
+        // Names that were given to feeds are checked when the config is read
+        if feed.name.is_none()
+            && feeds[..i]
+                .iter()
+                .any(|other| other.name.is_none() && other.name() == feed.name())
+        {
+            push(
+                "name",
+                format!(
+                    "has the same name as another feed, so they share cache and state files, set name to tell them apart: {}",
+                    feed.name()
+                ),
+            );
+        }
+        if Path::new(&feed.filename).extension().is_none() {
+            push(
+                "filename",
+                format!("{} has no extension, such as .rss", feed.filename),
+            );
+        }
+        if !config.item.is_empty()
+            && config
+                .heading
+                .iter()
+                .any(|heading| heading.selector == config.item)
+        {
+            push(
+                "heading",
+                "heading is the same as item, so each item is its own heading".to_string(),
+            );
+        }
+        for (field, selector) in config.selectors() {
+            if has_unbalanced_quotes(selector) {
+                push(
+                    &field,
+                    format!("selector has an unescaped quote: {selector}"),
+                );
+            }
+        }
+
+        // Without a link selector the link is taken from the first heading
+        let link = match &config.link {
+            Some(LinkConfig {
+                closest: Some(closest),
+                ..
+            }) => Some(("link.closest", closest.as_str())),
+            Some(link) => Some(("link", link.selector.as_str())),
+            None => config
+                .heading
+                .first()
+                .map(|heading| ("heading", heading.selector.as_str())),
+        };
+        if let (Some((field, selector)), SourceType::Html) = (link, config.source_type) {
+            if let Some(element) = non_anchor_element(selector) {
+                push(
+                    field,
+                    format!(
+                        "selector used for the link targets <{element}> elements, which don't have an href: {selector}"
+                    ),
+                );
+            }
+        }
+    }
+    lints
+}
+
+/// Whether `selector` has a quote that isn't closed or escaped
+fn has_unbalanced_quotes(selector: &str) -> bool {
+    let mut open = None;
+    let mut chars = selector.chars();
+    while let Some(c) = chars.next() {
+        match (c, open) {
+            ('\\', _) => {
+                chars.next();
+            }
+            ('"' | '\'', None) => open = Some(c),
+            (c, Some(quote)) if c == quote => open = None,
+            _ => {}
+        }
+    }
+    open.is_some()
+}
+
+/// The element type matched by any of the alternatives of `selector` that can't have an `href`
+///
+/// Alternatives without an element type or with an `href` attribute selector aren't considered.
+fn non_anchor_element(selector: &str) -> Option<String> {
+    selector.split(',').find_map(|alternative| {
+        let compound = alternative
+            .rsplit(|c: char| c.is_whitespace() || matches!(c, '>' | '+' | '~'))
+            .find(|compound| !compound.is_empty())?;
+        let element: String = compound
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect::<String>()
+            .to_ascii_lowercase();
+        let has_href = compound.contains("[href");
+        (!element.is_empty() && !matches!(element.as_str(), "a" | "area") && !has_href)
+            .then_some(element)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use basic_toml as toml;
+
+    use crate::config::Config;
+
+    #[test]
+    fn test_lint() {
+        let config: Config = toml::from_str(
+            r#"
+            [rsspls]
+
+            [[feed]]
+            title = "A"
+            filename = "a"
+            [feed.config]
+            url = "http://example.com/"
+            item = "li.post"
+            heading = "li.post"
+            summary = "p[title='it's']"
+
+            [[feed]]
+            title = "B"
+            filename = "b.rss"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+            link = "h2 > span, a.more"
+
+            [[feed]]
+            title = "C"
+            filename = "c.rss"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2 a[href]"
+            summary = "p[title=\"a \\\" b\"]"
+        "#,
+        )
+        .unwrap();
+        let lints: Vec<_> = lint(&config.feed)
+            .into_iter()
+            .map(|lint| format!("{} {}: {}", lint.feed, lint.field, lint.message))
+            .collect();
+        assert_eq!(
+            lints,
+            [
+                "a filename: a has no extension, such as .rss",
+                "a heading: heading is the same as item, so each item is its own heading",
+                "a summary: selector has an unescaped quote: p[title='it's']",
+                "a heading: selector used for the link targets <li> elements, which don't have an href: li.post",
+                "b link: selector used for the link targets <span> elements, which don't have an href: h2 > span, a.more",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lint_duplicate_names() {
+        let config: Config = toml::from_str(
+            r#"
+            [rsspls]
+
+            [[feed]]
+            title = "A"
+            filename = "a/news.rss"
+            [feed.config]
+            url = "http://a.example/"
+            item = "article"
+            heading = "h2 a"
+
+            [[feed]]
+            title = "B"
+            filename = "b/news.rss"
+            [feed.config]
+            url = "http://b.example/"
+            item = "article"
+            heading = "h2 a"
+        "#,
+        )
+        .unwrap();
+        let lints: Vec<_> = lint(&config.feed)
+            .into_iter()
+            .map(|lint| format!("{} {}: {}", lint.feed, lint.field, lint.message))
+            .collect();
+        assert_eq!(
+            lints,
+            ["news name: has the same name as another feed, so they share cache and state files, set name to tell them apart: news"]
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use std::env;
+
+use kuchiki::traits::TendrilSink;
+use log::{debug, info, warn};
+use reqwest::header::{HeaderMap, COOKIE, LOCATION};
+use reqwest::RequestBuilder;
+use simple_eyre::eyre::{self, bail, eyre, WrapErr};
+use url::Url;
+
+use crate::config::{ChannelConfig, LoginConfig};
+use crate::cookies::CookieJar;
+use crate::feed::request_headers;
+use crate::{download, Client};
+
+/// The maximum number of redirects followed after submitting the login form
+const MAX_REDIRECTS: usize = 10;
+/// Suffix of login form fields whose value is the name of an environment variable
+const ENV_SUFFIX: &str = "_env";
+
+/// Log in to the site of a feed by submitting its login form, returning the cookies it set
+///
+/// The login page is fetched first so that cookies and hidden fields, like CSRF tokens, from the
+/// form are submitted along with the configured fields.
+pub async fn log_in(
+    client: &Client,
+    channel_config: &ChannelConfig,
+    login: &LoginConfig,
+) -> eyre::Result<CookieJar> {
+    let http = client
+        .login_http
+        .as_ref()
+        .ok_or_else(|| eyre!("no HTTP client for logging in"))?;
+    let url = Url::parse(&login.url)
+        .wrap_err_with(|| format!("unable to parse {} as a URL", login.url))?;
+    let headers = request_headers(client, &url, &None, channel_config);
+    let mut jar = CookieJar::default();
+
+    info!("logging in at {url}");
+    let (form_url, html) =
+        send(client, http, &headers, &mut jar, http.get(url.clone()), url).await?;
+    let (action, mut fields) = form_fields(&html, &form_url);
+    for (name, value) in &login.form {
+        let value = value.expose()?;
+        let (name, value) = match name.strip_suffix(ENV_SUFFIX) {
+            Some(name) => (
+                name,
+                env::var(value).wrap_err_with(|| {
+                    format!("unable to read login field {name} from environment variable {value}")
+                })?,
+            ),
+            None => (name.as_str(), value.to_string()),
+        };
+        fields.retain(|(existing, _)| existing != name);
+        fields.push((name.to_string(), value));
+    }
+
+    debug!("submitting login form to {action}");
+    let request = http.post(action.clone()).form(&fields);
+    let (page_url, html) = send(client, http, &headers, &mut jar, request, action).await?;
+    if let Some(selector) = &login.success_selector {
+        let doc = kuchiki::parse_html().one(html);
+        if doc.select_first(selector).is_err() {
+            bail!("logging in failed as '{selector}' is not on {page_url}");
+        }
+    }
+    if jar.is_empty() {
+        warn!("logging in at {} didn't set any cookies", login.url);
+    }
+    Ok(jar)
+}
+
+/// Send `request`, following redirects and collecting cookies along the way
+///
+/// Redirects are followed with a GET request, which is what sites expect after a form is
+/// submitted.
+async fn send(
+    client: &Client,
+    http: &reqwest::Client,
+    headers: &HeaderMap,
+    jar: &mut CookieJar,
+    mut request: RequestBuilder,
+    mut url: Url,
+) -> eyre::Result<(Url, String)> {
+    for _ in 0..=MAX_REDIRECTS {
+        let mut headers = headers.clone();
+        if let Some(cookie) = jar.header(&url) {
+            headers.insert(COOKIE, cookie);
+        }
+        let resp = download::send(client, request.headers(headers))
+            .await
+            .wrap_err_with(|| format!("unable to fetch {url}"))?;
+        jar.store(&url, resp.headers());
+
+        let status = resp.status();
+        if status.is_redirection() {
+            let location = resp
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or_else(|| eyre!("redirect from {url} has no location"))?;
+            url = url
+                .join(location)
+                .wrap_err_with(|| format!("invalid redirect from {url}: {location}"))?;
+            debug!("following redirect to {url}");
+            request = http.get(url.clone());
+            continue;
+        }
+        if !status.is_success() {
+            bail!(
+                "failed to fetch {}: {} {}",
+                url,
+                status.as_str(),
+                status.canonical_reason().unwrap_or("Unknown Status")
+            );
+        }
+        let html = download::text(client, resp).await?;
+        return Ok((url, html));
+    }
+    bail!("stopped logging in after {MAX_REDIRECTS} redirects")
+}
+
+/// The URL the login form on a page is submitted to, and its hidden fields
+///
+/// The login form is the one with a password field, or the first form if none have one.
+fn form_fields(html: &str, url: &Url) -> (Url, Vec<(String, String)>) {
+    let doc = kuchiki::parse_html().one(html);
+    let forms: Vec<_> = doc
+        .select("form")
+        .map(|forms| forms.collect())
+        .unwrap_or_default();
+    let form = forms
+        .iter()
+        .find(|form| form.as_node().select_first("input[type=password]").is_ok())
+        .or(forms.first());
+    let Some(form) = form else {
+        return (url.clone(), Vec::new());
+    };
+
+    let action = form
+        .attributes
+        .borrow()
+        .get("action")
+        .map(str::trim)
+        .filter(|action| !action.is_empty())
+        .and_then(|action| url.join(action).ok())
+        .unwrap_or_else(|| url.clone());
+    let fields = form
+        .as_node()
+        .select("input[type=hidden][name]")
+        .map(|inputs| {
+            inputs
+                .map(|input| {
+                    let attrs = input.attributes.borrow();
+                    (
+                        attrs.get("name").unwrap_or_default().to_string(),
+                        attrs.get("value").unwrap_or_default().to_string(),
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    (action, fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_form_fields() {
+        let url = "https://forum.example.com/login".parse().unwrap();
+        let html = r#"<form action="/search"><input type="hidden" name="scope" value="all"></form>
+            <form method="post" action="do-login?next=%2F">
+            <input type="hidden" name="csrf" value="t0ken">
+            <input name="user"> <input type="password" name="pass">
+            </form>"#;
+        let (action, fields) = form_fields(html, &url);
+        assert_eq!(
+            action.as_str(),
+            "https://forum.example.com/do-login?next=%2F"
+        );
+        assert_eq!(fields, [("csrf".to_string(), "t0ken".to_string())]);
+
+        let (action, fields) = form_fields("<p>No form</p>", &url);
+        assert_eq!(action, url);
+        assert!(fields.is_empty());
+    }
+}
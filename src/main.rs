@@ -1,7 +1,52 @@
+mod archive;
+mod assertion;
+#[cfg(feature = "atom")]
+mod atom;
+mod auth;
+mod backoff;
 mod cache;
+mod check;
 mod cli;
 mod config;
+mod cookies;
+#[cfg(feature = "authoring")]
+mod crawl;
+mod curl;
+mod dedup;
+mod digest;
+mod download;
+mod error;
 mod feed;
+mod history;
+#[cfg(feature = "authoring")]
+mod import;
+mod json;
+mod jsonfeed;
+mod lint;
+mod login;
+mod number;
+mod output;
+mod paths;
+mod permissions;
+mod progress;
+#[cfg(feature = "authoring")]
+mod recipe;
+mod render;
+#[cfg(feature = "authoring")]
+mod repl;
+mod score;
+mod secret;
+mod share;
+mod sign;
+#[cfg(feature = "authoring")]
+mod snippet;
+mod state;
+mod syndication;
+mod template;
+mod text;
+mod tls;
+mod transform;
+mod wayback;
 
 #[cfg(windows)]
 mod dirs;
@@ -12,27 +57,52 @@ mod xdg;
 #[cfg(not(windows))]
 use crate::xdg as dirs;
 
+use std::collections::HashMap;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::pin::pin;
 use std::process::ExitCode;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{env, fs};
 
 use atomicwrites::AtomicFile;
 use eyre::{eyre, Report, WrapErr};
-use futures::future;
-use log::{debug, error, info};
-use reqwest::Client as HttpClient;
-use rss::Channel;
+use futures::future::{self, BoxFuture, FutureExt, Shared};
+use log::{debug, error, info, warn};
+use pretty_env_logger::env_logger::{Target, WriteStyle};
+use reqwest::{Client as HttpClient, ClientBuilder};
 use simple_eyre::eyre;
+use time::OffsetDateTime;
 
+use crate::auth::{Authorization, CustomHeaders};
+use crate::backoff::{BackingOff, Backoff, DEFAULT_BACKOFF_AFTER};
 use crate::cache::deserialise_cached_headers;
+use crate::cli::{Cli, Command};
 use crate::config::ConfigHash;
-use crate::config::{ChannelConfig, Config};
-use crate::dirs::Dirs;
+use crate::config::{ChannelConfig, Config, RssplsConfig, RunIf};
+use crate::cookies::CookieJar;
+use crate::dedup::Dedup;
+use crate::dirs::{BaseDirs, Dirs};
+use crate::download::{BudgetExceeded, Downloads};
+use crate::error::{ErrorKind, ResultExt};
 use crate::feed::{process_feed, ProcessResult};
+use crate::history::{FeedRecord, FeedStatus, RunRecord};
+use crate::output::{Output, Provenance};
+use crate::progress::{LogWriter, Progress};
+use crate::share::SharedFetches;
+use crate::state::FeedState;
 
 const RSSPLS_LOG: &str = "RSSPLS_LOG";
+/// How often feeds without a refresh interval are generated in daemon mode
+const DEFAULT_REFRESH: Duration = Duration::from_secs(60 * 60);
+/// How long requests to a failing host are skipped for when `backoff` isn't set
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+/// How long to wait for a connection when `connect_timeout` isn't set
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a request can take when `timeout` isn't set
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Clone)]
 pub struct Client {
@@ -40,6 +110,39 @@ pub struct Client {
     file_urls: bool,
     /// HTTP client
     http: HttpClient,
+    /// Proxy from the configuration file, for fetchers that don't use the HTTP client
+    proxy: Option<String>,
+    /// Timeouts of the HTTP client, for fetchers that don't use it
+    timeouts: Timeouts,
+    /// Directory to save fetched pages to when extraction fails
+    failed_html_dir: Option<PathBuf>,
+    /// Directory caching the pages linked from listings, each under its own URL
+    page_cache_dir: Option<PathBuf>,
+    /// How the generated feeds are written
+    output: Output,
+    /// Whether feeds are generated so that the same input always produces the same output
+    reproducible: bool,
+    /// Time used in place of the current time in reproducible mode, from `SOURCE_DATE_EPOCH`
+    source_date: Option<OffsetDateTime>,
+    /// Items shared between the feeds that are deduplicated
+    dedup: Option<Arc<Dedup>>,
+    /// Bytes downloaded by all feeds
+    downloads: Arc<Downloads>,
+    /// Bytes downloaded for the feed this client is used for
+    downloaded: Arc<AtomicU64>,
+    /// Pages used by more than one feed
+    shared: Arc<SharedFetches>,
+    /// Hosts that requests are skipped to after failures
+    backoff: Arc<Backoff>,
+    /// Client that doesn't follow redirects, so that the cookies set while logging in are kept.
+    /// Only built when a feed logs in.
+    login_http: Option<HttpClient>,
+    /// Custom headers of the feed this client is used for
+    headers: Option<Arc<CustomHeaders>>,
+    /// Cookies sent with requests, set by logging in
+    cookies: Option<Arc<CookieJar>>,
+    /// Credentials sent with requests to the feed's hosts
+    auth: Option<Arc<Authorization>>,
 }
 
 #[tokio::main]
@@ -56,35 +159,168 @@ async fn main() -> ExitCode {
 
 async fn try_main() -> eyre::Result<bool> {
     simple_eyre::install()?;
-    match env::var_os(RSSPLS_LOG) {
-        None => env::set_var(RSSPLS_LOG, "info"),
-        Some(_) => {}
+    let cli = cli::parse_args();
+    if env::var_os(RSSPLS_LOG).is_none() {
+        let verbose = matches!(cli, Ok(Some(ref cli)) if cli.verbose);
+        env::set_var(RSSPLS_LOG, if verbose { "debug" } else { "info" });
     }
-    pretty_env_logger::try_init_custom_env(RSSPLS_LOG)?;
+    // Show progress when generating feeds interactively, with logs written above it
+    let progress = match cli {
+        Ok(Some(Cli {
+            command: Command::Run,
+            daemon: false,
+            ..
+        })) => Progress::new(),
+        _ => None,
+    };
+    let mut logger = pretty_env_logger::formatted_builder();
+    if let Ok(filters) = env::var(RSSPLS_LOG) {
+        logger.parse_filters(&filters);
+    }
+    if let Some(progress) = &progress {
+        logger
+            .target(Target::Pipe(Box::new(LogWriter(Arc::clone(progress)))))
+            .write_style(WriteStyle::Always);
+    }
+    logger.try_init()?;
 
-    let cli = cli::parse_args().wrap_err("unable to parse CLI arguments")?;
+    let cli = cli.wrap_err("unable to parse CLI arguments")?;
     let cli = match cli {
         Some(cli) => cli,
         // Help or version info was printed and we should return
         None => return Ok(true),
     };
 
-    let config = Config::read(cli.config_path)?;
+    match cli.command {
+        Command::Run => {}
+        #[cfg(feature = "authoring")]
+        Command::Repl { url, user_agent } => {
+            let http = authoring_http(
+                cli.config_path.clone(),
+                cli.no_user_config,
+                user_agent.as_deref(),
+            )?;
+            return repl::run(&http, &url, user_agent).await.map(|()| true);
+        }
+        #[cfg(feature = "authoring")]
+        Command::Discover { url } => {
+            let http = authoring_http(cli.config_path.clone(), cli.no_user_config, None)?;
+            return crawl::run(&http, &url).await.map(|()| true);
+        }
+        #[cfg(feature = "authoring")]
+        Command::ImportBridge { location } => {
+            let snippet = import::bridge(
+                &authoring_http(cli.config_path.clone(), cli.no_user_config, None)?,
+                &location,
+            )
+            .await?;
+            print!("{}", snippet.to_toml()?);
+            return Ok(true);
+        }
+        #[cfg(feature = "authoring")]
+        Command::ImportOpml { location } => {
+            let snippet = import::opml(
+                &authoring_http(cli.config_path.clone(), cli.no_user_config, None)?,
+                &location,
+            )
+            .await?;
+            print!("{}", snippet.to_toml()?);
+            return Ok(true);
+        }
+        Command::History { feed } => {
+            let config = Config::read(cli.config_path, cli.no_user_config)?;
+            let dirs = base_dirs(cli.cache_dir, cli.state_dir, &config.rsspls)?;
+            let dirs = Arc::new(Mutex::new(dirs));
+            let runs = history::read(&history_path(&dirs)?)?;
+            history::show(&runs, feed.as_deref())?;
+            return Ok(true);
+        }
+        Command::Check { json, fetch } => {
+            let mut config = Config::read(cli.config_path, cli.no_user_config)?;
+            if !cli.feeds.is_empty() {
+                config.feed = select_feeds(config.feed, &cli.feeds)?;
+            }
+            let mut lints = lint::lint(&config.feed);
+            if fetch {
+                config.resolve_secrets()?;
+                let client = Client::new(&config.rsspls, &config.feed)?;
+                let (counts, problems) =
+                    check::check_pages(&client, &config.feed, ConfigHash(&config.hash)).await;
+                for count in &counts {
+                    if json {
+                        println!(
+                            "{}",
+                            serde_json::to_string(count).wrap_err("unable to serialise count")?
+                        );
+                        continue;
+                    }
+                    let matches = match count.items {
+                        Some(items) => format!("{} of {items} items", count.matches),
+                        None => count.matches.to_string(),
+                    };
+                    println!(
+                        "{}: {}: {} '{}': {matches}",
+                        count.feed, count.url, count.field, count.selector
+                    );
+                }
+                lints.extend(problems);
+            }
+            for lint in &lints {
+                if json {
+                    println!(
+                        "{}",
+                        serde_json::to_string(lint).wrap_err("unable to serialise lint")?
+                    );
+                } else {
+                    println!("{}: {}: {}", lint.feed, lint.field, lint.message);
+                }
+            }
+            return Ok(lints.is_empty());
+        }
+        Command::Paths => {
+            let mut config = Config::read(cli.config_path, cli.no_user_config)?;
+            if !cli.feeds.is_empty() {
+                config.feed = select_feeds(config.feed, &cli.feeds)?;
+            }
+            let output_dir = output_dir(cli.output_path, &config)?;
+            let dirs = base_dirs(cli.cache_dir, cli.state_dir, &config.rsspls)?;
+            paths::show(&config, output_dir.as_deref(), &dirs)?;
+            return Ok(true);
+        }
+        Command::CacheGc => {
+            let config = Config::read(cli.config_path, cli.no_user_config)?;
+            let dirs = base_dirs(cli.cache_dir, cli.state_dir, &config.rsspls)?;
+            let dirs = Arc::new(Mutex::new(dirs));
+            let stats = collect_page_cache(&dirs, &config.rsspls)?;
+            println!(
+                "removed {} pages ({} bytes), {} bytes remain",
+                stats.removed, stats.freed, stats.size
+            );
+            return Ok(true);
+        }
+        #[cfg(feature = "authoring")]
+        Command::RecipeInstall {
+            name,
+            params,
+            allow_commands,
+        } => {
+            let config_path = Config::path(cli.config_path)?;
+            let config = Config::read(Some(config_path.clone()), false)?;
+            let http = http_client_builder(&config.rsspls)?
+                .build()
+                .wrap_err("unable to build HTTP client")?;
+            recipe::install(&http, &config, &config_path, &name, &params, allow_commands).await?;
+            return Ok(true);
+        }
+    }
 
-    // Determine output directory
-    let output_dir = match cli.output_path {
-        Some(path) => Some(path),
-        None => config
-            .rsspls
-            .output
-            .map(|ref path| {
-                dirs::home_dir()
-                    .ok_or_else(|| eyre!("unable to determine home directory"))
-                    .map(|home| expand_tilde(path, home))
-            })
-            .transpose()?,
+    let mut config = Config::read(cli.config_path, cli.no_user_config)?;
+    if !cli.feeds.is_empty() {
+        config.feed = select_feeds(config.feed, &cli.feeds)?;
     }
-    .ok_or_else(|| {
+    config.resolve_secrets()?;
+
+    let output_dir = output_dir(cli.output_path, &config)?.ok_or_else(|| {
         eyre!("output directory must be supplied via --output or be present in configuration file")
     })?;
 
@@ -99,77 +335,627 @@ async fn try_main() -> eyre::Result<bool> {
         info!("created output directory: {}", output_dir.display());
     }
 
-    // Set up the HTTP client
-    let connect_timeout = Duration::from_secs(10);
-    let timeout = Duration::from_secs(30);
-    let mut client_builder = HttpClient::builder()
-        .connect_timeout(connect_timeout)
-        .timeout(timeout);
+    let dirs = base_dirs(cli.cache_dir, cli.state_dir, &config.rsspls)?;
+    let failed_html_dir = if config.rsspls.save_failed_html {
+        let dir = dirs
+            .place_cache_file("failed")
+            .wrap_err("unable to create path to failed page directory")?;
+        Some(dir)
+    } else {
+        None
+    };
+    let page_cache_dir = dirs
+        .place_cache_file("pages")
+        .wrap_err("unable to create path to page cache directory")?;
+    let hosts_path = dirs
+        .place_cache_file("hosts.toml")
+        .wrap_err("unable to create path to host state file")?;
 
-    // Add proxy if provided
-    match config.rsspls.proxy {
-        Some(proxy) => {
-            debug!("using proxy from configuration file: {}", proxy);
-            client_builder = client_builder.proxy(reqwest::Proxy::all(proxy)?)
+    let source_date = if config.rsspls.reproducible {
+        source_date_epoch()?
+    } else {
+        None
+    };
+
+    // Wrap up the base directories for sharing between tasks. Mutex is used so that only one
+    // thread at a time will attempt to create cache directories.
+    let dirs = Arc::new(Mutex::new(dirs));
+
+    let runner = Runner {
+        config: config.rsspls,
+        config_hash: Arc::new(config.hash),
+        output_dir,
+        dirs,
+        failed_html_dir,
+        page_cache_dir,
+        hosts_path,
+        source_date,
+    };
+    if cli.daemon {
+        runner.daemon(config.feed).await
+    } else {
+        runner.run(config.feed, progress.as_ref()).await
+    }
+}
+
+impl Client {
+    /// A client for fetching the pages of `feeds` that doesn't cache or write anything, as used
+    /// when checking their selectors. Runs replace the fields that differ for them.
+    fn new(config: &RssplsConfig, feeds: &[ChannelConfig]) -> eyre::Result<Client> {
+        let login_http = if feeds.iter().any(|feed| feed.login.is_some()) {
+            let http = http_client_builder(config)?
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .wrap_err("unable to build HTTP client")?;
+            Some(http)
+        } else {
+            None
+        };
+        Ok(Client {
+            file_urls: config.file_urls,
+            http: http_client_builder(config)?
+                .build()
+                .wrap_err("unable to build HTTP client")?,
+            proxy: config.proxy.clone(),
+            timeouts: Timeouts::new(config),
+            failed_html_dir: None,
+            page_cache_dir: None,
+            output: Output::new(&RssplsConfig::default(), None)?,
+            reproducible: false,
+            source_date: None,
+            dedup: None,
+            downloads: Arc::new(Downloads::new(config.download_budget)),
+            downloaded: Arc::default(),
+            shared: Arc::default(),
+            backoff: Arc::default(),
+            login_http,
+            headers: None,
+            cookies: None,
+            auth: None,
+        })
+    }
+}
+
+/// The settings shared by every run of the feeds
+struct Runner {
+    config: RssplsConfig,
+    config_hash: Arc<String>,
+    output_dir: PathBuf,
+    dirs: Dirs,
+    failed_html_dir: Option<PathBuf>,
+    page_cache_dir: PathBuf,
+    /// Where the failures of each host are kept between runs
+    hosts_path: PathBuf,
+    source_date: Option<OffsetDateTime>,
+}
+
+impl Runner {
+    /// Generate `feeds`, returning whether they were all generated without error
+    async fn run(
+        &self,
+        feeds: Vec<ChannelConfig>,
+        progress: Option<&Arc<Progress>>,
+    ) -> eyre::Result<bool> {
+        // Set up the HTTP client
+        let client = Client {
+            failed_html_dir: self.failed_html_dir.clone(),
+            page_cache_dir: Some(self.page_cache_dir.clone()),
+            output: Output::new(&self.config, self.source_date)?,
+            reproducible: self.config.reproducible,
+            source_date: self.source_date,
+            dedup: Dedup::new(&feeds).map(Arc::new),
+            shared: Arc::new(SharedFetches::new(feeds.iter().flat_map(|feed| {
+                feed.config
+                    .urls(self.source_date.unwrap_or_else(OffsetDateTime::now_utc))
+            }))),
+            backoff: Arc::new(Backoff::read(
+                &self.hosts_path,
+                self.config.backoff.unwrap_or(DEFAULT_BACKOFF),
+                self.config.backoff_after.unwrap_or(DEFAULT_BACKOFF_AFTER),
+            )),
+            ..Client::new(&self.config, &feeds)?
+        };
+
+        // Feeds that change how responses are decompressed need their own client
+        let feeds = feeds
+            .into_iter()
+            .map(|feed| {
+                let client = match &feed.compression {
+                    Some(compression) => Client {
+                        http: http_client_builder(&self.config)?
+                            .gzip(compression.gzip)
+                            .brotli(compression.brotli)
+                            .deflate(compression.deflate)
+                            .build()
+                            .wrap_err("unable to build HTTP client")?,
+                        downloaded: Arc::default(),
+                        ..client.clone()
+                    },
+                    None => Client {
+                        downloaded: Arc::default(),
+                        ..client.clone() // Client uses Arc internally
+                    },
+                };
+                Ok((feed, client))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        // Spawn the tasks. Feeds that depend on another feed wait for its outcome so they are set up
+        // after the feed they depend on.
+        let started = OffsetDateTime::now_utc();
+        let spinner = progress.as_ref().map(|progress| {
+            progress.set_total(feeds.len());
+            let progress = Arc::clone(progress);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_millis(100));
+                loop {
+                    interval.tick().await;
+                    progress.tick();
+                }
+            })
+        });
+        let mut outcomes: HashMap<String, SharedOutcome> = HashMap::new();
+        let mut handles = Vec::new();
+        let mut pending = feeds;
+        while !pending.is_empty() {
+            let (ready, waiting): (Vec<_>, Vec<_>) =
+                pending
+                    .into_iter()
+                    .partition(|(feed, _)| match &feed.depends_on {
+                        Some(dependency) => outcomes.contains_key(dependency),
+                        None => true,
+                    });
+            if ready.is_empty() {
+                // This is checked when the config is read
+                return Err(eyre!("feeds have circular dependencies"));
+            }
+            for (feed, client) in ready {
+                let dependency = feed
+                    .depends_on
+                    .as_ref()
+                    .and_then(|dependency| outcomes.get(dependency))
+                    .cloned();
+                let name = feed.name().to_string();
+                let outcome = run_feed(
+                    feed,
+                    client,
+                    dependency,
+                    Arc::clone(&self.config_hash),
+                    self.output_dir.clone(),
+                    Arc::clone(&self.dirs),
+                    progress.cloned(),
+                )
+                .boxed()
+                .shared();
+                outcomes.insert(name, outcome.clone());
+                handles.push(tokio::spawn(outcome));
+            }
+            pending = waiting;
         }
-        None => {
-            if let Ok(proxy) = env::var("http_proxy") {
-                debug!("using http proxy from 'http_proxy' env var: {}", proxy);
-                client_builder = client_builder.proxy(reqwest::Proxy::http(proxy)?)
+
+        // Run all the futures at the same time
+        // The ? here will fail on an error if the JoinHandle fails
+        let feeds = future::try_join_all(handles).await?;
+        if let (Some(progress), Some(spinner)) = (&progress, spinner) {
+            spinner.abort();
+            progress.clear();
+        }
+        let ok = !client.downloads.is_exceeded()
+            && feeds
+                .iter()
+                .all(|record| record.status != FeedStatus::Error);
+        print!("{}", history::run_summary(&feeds));
+        info!(
+            "downloaded {} in total",
+            download::format_size(client.downloads.total())
+        );
+
+        // Failing to record history shouldn't fail the run
+        let record = RunRecord {
+            timestamp: started.unix_timestamp(),
+            feeds,
+        };
+        if let Err(report) =
+            history_path(&self.dirs).and_then(|path| history::append(&path, &record))
+        {
+            warn!("{:?}", report);
+        }
+        if let Err(report) = collect_page_cache(&self.dirs, &self.config) {
+            warn!("{:?}", report);
+        }
+        if let Err(report) = client.backoff.write(&self.hosts_path) {
+            warn!("{:?}", report);
+        }
+
+        Ok(ok)
+    }
+
+    /// Generate each feed every time its refresh interval elapses, until SIGTERM or Ctrl-C
+    ///
+    /// The feeds a due feed depends on are generated with it. A run that's in progress when the
+    /// signal arrives is finished so that no output is left half written.
+    async fn daemon(&self, feeds: Vec<ChannelConfig>) -> eyre::Result<bool> {
+        let mut shutdown = pin!(shutdown_signal());
+        let mut due: HashMap<String, Instant> = HashMap::new();
+        loop {
+            let now = Instant::now();
+            let names: Vec<_> = feeds
+                .iter()
+                .filter(|feed| match due.get(feed.name()) {
+                    Some(due) => *due <= now,
+                    None => true,
+                })
+                .map(|feed| feed.name().to_string())
+                .collect();
+            if !names.is_empty() {
+                let selected = select_feeds(feeds.clone(), &names)?;
+                for feed in &selected {
+                    let refresh = feed.refresh.unwrap_or(DEFAULT_REFRESH);
+                    due.insert(feed.name().to_string(), now + refresh);
+                }
+                let mut run = pin!(self.run(selected, None));
+                let result = tokio::select! {
+                    result = &mut run => result,
+                    signal = &mut shutdown => {
+                        signal?;
+                        info!("finishing the current run before shutting down");
+                        if let Err(report) = run.await {
+                            error!("{:?}", report);
+                        }
+                        return Ok(true);
+                    }
+                };
+                if let Err(report) = result {
+                    error!("{:?}", report);
+                }
             }
-            if let Ok(proxy) = env::var("HTTPS_PROXY") {
-                debug!("using https proxy from 'HTTPS_PROXY' env var: {}", proxy);
-                client_builder = client_builder.proxy(reqwest::Proxy::https(proxy)?)
+
+            let next = due.values().min().copied().unwrap_or(now);
+            debug!(
+                "next run in {}s",
+                next.saturating_duration_since(Instant::now()).as_secs()
+            );
+            tokio::select! {
+                () = tokio::time::sleep_until(next.into()) => {}
+                signal = &mut shutdown => {
+                    signal?;
+                    info!("shutting down");
+                    return Ok(true);
+                }
             }
         }
-    };
+    }
+}
 
-    let client = Client {
-        file_urls: config.rsspls.file_urls,
-        http: client_builder
-            .build()
-            .wrap_err("unable to build HTTP client")?,
-    };
+/// Wait for SIGTERM or Ctrl-C
+async fn shutdown_signal() -> eyre::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
 
-    // Wrap up xdg::BaseDirectories for sharing between tasks. Mutex is used so that only one
-    // thread at a time will attempt to create cache directories.
-    let dirs = dirs::new()?;
-    let dirs = Arc::new(Mutex::new(dirs));
+        let mut terminate =
+            signal(SignalKind::terminate()).wrap_err("unable to listen for SIGTERM")?;
+        tokio::select! {
+            _ = terminate.recv() => info!("received SIGTERM"),
+            result = tokio::signal::ctrl_c() => result.wrap_err("unable to listen for Ctrl-C")?,
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .wrap_err("unable to listen for Ctrl-C")?;
+    Ok(())
+}
 
-    // Spawn the tasks
-    let config_hash = Arc::new(config.hash.clone());
-    let futures = config.feed.into_iter().map(|feed| {
-        let client = client.clone(); // Client uses Arc internally
-        let output_dir = output_dir.clone();
-        let dirs = Arc::clone(&dirs);
-        let config_hash = Arc::clone(&config_hash);
-        tokio::spawn(async move {
-            let res = process(
-                &feed,
-                &client,
-                ConfigHash(config_hash.as_str()),
-                output_dir,
-                dirs,
-            )
-            .await;
-            if let Err(ref report) = res {
-                // Eat errors when processing feeds so that we don't stop processing the others.
-                // Errors are reported, then we return a boolean indicating success or not, which
-                // is used to set the exit status of the program later.
-                error!("{:?}", report);
+type SharedOutcome = Shared<BoxFuture<'static, FeedRecord>>;
+
+/// Process `feed` once the feed it depends on, if any, has been processed
+async fn run_feed(
+    feed: ChannelConfig,
+    client: Client,
+    dependency: Option<SharedOutcome>,
+    config_hash: Arc<String>,
+    output_dir: PathBuf,
+    dirs: Dirs,
+    progress: Option<Arc<Progress>>,
+) -> FeedRecord {
+    let record = run_feed_after_dependency(
+        feed,
+        client,
+        dependency,
+        config_hash,
+        output_dir,
+        dirs,
+        progress.as_deref(),
+    )
+    .await;
+    if let Some(progress) = &progress {
+        progress.finished(&record);
+    }
+    record
+}
+
+async fn run_feed_after_dependency(
+    mut feed: ChannelConfig,
+    client: Client,
+    dependency: Option<SharedOutcome>,
+    config_hash: Arc<String>,
+    output_dir: PathBuf,
+    dirs: Dirs,
+    progress: Option<&Progress>,
+) -> FeedRecord {
+    if let Some(dependency) = dependency {
+        let dependency = dependency.await;
+        if let Some(reason) = skip_reason(&feed, &dependency) {
+            info!("skipping {} as {reason}", feed.name());
+            if let Some(dedup) = &client.dedup {
+                dedup.finish(feed.name());
             }
-            res.is_ok()
-        })
+            return FeedRecord {
+                name: feed.name().to_string(),
+                filename: feed.filename,
+                status: FeedStatus::Skipped,
+                items: 0,
+                new_items: 0,
+                error: None,
+                failed_assertions: Vec::new(),
+                downloaded: 0,
+            };
+        }
+        fill_in_dependency(&mut feed, &output_dir.join(&dependency.filename));
+    }
+    if let Some(progress) = progress {
+        progress.started(feed.name());
+    }
+
+    // Feeds still being processed when the download budget is used up are stopped
+    let res = tokio::select! {
+        res = process(
+            &feed,
+            &client,
+            ConfigHash(config_hash.as_str()),
+            output_dir,
+            dirs,
+        ) => res,
+        exceeded = client.downloads.exceeded() => Err(Report::new(exceeded)),
+    };
+    // Don't let the other deduplicated feeds wait for this one if it finished early
+    if let Some(dedup) = &client.dedup {
+        dedup.finish(feed.name());
+    }
+    let mut record = res.unwrap_or_else(|report| {
+        // Feeds on hosts that are being backed off from are tried again once the back off is
+        // over, and those stopped by the download budget in the next run
+        if report
+            .chain()
+            .any(|err| err.is::<BackingOff>() || err.is::<BudgetExceeded>())
+        {
+            warn!("skipping {}: {:#}", feed.name(), report);
+            return FeedRecord {
+                name: feed.name().to_string(),
+                filename: feed.filename,
+                status: FeedStatus::Skipped,
+                items: 0,
+                new_items: 0,
+                error: None,
+                failed_assertions: Vec::new(),
+                downloaded: 0,
+            };
+        }
+        // Eat errors when processing feeds so that we don't stop processing the others.
+        // Errors are reported, then the status is used to set the exit status of the program
+        // later.
+        error!("{:?}", report);
+        FeedRecord {
+            name: feed.name().to_string(),
+            filename: feed.filename,
+            status: FeedStatus::Error,
+            items: 0,
+            new_items: 0,
+            error: error::kind(&report),
+            failed_assertions: Vec::new(),
+            downloaded: 0,
+        }
     });
+    record.downloaded = client.downloaded.load(Ordering::Relaxed);
+    record
+}
 
-    // Run all the futures at the same time
-    // The ? here will fail on an error if the JoinHandle fails
-    let ok = future::try_join_all(futures)
-        .await?
+/// Select the feeds named in `references` and the feeds they depend on, in configured order
+fn select_feeds(
+    feeds: Vec<ChannelConfig>,
+    references: &[String],
+) -> eyre::Result<Vec<ChannelConfig>> {
+    let mut selected = Vec::new();
+    for reference in references {
+        let mut feed = Some(
+            config::find_feed(&feeds, reference)
+                .ok_or_else(|| eyre!("no feed named {reference} is configured"))?,
+        );
+        // Dependencies were checked when the config was read, so this ends
+        while let Some(next) = feed {
+            if selected.contains(&next.name()) {
+                break;
+            }
+            selected.push(next.name());
+            feed = next
+                .depends_on
+                .as_deref()
+                .and_then(|dependency| config::find_feed(&feeds, dependency));
+        }
+    }
+    let selected: Vec<_> = selected.into_iter().map(str::to_string).collect();
+    Ok(feeds
         .into_iter()
-        .fold(true, |ok, succeeded| ok & succeeded);
+        .filter(|feed| selected.iter().any(|name| name == feed.name()))
+        .collect())
+}
+
+/// The reason `feed` shouldn't be processed given the outcome of the feed it depends on
+fn skip_reason(feed: &ChannelConfig, dependency: &FeedRecord) -> Option<String> {
+    match (dependency.status, feed.run_if) {
+        (FeedStatus::Error | FeedStatus::Skipped, _) => {
+            Some(format!("{} wasn't processed", dependency.name))
+        }
+        (_, RunIf::NewItems) if dependency.new_items == 0 => {
+            Some(format!("{} had no new items", dependency.name))
+        }
+        (FeedStatus::NotModified, RunIf::Items) => None,
+        (_, RunIf::Items) if dependency.items == 0 => {
+            Some(format!("{} had no items", dependency.name))
+        }
+        _ => None,
+    }
+}
+
+/// Fill in `{depends.link}` and `{depends.title}` placeholders in the URLs of `feed` from the
+/// first item of the dependency's output
+fn fill_in_dependency(feed: &mut ChannelConfig, dependency_path: &Path) {
+    if !feed.config.url.iter().any(|url| url.contains("{depends.")) {
+        return;
+    }
+    let first_item = fs::read_to_string(dependency_path)
+        .map_err(Report::from)
+        .and_then(|xml| syndication::parse_items(&xml))
+        .map(|items| items.into_iter().next())
+        .unwrap_or_else(|err| {
+            warn!("unable to read {}: {err}", dependency_path.display());
+            None
+        });
+    let Some(item) = first_item else {
+        return;
+    };
+    for url in &mut feed.config.url {
+        *url = template::render(url, |name| match name {
+            "depends.link" => item.link().map(str::to_string),
+            "depends.title" => item.title().map(str::to_string),
+            _ => None,
+        });
+    }
+}
+
+/// The time set in the `SOURCE_DATE_EPOCH` environment variable
+fn source_date_epoch() -> eyre::Result<Option<OffsetDateTime>> {
+    let Ok(epoch) = env::var("SOURCE_DATE_EPOCH") else {
+        return Ok(None);
+    };
+    epoch
+        .trim()
+        .parse::<i64>()
+        .map_err(Report::from)
+        .and_then(|epoch| OffsetDateTime::from_unix_timestamp(epoch).map_err(Report::from))
+        .map(Some)
+        .wrap_err_with(|| format!("invalid SOURCE_DATE_EPOCH: {epoch}"))
+}
+
+/// The output directory from the command line or config, if either has one
+fn output_dir(cli_path: Option<PathBuf>, config: &Config) -> eyre::Result<Option<PathBuf>> {
+    match cli_path {
+        Some(path) => Ok(Some(path)),
+        None => config
+            .rsspls
+            .output
+            .as_ref()
+            .map(|path| dirs::expand_path(path))
+            .transpose()
+            .map(|path| {
+                path.map(|path| {
+                    if config.rsspls.output_relative_to_config {
+                        relative_to_file(&config.path, path)
+                    } else {
+                        path
+                    }
+                })
+            }),
+    }
+}
+
+/// The base directories, with the cache and state directories given on the command line or in
+/// `config` in place of the defaults
+fn base_dirs(
+    cli_cache_dir: Option<PathBuf>,
+    cli_state_dir: Option<PathBuf>,
+    config: &RssplsConfig,
+) -> eyre::Result<BaseDirs> {
+    let dir = |cli_dir: Option<PathBuf>, config_dir: &Option<String>| match cli_dir {
+        Some(dir) => Ok(Some(dir)),
+        None => config_dir
+            .as_ref()
+            .map(|dir| dirs::expand_path(dir))
+            .transpose(),
+    };
+    dirs::new(
+        dir(cli_cache_dir, &config.cache_dir)?,
+        dir(cli_state_dir, &config.state_dir)?,
+    )
+}
 
-    Ok(ok)
+/// Shrink the page cache to the size set in `config`
+fn collect_page_cache(dirs: &Dirs, config: &RssplsConfig) -> eyre::Result<cache::GcStats> {
+    let dir = {
+        let dirs = dirs.lock().map_err(|_| eyre!("unable to acquire mutex"))?;
+        dirs.place_cache_file("pages")
+            .wrap_err("unable to create path to page cache directory")?
+    };
+    let max_size = config
+        .page_cache_size
+        .unwrap_or(cache::DEFAULT_PAGE_CACHE_SIZE);
+    cache::gc(&dir, max_size).wrap_err("unable to collect page cache")
+}
+
+fn history_path(dirs: &Dirs) -> eyre::Result<PathBuf> {
+    let dirs = dirs.lock().map_err(|_| eyre!("unable to acquire mutex"))?;
+    place_state_file(&dirs, "history.jsonl").wrap_err("unable to create path to history file")
+}
+
+/// The path of `name` in the state directory
+///
+/// Earlier versions kept the state in the cache directory, a file left there is moved to the
+/// state directory the first time it's used.
+fn place_state_file(dirs: &BaseDirs, name: &str) -> eyre::Result<PathBuf> {
+    let path = dirs.place_state_file(name)?;
+    let old_path = dirs.cache_file(name)?;
+    if old_path != path {
+        move_file(&old_path, &path)?;
+    }
+    Ok(path)
+}
+
+/// Create the paths of the cache and state files of `feed`
+///
+/// The files of a feed that has been given a name since the last run are renamed after it.
+fn place_feed_files(dirs: &BaseDirs, feed: &ChannelConfig) -> eyre::Result<(PathBuf, PathBuf)> {
+    let cache_path = dirs
+        .place_cache_file(feed.cache_filename())
+        .wrap_err("unable to create path to cache file")?;
+    let state_path = place_state_file(dirs, &feed.state_filename())
+        .wrap_err("unable to create path to state file")?;
+    if let Some((cache_filename, state_filename)) = feed.unnamed_filenames() {
+        move_file(&dirs.cache_file(&cache_filename)?, &cache_path)?;
+        move_file(&place_state_file(dirs, &state_filename)?, &state_path)?;
+    }
+    Ok((cache_path, state_path))
+}
+
+/// Move the file at `old_path` to `path`, unless it's missing or `path` already exists
+fn move_file(old_path: &Path, path: &Path) -> eyre::Result<()> {
+    if !old_path.exists() || path.exists() {
+        return Ok(());
+    }
+    info!("moving {} to {}", old_path.display(), path.display());
+    // Renaming fails if the directories are on different file systems
+    if fs::rename(old_path, path).is_err() {
+        fs::copy(old_path, path).wrap_err_with(|| {
+            format!(
+                "unable to move {} to {}",
+                old_path.display(),
+                path.display()
+            )
+        })?;
+        fs::remove_file(old_path)
+            .wrap_err_with(|| format!("unable to remove {}", old_path.display()))?;
+    }
+    Ok(())
 }
 
 async fn process(
@@ -178,7 +964,7 @@ async fn process(
     config_hash: ConfigHash<'_>,
     output_dir: PathBuf,
     dirs: Dirs,
-) -> Result<(), Report> {
+) -> Result<FeedRecord, Report> {
     // Generate paths up front so we report any errors before making requests
     let filename = Path::new(&feed.filename);
     let filename = filename
@@ -186,52 +972,193 @@ async fn process(
         .map(Path::new)
         .ok_or_else(|| eyre!("{} is not a valid file name", filename.display()))?;
     let output_path = output_dir.join(filename);
-    let cache_filename = filename.with_extension("toml");
-    let cache_path = {
+    let (cache_path, state_path) = {
         let dirs = dirs.lock().map_err(|_| eyre!("unable to acquire mutex"))?;
-        dirs.place_cache_file(&cache_filename)
-            .wrap_err("unable to create path to cache file")
+        place_feed_files(&dirs, feed)
     }?;
     let cached_headers = deserialise_cached_headers(&cache_path, config_hash);
+    let mut state = FeedState::read(&state_path);
+    let fetched = client.source_date.unwrap_or_else(OffsetDateTime::now_utc);
+    let provenance = Provenance {
+        sources: feed.config.urls(fetched),
+        fetched,
+        config_hash: config_hash.0,
+    };
 
-    process_feed(client, feed, config_hash, &cached_headers)
+    process_feed(client, feed, config_hash, &cached_headers, &mut state)
         .await
+        .map_err(Report::from)
         .and_then(|ref process_result| {
             match process_result {
-                ProcessResult::NotModified => Ok(()),
-                ProcessResult::Ok { channel, headers } => {
+                ProcessResult::NotModified => Ok(FeedRecord {
+                    name: feed.name().to_string(),
+                    filename: feed.filename.clone(),
+                    status: FeedStatus::NotModified,
+                    items: 0,
+                    new_items: 0,
+                    error: None,
+                    failed_assertions: Vec::new(),
+                    downloaded: 0,
+                }),
+                ProcessResult::Ok {
+                    channel,
+                    splits,
+                    headers,
+                    new_items,
+                    failed_assertions,
+                } => {
                     // TODO: channel.validate()
-                    write_channel(channel, &output_path).wrap_err_with(|| {
-                        format!("unable to write output file: {}", output_path.display())
-                    })?;
+                    client
+                        .output
+                        .write(channel, feed.format, &output_path, &provenance)
+                        .kind(ErrorKind::Write)?;
+
+                    for (split_filename, split) in splits {
+                        let split_path = Path::new(split_filename)
+                            .file_name()
+                            .map(|name| output_dir.join(name))
+                            .ok_or_else(|| eyre!("{} is not a valid file name", split_filename))?;
+                        client
+                            .output
+                            .write(split, feed.format, &split_path, &provenance)
+                            .kind(ErrorKind::Write)?;
+                    }
+
+                    state.write(&state_path).kind(ErrorKind::Write)?;
 
-                    // Update the cache
+                    // Update the cache, only once the output has been written so that a cache hit
+                    // always corresponds to an up to date feed
                     if let Some(headers) = headers {
                         debug!("write cache {}", cache_path.display());
-                        fs::write(cache_path, headers).wrap_err("unable to write to cache")?;
+                        write_cache(&cache_path, headers)
+                            .wrap_err("unable to write to cache")
+                            .kind(ErrorKind::Write)?;
                     }
 
-                    Ok(())
+                    Ok(FeedRecord {
+                        name: feed.name().to_string(),
+                        filename: feed.filename.clone(),
+                        status: FeedStatus::Ok,
+                        items: channel.items().len(),
+                        new_items: *new_items,
+                        error: None,
+                        failed_assertions: failed_assertions.clone(),
+                        downloaded: 0,
+                    })
                 }
             }
         })
-        .wrap_err_with(|| format!("error processing feed for {}", feed.config.url))
+        .wrap_err_with(|| {
+            format!(
+                "error processing feed {} for {}",
+                feed.name(),
+                feed.config.url.join(", ")
+            )
+        })
+}
+
+fn write_cache(cache_path: &Path, headers: &str) -> Result<(), Report> {
+    // Like the output, the cache is written atomically so that a crash can't leave it truncated
+    Ok(write_atomically(cache_path, headers.as_bytes())?)
+}
+
+/// Write `contents` to a temporary file that is synced and then moved to `path`, so that a crash
+/// can't leave the file at `path` truncated
+fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let file = AtomicFile::new(path, atomicwrites::AllowOverwrite);
+    file.write(|f| f.write_all(contents))
+        .map_err(|err| match err {
+            atomicwrites::Error::Internal(err) | atomicwrites::Error::User(err) => err,
+        })
+}
+
+/// How long requests wait to connect, and can take in total
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    pub connect: Duration,
+    pub total: Duration,
+}
+
+impl Timeouts {
+    fn new(config: &RssplsConfig) -> Timeouts {
+        Timeouts {
+            connect: config.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            total: config.timeout.unwrap_or(DEFAULT_TIMEOUT),
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts::new(&RssplsConfig::default())
+    }
 }
 
-fn write_channel(channel: &Channel, output_path: &Path) -> Result<(), Report> {
-    // Write the new file into a temporary location, then move it into place
-    let file = AtomicFile::new(output_path, atomicwrites::AllowOverwrite);
-    file.write(|f| {
-        info!("write {}", output_path.display());
-        channel
-            .write_to(f)
-            .map(drop)
-            .wrap_err("unable to write feed")
-    })
-    .map_err(|err| match err {
-        atomicwrites::Error::Internal(atomic_err) => atomic_err.into(),
-        atomicwrites::Error::User(myerr) => myerr,
-    })
+/// The HTTP client used by the commands that help write the config, set up from the
+/// configuration file at `config_path` if there is one, sending `user_agent` if set
+#[cfg(feature = "authoring")]
+fn authoring_http(
+    config_path: Option<PathBuf>,
+    no_user_config: bool,
+    user_agent: Option<&str>,
+) -> eyre::Result<HttpClient> {
+    let has_config = config_path.is_some()
+        || no_user_config
+        || Config::path(None)?.exists()
+        || dirs::system_config_file().is_some_and(|path| path.exists());
+    let config = if has_config {
+        Config::read(config_path, no_user_config)?.rsspls
+    } else {
+        RssplsConfig::default()
+    };
+    let mut builder = http_client_builder(&config)?;
+    if let Some(user_agent) = user_agent {
+        builder = builder.user_agent(user_agent);
+    }
+    builder.build().wrap_err("unable to build HTTP client")
+}
+
+/// Set up the HTTP client builder with the timeouts and proxy from `config`
+fn http_client_builder(config: &RssplsConfig) -> eyre::Result<ClientBuilder> {
+    let timeouts = Timeouts::new(config);
+    let mut client_builder = HttpClient::builder()
+        .connect_timeout(timeouts.connect)
+        .timeout(timeouts.total);
+
+    match config.http2 {
+        Some(true) => client_builder = client_builder.http2_prior_knowledge(),
+        Some(false) => client_builder = client_builder.http1_only(),
+        None => {}
+    }
+    if let Some(timeout) = config.pool_idle_timeout {
+        client_builder = client_builder.pool_idle_timeout(timeout);
+    }
+    if let Some(max) = config.pool_max_idle_per_host {
+        client_builder = client_builder.pool_max_idle_per_host(max);
+    }
+    if let Some(roots) = config.tls_roots {
+        client_builder = tls::configure(client_builder, roots)?;
+    }
+
+    // Add proxy if provided
+    match &config.proxy {
+        Some(proxy) => {
+            debug!("using proxy from configuration file: {}", proxy);
+            client_builder = client_builder.proxy(reqwest::Proxy::all(proxy)?)
+        }
+        None => {
+            if let Ok(proxy) = env::var("http_proxy") {
+                debug!("using http proxy from 'http_proxy' env var: {}", proxy);
+                client_builder = client_builder.proxy(reqwest::Proxy::http(proxy)?)
+            }
+            if let Ok(proxy) = env::var("HTTPS_PROXY") {
+                debug!("using https proxy from 'HTTPS_PROXY' env var: {}", proxy);
+                client_builder = client_builder.proxy(reqwest::Proxy::https(proxy)?)
+            }
+        }
+    };
+
+    Ok(client_builder)
 }
 
 pub fn version_string() -> String {
@@ -258,9 +1185,133 @@ fn expand_tilde<P: Into<PathBuf>>(path: P, mut home: PathBuf) -> PathBuf {
     }
 }
 
+/// Resolve `path` against the directory containing `file` if it's relative
+fn relative_to_file(file: &Path, path: PathBuf) -> PathBuf {
+    match file.parent() {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use basic_toml as toml;
+
+    #[test]
+    fn test_select_feeds() {
+        let feed = |name: &str, depends_on: &str| {
+            format!(
+                r#"
+                [[feed]]
+                title = "{name}"
+                filename = "{name}.rss"
+                depends_on = "{depends_on}"
+                [feed.config]
+                url = "http://example.com/"
+                item = "article"
+                heading = "h2"
+                "#
+            )
+        };
+        let raw = format!(
+            "[rsspls]\n{}",
+            [
+                feed("a", "b"),
+                feed("b", "c.rss"),
+                feed("c", ""),
+                feed("d", "")
+            ]
+            .concat()
+        );
+        let mut config: Config = toml::from_str(&raw).unwrap();
+        for feed in &mut config.feed {
+            feed.depends_on = feed.depends_on.take().filter(|name| !name.is_empty());
+        }
+
+        let names = |feeds: Vec<ChannelConfig>| -> Vec<String> {
+            feeds.iter().map(|feed| feed.name().to_string()).collect()
+        };
+        let selected = select_feeds(config.feed.clone(), &["d".to_string(), "a".to_string()]);
+        assert_eq!(names(selected.unwrap()), ["a", "b", "c", "d"]);
+        let selected = select_feeds(config.feed.clone(), &["b.rss".to_string()]);
+        assert_eq!(names(selected.unwrap()), ["b", "c"]);
+        assert!(select_feeds(config.feed, &["e".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_place_state_file() {
+        let root = env::temp_dir().join(format!("rsspls-state-{}", std::process::id()));
+        let (cache_dir, state_dir) = (root.join("cache"), root.join("state"));
+        fs::create_dir_all(&cache_dir).unwrap();
+        fs::write(cache_dir.join("news.state.toml"), "items = []").unwrap();
+        let dirs = dirs::new(Some(cache_dir.clone()), Some(state_dir.clone())).unwrap();
+
+        let path = place_state_file(&dirs, "news.state.toml").unwrap();
+        assert_eq!(path, state_dir.join("news.state.toml"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "items = []");
+        assert!(!cache_dir.join("news.state.toml").exists());
+
+        // A file in the state directory isn't replaced by one left in the cache
+        fs::write(cache_dir.join("news.state.toml"), "stale").unwrap();
+        place_state_file(&dirs, "news.state.toml").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "items = []");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_place_feed_files() {
+        let root = env::temp_dir().join(format!("rsspls-feed-files-{}", std::process::id()));
+        let (cache_dir, state_dir) = (root.join("cache"), root.join("state"));
+        fs::create_dir_all(&cache_dir).unwrap();
+        fs::write(cache_dir.join("news.toml"), "headers").unwrap();
+        fs::write(cache_dir.join("news.state.toml"), "seen = []").unwrap();
+        let dirs = dirs::new(Some(cache_dir.clone()), Some(state_dir.clone())).unwrap();
+        let config: Config = toml::from_str(
+            r#"
+            [rsspls]
+
+            [[feed]]
+            title = "News"
+            filename = "news.rss"
+            name = "local-news"
+            [feed.config]
+            url = "http://example.com/"
+            item = "article"
+            heading = "h2"
+        "#,
+        )
+        .unwrap();
+
+        let (cache_path, state_path) = place_feed_files(&dirs, &config.feed[0]).unwrap();
+        assert_eq!(cache_path, cache_dir.join("local-news.toml"));
+        assert_eq!(state_path, state_dir.join("local-news.state.toml"));
+        assert_eq!(fs::read_to_string(&cache_path).unwrap(), "headers");
+        assert_eq!(fs::read_to_string(&state_path).unwrap(), "seen = []");
+        assert!(!cache_dir.join("news.toml").exists());
+        assert!(!cache_dir.join("news.state.toml").exists());
+        assert!(!state_dir.join("news.state.toml").exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_relative_to_file() {
+        let resolved = relative_to_file(
+            Path::new("/srv/site/rsspls/feeds.toml"),
+            PathBuf::from("../docs/feeds"),
+        );
+        assert_eq!(resolved, Path::new("/srv/site/rsspls/../docs/feeds"));
+
+        let resolved = relative_to_file(Path::new("feeds.toml"), PathBuf::from("feeds"));
+        assert_eq!(resolved, Path::new("feeds"));
+
+        let resolved = relative_to_file(
+            Path::new("/srv/site/rsspls/feeds.toml"),
+            PathBuf::from("/var/www/feeds"),
+        );
+        assert_eq!(resolved, Path::new("/var/www/feeds"));
+    }
 
     #[test]
     #[cfg(not(windows))]
@@ -293,4 +1344,20 @@ mod tests {
         let expanded = expand_tilde(r"~\some\where", PathBuf::from(r"C:\"));
         assert_eq!(expanded, Path::new(r"C:\some\where"));
     }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_expand_env_vars() {
+        let lookup = |name: &str| (name == "APPDATA").then(|| r"C:\Users\Foo\AppData".to_string());
+        assert_eq!(
+            dirs::expand_env_vars(r"%APPDATA%\rsspls", lookup),
+            r"C:\Users\Foo\AppData\rsspls"
+        );
+        assert_eq!(
+            dirs::expand_env_vars(r"%UNSET%%APPDATA%", lookup),
+            r"%UNSET%C:\Users\Foo\AppData"
+        );
+        assert_eq!(dirs::expand_env_vars("100% %", lookup), "100% %");
+        assert_eq!(dirs::expand_env_vars("a%%b", lookup), "a%%b");
+    }
 }
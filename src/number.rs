@@ -0,0 +1,98 @@
+/// Characters that separate groups of thousands in numbers on web pages
+const GROUP_SEPARATORS: &[char] = &[',', '.', ' ', '\u{a0}', '\u{202f}', '\'', '’'];
+
+/// Parse the first number in `text`, such as a price, ignoring any currency symbols and
+/// thousands separators
+///
+/// A separator is only treated as grouping thousands when it's followed by exactly three
+/// digits, otherwise it ends the number.
+pub fn parse(text: &str, decimal_point: char) -> Option<f64> {
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let negative = text[..start]
+        .trim_end_matches(|c: char| !c.is_ascii_digit() && c != '-' && c != '−')
+        .ends_with(['-', '−']);
+
+    let chars: Vec<char> = text[start..].chars().collect();
+    let mut number = String::new();
+    if negative {
+        number.push('-');
+    }
+    let mut seen_decimal_point = false;
+    for (i, &c) in chars.iter().enumerate() {
+        let is_digit = |i: usize| chars.get(i).is_some_and(char::is_ascii_digit);
+        if c.is_ascii_digit() {
+            number.push(c);
+        } else if c == decimal_point && !seen_decimal_point && is_digit(i + 1) {
+            seen_decimal_point = true;
+            number.push('.');
+        } else if GROUP_SEPARATORS.contains(&c)
+            && !seen_decimal_point
+            && (1..=3).all(|offset| is_digit(i + offset))
+            && !is_digit(i + 4)
+        {
+            // Thousands separator, skipped
+        } else {
+            break;
+        }
+    }
+    number.parse().ok()
+}
+
+/// Format `value` with `decimals` decimal places (as many as needed if `None`), separating
+/// thousands with `thousands` if supplied
+pub fn format(value: f64, decimals: Option<usize>, thousands: Option<&str>) -> String {
+    let formatted = match decimals {
+        Some(decimals) => format!("{value:.decimals$}"),
+        None => value.to_string(),
+    };
+    let Some(separator) = thousands else {
+        return formatted;
+    };
+
+    let (sign, unsigned) = match formatted.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", formatted.as_str()),
+    };
+    let (integer, fraction) = match unsigned.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (unsigned, None),
+    };
+    let mut grouped = String::from(sign);
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push_str(separator);
+        }
+        grouped.push(digit);
+    }
+    if let Some(fraction) = fraction {
+        grouped.push('.');
+        grouped.push_str(fraction);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("$1,234.50", '.'), Some(1234.5));
+        assert_eq!(parse("1.234,50 €", ','), Some(1234.5));
+        assert_eq!(parse("Price: 12 500 kr", '.'), Some(12500.0));
+        assert_eq!(parse("CHF 1'000", '.'), Some(1000.0));
+        assert_eq!(parse("-€20", '.'), Some(-20.0));
+        assert_eq!(parse("Score 4.5/5", '.'), Some(4.5));
+        assert_eq!(parse("3 rooms, 2 baths", '.'), Some(3.0));
+        assert_eq!(parse("Sold out", '.'), None);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format(1234.5, Some(2), Some(",")), "1,234.50");
+        assert_eq!(format(-1234567.0, None, Some(" ")), "-1 234 567");
+        assert_eq!(format(123.0, None, Some(",")), "123");
+        assert_eq!(format(4.5, None, None), "4.5");
+        assert_eq!(format(4.0, Some(0), None), "4");
+    }
+}
@@ -0,0 +1,387 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use atomicwrites::AtomicFile;
+use encoding_rs::{Encoding, UTF_8};
+use eyre::{eyre, WrapErr};
+use log::{debug, info, warn};
+use rss::Channel;
+use simple_eyre::eyre;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+#[cfg(feature = "atom")]
+use crate::atom;
+use crate::config::{OutputFormat, RssplsConfig};
+use crate::jsonfeed;
+use crate::permissions::OutputPermissions;
+use crate::sign::{self, Signer};
+
+/// The end of an XML declaration
+const XML_DECLARATION_END: &str = "?>";
+/// The start of the comment recording where a feed came from
+const PROVENANCE_START: &str = "<!-- rsspls provenance:";
+const COMMENT_END: &str = "-->";
+
+/// How the generated feeds are written to the output directory
+#[derive(Debug, Clone)]
+pub struct Output {
+    permissions: OutputPermissions,
+    /// Whether output files that are symlinks are written through to their target
+    follow_symlinks: bool,
+    /// Whether the XML is indented
+    pretty: bool,
+    /// The encoding the feed is written in
+    encoding: &'static Encoding,
+    /// Signs the feeds, if they're signed
+    signer: Option<Signer>,
+    /// Time used in the signatures in place of the current time, in reproducible mode
+    source_date: Option<OffsetDateTime>,
+    /// Whether a comment recording where each feed came from is added to it
+    provenance: bool,
+}
+
+/// Where a feed came from, recorded in the feed when `provenance` is enabled
+#[derive(Debug)]
+pub struct Provenance<'a> {
+    /// The pages the feed was generated from
+    pub sources: Vec<String>,
+    /// When the pages were fetched
+    pub fetched: OffsetDateTime,
+    pub config_hash: &'a str,
+}
+
+impl Output {
+    pub fn new(config: &RssplsConfig, source_date: Option<OffsetDateTime>) -> eyre::Result<Self> {
+        let encoding = match &config.encoding {
+            Some(label) => {
+                let encoding = Encoding::for_label(label.as_bytes())
+                    .ok_or_else(|| eyre!("unknown encoding: {label}"))?;
+                // The UTF-16 encodings and replacement can only be decoded
+                if encoding.output_encoding() != encoding {
+                    return Err(eyre!("feeds can't be written in the {label} encoding"));
+                }
+                encoding
+            }
+            None => UTF_8,
+        };
+        Ok(Output {
+            permissions: OutputPermissions::new(
+                config.output_mode,
+                config.output_group.as_deref(),
+            )?,
+            follow_symlinks: config.follow_output_symlinks,
+            pretty: config.pretty,
+            encoding,
+            signer: config.sign.as_ref().map(Signer::load).transpose()?,
+            source_date,
+            provenance: config.provenance,
+        })
+    }
+
+    /// Write `channel` to `output_path` in `format`
+    pub fn write(
+        &self,
+        channel: &Channel,
+        format: OutputFormat,
+        output_path: &Path,
+        provenance: &Provenance<'_>,
+    ) -> eyre::Result<()> {
+        let output_path = output_target(output_path, self.follow_symlinks)?;
+        let mut xml = self.serialise(channel, format)?;
+        if self.provenance && format != OutputFormat::Json {
+            xml = add_provenance(xml, provenance)?;
+            // The comment changes every run, so it's left out when checking whether the feed
+            // itself has changed
+            let unchanged = fs::read(&output_path)
+                .is_ok_and(|existing| without_provenance(&existing) == without_provenance(&xml));
+            if unchanged {
+                info!("{} is unchanged", output_path.display());
+                return Ok(());
+            }
+        }
+
+        // Write the new file into a temporary location, then move it into place
+        info!("write {}", output_path.display());
+        self.write_file(&output_path, &xml)?;
+
+        // The signature is written after the feed so that a failed run doesn't leave a signature
+        // for a feed that isn't there
+        if let Some(signer) = &self.signer {
+            let file_name = output_path
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default();
+            let now = self.source_date.unwrap_or_else(OffsetDateTime::now_utc);
+            let signature = signer.sign(&xml, &file_name, now);
+            let signature_path = sign::signature_path(&output_path);
+            debug!("write signature {}", signature_path.display());
+            self.write_file(&signature_path, signature.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> eyre::Result<()> {
+        let file = AtomicFile::new(path, atomicwrites::AllowOverwrite);
+        file.write(|f| std::io::Write::write_all(f, contents))
+            .map_err(|err| match err {
+                atomicwrites::Error::Internal(err) | atomicwrites::Error::User(err) => err,
+            })
+            .wrap_err_with(|| format!("unable to write output file: {}", path.display()))?;
+        self.permissions.apply(path)
+    }
+
+    fn serialise(&self, channel: &Channel, format: OutputFormat) -> eyre::Result<Vec<u8>> {
+        let xml = match format {
+            OutputFormat::Rss => self.serialise_rss(channel)?,
+            OutputFormat::Atom => self.serialise_atom(channel)?,
+            // JSON is always UTF-8
+            OutputFormat::Json => return jsonfeed::write(channel, self.pretty),
+        };
+        if self.encoding == UTF_8 {
+            return Ok(xml);
+        }
+
+        // NOTE(unwrap): The rss and atom_syndication crates only write UTF-8
+        let xml = String::from_utf8(xml).unwrap();
+        let body = match xml.strip_prefix("<?xml") {
+            Some(declaration) => declaration
+                .split_once(XML_DECLARATION_END)
+                .map_or(declaration, |(_, body)| body.trim_start()),
+            None => &xml,
+        };
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="{}"?>{body}"#,
+            self.encoding.name()
+        );
+        // Characters that can't be represented are written as numeric character references
+        let (encoded, _, _) = self.encoding.encode(&xml);
+        Ok(encoded.into_owned())
+    }
+
+    fn serialise_rss(&self, channel: &Channel) -> eyre::Result<Vec<u8>> {
+        if self.pretty {
+            channel.pretty_write_to(Vec::new(), b' ', 2)
+        } else {
+            channel.write_to(Vec::new())
+        }
+        .wrap_err("unable to write feed")
+    }
+
+    #[cfg(feature = "atom")]
+    fn serialise_atom(&self, channel: &Channel) -> eyre::Result<Vec<u8>> {
+        let config = atom_syndication::WriteConfig {
+            write_document_declaration: true,
+            indent_size: self.pretty.then_some(2),
+        };
+        let now = self.source_date.unwrap_or_else(OffsetDateTime::now_utc);
+        atom::from_channel(channel, now)
+            .write_with_config(Vec::new(), config)
+            .wrap_err("unable to write feed")
+    }
+
+    #[cfg(not(feature = "atom"))]
+    fn serialise_atom(&self, _channel: &Channel) -> eyre::Result<Vec<u8>> {
+        Err(eyre!(
+            "unable to write Atom feed as rsspls was built without the atom feature"
+        ))
+    }
+}
+
+/// Add the provenance comment after the XML declaration of `xml`
+fn add_provenance(xml: Vec<u8>, provenance: &Provenance<'_>) -> eyre::Result<Vec<u8>> {
+    let fetched = provenance.fetched;
+    let fetched = fetched
+        .replace_nanosecond(0)
+        .unwrap_or(fetched)
+        .format(&Rfc3339)
+        .wrap_err("unable to format fetch time")?;
+    // A comment can't contain --
+    let sources = provenance.sources.join(" ").replace("--", "-%2D");
+    let comment = format!(
+        "\n{PROVENANCE_START} source={sources} fetched={fetched} version={} config={} {COMMENT_END}",
+        env!("CARGO_PKG_VERSION"),
+        provenance.config_hash
+    );
+    let end = find(&xml, XML_DECLARATION_END.as_bytes())
+        .map(|start| start + XML_DECLARATION_END.len())
+        .unwrap_or(0);
+    let mut with_comment = xml;
+    with_comment.splice(end..end, comment.into_bytes());
+    Ok(with_comment)
+}
+
+/// `xml` without its provenance comment
+fn without_provenance(xml: &[u8]) -> Vec<u8> {
+    let Some(start) = find(xml, PROVENANCE_START.as_bytes()) else {
+        return xml.to_vec();
+    };
+    let end = find(&xml[start..], COMMENT_END.as_bytes())
+        .map_or(xml.len(), |end| start + end + COMMENT_END.len());
+    // Also remove the newline the comment is written on
+    let start = if start > 0 && xml[start - 1] == b'\n' {
+        start - 1
+    } else {
+        start
+    };
+    [&xml[..start], &xml[end..]].concat()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// The path to write the output for `output_path` to
+///
+/// Renaming the new file into place replaces a symlink at `output_path`, so when
+/// `follow_symlinks` is set the output is written to the file the link points to instead.
+fn output_target(output_path: &Path, follow_symlinks: bool) -> eyre::Result<PathBuf> {
+    // Limit the number of links followed in case there's a loop
+    const MAX_LINKS: usize = 40;
+
+    let mut path = output_path.to_path_buf();
+    for _ in 0..MAX_LINKS {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {}
+            _ => return Ok(path),
+        }
+        if !follow_symlinks {
+            warn!(
+                "{} is a symlink that will be replaced, set follow_output_symlinks to write to \
+                 its target instead",
+                path.display()
+            );
+            return Ok(path);
+        }
+        let target = fs::read_link(&path)
+            .wrap_err_with(|| format!("unable to read symlink {}", path.display()))?;
+        // Relative links are relative to the directory containing the link
+        path = match path.parent() {
+            Some(dir) => dir.join(target),
+            None => target,
+        };
+        debug!(
+            "{} is a symlink to {}",
+            output_path.display(),
+            path.display()
+        );
+    }
+    Err(eyre!(
+        "too many levels of symlinks at {}",
+        output_path.display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    use rss::ItemBuilder;
+
+    /// The XML declaration written by the rss crate
+    const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="utf-8"?>"#;
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_output_target() {
+        let dir = env::temp_dir().join(format!("rsspls-output-target-{}", std::process::id()));
+        fs::create_dir_all(dir.join("published")).unwrap();
+        let link = dir.join("feed.rss");
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink("published/feed.rss", &link).unwrap();
+
+        let followed = output_target(&link, true);
+        let not_followed = output_target(&link, false);
+        let regular = output_target(&dir.join("other.rss"), true);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(followed.unwrap(), dir.join("published/feed.rss"));
+        assert_eq!(not_followed.unwrap(), link);
+        assert_eq!(regular.unwrap(), dir.join("other.rss"));
+    }
+
+    #[test]
+    fn test_serialise() {
+        let channel = rss::ChannelBuilder::default()
+            .title("Café")
+            .items(vec![ItemBuilder::default().title("€5".to_string()).build()])
+            .build();
+        let mut output = Output {
+            permissions: OutputPermissions::default(),
+            follow_symlinks: false,
+            pretty: true,
+            encoding: UTF_8,
+            signer: None,
+            source_date: None,
+            provenance: false,
+        };
+        let xml =
+            String::from_utf8(output.serialise(&channel, OutputFormat::Rss).unwrap()).unwrap();
+        assert!(xml.starts_with(XML_DECLARATION));
+        assert!(xml.contains("\n  <channel>\n    <title>Café</title>"));
+
+        output.pretty = false;
+        output.encoding = Encoding::for_label(b"latin1").unwrap();
+        let xml = output.serialise(&channel, OutputFormat::Rss).unwrap();
+        assert!(xml.starts_with(br#"<?xml version="1.0" encoding="windows-1252"?><rss"#));
+        assert!(xml.windows(5).any(|window| window == b"Caf\xe9<"));
+        assert!(xml.windows(3).any(|window| window == b"\x805<"));
+    }
+
+    #[test]
+    fn test_provenance() {
+        let provenance = Provenance {
+            sources: vec!["http://example.com/a--b".to_string()],
+            fetched: time::macros::datetime!(2024-08-01 10:20:30 UTC),
+            config_hash: "abc123",
+        };
+        let xml = add_provenance(
+            format!("{XML_DECLARATION}<rss></rss>").into_bytes(),
+            &provenance,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(xml.clone()).unwrap(),
+            format!(
+                "{XML_DECLARATION}\n<!-- rsspls provenance: source=http://example.com/a-%2Db \
+                 fetched=2024-08-01T10:20:30Z version={} config=abc123 --><rss></rss>",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        assert_eq!(
+            without_provenance(&xml),
+            format!("{XML_DECLARATION}<rss></rss>").into_bytes()
+        );
+        assert_eq!(
+            without_provenance(b"<rss><!-- other --></rss>"),
+            b"<rss><!-- other --></rss>"
+        );
+    }
+
+    #[cfg(feature = "atom")]
+    #[test]
+    fn test_serialise_atom() {
+        let channel = rss::ChannelBuilder::default()
+            .title("Café")
+            .link("http://example.com/")
+            .last_build_date("Thu, 1 Aug 2024 10:00:00 +0000".to_string())
+            .build();
+        let output = Output {
+            permissions: OutputPermissions::default(),
+            follow_symlinks: false,
+            pretty: false,
+            encoding: Encoding::for_label(b"latin1").unwrap(),
+            signer: None,
+            source_date: None,
+            provenance: false,
+        };
+        let xml = output.serialise(&channel, OutputFormat::Atom).unwrap();
+        assert!(xml.starts_with(
+            br#"<?xml version="1.0" encoding="windows-1252"?><feed xmlns="http://www.w3.org/2005/Atom">"#
+        ));
+        assert!(xml.windows(5).any(|window| window == b"Caf\xe9<"));
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use simple_eyre::eyre::{self, WrapErr};
+use time::OffsetDateTime;
+
+use crate::config::Config;
+use crate::dirs::BaseDirs;
+use crate::history;
+
+/// Print where the config, cache, and output files of each feed are, and whether they exist
+///
+/// Nothing is created, so this shows where a run would put its files without making one.
+pub fn show(config: &Config, output_dir: Option<&Path>, dirs: &BaseDirs) -> eyre::Result<()> {
+    let config_path = fs::canonicalize(&config.path).unwrap_or_else(|_| config.path.clone());
+    println!("config   {}", describe(&config_path));
+    if let Some(system_path) = &config.system_path {
+        println!("system   {}", describe(system_path));
+    }
+    match output_dir {
+        Some(dir) => println!("output   {}", describe(dir)),
+        None => println!("output   not set"),
+    }
+    println!("history  {}", describe(&state_file(dirs, "history.jsonl")?));
+    for (label, name) in [("hosts", "hosts.toml"), ("pages", "pages")] {
+        println!("{label:<8} {}", describe(&cache_file(dirs, name)?));
+    }
+
+    for feed in &config.feed {
+        println!();
+        println!("{}", feed.name());
+        let output_path = output_dir.and_then(|dir| {
+            Path::new(&feed.filename)
+                .file_name()
+                .map(|name| dir.join(name))
+        });
+        match output_path {
+            Some(path) => println!("  output {}", describe(&path)),
+            None => println!("  output not set"),
+        }
+        println!(
+            "  cache  {}",
+            describe(&cache_file(dirs, &feed.cache_filename())?)
+        );
+        println!(
+            "  state  {}",
+            describe(&state_file(dirs, &feed.state_filename())?)
+        );
+    }
+    Ok(())
+}
+
+fn cache_file(dirs: &BaseDirs, name: &str) -> eyre::Result<PathBuf> {
+    dirs.cache_file(name)
+        .wrap_err_with(|| format!("unable to determine path to {name}"))
+}
+
+/// The path of `name` in the state directory, or where it was kept in the cache directory if it
+/// hasn't been moved yet
+fn state_file(dirs: &BaseDirs, name: &str) -> eyre::Result<PathBuf> {
+    let path = dirs
+        .state_file(name)
+        .wrap_err_with(|| format!("unable to determine path to {name}"))?;
+    let old_path = cache_file(dirs, name)?;
+    if !path.exists() && old_path.exists() {
+        return Ok(old_path);
+    }
+    Ok(path)
+}
+
+/// `path` followed by when it was modified, or that it's missing
+fn describe(path: &Path) -> String {
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            let modified = metadata
+                .modified()
+                .ok()
+                .map(|modified| OffsetDateTime::from(modified).unix_timestamp())
+                .map_or_else(|| "unknown".to_string(), history::format_timestamp);
+            format!("{} (modified {modified})", path.display())
+        }
+        Err(_) => format!("{} (missing)", path.display()),
+    }
+}
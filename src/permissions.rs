@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use simple_eyre::eyre;
+
+/// Permissions applied to output files once they have been written
+///
+/// Files written via `AtomicFile` are created with restrictive permissions, which can prevent a
+/// web server from reading them.
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(windows, allow(dead_code))]
+pub struct OutputPermissions {
+    mode: Option<u32>,
+    gid: Option<u32>,
+}
+
+#[cfg(not(windows))]
+impl OutputPermissions {
+    /// Set up the permissions, looking up the id of `group` if supplied
+    pub fn new(mode: Option<u32>, group: Option<&str>) -> eyre::Result<Self> {
+        let gid = group
+            .map(|group| {
+                unix::group_id(group).ok_or_else(|| eyre::eyre!("unknown output_group: {group}"))
+            })
+            .transpose()?;
+        Ok(OutputPermissions { mode, gid })
+    }
+
+    pub fn apply(&self, path: &Path) -> eyre::Result<()> {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        use eyre::WrapErr;
+
+        if let Some(mode) = self.mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))
+                .wrap_err_with(|| format!("unable to set mode {mode:o} on {}", path.display()))?;
+        }
+        if let Some(gid) = self.gid {
+            unix::chgrp(path, gid)
+                .wrap_err_with(|| format!("unable to set group of {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+impl OutputPermissions {
+    pub fn new(mode: Option<u32>, group: Option<&str>) -> eyre::Result<Self> {
+        if mode.is_some() || group.is_some() {
+            log::warn!("output_mode and output_group are not supported on Windows, ignoring");
+        }
+        Ok(OutputPermissions::default())
+    }
+
+    pub fn apply(&self, _path: &Path) -> eyre::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(windows))]
+mod unix {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    use crate::xdg::lookup_name;
+
+    /// Look up the id of `group` in the group database
+    pub fn group_id(group: &str) -> Option<u32> {
+        lookup_name(group, libc::getgrnam_r, |entry: &libc::group| {
+            Some(entry.gr_gid)
+        })
+    }
+
+    /// Change the group of the file at `path`, leaving the owner as is
+    pub fn chgrp(path: &Path, gid: u32) -> io::Result<()> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: path is a valid NUL terminated string, and -1 leaves the owner unchanged
+        let ret = unsafe { libc::chown(path.as_ptr(), u32::MAX, gid) };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
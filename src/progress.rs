@@ -0,0 +1,167 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::history::{FeedRecord, FeedStatus};
+
+const SPINNER: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+/// The number of running feeds named in the status line
+const MAX_NAMES: usize = 3;
+
+/// A status line showing how many feeds have been processed, kept at the bottom of the terminal
+///
+/// Log messages are written through `LogWriter` so that they appear above the status line
+/// instead of being mixed up with it.
+pub struct Progress {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    total: usize,
+    /// Names of the feeds being processed, in the order they started
+    running: Vec<String>,
+    done: usize,
+    failed: usize,
+    not_modified: usize,
+    frame: usize,
+    /// Set once the run is over, after which the status line isn't drawn
+    cleared: bool,
+}
+
+impl Progress {
+    /// Create the status line if stdout and stderr are terminals
+    pub fn new() -> Option<Arc<Self>> {
+        (io::stdout().is_terminal() && io::stderr().is_terminal()).then(|| {
+            Arc::new(Progress {
+                state: Mutex::default(),
+            })
+        })
+    }
+
+    pub fn set_total(&self, total: usize) {
+        self.update(|state| state.total = total);
+    }
+
+    pub fn started(&self, name: &str) {
+        self.update(|state| state.running.push(name.to_string()));
+    }
+
+    pub fn finished(&self, record: &FeedRecord) {
+        self.update(|state| {
+            state.running.retain(|name| name != record.key());
+            state.done += 1;
+            match record.status {
+                FeedStatus::Error => state.failed += 1,
+                FeedStatus::NotModified => state.not_modified += 1,
+                FeedStatus::Ok | FeedStatus::Skipped => {}
+            }
+        });
+    }
+
+    /// Advance the spinner
+    pub fn tick(&self) {
+        self.update(|state| state.frame = (state.frame + 1) % SPINNER.len());
+    }
+
+    /// Remove the status line once the run is over
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.cleared = true;
+        clear_line();
+    }
+
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        let mut state = self.state.lock().unwrap();
+        f(&mut state);
+        draw(&state);
+    }
+}
+
+/// A log target that writes above the status line
+pub struct LogWriter(pub Arc<Progress>);
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let state = self.0.state.lock().unwrap();
+        clear_line();
+        io::stderr().write_all(buf)?;
+        draw(&state);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+fn clear_line() {
+    let mut stdout = io::stdout().lock();
+    let _ = write!(stdout, "\r\x1b[K");
+    let _ = stdout.flush();
+}
+
+fn draw(state: &State) {
+    if state.cleared {
+        return;
+    }
+    let mut stdout = io::stdout().lock();
+    let _ = write!(stdout, "\r\x1b[K{}", status_line(state));
+    let _ = stdout.flush();
+}
+
+fn status_line(state: &State) -> String {
+    let mut line = format!(
+        "{} [{}/{}] {} running",
+        SPINNER[state.frame],
+        state.done,
+        state.total,
+        state.running.len()
+    );
+    if state.not_modified > 0 {
+        line.push_str(&format!(", {} unmodified", state.not_modified));
+    }
+    if state.failed > 0 {
+        line.push_str(&format!(", {} failed", state.failed));
+    }
+    if !state.running.is_empty() {
+        let names: Vec<_> = state
+            .running
+            .iter()
+            .take(MAX_NAMES)
+            .map(String::as_str)
+            .collect();
+        line.push_str(": ");
+        line.push_str(&names.join(", "));
+        if state.running.len() > MAX_NAMES {
+            line.push_str(", …");
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_line() {
+        let mut state = State {
+            total: 10,
+            running: vec!["a.rss".to_string(), "b.rss".to_string()],
+            done: 3,
+            ..State::default()
+        };
+        assert_eq!(status_line(&state), "⠋ [3/10] 2 running: a.rss, b.rss");
+
+        state
+            .running
+            .extend(["c.rss".to_string(), "d.rss".to_string()]);
+        state.failed = 1;
+        state.not_modified = 2;
+        state.frame = 1;
+        assert_eq!(
+            status_line(&state),
+            "⠙ [3/10] 4 running, 2 unmodified, 1 failed: a.rss, b.rss, c.rss, …"
+        );
+    }
+}
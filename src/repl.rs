@@ -0,0 +1,288 @@
+use std::io::{self, BufRead, Write};
+
+use kuchiki::traits::TendrilSink;
+use kuchiki::{NodeRef, Selectors};
+use reqwest::Client as HttpClient;
+use simple_eyre::eyre::{self, bail, eyre};
+use url::Url;
+
+use crate::feed::{fetch_location, rewrite_urls};
+use crate::snippet::{ConfigSnippet, FeedConfigSnippet, FeedSnippet};
+
+/// The maximum number of matches to print for a selector
+const MAX_MATCHES: usize = 10;
+/// The maximum number of items that field selectors are shown for
+const MAX_ITEMS: usize = 5;
+/// Text longer than this is truncated when printed
+const MAX_TEXT_LEN: usize = 100;
+
+const HELP: &str = "\
+Enter a CSS selector to see the elements it matches. Once an item selector has
+been set field selectors are evaluated within each item.
+
+COMMANDS:
+    item SELECTOR       Set the item selector
+    heading SELECTOR    Set the heading selector
+    link SELECTOR       Set the link selector
+    summary SELECTOR    Set the summary selector
+    date SELECTOR       Set the date selector
+    media SELECTOR      Set the media selector
+    config              Print a config snippet for the selectors set so far
+    help                Show this help
+    quit                Exit (so does Ctrl-D)";
+
+/// Run the interactive selector prompt against the page at `location`, fetched with `http`
+///
+/// `user_agent` is the User-Agent `http` sends, which is included in the config snippet.
+pub async fn run(
+    http: &HttpClient,
+    location: &str,
+    user_agent: Option<String>,
+) -> eyre::Result<()> {
+    let (url, html) = fetch_location(http, location).await?;
+    let mut repl = Repl::new(&url, &html, user_agent)?;
+    let stdin = io::stdin();
+    repl.prompt(stdin.lock(), &mut io::stdout())
+}
+
+struct Repl {
+    doc: NodeRef,
+    url: Url,
+    title: String,
+    user_agent: Option<String>,
+    config: FeedConfigSnippet,
+}
+
+impl Repl {
+    fn new(url: &Url, html: &str, user_agent: Option<String>) -> eyre::Result<Self> {
+        let doc = kuchiki::parse_html().one(html);
+        let base_url = Url::options().base_url(Some(url));
+        rewrite_urls(&doc, &base_url)?;
+
+        let title = doc
+            .select_first("title")
+            .map(|title| title.text_contents().trim().to_string())
+            .unwrap_or_default();
+        Ok(Repl {
+            doc,
+            url: url.clone(),
+            title,
+            user_agent,
+            config: FeedConfigSnippet {
+                url: url.to_string(),
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Read commands from `input` until quit or the end of the input, then print the config
+    /// snippet if the item and heading selectors have been set
+    fn prompt<R: BufRead, W: Write>(&mut self, input: R, out: &mut W) -> eyre::Result<()> {
+        writeln!(out, "loaded {} ({})", self.url, self.title)?;
+        writeln!(out, "type 'help' for help")?;
+
+        let mut lines = input.lines();
+        loop {
+            write!(out, "> ")?;
+            out.flush()?;
+            let Some(line) = lines.next().transpose()? else {
+                writeln!(out)?;
+                break;
+            };
+            match self.command(line.trim(), out) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => writeln!(out, "error: {err}")?,
+            }
+        }
+
+        if !self.config.item.is_empty() && !self.config.heading.is_empty() {
+            self.print_snippet(out)?;
+        }
+        Ok(())
+    }
+
+    /// Run the command on `line`, returning false if it was to quit
+    fn command<W: Write>(&mut self, line: &str, out: &mut W) -> eyre::Result<bool> {
+        let (command, arg) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(command, arg)| (command, arg.trim()));
+
+        let doc = &self.doc;
+        let config = &mut self.config;
+        match command {
+            "" => {}
+            "help" => writeln!(out, "{HELP}")?,
+            "quit" | "exit" => return Ok(false),
+            "config" => self.print_snippet(out)?,
+            "item" => {
+                set(arg, |selector| config.item = selector)?;
+                show(out, doc, None, &config.item)?;
+            }
+            "heading" => {
+                set(arg, |selector| config.heading = selector)?;
+                show_in_items(out, doc, config, &config.heading)?;
+            }
+            "link" => {
+                set(arg, |selector| config.link = Some(selector))?;
+                show_in_items(out, doc, config, arg)?;
+            }
+            "summary" => {
+                set(arg, |selector| config.summary = Some(selector))?;
+                show_in_items(out, doc, config, arg)?;
+            }
+            "date" => {
+                set(arg, |selector| config.date = Some(selector))?;
+                show_in_items(out, doc, config, arg)?;
+            }
+            "media" => {
+                set(arg, |selector| config.media = Some(selector))?;
+                show_in_items(out, doc, config, arg)?;
+            }
+            _ => show_in_items(out, doc, config, line)?,
+        }
+        Ok(true)
+    }
+
+    fn print_snippet<W: Write>(&self, out: &mut W) -> eyre::Result<()> {
+        if self.config.item.is_empty() || self.config.heading.is_empty() {
+            bail!("item and heading selectors need to be set first");
+        }
+        let mut feed = FeedSnippet::new(self.title.clone(), self.config.clone());
+        feed.user_agent.clone_from(&self.user_agent);
+        let snippet = ConfigSnippet { feed: vec![feed] };
+        writeln!(out, "{}", snippet.to_toml()?)?;
+        Ok(())
+    }
+}
+
+fn set<F: FnOnce(String)>(selector: &str, update: F) -> eyre::Result<()> {
+    if selector.is_empty() {
+        bail!("a selector is required");
+    }
+    Selectors::compile(selector).map_err(|()| eyre!("invalid selector: {selector}"))?;
+    update(selector.to_string());
+    Ok(())
+}
+
+/// Show the elements matching `selector` within each item, or the whole document if there is
+/// no item selector yet
+fn show_in_items<W: Write>(
+    out: &mut W,
+    doc: &NodeRef,
+    config: &FeedConfigSnippet,
+    selector: &str,
+) -> eyre::Result<()> {
+    if config.item.is_empty() {
+        return show(out, doc, None, selector);
+    }
+
+    let items = doc
+        .select(&config.item)
+        .map_err(|()| eyre!("invalid selector: {}", config.item))?;
+    for (i, item) in items.take(MAX_ITEMS).enumerate() {
+        show(out, item.as_node(), Some(i + 1), selector)?;
+    }
+    Ok(())
+}
+
+fn show<W: Write>(
+    out: &mut W,
+    node: &NodeRef,
+    item: Option<usize>,
+    selector: &str,
+) -> eyre::Result<()> {
+    if selector.is_empty() {
+        return Ok(());
+    }
+    let matches: Vec<_> = node
+        .select(selector)
+        .map_err(|()| eyre!("invalid selector: {selector}"))?
+        .collect();
+
+    match item {
+        Some(item) => writeln!(out, "item {item}: {} matches", matches.len())?,
+        None => writeln!(out, "{} matches", matches.len())?,
+    }
+    for (i, element) in matches.iter().take(MAX_MATCHES).enumerate() {
+        let attrs = element.attributes.borrow();
+        let mut description = format!("<{}", element.name.local);
+        for attr in ["id", "class", "href", "src", "datetime"] {
+            if let Some(value) = attrs.get(attr) {
+                description.push_str(&format!(" {attr}=\"{value}\""));
+            }
+        }
+        description.push('>');
+        writeln!(
+            out,
+            "  [{i}] {description} {}",
+            truncate(&element.text_contents())
+        )?;
+    }
+    if matches.len() > MAX_MATCHES {
+        writeln!(out, "  … {} more", matches.len() - MAX_MATCHES)?;
+    }
+    Ok(())
+}
+
+/// Collapse whitespace in `text` and truncate it for display
+fn truncate(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(MAX_TEXT_LEN) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<html><head><title>News</title></head><body>
+        <ul>
+            <li class="story"><a href="/one">One</a></li>
+            <li class="story"><a href="/two">Two</a></li>
+        </ul>
+    </body></html>"#;
+
+    fn run_repl(input: &str) -> String {
+        let url = Url::parse("https://example.com/news/").unwrap();
+        let mut repl = Repl::new(&url, PAGE, Some("Mozilla/5.0".to_string())).unwrap();
+        let mut out = Vec::new();
+        repl.prompt(input.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_prompt() {
+        let out = run_repl("item li.story\nheading a\nbogus[\nquit\nitem ignored\n");
+        assert!(out.starts_with("loaded https://example.com/news/ (News)\n"));
+        assert!(out.contains("> 2 matches\n  [0] <li class=\"story\"> One\n"));
+        assert!(out.contains("item 1: 1 matches\n  [0] <a href=\"https://example.com/one\"> One\n"));
+        assert!(out.contains("error: invalid selector: bogus["));
+        assert!(!out.contains("ignored"));
+        // The snippet is printed on quit
+        assert!(out.ends_with(
+            "> [[feed]]\n\
+            title = \"News\"\n\
+            filename = \"news.rss\"\n\
+            user_agent = \"Mozilla/5.0\"\n\n\
+            [feed.config]\n\
+            url = \"https://example.com/news/\"\n\
+            item = \"li.story\"\n\
+            heading = \"a\"\n\n"
+        ));
+    }
+
+    #[test]
+    fn test_prompt_eof() {
+        let out = run_repl("item li.story\nheading a\n");
+        assert!(out.contains("> \n[[feed]]\n"));
+        assert!(out.contains("heading = \"a\"\n"));
+
+        // Without the selectors there's no snippet to print
+        let out = run_repl("item li.story\nconfig\n");
+        assert!(out.contains("error: item and heading selectors need to be set first"));
+        assert!(!out.contains("[[feed]]"));
+    }
+}
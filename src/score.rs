@@ -0,0 +1,102 @@
+use std::slice;
+
+use kuchiki::traits::TendrilSink;
+use rss::{CategoryBuilder, Item};
+
+use crate::config::ScoreConfig;
+use crate::text;
+
+/// Domain of the category that holds the score of an item
+pub const SCORE_DOMAIN: &str = "rsspls:score";
+
+/// Score `items` by the keywords in their title and description, adding the score as a category
+/// and ordering them by it if configured
+pub fn apply(config: &ScoreConfig, items: Vec<Item>) -> Vec<Item> {
+    let mut scored: Vec<_> = items
+        .into_iter()
+        .map(|mut item| {
+            let score = score(config, &item);
+            if config.category {
+                let category = CategoryBuilder::default()
+                    .name(score.to_string())
+                    .domain(Some(SCORE_DOMAIN.to_string()))
+                    .build();
+                item.categories.push(category);
+            }
+            (score, item)
+        })
+        .collect();
+    if config.order {
+        // The sort is stable so items with the same score stay in their original order
+        scored.sort_by_key(|(score, _)| -score);
+    }
+    scored.into_iter().map(|(_, item)| item).collect()
+}
+
+/// The sum of the weights of the keywords that appear in `item`
+fn score(config: &ScoreConfig, item: &Item) -> i64 {
+    let mut text = item.title().unwrap_or_default().to_string();
+    if let Some(description) = item.description() {
+        // Only the text of the description is considered, not the markup
+        text.push('\n');
+        text.push_str(&kuchiki::parse_html().one(description).text_contents());
+    }
+    config
+        .keywords
+        .iter()
+        .filter(|(keyword, _)| text::contains_keyword(&text, slice::from_ref(keyword)))
+        .map(|(_, weight)| weight)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rss::ItemBuilder;
+
+    #[test]
+    fn test_apply() {
+        let config = ScoreConfig {
+            keywords: [
+                ("rust".to_string(), 5),
+                ("release".to_string(), 2),
+                ("href".to_string(), 100),
+                ("spam".to_string(), -10),
+            ]
+            .into(),
+            order: true,
+            category: true,
+        };
+        let item = |title: &str, description: &str| {
+            ItemBuilder::default()
+                .title(title.to_string())
+                .description(description.to_string())
+                .build()
+        };
+        let items = vec![
+            item("Weekly news", r#"<a href="/spam">spam</a>"#),
+            item("Nothing here", ""),
+            item("Rust release", "<p>RUST 1.80</p>"),
+        ];
+
+        let scored: Vec<_> = apply(&config, items)
+            .iter()
+            .map(|item| {
+                let category = item.categories().last().unwrap();
+                assert_eq!(category.domain(), Some(SCORE_DOMAIN));
+                (
+                    item.title().unwrap().to_string(),
+                    category.name().to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            scored,
+            [
+                ("Rust release".to_string(), "7".to_string()),
+                ("Nothing here".to_string(), "0".to_string()),
+                ("Weekly news".to_string(), "-10".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,437 @@
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use log::debug;
+use serde::Deserialize;
+use simple_eyre::eyre::{self, bail, eyre, WrapErr};
+
+const AGE: &str = "age";
+const SOPS: &str = "sops";
+
+/// A password or token in the config, given as is, encrypted so that the config can be
+/// committed, or kept in the keyring of the OS
+///
+/// Encrypted and keyring secrets are resolved before the feeds that use them are fetched.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Secret {
+    /// Given as is, or once it has been decrypted
+    Plain(String),
+    /// Encrypted with age, e.g. `{ encrypted = "-----BEGIN AGE ENCRYPTED FILE-----..." }`
+    Age {
+        /// The ASCII armored output of `age --encrypt --armor`
+        encrypted: String,
+        /// Identity file to decrypt with, overriding `age_identity` in `[rsspls]`
+        identity: Option<String>,
+    },
+    /// Extracted from a file encrypted with sops, e.g.
+    /// `{ sops = "secrets.yaml", extract = '["forum"]["password"]' }`
+    Sops {
+        /// The encrypted file, relative to the config file
+        sops: String,
+        /// The value in the file to use, the whole file if not set
+        extract: Option<String>,
+    },
+    /// Read from the keyring of the OS, e.g. `{ keyring = "forum.example.com/alice" }`
+    Keyring {
+        /// The service and account of the entry, separated by `/`
+        keyring: String,
+    },
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Leave out the secret, the encrypted forms are safe to show
+        match self {
+            Secret::Plain(_) => f.write_str("Plain(..)"),
+            Secret::Age { identity, .. } => f
+                .debug_struct("Age")
+                .field("identity", identity)
+                .finish_non_exhaustive(),
+            Secret::Sops { sops, extract } => f
+                .debug_struct("Sops")
+                .field("sops", sops)
+                .field("extract", extract)
+                .finish(),
+            Secret::Keyring { keyring } => {
+                f.debug_struct("Keyring").field("keyring", keyring).finish()
+            }
+        }
+    }
+}
+
+impl Secret {
+    /// The value of the secret, which has been decrypted if it was encrypted
+    pub fn expose(&self) -> eyre::Result<&str> {
+        match self {
+            Secret::Plain(value) => Ok(value),
+            Secret::Age { .. } | Secret::Sops { .. } | Secret::Keyring { .. } => {
+                Err(eyre!("secret hasn't been decrypted"))
+            }
+        }
+    }
+}
+
+/// Decrypts the encrypted secrets in the config
+pub struct Resolver {
+    /// The config file, which paths are relative to
+    config_path: PathBuf,
+    /// Identity file for secrets encrypted with age
+    age_identity: Option<PathBuf>,
+}
+
+impl Resolver {
+    pub fn new(config_path: &Path, age_identity: Option<PathBuf>) -> Resolver {
+        Resolver {
+            config_path: config_path.to_path_buf(),
+            age_identity,
+        }
+    }
+
+    /// Decrypt `secret` in place if it's encrypted
+    pub fn resolve(&self, secret: &mut Secret) -> eyre::Result<()> {
+        let value = match secret {
+            Secret::Plain(_) => return Ok(()),
+            Secret::Age {
+                encrypted,
+                identity,
+            } => {
+                let identity = match identity {
+                    Some(identity) => self.path(identity)?,
+                    None => self.age_identity.clone().ok_or_else(|| {
+                        eyre!("no identity to decrypt with, set age_identity in [rsspls]")
+                    })?,
+                };
+                let args = [
+                    OsStr::new("--decrypt"),
+                    OsStr::new("--identity"),
+                    identity.as_os_str(),
+                ];
+                run(AGE, &args, Some(encrypted.as_bytes()))?
+            }
+            Secret::Sops { sops, extract } => {
+                let path = self.path(sops)?;
+                let mut args = vec![OsStr::new("--decrypt")];
+                if let Some(extract) = extract {
+                    args.extend([OsStr::new("--extract"), OsStr::new(extract)]);
+                }
+                args.push(path.as_os_str());
+                run(SOPS, &args, None)?
+            }
+            Secret::Keyring { keyring } => {
+                let (service, account) = keyring.split_once('/').ok_or_else(|| {
+                    eyre!("keyring entry '{keyring}' should be the service and account, separated by /")
+                })?;
+                keyring_lookup(service, account)
+                    .wrap_err_with(|| format!("unable to read {keyring} from the keyring"))?
+            }
+        };
+        *secret = Secret::Plain(value);
+        Ok(())
+    }
+
+    fn path(&self, path: &str) -> eyre::Result<PathBuf> {
+        let path = crate::dirs::expand_path(path)?;
+        Ok(crate::relative_to_file(&self.config_path, path))
+    }
+}
+
+/// The program that reads passwords from the keyring of the OS
+#[cfg(target_os = "macos")]
+const KEYRING: &str = "security";
+#[cfg(all(unix, not(target_os = "macos")))]
+const KEYRING: &str = "secret-tool";
+
+/// Look up the password of `account` of `service` in the macOS Keychain, or with the Secret
+/// Service, e.g. GNOME Keyring or KWallet
+#[cfg(unix)]
+fn keyring_lookup(service: &str, account: &str) -> eyre::Result<String> {
+    run(KEYRING, &keyring_args(service, account), None)
+}
+
+/// The arguments to [`KEYRING`] that print the password of `account` of `service`
+#[cfg(target_os = "macos")]
+fn keyring_args<'a>(service: &'a str, account: &'a str) -> [&'a OsStr; 6] {
+    ["find-generic-password", "-s", service, "-a", account, "-w"].map(OsStr::new)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn keyring_args<'a>(service: &'a str, account: &'a str) -> [&'a OsStr; 5] {
+    ["lookup", "service", service, "account", account].map(OsStr::new)
+}
+
+/// Look up the password of `account` of `service` in the Windows Credential Manager
+///
+/// This is the generic credential with `service` as its address and `account` as its user name,
+/// like one added with `cmdkey /generic:service /user:account /pass`.
+#[cfg(windows)]
+fn keyring_lookup(service: &str, account: &str) -> eyre::Result<String> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::{io, ptr, slice};
+
+    use windows_sys::Win32::Foundation::{GetLastError, ERROR_NOT_FOUND};
+    use windows_sys::Win32::Security::Credentials::{
+        CredFree, CredReadW, CREDENTIALW, CRED_TYPE_GENERIC,
+    };
+
+    let target: Vec<u16> = OsStr::new(service).encode_wide().chain([0]).collect();
+    let mut credential: *mut CREDENTIALW = ptr::null_mut();
+    // SAFETY: target is NUL terminated, and credential is only read if the call succeeds
+    if unsafe { CredReadW(target.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) } == 0 {
+        let code = unsafe { GetLastError() };
+        if code == ERROR_NOT_FOUND {
+            bail!("there is no credential for {service} in the Credential Manager");
+        }
+        return Err(io::Error::from_raw_os_error(code as i32))
+            .wrap_err("unable to read from the Credential Manager");
+    }
+    // SAFETY: CredReadW succeeded so credential points to a credential, which is copied out of
+    // before it's freed
+    let (user_name, blob) = unsafe {
+        let user_name = wide_string((*credential).UserName);
+        let blob = match (*credential).CredentialBlob {
+            blob if blob.is_null() => Vec::new(),
+            blob => slice::from_raw_parts(blob, (*credential).CredentialBlobSize as usize).to_vec(),
+        };
+        CredFree(credential.cast());
+        (user_name, blob)
+    };
+    match user_name {
+        Some(user_name) if user_name.eq_ignore_ascii_case(account) => decode_credential(&blob),
+        Some(user_name) => bail!("the credential for {service} is for {user_name}, not {account}"),
+        None => bail!("the credential for {service} has no user name"),
+    }
+}
+
+/// Copy the NUL terminated UTF-16 string at `ptr`, if it's not null
+///
+/// # Safety
+///
+/// `ptr` must be null or point to a NUL terminated string.
+#[cfg(windows)]
+unsafe fn wide_string(ptr: *const u16) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    let len = (0..).take_while(|&i| *ptr.add(i) != 0).count();
+    Some(String::from_utf16_lossy(std::slice::from_raw_parts(
+        ptr, len,
+    )))
+}
+
+/// Decode the password stored in a credential
+///
+/// The Credential Manager and cmdkey store passwords as UTF-16, but some programs store UTF-8.
+/// UTF-16 is told apart by the NUL and other control character bytes it has for most text.
+#[cfg(any(windows, test))]
+fn decode_credential(blob: &[u8]) -> eyre::Result<String> {
+    match std::str::from_utf8(blob) {
+        Ok(password) if !password.chars().any(char::is_control) => Ok(password.to_string()),
+        _ if blob.len().is_multiple_of(2) => {
+            let units: Vec<u16> = blob
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .collect();
+            String::from_utf16(&units).wrap_err("password isn't UTF-16 or UTF-8")
+        }
+        _ => bail!("password isn't UTF-16 or UTF-8"),
+    }
+}
+
+/// Run `program` with `input` on stdin, returning its output without the trailing newline
+fn run(program: &str, args: &[&OsStr], input: Option<&[u8]>) -> eyre::Result<String> {
+    debug!("decrypting secret with {program}");
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("unable to run {program}"))?;
+    // A program that fails before reading its input closes the pipe, its error is more helpful
+    // than the one from writing
+    let written = match (child.stdin.take(), input) {
+        (Some(mut stdin), Some(input)) => stdin.write_all(input),
+        _ => Ok(()),
+    };
+    let output = child
+        .wait_with_output()
+        .wrap_err_with(|| format!("unable to read output of {program}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match stderr.trim() {
+            "" => bail!("{program} exited with {}", output.status),
+            stderr => bail!("{program} exited with {}: {stderr}", output.status),
+        }
+    }
+    written.wrap_err_with(|| format!("unable to write to {program}"))?;
+    let value = String::from_utf8(output.stdout)
+        .wrap_err_with(|| format!("output of {program} isn't UTF-8"))?;
+    Ok(value.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use basic_toml as toml;
+
+    #[derive(Deserialize)]
+    struct Secrets {
+        plain: Secret,
+        age: Secret,
+        sops: Secret,
+        keyring: Secret,
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let raw = r#"
+            plain = "hunter2"
+            age = { encrypted = "-----BEGIN AGE ENCRYPTED FILE-----" }
+            sops = { sops = "secrets.yaml", extract = '["forum"]["password"]' }
+            keyring = { keyring = "forum.example.com" }
+        "#;
+        let secrets: Secrets = toml::from_str(raw).unwrap();
+        assert_eq!(secrets.plain.expose().unwrap(), "hunter2");
+        assert_eq!(format!("{:?}", secrets.plain), "Plain(..)");
+        assert!(matches!(secrets.age, Secret::Age { identity: None, .. }));
+        assert_eq!(
+            secrets.sops,
+            Secret::Sops {
+                sops: "secrets.yaml".to_string(),
+                extract: Some(r#"["forum"]["password"]"#.to_string()),
+            }
+        );
+        assert!(secrets.sops.expose().is_err());
+
+        let mut age = secrets.age;
+        let err = Resolver::new(Path::new("feeds.toml"), None)
+            .resolve(&mut age)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no identity to decrypt with, set age_identity in [rsspls]"
+        );
+
+        let mut keyring = secrets.keyring;
+        assert_eq!(
+            format!("{keyring:?}"),
+            r#"Keyring { keyring: "forum.example.com" }"#
+        );
+        let err = Resolver::new(Path::new("feeds.toml"), None)
+            .resolve(&mut keyring)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "keyring entry 'forum.example.com' should be the service and account, separated by /"
+        );
+    }
+
+    #[test]
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn test_keyring_args() {
+        assert_eq!(
+            keyring_args("forum.example.com", "alice"),
+            ["lookup", "service", "forum.example.com", "account", "alice"]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn test_keyring_args() {
+        assert_eq!(
+            keyring_args("forum.example.com", "alice"),
+            [
+                "find-generic-password",
+                "-s",
+                "forum.example.com",
+                "-a",
+                "alice",
+                "-w"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_credential() {
+        let utf16: Vec<u8> = "hunter2"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        assert_eq!(decode_credential(&utf16).unwrap(), "hunter2");
+        let utf16: Vec<u8> = "пароль".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        assert_eq!(decode_credential(&utf16).unwrap(), "пароль");
+        assert_eq!(decode_credential(b"hunter").unwrap(), "hunter");
+        assert_eq!(decode_credential(b"hunter2").unwrap(), "hunter2");
+        assert_eq!(
+            decode_credential("mot de passé".as_bytes()).unwrap(),
+            "mot de passé"
+        );
+        assert!(decode_credential(&[0xff]).is_err());
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_keyring_lookup() {
+        use std::os::windows::ffi::OsStrExt;
+
+        use windows_sys::Win32::Security::Credentials::{
+            CredDeleteW, CredWriteW, CREDENTIALW, CRED_PERSIST_SESSION, CRED_TYPE_GENERIC,
+        };
+
+        let wide =
+            |value: &str| -> Vec<u16> { OsStr::new(value).encode_wide().chain([0]).collect() };
+        let service = "rsspls-test.example.com";
+        let mut target = wide(service);
+        let mut user_name = wide("alice");
+        let mut password: Vec<u8> = "hunter2"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        // SAFETY: all fields are integers or pointers, which are valid when zero
+        let mut credential: CREDENTIALW = unsafe { std::mem::zeroed() };
+        credential.Type = CRED_TYPE_GENERIC;
+        credential.TargetName = target.as_mut_ptr();
+        credential.UserName = user_name.as_mut_ptr();
+        credential.CredentialBlob = password.as_mut_ptr();
+        credential.CredentialBlobSize = password.len() as u32;
+        credential.Persist = CRED_PERSIST_SESSION;
+        // SAFETY: the strings and blob outlive the call
+        assert_ne!(unsafe { CredWriteW(&credential, 0) }, 0);
+
+        let looked_up = keyring_lookup(service, "alice");
+        let wrong_account = keyring_lookup(service, "bob");
+        // SAFETY: target is NUL terminated
+        unsafe { CredDeleteW(target.as_ptr(), CRED_TYPE_GENERIC, 0) };
+        assert_eq!(looked_up.unwrap(), "hunter2");
+        assert_eq!(
+            wrong_account.unwrap_err().to_string(),
+            "the credential for rsspls-test.example.com is for alice, not bob"
+        );
+        assert_eq!(
+            keyring_lookup(service, "alice").unwrap_err().to_string(),
+            "there is no credential for rsspls-test.example.com in the Credential Manager"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run() {
+        let args = [OsStr::new("-c"), OsStr::new("tr a-z A-Z")];
+        let output = run("sh", &args, Some(b"s3cret\n")).unwrap();
+        assert_eq!(output, "S3CRET");
+
+        let args = [OsStr::new("-c"), OsStr::new("echo bad key >&2; exit 1")];
+        let err = run("sh", &args, None).unwrap_err();
+        assert_eq!(err.to_string(), "sh exited with exit status: 1: bad key");
+        let args = [OsStr::new("-c"), OsStr::new("exit 1")];
+        let err = run("sh", &args, None).unwrap_err();
+        assert_eq!(err.to_string(), "sh exited with exit status: 1");
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+
+use futures::future::{BoxFuture, FutureExt, Shared};
+
+use crate::feed::FetchResult;
+
+/// A fetch that can be awaited by each feed using the page, along with the bytes it downloaded
+pub type SharedFetch = Shared<BoxFuture<'static, (Result<FetchResult, String>, u64)>>;
+
+/// Fetches of the pages used by more than one feed, so that each is only fetched once per run
+///
+/// The response is shared rather than the parsed document, as a document can't be sent between
+/// the tasks processing the feeds. Each feed parses its own copy.
+#[derive(Default)]
+pub struct SharedFetches {
+    /// URLs of the pages used by more than one feed
+    urls: Vec<String>,
+    fetches: Mutex<HashMap<String, SharedFetch>>,
+}
+
+impl SharedFetches {
+    /// Track the pages that appear more than once in `urls`
+    pub fn new(urls: impl IntoIterator<Item = String>) -> Self {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for url in urls {
+            *counts.entry(url).or_default() += 1;
+        }
+        let mut urls: Vec<_> = counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(url, _)| url)
+            .collect();
+        urls.sort();
+        SharedFetches {
+            urls,
+            fetches: Mutex::default(),
+        }
+    }
+
+    pub fn is_shared(&self, url: &str) -> bool {
+        self.urls
+            .binary_search_by(|shared| shared.as_str().cmp(url))
+            .is_ok()
+    }
+
+    /// The fetch for the request identified by `key`, started with `fetch` if it's the first
+    pub fn get_or_insert_with(
+        &self,
+        key: String,
+        fetch: impl FnOnce() -> BoxFuture<'static, (Result<FetchResult, String>, u64)>,
+    ) -> SharedFetch {
+        let mut fetches = self.fetches.lock().unwrap_or_else(PoisonError::into_inner);
+        fetches
+            .entry(key)
+            .or_insert_with(|| fetch().shared())
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_shared_fetches() {
+        let shared = SharedFetches::new(
+            [
+                "http://a.example/",
+                "http://b.example/",
+                "http://a.example/",
+            ]
+            .map(String::from),
+        );
+        assert!(shared.is_shared("http://a.example/"));
+        assert!(!shared.is_shared("http://b.example/"));
+
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let fetch = || {
+            let fetched = Arc::clone(&fetched);
+            async move {
+                fetched.fetch_add(1, Ordering::Relaxed);
+                (Ok(FetchResult::NotModified), 0)
+            }
+            .boxed()
+        };
+        let first = shared.get_or_insert_with("a".to_string(), fetch);
+        let second = shared.get_or_insert_with("a".to_string(), fetch);
+        assert!(matches!(first.await, (Ok(FetchResult::NotModified), 0)));
+        assert!(matches!(second.await, (Ok(FetchResult::NotModified), 0)));
+        assert_eq!(fetched.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,299 @@
+use std::path::Path;
+use std::{env, fs};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use cryptoxide::blake2b::Blake2b;
+use cryptoxide::digest::Digest;
+use cryptoxide::ed25519;
+use cryptoxide::scrypt::{scrypt, ScryptParams};
+use simple_eyre::eyre::{self, bail, eyre, WrapErr};
+use time::OffsetDateTime;
+
+use crate::config::SignConfig;
+
+/// Algorithm of minisign keys and of signatures over the file itself
+const SIGNATURE_ALGORITHM: &[u8; 2] = b"Ed";
+/// Algorithm of minisign signatures over the BLAKE2b hash of the file
+const HASHED_SIGNATURE_ALGORITHM: &[u8; 2] = b"ED";
+/// Key derivation function of password protected secret keys
+const SCRYPT: &[u8; 2] = b"Sc";
+const NO_KDF: &[u8; 2] = &[0, 0];
+const BLAKE2B: &[u8; 2] = b"B2";
+const KEY_ID_LENGTH: usize = 8;
+const SALT_LENGTH: usize = 32;
+const CHECKSUM_LENGTH: usize = 32;
+/// The key ID, key pair, and checksum that are encrypted in the secret key file
+const KEYNUM_LENGTH: usize = KEY_ID_LENGTH + ed25519::KEYPAIR_LENGTH + CHECKSUM_LENGTH;
+const SECRET_KEY_LENGTH: usize = 2 + 2 + 2 + SALT_LENGTH + 8 + 8 + KEYNUM_LENGTH;
+
+/// Signs the generated feeds with a minisign secret key
+///
+/// The signature of each feed is written to a `.minisig` file alongside it, which can be checked
+/// with `minisign -V -p <public key> -m <feed>`.
+#[derive(Clone)]
+pub struct Signer {
+    key_id: [u8; KEY_ID_LENGTH],
+    keypair: [u8; ed25519::KEYPAIR_LENGTH],
+}
+
+impl std::fmt::Debug for Signer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Leave out the secret key
+        f.debug_struct("Signer")
+            .field("key_id", &key_id_hex(&self.key_id))
+            .finish()
+    }
+}
+
+impl Signer {
+    /// Read the secret key in `config`, decrypting it with the password from the config or the
+    /// environment if it's protected by one
+    pub fn load(config: &SignConfig) -> eyre::Result<Signer> {
+        let raw = fs::read_to_string(&config.key)
+            .wrap_err_with(|| format!("unable to read signing key: {}", config.key))?;
+        let password = match (&config.password, &config.password_env) {
+            (Some(password), _) => Some(
+                password
+                    .expose()
+                    .wrap_err("unable to read signing key password")?
+                    .to_string(),
+            ),
+            (None, Some(name)) => Some(env::var(name).wrap_err_with(|| {
+                format!("unable to read signing key password from environment variable {name}")
+            })?),
+            (None, None) => None,
+        };
+        Signer::parse(&raw, password.as_deref())
+            .wrap_err_with(|| format!("invalid signing key: {}", config.key))
+    }
+
+    fn parse(raw: &str, password: Option<&str>) -> eyre::Result<Signer> {
+        // The key follows the untrusted comment line
+        let encoded = raw
+            .lines()
+            .find(|line| !line.starts_with("untrusted comment:") && !line.trim().is_empty())
+            .ok_or_else(|| eyre!("no key in file"))?;
+        let key = STANDARD
+            .decode(encoded.trim())
+            .wrap_err("key is not valid base64")?;
+        if key.len() != SECRET_KEY_LENGTH {
+            bail!("not a minisign secret key");
+        }
+        let (algorithm, rest) = key.split_at(2);
+        let (kdf, rest) = rest.split_at(2);
+        let (checksum_algorithm, rest) = rest.split_at(2);
+        let (salt, rest) = rest.split_at(SALT_LENGTH);
+        let (opslimit, rest) = rest.split_at(8);
+        let (memlimit, keynum) = rest.split_at(8);
+        if algorithm != SIGNATURE_ALGORITHM || checksum_algorithm != BLAKE2B {
+            bail!("unsupported key algorithm");
+        }
+
+        let mut keynum = keynum.to_vec();
+        if kdf == SCRYPT {
+            let password =
+                password.ok_or_else(|| eyre!("key is encrypted but no password was given"))?;
+            let params = scrypt_params(le_u64(opslimit), le_u64(memlimit));
+            let mut stream = [0; KEYNUM_LENGTH];
+            scrypt(password.as_bytes(), salt, &params, &mut stream);
+            for (byte, mask) in keynum.iter_mut().zip(stream) {
+                *byte ^= mask;
+            }
+        } else if kdf != NO_KDF {
+            bail!("unsupported key derivation function");
+        }
+
+        let (key_id, rest) = keynum.split_at(KEY_ID_LENGTH);
+        let (keypair, checksum) = rest.split_at(ed25519::KEYPAIR_LENGTH);
+        let mut context = Blake2b::new(CHECKSUM_LENGTH);
+        context.input(algorithm);
+        context.input(key_id);
+        context.input(keypair);
+        let mut expected = [0; CHECKSUM_LENGTH];
+        context.result(&mut expected);
+        if checksum != expected {
+            bail!("checksum doesn't match, the password may be wrong");
+        }
+
+        let mut signer = Signer {
+            key_id: [0; KEY_ID_LENGTH],
+            keypair: [0; ed25519::KEYPAIR_LENGTH],
+        };
+        signer.key_id.copy_from_slice(key_id);
+        signer.keypair.copy_from_slice(keypair);
+        Ok(signer)
+    }
+
+    /// The contents of the `.minisig` file for `data`, written to a file called `file_name`
+    pub fn sign(&self, data: &[u8], file_name: &str, now: OffsetDateTime) -> String {
+        let mut context = Blake2b::new(64);
+        context.input(data);
+        let mut hash = [0; 64];
+        context.result(&mut hash);
+        let signature = ed25519::signature(&hash, &self.keypair);
+
+        let mut encoded = HASHED_SIGNATURE_ALGORITHM.to_vec();
+        encoded.extend_from_slice(&self.key_id);
+        encoded.extend_from_slice(&signature);
+        // Tabs and newlines would break the comment, and its parsing by minisign
+        let file_name = file_name.replace(['\t', '\n', '\r'], " ");
+        let trusted_comment = format!(
+            "timestamp:{}\tfile:{file_name}\thashed",
+            now.unix_timestamp()
+        );
+        let global_signature = ed25519::signature(
+            &[&signature[..], trusted_comment.as_bytes()].concat(),
+            &self.keypair,
+        );
+
+        format!(
+            "untrusted comment: signature from rsspls secret key {}\n{}\ntrusted comment: {trusted_comment}\n{}\n",
+            key_id_hex(&self.key_id),
+            STANDARD.encode(encoded),
+            STANDARD.encode(global_signature),
+        )
+    }
+}
+
+/// The path of the signature of the file at `path`
+pub fn signature_path(path: &Path) -> std::path::PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".minisig");
+    path.into()
+}
+
+/// The scrypt parameters libsodium picks for `opslimit` and `memlimit`, which minisign stores in
+/// the secret key
+fn scrypt_params(opslimit: u64, memlimit: u64) -> ScryptParams {
+    let opslimit = opslimit.max(32768);
+    let r = 8;
+    let log_n = |max_n: u64| (1..63).find(|&log_n| 1 << log_n > max_n / 2).unwrap_or(63);
+    if opslimit < memlimit / 32 {
+        ScryptParams::new(log_n(opslimit / (u64::from(r) * 4)), r, 1)
+    } else {
+        let log_n = log_n(memlimit / (u64::from(r) * 128));
+        let max_rp = ((opslimit / 4) / (1 << log_n)).min(0x3fff_ffff) as u32;
+        ScryptParams::new(log_n, r, (max_rp / r).max(1))
+    }
+}
+
+fn le_u64(bytes: &[u8]) -> u64 {
+    let mut le = [0; 8];
+    le.copy_from_slice(bytes);
+    u64::from_le_bytes(le)
+}
+
+/// The key ID as minisign shows it
+fn key_id_hex(key_id: &[u8; KEY_ID_LENGTH]) -> String {
+    key_id
+        .iter()
+        .rev()
+        .map(|byte| format!("{byte:02X}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    /// Build a secret key file for `seed`, encrypting it with `password` if given
+    fn secret_key(seed: [u8; 32], password: Option<&str>) -> String {
+        let (keypair, _) = ed25519::keypair(&seed);
+        let key_id = [1, 2, 3, 4, 5, 6, 7, 8];
+        let salt = [9; SALT_LENGTH];
+        let (opslimit, memlimit) = (32768u64, 16u64 << 20);
+        let mut context = Blake2b::new(CHECKSUM_LENGTH);
+        context.input(SIGNATURE_ALGORITHM);
+        context.input(&key_id);
+        context.input(&keypair);
+        let mut checksum = [0; CHECKSUM_LENGTH];
+        context.result(&mut checksum);
+        let mut keynum = [&key_id[..], &keypair, &checksum].concat();
+        if let Some(password) = password {
+            let mut stream = [0; KEYNUM_LENGTH];
+            scrypt(
+                password.as_bytes(),
+                &salt,
+                &scrypt_params(opslimit, memlimit),
+                &mut stream,
+            );
+            for (byte, mask) in keynum.iter_mut().zip(stream) {
+                *byte ^= mask;
+            }
+        }
+        let kdf = if password.is_some() { SCRYPT } else { NO_KDF };
+        let key = [
+            &SIGNATURE_ALGORITHM[..],
+            kdf,
+            BLAKE2B,
+            &salt,
+            &opslimit.to_le_bytes(),
+            &memlimit.to_le_bytes(),
+            &keynum,
+        ]
+        .concat();
+        format!(
+            "untrusted comment: minisign encrypted secret key\n{}\n",
+            STANDARD.encode(key)
+        )
+    }
+
+    #[test]
+    fn test_sign() {
+        let seed = [42; 32];
+        let (keypair, public_key) = ed25519::keypair(&seed);
+        let signer = Signer::parse(&secret_key(seed, None), None).unwrap();
+        assert_eq!(signer.keypair, keypair);
+
+        let now = datetime!(2024-08-01 10:20:30 UTC);
+        let minisig = signer.sign(b"<rss></rss>", "news.rss", now);
+        let lines: Vec<_> = minisig.lines().collect();
+        assert_eq!(
+            lines[0],
+            "untrusted comment: signature from rsspls secret key 0807060504030201"
+        );
+        assert_eq!(
+            lines[2],
+            "trusted comment: timestamp:1722507630\tfile:news.rss\thashed"
+        );
+
+        let signature = STANDARD.decode(lines[1]).unwrap();
+        assert_eq!(&signature[..2], HASHED_SIGNATURE_ALGORITHM);
+        assert_eq!(&signature[2..10], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut context = Blake2b::new(64);
+        context.input(b"<rss></rss>");
+        let mut hash = [0; 64];
+        context.result(&mut hash);
+        let signature = <[u8; 64]>::try_from(&signature[10..]).unwrap();
+        assert!(ed25519::verify(&hash, &public_key, &signature));
+
+        let trusted = lines[2].strip_prefix("trusted comment: ").unwrap();
+        let global = STANDARD.decode(lines[3]).unwrap();
+        assert!(ed25519::verify(
+            &[&signature[..], trusted.as_bytes()].concat(),
+            &public_key,
+            &<[u8; 64]>::try_from(&global[..]).unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_encrypted_key() {
+        let seed = [7; 32];
+        let raw = secret_key(seed, Some("hunter2"));
+        let signer = Signer::parse(&raw, Some("hunter2")).unwrap();
+        assert_eq!(signer.keypair, ed25519::keypair(&seed).0);
+
+        let err = Signer::parse(&raw, Some("wrong")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "checksum doesn't match, the password may be wrong"
+        );
+        let err = Signer::parse(&raw, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "key is encrypted but no password was given"
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use basic_toml as toml;
+use serde::Serialize;
+use simple_eyre::eyre::{self, WrapErr};
+
+use crate::text::slugify;
+
+/// Configuration generated by commands for the user to add to their config file
+#[derive(Debug, Default, Serialize)]
+pub struct ConfigSnippet {
+    pub feed: Vec<FeedSnippet>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedSnippet {
+    pub title: String,
+    pub filename: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    pub config: FeedConfigSnippet,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct FeedConfigSnippet {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_type: Option<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub item: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub heading: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media: Option<String>,
+}
+
+impl ConfigSnippet {
+    pub fn to_toml(&self) -> eyre::Result<String> {
+        toml::to_string(self).wrap_err("unable to serialise config")
+    }
+}
+
+impl FeedSnippet {
+    /// Create a feed snippet with a file name derived from `title`
+    pub fn new(title: String, config: FeedConfigSnippet) -> Self {
+        let slug = slugify(&title);
+        let filename = if slug.is_empty() {
+            "feed.rss".to_string()
+        } else {
+            format!("{slug}.rss")
+        };
+        FeedSnippet {
+            title,
+            filename,
+            user_agent: None,
+            config,
+        }
+    }
+}
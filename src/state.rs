@@ -0,0 +1,204 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::mem;
+use std::path::Path;
+
+use basic_toml as toml;
+use log::debug;
+use rss::{GuidBuilder, Item, ItemBuilder};
+use serde::{Deserialize, Serialize};
+use simple_eyre::eyre::{self, WrapErr};
+
+/// The number of GUIDs of items that are no longer in the feed kept in the state, so that items
+/// that come back aren't new again
+const SEEN_HISTORY: usize = 1000;
+
+/// State for a feed that persists between runs
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FeedState {
+    /// GUIDs of the items seen by previous runs, most recently seen first
+    #[serde(default)]
+    pub seen: Vec<String>,
+    // NOTE: Plain values need to come before arrays of tables for serialisation to TOML, and
+    // empty arrays of tables are skipped for the same reason
+    /// Unix timestamp of the newest item seen, or when a new undated item was first seen
+    pub newest_item: Option<i64>,
+    /// Unix timestamp of the start of the current digest period
+    pub digest_started: Option<i64>,
+    /// Digest of the region of the page the items were extracted from
+    pub region_hash: Option<String>,
+    /// The date the feed was backfilled from with the Wayback Machine
+    pub wayback_from: Option<String>,
+    /// The URLs of the archived copies of the items' links, by link
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub archived: BTreeMap<String, String>,
+    /// Unix timestamp of when each item was first seen, by GUID
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub first_seen: BTreeMap<String, i64>,
+    /// The items in the feed produced by the last run, when `keep_items` is set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kept: Vec<StoredItem>,
+    /// Items collected for the next digest
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending: Vec<StoredItem>,
+    /// Digest items produced by previous runs, newest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub digests: Vec<StoredItem>,
+    /// Items found in the Wayback Machine's snapshots of the page
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backfill: Vec<StoredItem>,
+    /// The titles and links of the items written by the last run, when `dedup` is set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deduped: Vec<StoredItem>,
+}
+
+/// An item stored in the state file, which is also how items are exchanged with transforms
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoredItem {
+    pub title: Option<String>,
+    pub link: Option<String>,
+    pub description: Option<String>,
+    /// RFC 2822 publication date
+    pub pub_date: Option<String>,
+    pub guid: Option<String>,
+}
+
+impl From<&Item> for StoredItem {
+    fn from(item: &Item) -> Self {
+        StoredItem {
+            title: item.title.clone(),
+            link: item.link.clone(),
+            description: item.description.clone(),
+            pub_date: item.pub_date.clone(),
+            guid: item.guid.as_ref().map(|guid| guid.value.clone()),
+        }
+    }
+}
+
+impl From<StoredItem> for Item {
+    fn from(stored: StoredItem) -> Self {
+        ItemBuilder::default()
+            .title(stored.title)
+            .link(stored.link)
+            .description(stored.description)
+            .pub_date(stored.pub_date)
+            .guid(
+                stored
+                    .guid
+                    .map(|value| GuidBuilder::default().value(value).permalink(false).build()),
+            )
+            .build()
+    }
+}
+
+impl FeedState {
+    /// Read the state at `path`, falling back to empty state if it's missing or invalid
+    pub fn read(path: &Path) -> FeedState {
+        let Ok(raw) = fs::read(path) else {
+            return FeedState::default();
+        };
+        match toml::from_slice(&raw) {
+            Ok(state) => {
+                debug!("using state at: {}", path.display());
+                state
+            }
+            Err(err) => {
+                debug!("ignoring invalid state at {}: {}", path.display(), err);
+                FeedState::default()
+            }
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> eyre::Result<()> {
+        debug!("write state {}", path.display());
+        let serialised = toml::to_string(self).wrap_err("unable to serialise state")?;
+        crate::write_atomically(path, serialised.as_bytes()).wrap_err("unable to write state")
+    }
+
+    /// Whether an item with `guid` was seen by a previous run
+    pub fn has_seen(&self, guid: &str) -> bool {
+        self.seen.iter().any(|seen| seen == guid)
+    }
+
+    /// Record the GUIDs of the items of this run as the most recently seen
+    ///
+    /// Of the items that are no longer in the feed, only the most recently seen are kept.
+    pub fn record_seen<'a>(&mut self, guids: impl IntoIterator<Item = &'a str>) {
+        let mut current = BTreeSet::new();
+        let mut seen: Vec<String> = guids
+            .into_iter()
+            .filter(|guid| current.insert(*guid))
+            .map(str::to_string)
+            .collect();
+        let limit = seen.len() + SEEN_HISTORY;
+        seen.extend(
+            mem::take(&mut self.seen)
+                .into_iter()
+                .filter(|guid| !current.contains(guid.as_str())),
+        );
+        seen.truncate(limit);
+        self.seen = seen;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let state = FeedState {
+            seen: vec!["a".to_string()],
+            newest_item: Some(1690000000),
+            digest_started: Some(1700000000),
+            region_hash: Some("0123abcd".to_string()),
+            wayback_from: Some("2023-01-01".to_string()),
+            first_seen: BTreeMap::from([("a".to_string(), 1690000000)]),
+            kept: vec![StoredItem {
+                guid: Some("a".to_string()),
+                ..Default::default()
+            }],
+            archived: BTreeMap::from([(
+                "http://example.com/a".to_string(),
+                "https://archive.ph/newest/http://example.com/a".to_string(),
+            )]),
+            pending: vec![StoredItem {
+                title: Some("A".to_string()),
+                ..Default::default()
+            }],
+            digests: Vec::new(),
+            backfill: vec![StoredItem {
+                link: Some("http://example.com/old".to_string()),
+                ..Default::default()
+            }],
+            deduped: vec![StoredItem {
+                title: Some("Deduplicated".to_string()),
+                link: Some("http://example.com/dup".to_string()),
+                ..Default::default()
+            }],
+        };
+        let serialised = toml::to_string(&state).unwrap();
+        let deserialised: FeedState = toml::from_str(&serialised).unwrap();
+        assert_eq!(deserialised, state);
+    }
+
+    #[test]
+    fn test_record_seen() {
+        let mut state = FeedState::default();
+        state.record_seen(["a", "b", "a"]);
+        assert_eq!(state.seen, ["a", "b"]);
+        state.record_seen(["c", "b"]);
+        assert_eq!(state.seen, ["c", "b", "a"]);
+        assert!(state.has_seen("a"));
+        assert!(!state.has_seen("d"));
+
+        // Only the most recently seen of the items no longer in the feed are kept
+        let old: Vec<_> = (0..SEEN_HISTORY + 10).map(|i| i.to_string()).collect();
+        state.record_seen(old.iter().map(String::as_str));
+        state.record_seen(["x", "y"]);
+        assert_eq!(state.seen.len(), SEEN_HISTORY + 2);
+        assert_eq!(state.seen[..3], ["x", "y", "0"]);
+        assert!(!state.has_seen("c"));
+    }
+}
@@ -0,0 +1,124 @@
+#[cfg(feature = "atom")]
+use atom_syndication::{Entry, Feed};
+#[cfg(feature = "atom")]
+use rss::ItemBuilder;
+use rss::{Channel, GuidBuilder, Item};
+use simple_eyre::eyre::{self, eyre};
+
+use crate::jsonfeed;
+
+/// Parse the items out of a RSS, Atom, or JSON feed
+pub fn parse_items(body: &str) -> eyre::Result<Vec<Item>> {
+    if body.trim_start().starts_with('{') {
+        return jsonfeed::parse_items(body);
+    }
+
+    let rss_err = match body.parse::<Channel>() {
+        Ok(channel) => {
+            return Ok(channel
+                .into_items()
+                .into_iter()
+                .map(|mut item| {
+                    // Items without a GUID are identified by their link so that they can still be
+                    // tracked between runs
+                    if item.guid().is_none() {
+                        let guid = item
+                            .link()
+                            .map(|link| GuidBuilder::default().value(link).build());
+                        item.set_guid(guid);
+                    }
+                    item
+                })
+                .collect());
+        }
+        Err(err) => err,
+    };
+
+    #[cfg(feature = "atom")]
+    match body.parse::<Feed>() {
+        Ok(feed) => Ok(feed.entries().iter().map(atom_entry_to_item).collect()),
+        Err(atom_err) => Err(eyre!(
+            "unable to parse feed as RSS ({rss_err}) or Atom ({atom_err})"
+        )),
+    }
+    #[cfg(not(feature = "atom"))]
+    Err(eyre!(
+        "unable to parse feed as RSS ({rss_err}), this build of rsspls does not support Atom"
+    ))
+}
+
+#[cfg(feature = "atom")]
+fn atom_entry_to_item(entry: &Entry) -> Item {
+    let link = entry
+        .links()
+        .iter()
+        .find(|link| link.rel() == "alternate")
+        .or_else(|| entry.links().first())
+        .map(|link| link.href().to_string());
+    let description = entry
+        .content()
+        .and_then(|content| content.value())
+        .or_else(|| entry.summary().map(|summary| summary.as_str()))
+        .map(str::to_string);
+    let date = entry.published().unwrap_or_else(|| entry.updated());
+    let guid = GuidBuilder::default()
+        .value(entry.id())
+        .permalink(false)
+        .build();
+
+    ItemBuilder::default()
+        .title(Some(entry.title().as_str().to_string()))
+        .link(link)
+        .description(description)
+        .pub_date(Some(date.to_rfc2822()))
+        .guid(Some(guid))
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rss() {
+        let items = parse_items(
+            r#"<?xml version="1.0"?>
+<rss version="2.0"><channel><title>Test</title><link>http://example.com/</link><description></description>
+<item><title>One</title><link>http://example.com/1</link></item>
+</channel></rss>"#,
+        )
+        .unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].title(), Some("One"));
+        assert_eq!(
+            items[0].guid().map(|guid| guid.value()),
+            Some("http://example.com/1")
+        );
+    }
+
+    #[cfg(feature = "atom")]
+    #[test]
+    fn test_parse_atom() {
+        let items = parse_items(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Test</title>
+  <id>urn:test</id>
+  <updated>2024-08-01T10:00:00Z</updated>
+  <entry>
+    <title>One</title>
+    <id>urn:test:1</id>
+    <link rel="alternate" href="http://example.com/1"/>
+    <updated>2024-08-01T10:00:00Z</updated>
+    <summary>Summary</summary>
+  </entry>
+</feed>"#,
+        )
+        .unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].link(), Some("http://example.com/1"));
+        assert_eq!(items[0].description(), Some("Summary"));
+        assert_eq!(items[0].pub_date(), Some("Thu, 1 Aug 2024 10:00:00 +0000"));
+        assert_eq!(items[0].guid().map(|guid| guid.value()), Some("urn:test:1"));
+    }
+}
@@ -0,0 +1,60 @@
+const BRACES: &[char] = &['{', '}'];
+
+/// Render `template`, replacing `{name}` placeholders with the value returned by `lookup`
+///
+/// Placeholders that `lookup` returns `None` for are left as is. `{{` and `}}` can be used to
+/// produce literal braces.
+pub fn render<F>(template: &str, mut lookup: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(BRACES) {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            rendered.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+
+        let placeholder = rest
+            .strip_prefix('{')
+            .and_then(|inner| inner.find('}').map(|end| &inner[..end]));
+        match placeholder.and_then(|name| lookup(name).map(|value| (name, value))) {
+            Some((name, value)) => {
+                rendered.push_str(&value);
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                rendered.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let lookup = |name: &str| match name {
+            "price" => Some("$10".to_string()),
+            "summary" => Some("<p>Nice</p>".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            render("<p>{price}</p>{summary}", lookup),
+            "<p>$10</p><p>Nice</p>"
+        );
+        assert_eq!(
+            render("{{price}} {unknown} {price", lookup),
+            "{price} {unknown} {price"
+        );
+    }
+}
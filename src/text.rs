@@ -0,0 +1,206 @@
+use html5ever::data::NAMED_ENTITIES;
+use html5ever::{local_name, namespace_url, ns, QualName};
+use kuchiki::iter::NodeIterator;
+use kuchiki::NodeRef;
+use unicode_normalization::UnicodeNormalization;
+
+/// Characters that are invisible when rendered and only get in the way of matching text
+const INVISIBLE: &[char] = &[
+    '\u{00AD}', // SOFT HYPHEN
+    '\u{200B}', // ZERO WIDTH SPACE
+    '\u{2060}', // WORD JOINER
+    '\u{FEFF}', // ZERO WIDTH NO-BREAK SPACE (BOM)
+];
+
+/// Normalise text extracted from a page
+///
+/// Decodes any HTML entities that survived parsing (E.g. double encoded `&amp;amp;`),
+/// strips invisible characters like soft hyphens, then normalises to Unicode NFC.
+pub fn normalize(text: &str) -> String {
+    let decoded = decode_entities(text);
+    decoded
+        .chars()
+        .filter(|c| !INVISIBLE.contains(c))
+        .nfc()
+        .collect()
+}
+
+/// Normalise all text nodes within `node` in place
+pub fn normalize_tree(node: &NodeRef) {
+    for text in node.inclusive_descendants().text_nodes() {
+        let mut text = text.borrow_mut();
+        let normalized = normalize(&text);
+        *text = normalized;
+    }
+}
+
+/// Wrap case-insensitive occurrences of `keywords` in text within `node` with `<mark>`
+pub fn highlight_tree(node: &NodeRef, keywords: &[String]) {
+    // Collect the text nodes first as the tree is modified while highlighting
+    let text_nodes: Vec<_> = node.inclusive_descendants().text_nodes().collect();
+    for text_node in text_nodes {
+        let already_marked = text_node
+            .as_node()
+            .parent()
+            .and_then(|parent| parent.into_element_ref())
+            .is_some_and(|parent| parent.name.local == local_name!("mark"));
+        if already_marked {
+            continue;
+        }
+
+        let text = text_node.borrow().clone();
+        let mut rest = text.as_str();
+        let mut found = false;
+        while let Some((start, end)) = find_keyword(rest, keywords) {
+            found = true;
+            if start > 0 {
+                text_node
+                    .as_node()
+                    .insert_before(NodeRef::new_text(&rest[..start]));
+            }
+            let mark =
+                NodeRef::new_element(QualName::new(None, ns!(html), local_name!("mark")), []);
+            mark.append(NodeRef::new_text(&rest[start..end]));
+            text_node.as_node().insert_before(mark);
+            rest = &rest[end..];
+        }
+
+        if found {
+            if rest.is_empty() {
+                text_node.as_node().detach();
+            } else {
+                *text_node.borrow_mut() = rest.to_string();
+            }
+        }
+    }
+}
+
+/// Whether `text` contains any of `keywords`, ignoring ASCII case
+pub fn contains_keyword(text: &str, keywords: &[String]) -> bool {
+    find_keyword(text, keywords).is_some()
+}
+
+/// Find the earliest occurrence of any of `keywords` in `text`, ignoring ASCII case
+fn find_keyword(text: &str, keywords: &[String]) -> Option<(usize, usize)> {
+    text.char_indices().find_map(|(i, _)| {
+        keywords
+            .iter()
+            .filter(|keyword| !keyword.is_empty())
+            .find(|keyword| {
+                text.as_bytes()
+                    .get(i..i + keyword.len())
+                    .is_some_and(|candidate| candidate.eq_ignore_ascii_case(keyword.as_bytes()))
+            })
+            .map(|keyword| (i, i + keyword.len()))
+    })
+}
+
+/// Escape text for inclusion in HTML
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Turn `value` into a string suitable for use in a file name
+pub fn slugify(value: &str) -> String {
+    let mut slug = String::with_capacity(value.len());
+    for c in value.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let len = slug.trim_end_matches('-').len();
+    slug.truncate(len);
+    slug
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match decode_entity(rest) {
+            Some((chars, len)) => {
+                decoded.extend(chars.into_iter().flatten());
+                rest = &rest[len..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Decode the entity at the start of `s`, returning the decoded chars and length consumed
+fn decode_entity(s: &str) -> Option<([Option<char>; 2], usize)> {
+    let end = s.find(';')?;
+    let entity = &s[1..end];
+    let chars = if let Some(num) = entity.strip_prefix('#') {
+        let code = match num.strip_prefix('x').or_else(|| num.strip_prefix('X')) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => num.parse().ok()?,
+        };
+        [Some(char::from_u32(code)?), None]
+    } else {
+        if entity.is_empty() || !entity.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        let (first, second) = NAMED_ENTITIES.get(&s[1..=end])?;
+        [
+            char::from_u32(*first),
+            char::from_u32(*second).filter(|&c| c != '\0'),
+        ]
+    };
+    Some((chars, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("Caf\u{0065}\u{0301}"), "Caf\u{00E9}");
+        assert_eq!(normalize("key\u{00AD}word\u{200B}s"), "keywords");
+        assert_eq!(
+            normalize("Fish &amp; Chips &#8211; &#x2014;"),
+            "Fish & Chips – —"
+        );
+        assert_eq!(
+            normalize("AT&T & co; &bogus; &#xZZ;"),
+            "AT&T & co; &bogus; &#xZZ;"
+        );
+    }
+
+    #[test]
+    fn test_highlight_tree() {
+        use kuchiki::traits::TendrilSink;
+
+        let doc = kuchiki::parse_html().one("<p>Rust is <b>rusty</b>, rust.</p>");
+        let p = doc.select_first("p").unwrap();
+        let keywords = vec!["rust".to_string()];
+        highlight_tree(p.as_node(), &keywords);
+        // Highlighting again should not double up the marks
+        highlight_tree(p.as_node(), &keywords);
+        assert_eq!(
+            p.as_node().to_string(),
+            "<p><mark>Rust</mark> is <b><mark>rust</mark>y</b>, <mark>rust</mark>.</p>"
+        );
+    }
+}
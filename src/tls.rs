@@ -0,0 +1,85 @@
+use reqwest::ClientBuilder;
+use simple_eyre::eyre::{self, bail};
+
+use crate::config::TlsRoots;
+
+/// Environment variable naming a PEM file of CA certificates to use instead of the system bundle
+#[cfg(all(feature = "rust-tls", not(feature = "native-tls")))]
+const SSL_CERT_FILE: &str = "SSL_CERT_FILE";
+
+/// Locations of the CA certificate bundle on common systems
+#[cfg(all(feature = "rust-tls", not(feature = "native-tls")))]
+const CA_BUNDLE_PATHS: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt", // Debian, Ubuntu, Arch, Alpine
+    "/etc/pki/tls/certs/ca-bundle.crt",   // Fedora, RHEL
+    "/etc/ssl/ca-bundle.pem",             // openSUSE
+    "/etc/ssl/cert.pem",                  // macOS, OpenBSD
+    "/usr/local/share/certs/ca-root-nss.crt", // FreeBSD
+];
+
+/// Set the certificates used to verify servers to `roots`
+pub fn configure(builder: ClientBuilder, roots: TlsRoots) -> eyre::Result<ClientBuilder> {
+    match roots {
+        TlsRoots::Bundled => bundled(builder),
+        TlsRoots::Native => native(builder),
+    }
+}
+
+#[cfg(feature = "rust-tls")]
+fn bundled(builder: ClientBuilder) -> eyre::Result<ClientBuilder> {
+    // The webpki roots are built into rustls
+    Ok(builder.use_rustls_tls())
+}
+
+#[cfg(not(feature = "rust-tls"))]
+fn bundled(_builder: ClientBuilder) -> eyre::Result<ClientBuilder> {
+    bail!("tls_roots = \"bundled\" requires rsspls to be built with the rust-tls feature")
+}
+
+#[cfg(feature = "native-tls")]
+fn native(builder: ClientBuilder) -> eyre::Result<ClientBuilder> {
+    // The platform TLS implementation uses the system's certificate store
+    Ok(builder.use_native_tls())
+}
+
+#[cfg(all(feature = "rust-tls", not(feature = "native-tls")))]
+fn native(builder: ClientBuilder) -> eyre::Result<ClientBuilder> {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use log::debug;
+    use reqwest::Certificate;
+    use simple_eyre::eyre::WrapErr;
+
+    let path = match env::var_os(SSL_CERT_FILE) {
+        Some(path) => PathBuf::from(path),
+        None => match CA_BUNDLE_PATHS
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.is_file())
+        {
+            Some(path) => path,
+            None => bail!(
+                "unable to find the system CA certificates, set {SSL_CERT_FILE} to the path of a PEM file containing them"
+            ),
+        },
+    };
+    debug!("loading CA certificates from {}", path.display());
+    let pem = fs::read(&path)
+        .wrap_err_with(|| format!("unable to read CA certificates from {}", path.display()))?;
+    let certificates = Certificate::from_pem_bundle(&pem)
+        .wrap_err_with(|| format!("unable to parse CA certificates in {}", path.display()))?;
+    if certificates.is_empty() {
+        bail!("no CA certificates found in {}", path.display());
+    }
+    Ok(certificates.into_iter().fold(
+        builder.use_rustls_tls().tls_built_in_root_certs(false),
+        ClientBuilder::add_root_certificate,
+    ))
+}
+
+#[cfg(not(any(feature = "rust-tls", feature = "native-tls")))]
+fn native(_builder: ClientBuilder) -> eyre::Result<ClientBuilder> {
+    bail!("tls_roots = \"native\" requires rsspls to be built with the rust-tls or native-tls feature")
+}
@@ -0,0 +1,264 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use log::{debug, error};
+use reqwest::header::CONTENT_TYPE;
+use rss::{GuidBuilder, Item};
+use simple_eyre::eyre::{self, bail, eyre, WrapErr};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::config::FeedConfig;
+use crate::state::StoredItem;
+use crate::{download, Client};
+
+/// How long a transform command is given for each item before it's killed
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Update `item` with the values from `transformed`
+fn apply_to(transformed: StoredItem, mut item: Item) -> Item {
+    let permalink = item.guid.as_ref().is_some_and(|guid| guid.permalink);
+    item.title = transformed.title;
+    item.link = transformed.link;
+    item.description = transformed.description;
+    item.pub_date = transformed.pub_date;
+    item.guid = transformed.guid.map(|value| {
+        GuidBuilder::default()
+            .value(value)
+            .permalink(permalink)
+            .build()
+    });
+    item
+}
+
+/// Run the transforms configured for the feed over `items`
+pub async fn apply(client: &Client, config: &FeedConfig, items: Vec<Item>) -> Vec<Item> {
+    let items = apply_command(config, items).await;
+    match &config.transform_url {
+        Some(url) => apply_http(client, url, items).await,
+        None => items,
+    }
+}
+
+async fn apply_command(config: &FeedConfig, items: Vec<Item>) -> Vec<Item> {
+    let Some((program, args)) = config.transform_command.split_first() else {
+        return items;
+    };
+
+    let mut transformed = Vec::with_capacity(items.len());
+    for item in items {
+        match run_command(program, args, &item).await {
+            Ok(Some(new_item)) => transformed.push(apply_to(new_item, item)),
+            Ok(None) => debug!("transform command dropped item {:?}", item.title),
+            Err(report) => {
+                // Keep the original item so that a misbehaving transform doesn't empty the feed
+                error!("{:?}", report.wrap_err("unable to transform item"));
+                transformed.push(item);
+            }
+        }
+    }
+    transformed
+}
+
+async fn apply_http(client: &Client, url: &str, items: Vec<Item>) -> Vec<Item> {
+    match post_items(client, url, &items).await {
+        Ok(transformed) => merge_items(items, transformed),
+        Err(report) => {
+            error!("{:?}", report.wrap_err("unable to transform items"));
+            items
+        }
+    }
+}
+
+/// POST `items` as a JSON array to `url` and parse the array of items in the response
+async fn post_items(client: &Client, url: &str, items: &[Item]) -> eyre::Result<Vec<StoredItem>> {
+    let body = serde_json::to_vec(&items.iter().map(StoredItem::from).collect::<Vec<_>>())?;
+    debug!("posting {} items to transform url {url}", items.len());
+    let request = client
+        .http
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body);
+    let resp = download::send(client, request)
+        .await
+        .wrap_err_with(|| format!("unable to post items to {url}"))?;
+
+    let status = resp.status();
+    if !status.is_success() {
+        bail!(
+            "transform url {url} failed: {} {}",
+            status.as_str(),
+            status.canonical_reason().unwrap_or("Unknown Status")
+        );
+    }
+
+    let body = download::body(client, resp)
+        .await
+        .wrap_err("unable to read transform response body")?;
+    serde_json::from_slice(&body).wrap_err("unable to parse response of transform url")
+}
+
+/// Build the final list of items from the transformed ones
+///
+/// Transformed items are matched to the originals by GUID so that fields not exchanged with the
+/// transform (like enclosures) are retained. Items without a match are created from scratch.
+fn merge_items(originals: Vec<Item>, transformed: Vec<StoredItem>) -> Vec<Item> {
+    let mut originals: Vec<_> = originals.into_iter().map(Some).collect();
+    transformed
+        .into_iter()
+        .map(|new_item| {
+            let original = new_item.guid.as_deref().and_then(|guid| {
+                originals
+                    .iter_mut()
+                    .find(|item| {
+                        item.as_ref()
+                            .and_then(|item| item.guid.as_ref())
+                            .is_some_and(|orig| orig.value == guid)
+                    })
+                    .and_then(Option::take)
+            });
+            apply_to(new_item, original.unwrap_or_default())
+        })
+        .collect()
+}
+
+/// Pass `item` as JSON to the command on stdin and parse the item it prints
+///
+/// Returns `None` if the command produces no output, indicating the item should be dropped.
+async fn run_command(
+    program: &str,
+    args: &[String],
+    item: &Item,
+) -> eyre::Result<Option<StoredItem>> {
+    let input = serde_json::to_vec(&StoredItem::from(item))?;
+    debug!("running transform command {program} {args:?}");
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .wrap_err_with(|| format!("unable to run transform command: {program}"))?;
+
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| eyre!("unable to open stdin of transform command"))?;
+    // The item is written while the output is read, otherwise a command that writes more than
+    // fits in the pipe before it has read all of its input would never finish
+    let write = async move {
+        let written = stdin.write_all(&input).await;
+        // Close stdin so the command sees EOF
+        drop(stdin);
+        written
+    };
+    let (written, output) = tokio::time::timeout(TIMEOUT, async {
+        tokio::join!(write, child.wait_with_output())
+    })
+    .await
+    .map_err(|_| {
+        eyre!(
+            "transform command {program} took longer than {}s",
+            TIMEOUT.as_secs()
+        )
+    })?;
+    let output = output.wrap_err("error waiting for transform command")?;
+    if !output.status.success() {
+        bail!("transform command {program} failed: {}", output.status);
+    }
+    written.wrap_err("unable to write item to transform command")?;
+
+    if output.stdout.iter().all(u8::is_ascii_whitespace) {
+        return Ok(None);
+    }
+    serde_json::from_slice(&output.stdout)
+        .map(Some)
+        .wrap_err("unable to parse output of transform command")
+}
+
+#[cfg(test)]
+mod tests {
+    use rss::ItemBuilder;
+
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_transform_command() {
+        let item = ItemBuilder::default()
+            .title(Some("Hello".to_string()))
+            .link(Some("http://example.com/".to_string()))
+            .build();
+        let args = vec!["-c".to_string(), r#"sed 's/Hello/Bonjour/'"#.to_string()];
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()
+            .unwrap();
+        let new_item = runtime
+            .block_on(run_command("sh", &args, &item))
+            .unwrap()
+            .unwrap();
+        let transformed = apply_to(new_item, item);
+
+        assert_eq!(transformed.title.as_deref(), Some("Bonjour"));
+        assert_eq!(transformed.link.as_deref(), Some("http://example.com/"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_transform_command_large_output() {
+        // More than fits in a pipe is written before the input is read
+        let description = "x".repeat(1 << 20);
+        let item = ItemBuilder::default()
+            .description(Some(description.clone()))
+            .build();
+        let args = vec![
+            "-c".to_string(),
+            r#"head -c 1048576 /dev/zero | tr '\0' ' '; cat"#.to_string(),
+        ];
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()
+            .unwrap();
+        let transformed = runtime
+            .block_on(run_command("sh", &args, &item))
+            .unwrap()
+            .unwrap();
+        assert_eq!(transformed.description, Some(description));
+    }
+
+    #[test]
+    fn test_merge_items() {
+        let guid = GuidBuilder::default().value("a".to_string()).build();
+        let enclosure = rss::EnclosureBuilder::default()
+            .url("http://example.com/a.mp3".to_string())
+            .build();
+        let original = ItemBuilder::default()
+            .title(Some("A".to_string()))
+            .guid(Some(guid))
+            .enclosure(Some(enclosure))
+            .build();
+        let transformed = vec![
+            StoredItem {
+                title: Some("B".to_string()),
+                ..Default::default()
+            },
+            StoredItem {
+                title: Some("Summary of A".to_string()),
+                guid: Some("a".to_string()),
+                ..Default::default()
+            },
+        ];
+
+        let items = merge_items(vec![original], transformed);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].title.as_deref(), Some("B"));
+        assert!(items[0].enclosure.is_none());
+        assert_eq!(items[1].title.as_deref(), Some("Summary of A"));
+        assert!(items[1].enclosure.is_some());
+    }
+}
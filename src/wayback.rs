@@ -0,0 +1,104 @@
+use simple_eyre::eyre::{self, eyre, WrapErr};
+use time::macros::format_description;
+use time::Date;
+use url::Url;
+
+/// The Wayback Machine CDX API, which lists the snapshots of a URL
+const CDX_URL: &str = "https://web.archive.org/cdx/search/cdx";
+/// The Wayback Machine, `id_` after the timestamp requests the page as it was archived without
+/// the Wayback Machine's toolbar and rewritten links
+const SNAPSHOT_URL: &str = "https://web.archive.org/web";
+/// The maximum number of snapshots that items are extracted from
+const MAX_SNAPSHOTS: usize = 12;
+
+/// The URL of the list of successful snapshots of `url` taken since `from`, at most one a month
+pub fn index_url(url: &Url, from: Date) -> eyre::Result<Url> {
+    let from = from
+        .format(format_description!("[year][month][day]"))
+        .wrap_err("unable to format Wayback Machine date")?;
+    Url::parse_with_params(
+        CDX_URL,
+        [
+            ("url", url.as_str()),
+            ("from", &from),
+            ("output", "json"),
+            ("fl", "timestamp,original"),
+            ("filter", "statuscode:200"),
+            ("collapse", "timestamp:6"),
+        ],
+    )
+    .wrap_err("unable to build Wayback Machine URL")
+}
+
+/// Parse the snapshot index into the URLs of the snapshots, newest first
+///
+/// At most `MAX_SNAPSHOTS` are returned, spread over the whole period so that items from all of it
+/// are found.
+pub fn snapshots(index: &str) -> eyre::Result<Vec<Url>> {
+    let rows: Vec<Vec<String>> =
+        serde_json::from_str(index).wrap_err("unable to parse Wayback Machine index")?;
+    // The first row names the fields
+    let snapshots = rows
+        .iter()
+        .skip(1)
+        .map(|row| match row.as_slice() {
+            [timestamp, original, ..] => {
+                Url::parse(&format!("{SNAPSHOT_URL}/{timestamp}id_/{original}"))
+                    .wrap_err("invalid snapshot in Wayback Machine index")
+            }
+            _ => Err(eyre!("unexpected row in Wayback Machine index: {row:?}")),
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let count = snapshots.len().min(MAX_SNAPSHOTS);
+    Ok((0..count)
+        .map(|i| snapshots[snapshots.len() - 1 - i * snapshots.len() / count].clone())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Month;
+
+    #[test]
+    fn test_index_url() {
+        let url = "http://example.com/news?page=1".parse().unwrap();
+        let from = Date::from_calendar_date(2023, Month::January, 1).unwrap();
+        assert_eq!(
+            index_url(&url, from).unwrap().as_str(),
+            "https://web.archive.org/cdx/search/cdx?url=http%3A%2F%2Fexample.com%2Fnews%3Fpage%3D1&from=20230101&output=json&fl=timestamp%2Coriginal&filter=statuscode%3A200&collapse=timestamp%3A6"
+        );
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let index = r#"[["timestamp","original"],
+            ["20230105120000","http://example.com/news"],
+            ["20230203120000","http://example.com/news"]]"#;
+        let urls: Vec<_> = snapshots(index)
+            .unwrap()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                "https://web.archive.org/web/20230203120000id_/http://example.com/news",
+                "https://web.archive.org/web/20230105120000id_/http://example.com/news"
+            ]
+        );
+        assert!(snapshots("[]").unwrap().is_empty());
+
+        let rows: Vec<_> = (1..=30)
+            .map(|day| format!(r#"["202301{day:02}000000","http://example.com/"]"#))
+            .collect();
+        let index = format!(r#"[["timestamp","original"],{}]"#, rows.join(","));
+        let urls = snapshots(&index).unwrap();
+        assert_eq!(urls.len(), MAX_SNAPSHOTS);
+        assert!(urls[0].as_str().contains("/20230130000000id_/"));
+        assert!(urls[MAX_SNAPSHOTS - 1]
+            .as_str()
+            .contains("/20230103000000id_/"));
+    }
+}
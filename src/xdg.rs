@@ -1,16 +1,38 @@
 use std::{
-    path::PathBuf,
+    ffi::{CStr, CString, OsStr},
+    fs, io, mem,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    ptr,
     sync::{Arc, Mutex},
 };
 
-use eyre::WrapErr;
+use eyre::{eyre, WrapErr};
 use simple_eyre::eyre;
 
-pub type Dirs = Arc<Mutex<xdg::BaseDirectories>>;
+pub type Dirs = Arc<Mutex<BaseDirs>>;
 
-pub fn new() -> eyre::Result<xdg::BaseDirectories> {
-    xdg::BaseDirectories::with_prefix("rsspls")
-        .wrap_err("unable to determine home directory of current user")
+pub struct BaseDirs {
+    xdg: xdg::BaseDirectories,
+    /// Directory used in place of `$XDG_CACHE_HOME/rsspls`
+    cache_dir: Option<PathBuf>,
+    /// Directory used in place of `$XDG_STATE_HOME/rsspls`
+    state_dir: Option<PathBuf>,
+}
+
+pub fn new(cache_dir: Option<PathBuf>, state_dir: Option<PathBuf>) -> eyre::Result<BaseDirs> {
+    let xdg = xdg::BaseDirectories::with_prefix("rsspls")
+        .wrap_err("unable to determine home directory of current user")?;
+    Ok(BaseDirs {
+        xdg,
+        cache_dir,
+        state_dir,
+    })
+}
+
+/// The config file shared by all users, which the config of each user is layered on
+pub fn system_config_file() -> Option<PathBuf> {
+    Some(PathBuf::from("/etc/rsspls/feeds.toml"))
 }
 
 pub fn home_dir() -> Option<PathBuf> {
@@ -19,3 +41,156 @@ pub fn home_dir() -> Option<PathBuf> {
     #[allow(deprecated)]
     std::env::home_dir()
 }
+
+/// Expand a leading `~` or `~user` in `path`
+pub fn expand_path(path: &str) -> eyre::Result<PathBuf> {
+    if let Some(path) = expand_user(path, user_home_dir) {
+        return Ok(path);
+    }
+    let home = home_dir().ok_or_else(|| eyre!("unable to determine home directory"))?;
+    Ok(crate::expand_tilde(path, home))
+}
+
+/// Expand `~user` at the start of `path` to the home directory of that user
+///
+/// Returns `None` if `path` doesn't start with `~user` or the user doesn't exist.
+fn expand_user(path: &str, user_home_dir: impl Fn(&str) -> Option<PathBuf>) -> Option<PathBuf> {
+    let rest = path.strip_prefix('~')?;
+    let (user, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    if user.is_empty() {
+        return None;
+    }
+    let mut home = user_home_dir(user)?;
+    if !rest.is_empty() {
+        home.push(rest);
+    }
+    Some(home)
+}
+
+/// Look up the home directory of `user` in the passwd database
+fn user_home_dir(user: &str) -> Option<PathBuf> {
+    lookup_name(user, libc::getpwnam_r, |passwd: &libc::passwd| {
+        if passwd.pw_dir.is_null() {
+            return None;
+        }
+        // SAFETY: on success pw_dir points to a NUL terminated string in the lookup's buffer,
+        // which is still alive
+        let dir = unsafe { CStr::from_ptr(passwd.pw_dir) };
+        Some(PathBuf::from(OsStr::from_bytes(dir.to_bytes())))
+    })
+}
+
+/// The signature shared by `getpwnam_r` and `getgrnam_r`
+pub type LookupFn<T> = unsafe extern "C" fn(
+    *const libc::c_char,
+    *mut T,
+    *mut libc::c_char,
+    libc::size_t,
+    *mut *mut T,
+) -> libc::c_int;
+
+/// Look up the entry for `name` with `lookup`, growing the buffer for its strings until they fit,
+/// and pass it to `read` while the buffer is alive
+///
+/// `T` must be a plain C struct for which all zeros is a valid value, such as `libc::passwd`.
+pub fn lookup_name<T, R>(
+    name: &str,
+    lookup: LookupFn<T>,
+    read: impl FnOnce(&T) -> Option<R>,
+) -> Option<R> {
+    const MAX_BUF_LEN: usize = 1 << 20;
+
+    let name = CString::new(name).ok()?;
+    let mut buf = vec![0u8; 1024];
+    // SAFETY: T is a plain C struct for which all zeros is a valid value
+    let mut entry: T = unsafe { mem::zeroed() };
+    let mut result = ptr::null_mut();
+    loop {
+        // SAFETY: the pointers are valid for the duration of the call and buf.len() is the
+        // size of buf
+        let ret = unsafe {
+            lookup(
+                name.as_ptr(),
+                &mut entry,
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if ret == libc::ERANGE && buf.len() < MAX_BUF_LEN {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        break;
+    }
+    if result.is_null() {
+        return None;
+    }
+    read(&entry)
+}
+
+impl BaseDirs {
+    pub fn place_config_file<P: AsRef<Path>>(&self, path: P) -> io::Result<PathBuf> {
+        self.xdg.place_config_file(path)
+    }
+
+    pub fn place_cache_file<P: AsRef<Path>>(&self, path: P) -> io::Result<PathBuf> {
+        match &self.cache_dir {
+            Some(cache_dir) => place_file(cache_dir, path),
+            None => self.xdg.place_cache_file(path),
+        }
+    }
+
+    /// Like `place_cache_file` but without creating any directories
+    pub fn cache_file<P: AsRef<Path>>(&self, path: P) -> io::Result<PathBuf> {
+        match &self.cache_dir {
+            Some(cache_dir) => Ok(cache_dir.join(path)),
+            None => Ok(self.xdg.get_cache_file(path)),
+        }
+    }
+
+    pub fn place_state_file<P: AsRef<Path>>(&self, path: P) -> io::Result<PathBuf> {
+        match &self.state_dir {
+            Some(state_dir) => place_file(state_dir, path),
+            None => self.xdg.place_state_file(path),
+        }
+    }
+
+    /// Like `place_state_file` but without creating any directories
+    pub fn state_file<P: AsRef<Path>>(&self, path: P) -> io::Result<PathBuf> {
+        match &self.state_dir {
+            Some(state_dir) => Ok(state_dir.join(path)),
+            None => Ok(self.xdg.get_state_file(path)),
+        }
+    }
+}
+
+/// The path of `path` in `dir`, creating leading directories like xdg does
+fn place_file<P: AsRef<Path>>(dir: &Path, path: P) -> io::Result<PathBuf> {
+    let path = dir.join(path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_user() {
+        let lookup = |user: &str| (user == "www").then(|| PathBuf::from("/home/www"));
+        assert_eq!(
+            expand_user("~www/public_html/feeds", lookup),
+            Some(PathBuf::from("/home/www/public_html/feeds"))
+        );
+        assert_eq!(
+            expand_user("~www", lookup),
+            Some(PathBuf::from("/home/www"))
+        );
+        assert_eq!(expand_user("~nobody/feeds", lookup), None);
+        assert_eq!(expand_user("~/feeds", lookup), None);
+        assert_eq!(expand_user("feeds", lookup), None);
+    }
+}